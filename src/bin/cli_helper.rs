@@ -1,74 +1,408 @@
-use rust_usb_bootable_creator::utils;
+use rust_usb_bootable_creator::checksum;
+use rust_usb_bootable_creator::flows::job::{WriteJob, WriteMode, BYPASS_OPTIONS};
+use rust_usb_bootable_creator::windows::unattend::UnattendFlags;
+use rust_usb_bootable_creator::utils::{self, IsoType};
+use rust_usb_bootable_creator::flows::boot_check;
 use rust_usb_bootable_creator::flows::windows_flow;
 use rust_usb_bootable_creator::flows::linux_flow;
+use rust_usb_bootable_creator::flows::linux_persistence;
+use rust_usb_bootable_creator::flows::image_copy;
+use rust_usb_bootable_creator::flows::media_manifest;
+use rust_usb_bootable_creator::flows::media_scan;
+use rust_usb_bootable_creator::flows::preflight::{self, CheckStatus};
+use rust_usb_bootable_creator::flows::syslinux_multiboot;
 
 use std::env;
 use std::io::{self, Write};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("detect") {
+        let Some(iso_path) = args.get(2) else {
+            eprintln!("Usage: cli_helper detect <iso_path>");
+            std::process::exit(1);
+        };
+        run_detect(iso_path);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("scan") {
+        let [_, _, usb_device] = args.as_slice() else {
+            eprintln!("Usage: cli_helper scan <usb_device>");
+            std::process::exit(1);
+        };
+        match media_scan::scan_device(usb_device) {
+            Ok(report) => println!("{}", serde_json::to_string(&report).unwrap_or_default()),
+            Err(e) => {
+                eprintln!("Failed to scan {}: {}", usb_device, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("update-unattend") {
+        let [_, _, usb_device, options @ ..] = args.as_slice() else {
+            eprintln!("Usage: cli_helper update-unattend <usb_device> [--bypass-tpm] [--bypass-secure-boot] [--bypass-ram] [--boot-wim]");
+            std::process::exit(1);
+        };
+        let mut flags = UnattendFlags::empty();
+        let mut inject_boot_wim = false;
+        for option in options {
+            match BYPASS_OPTIONS.iter().find(|(_, name)| name == option) {
+                Some((flag, _)) => flags |= *flag,
+                None if option == "--boot-wim" => inject_boot_wim = true,
+                None => {
+                    eprintln!("unknown option {}", option);
+                    std::process::exit(1);
+                }
+            }
+        }
+        exit_if_live_system(usb_device);
+        match windows_flow::update_unattend_on_usb(usb_device, flags, inject_boot_wim, &mut std::io::stdout()) {
+            Ok(partition) => println!("Answer file updated on {}", partition),
+            Err(e) => {
+                eprintln!("Failed to update the answer file: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("verify-media") {
+        let [_, _, usb_device] = args.as_slice() else {
+            eprintln!("Usage: cli_helper verify-media <usb_device>");
+            std::process::exit(1);
+        };
+        match media_manifest::verify_media(usb_device, &mut std::io::stdout()) {
+            Ok(check) if check.is_clean() => println!("All {} files match the manifest", check.files),
+            Ok(check) => {
+                for (what, paths) in [("Changed", &check.changed), ("Missing", &check.missing), ("Added", &check.added)] {
+                    for path in paths {
+                        println!("{}: {}", what, path);
+                    }
+                }
+                eprintln!(
+                    "Media does not match its manifest: {} changed, {} missing, {} added",
+                    check.changed.len(),
+                    check.missing.len(),
+                    check.added.len()
+                );
+                std::process::exit(3);
+            }
+            Err(e) => {
+                eprintln!("Failed to verify {}: {}", usb_device, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("verify") {
+        match args.as_slice() {
+            [_, _, flag, usb_device] if flag == "--boot" => verify_boot_or_exit(usb_device),
+            [_, _, iso_path, expected] => verify_or_exit(iso_path, expected),
+            _ => {
+                eprintln!("Usage: cli_helper verify <iso_path> <sha256>");
+                eprintln!("       cli_helper verify --boot <usb_device>");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("multiboot") {
+        let [_, _, usb_device, isos @ ..] = args.as_slice() else {
+            eprintln!("Usage: cli_helper multiboot <usb_device> <iso_path>...");
+            std::process::exit(1);
+        };
+        exit_if_live_system(usb_device);
+        if let Err(e) = syslinux_multiboot::write_multiboot_usb(isos, usb_device, &mut std::io::stdout()) {
+            eprintln!("Failed to create multiboot drive: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("multiboot-add") {
+        let [_, _, usb_device, isos @ ..] = args.as_slice() else {
+            eprintln!("Usage: cli_helper multiboot-add <usb_device> <iso_path>...");
+            std::process::exit(1);
+        };
+        exit_if_live_system(usb_device);
+        if let Err(e) = syslinux_multiboot::add_isos_to_multiboot(isos, usb_device, &mut std::io::stdout()) {
+            eprintln!("Failed to add ISOs: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     if args.len() < 3 {
-        eprintln!("Usage: cli_helper <iso_path> <usb_device> [--use-dd-mode]");
+        eprintln!("Usage: cli_helper <iso_path> <usb_device> [cluster_bytes] [options]");
+        eprintln!("       cli_helper detect <iso_path>");
+        eprintln!("       cli_helper scan <usb_device>  (describe what a written stick holds, as JSON)");
+        eprintln!("       cli_helper update-unattend <usb_device> [--bypass-*] [--boot-wim]  (change bypass flags on a Windows stick in place)");
+        eprintln!("       cli_helper verify <iso_path> <sha256>");
+        eprintln!("       cli_helper verify --boot <usb_device>  (check the EFI loaders on a written stick)");
+        eprintln!("       cli_helper verify-media <usb_device>  (re-hash a stick written with --manifest)");
+        eprintln!("       cli_helper multiboot <usb_device> <iso_path>...  (BIOS syslinux menu, ISOs booted via memdisk)");
+        eprintln!("       cli_helper multiboot-add <usb_device> <iso_path>...  (copy ISOs to an existing Ventoy or multiboot stick)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --type=windows|linux|raw|image  Skip detection and use this image type");
+        eprintln!("  --use-dd-mode              Write a Windows ISO directly with dd");
+        eprintln!("  --partition=N              Write a raw image into partition N only, keeping the partition table (raw)");
+        eprintln!("  --bypass-tpm, --bypass-secure-boot, --bypass-ram");
+        eprintln!("  --fat32-sectors=N          FAT32 sectors per cluster for BOOT (Windows; default auto)");
+        eprintln!("  --oem-folder=DIR           Copy a $OEM$ folder to sources/$OEM$ (Windows)");
+        eprintln!("  --esp-size=MB              Dedicated ESP of MB (300-4096) instead of the 1 GiB BOOT partition (Windows)");
+        eprintln!("  --exclude=PATTERN          Skip files matching an rsync pattern when copying INSTALL (Windows, repeatable)");
+        eprintln!("  --boot-test                Copy the boot files only, to test boot menu detection; cannot install (Windows)");
+        eprintln!("  --tool-efi=FILE            Put an EFI application (e.g. memtest86+) on an extra bootable TOOLS partition (Windows)");
+        eprintln!("  --legacy                   Windows 7/8 layout: MBR, one active NTFS partition, BIOS boot code (needs ms-sys)");
+        eprintln!("  --fix-pmbr                 Regenerate the GPT protective MBR (Linux)");
+        eprintln!("  --repartition=gpt|mbr      Replace the ISO's table and copy its files to FAT32 instead of dd (Linux, UEFI only)");
+        eprintln!("  --persistence              Add a persistence partition (Linux)");
+        eprintln!("  --persistence-size=MB, --persistence-type=casper|overlayfs, --persistence-label=LABEL");
+        eprintln!("  --table=gpt|mbr, --persistence-start=SECTOR, --kernel-param=PARAM (repeatable)");
+        eprintln!("  --remount=never|on-failure|always  Remount partitions unmounted for persistence (default on-failure)");
+        eprintln!("  --data-partition=MB, --data-fs=exfat|fat32  Add a storage partition after the Linux image (default exFAT)");
+        eprintln!("  --persistence-reserved=PERCENT  ext4 blocks reserved for root on persistence (0-50, default 0)");
+        eprintln!("  --sha256=HASH              Verify the ISO before writing");
+        eprintln!("  --expected-size=SIZE       Quick check against the published size (e.g. 4.89GB)");
+        eprintln!("  --max-speed=MB             Cap the write speed in MB/s (Linux and raw)");
+        eprintln!("  --thorough-flush           Flush device buffers and read back its ends after writing (caching USB bridges)");
+        eprintln!("  --overwrite-multiboot      Erase the device even if it holds a Ventoy or multiboot layout");
+        eprintln!("  --name=NAME, --description=TEXT  Store a name in .majusb/label.json, shown by cli_helper scan");
+        eprintln!("  --manifest                 Store SHA-256 hashes of the written files in .majusb/manifest.json");
+        eprintln!("  --verify-boot              Check after writing that the stick holds a valid EFI loader");
+        eprintln!("  --rewrite                  Delta-copy Windows files instead of --whole-file (rewriting the same stick)");
+        eprintln!("  --kickstart=FILE           Copy FILE as ks.cfg and boot with inst.ks= (Fedora/RHEL installers)");
+        eprintln!("  --preseed=FILE             Add an automated install boot entry using FILE (Debian installers)");
+        eprintln!("  --autoinstall=USER_DATA, --meta-data=FILE  Add a CIDATA partition for Ubuntu autoinstall");
         std::process::exit(1);
     }
-    let iso_path = &args[1];
-    let usb_device = &args[2];
-    let use_dd_mode = args.iter().any(|a| a == "--use-dd-mode");
-    let bypass_tpm = args.iter().any(|a| a == "--bypass-tpm");
-    let bypass_secure_boot = args.iter().any(|a| a == "--bypass-secure-boot");
-    let bypass_ram = args.iter().any(|a| a == "--bypass-ram");
-    // Optionally: parse use_wim and cluster from args
 
-    // Detect OS type (now as root)
-    let is_win = utils::is_windows_iso(iso_path)
-        .unwrap_or_else(|| {
+    // Detect OS type (now as root) unless --type is given
+    let mut job = WriteJob::from_cli_args(&args[1..], |iso_path| {
+        // Elevated, but a root-squashed or private mount can still refuse access; say so instead of "detection failed"
+        exit_if_unreadable(iso_path);
+        let detected = utils::is_windows_iso(iso_path);
+        if detected.is_none() {
             eprintln!("Detection failed, assuming Linux ISO");
-            false
-        });
-    if is_win {
-        println!("Detected: Windows ISO");
-        io::stdout().flush().ok();
-        if use_dd_mode {
+        }
+        detected
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("Invalid arguments: {}", e);
+        std::process::exit(1);
+    });
+    let resolved = utils::resolve_image_path(&job.iso_path);
+    if resolved != job.iso_path {
+        println!("{} is a symlink; writing {}", job.iso_path, resolved);
+        job.iso_path = resolved;
+    }
+    let resolved = utils::resolve_device_path(&job.device);
+    if resolved != job.device {
+        println!("{} is a link; writing to {}", job.device, resolved);
+        job.device = resolved;
+    }
+
+    // Tools, image, published size, space, write protection, system and source disks
+    let report = preflight::run_preflight(&job);
+    for (item, line) in report.items.iter().zip(report.lines()) {
+        if item.status == CheckStatus::Pass {
+            println!("{}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+    if report.has_failures() {
+        eprintln!("Preflight checks failed; nothing was written");
+        std::process::exit(1);
+    }
+
+    if let Some(expected) = &job.expected_sha256 {
+        verify_or_exit(&job.iso_path, expected);
+    }
+
+    let iso_path = job.iso_path.as_str();
+    let usb_device = job.device.as_str();
+    if !job.overwrite_multiboot && !matches!(job.mode, WriteMode::RawPartition { .. }) {
+        exit_if_multiboot(usb_device);
+    }
+    match &job.mode {
+        WriteMode::WindowsDirectDd => {
+            println!("Detected: Windows ISO");
+            io::stdout().flush().ok();
             let result = windows_flow::write_windows_iso_direct_dd(
                 iso_path, usb_device, &mut std::io::stdout()
             );
             if let Err(e) = result {
-                eprintln!("Failed to write ISO (dd mode): {}", e);
-                std::process::exit(1);
+                exit_write_failed(&job, "Failed to write ISO (dd mode)", e);
             }
-        } else {
-            let cluster_bytes: u64 = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(4096);
-            let mut flags = rust_usb_bootable_creator::windows::unattend::UnattendFlags::empty();
-            if bypass_tpm {
-                flags |= rust_usb_bootable_creator::windows::unattend::UnattendFlags::BYPASS_TPM;
+        }
+        WriteMode::Windows { .. } => {
+            println!("Detected: Windows ISO");
+            io::stdout().flush().ok();
+            let options = job.windows_options().unwrap_or_default();
+            let result = windows_flow::write_windows_iso_to_usb_stream_with_options(iso_path, usb_device, &options);
+            if let Err(e) = result {
+                exit_write_failed(&job, "Failed to write ISO", e);
             }
-            if bypass_secure_boot {
-                flags |= rust_usb_bootable_creator::windows::unattend::UnattendFlags::BYPASS_SECURE_BOOT;
+        }
+        WriteMode::Linux { .. } | WriteMode::RawImage => {
+            println!("Detected: {}", if matches!(job.mode, WriteMode::RawImage) { "Raw image" } else { "Linux ISO" });
+            io::stdout().flush().ok();
+            let options = job.linux_options().unwrap_or_default();
+            if options.needs_extra_steps() {
+                match linux_flow::write_iso_to_usb_with_options(iso_path, usb_device, &options, &mut std::io::stdout()) {
+                    Ok(report) => {
+                        for warning in report.warnings {
+                            eprintln!("Warning: {}", warning);
+                        }
+                        if let Some(max_mb) = report.persistence_max_mb {
+                            eprintln!("Retry with --persistence-size={} to use the space that is left.", max_mb);
+                        }
+                    }
+                    Err(e) => {
+                        exit_write_failed(&job, "Failed to write ISO", e);
+                    }
+                }
+            } else {
+                let result = linux_flow::write_iso_to_usb_stream(iso_path, usb_device, 4096);
+                if let Err(e) = result {
+                    exit_write_failed(&job, "Failed to write ISO", e);
+                }
+                if options.fix_protective_mbr {
+                    let mut warnings = Vec::new();
+                    if let Err(e) = linux_flow::fix_protective_mbr(usb_device, &mut std::io::stdout(), &mut warnings) {
+                        exit_write_failed(&job, "Failed to fix protective MBR", e);
+                    }
+                    for warning in warnings {
+                        eprintln!("Warning: {}", warning);
+                    }
+                }
             }
-            if bypass_ram {
-                flags |= rust_usb_bootable_creator::windows::unattend::UnattendFlags::BYPASS_RAM;
+        }
+        WriteMode::RawPartition { number } => {
+            println!("Detected: Raw image");
+            println!("Writing raw image into partition {}", job.target());
+            io::stdout().flush().ok();
+            let options = image_copy::CopyOptions { max_speed_mbps: job.max_speed_mbps, ..Default::default() };
+            if let Err(e) = image_copy::write_image_to_partition(iso_path, usb_device, *number, options, &mut std::io::stdout()) {
+                exit_write_failed(&job, "Failed to write image", e);
             }
-
-            let result = windows_flow::write_windows_iso_to_usb_stream_with_bypass(
-                iso_path, usb_device, cluster_bytes, if flags.is_empty() { None } else { Some(flags) }
-            );
-            if let Err(e) = result {
-                eprintln!("Failed to write ISO: {}", e);
-                std::process::exit(1);
+        }
+        WriteMode::RestoreImage => {
+            println!("Restoring disk image");
+            io::stdout().flush().ok();
+            if let Err(e) = image_copy::restore_image_to_device(iso_path, usb_device, None, &mut std::io::stdout()) {
+                exit_write_failed(&job, "Failed to restore image", e);
             }
         }
-    } else {
-        println!("Detected: Linux ISO");
-        io::stdout().flush().ok();
-        let cluster_bytes: u64 = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(4096);
-        let result = linux_flow::write_iso_to_usb_stream(
-            iso_path, usb_device, cluster_bytes
+    }
+    if let Err(e) = job.finalize(&mut std::io::stdout(), &mut Vec::new()) {
+        eprintln!("Warning: {}", e);
+    }
+    println!("Done!");
+    io::stdout().flush().ok();
+}
+
+/// Print why the ISO cannot be read and exit, so access problems are not reported as an unknown image
+fn exit_if_unreadable(iso_path: &str) {
+    if let Err(e) = utils::check_iso_readable(iso_path) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Refuse to touch the stick the running live system booted from
+fn exit_if_live_system(usb_device: &str) {
+    let usb_device = utils::resolve_device_path(usb_device);
+    if utils::live_system_disks().contains(&usb_device) {
+        eprintln!("Refusing to write to {}: it is the live USB this system is running from", usb_device);
+        std::process::exit(1);
+    }
+}
+
+/// Report a write that failed after it started changing the device, with what that leaves behind
+fn exit_write_failed(job: &WriteJob, what: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", what, error);
+    eprintln!("{}", job.partial_write_notice());
+    std::process::exit(1);
+}
+
+/// Refuse to erase a Ventoy or multiboot stick, whose ISOs would be lost, unless asked to
+fn exit_if_multiboot(usb_device: &str) {
+    if let Ok(Some(layout)) = media_scan::find_multiboot_layout(usb_device) {
+        eprintln!(
+            "Refusing to erase {}: it holds a {} multiboot layout with ISOs on {}.",
+            usb_device,
+            layout.name(),
+            layout.data_partition().path
         );
-        if let Err(e) = result {
-            eprintln!("Failed to write ISO: {}", e);
+        eprintln!("Use `cli_helper multiboot-add` to add ISOs to it, or pass --overwrite-multiboot to erase it.");
+        std::process::exit(1);
+    }
+}
+
+/// Hash `iso_path` with a percentage display; exits with status 1 when it does not match `expected`
+fn verify_or_exit(iso_path: &str, expected: &str) {
+    println!("Verifying SHA-256...");
+    let mut last_percent = None;
+    let mut report = |done, total| {
+        let percent = checksum::progress_percent(done, total);
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            print!("\rVerifying SHA-256: {}%", percent);
+            io::stdout().flush().ok();
+        }
+    };
+    let result = checksum::verify_sha256(iso_path, expected, &mut report);
+    println!();
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!("Checksum OK");
+}
+
+/// Check the EFI loaders on `usb_device`; exits with status 3 when the check fails
+fn verify_boot_or_exit(usb_device: &str) {
+    match boot_check::check_boot_files(usb_device, &mut io::stdout()) {
+        Ok(check) if check.passed() => println!("Boot check passed: {}", check.summary()),
+        Ok(check) => {
+            eprintln!("Boot check failed: {}", check.summary());
+            std::process::exit(3);
+        }
+        Err(e) => {
+            eprintln!("Failed to check {}: {}", usb_device, e);
             std::process::exit(1);
         }
     }
-    println!("Done!");
-    io::stdout().flush().ok();
+}
+
+/// Print the detected ISO type as a single JSON object; exits nonzero when detection fails
+fn run_detect(iso_path: &str) {
+    exit_if_unreadable(iso_path);
+    let report = match utils::detect_iso_type(iso_path) {
+        Some(iso_type @ IsoType::Windows(_)) => utils::detect_report(iso_type),
+        Some(IsoType::Linux) => {
+            let persistence = match linux_persistence::detect_persistence_type(iso_path) {
+                Ok(kind) => kind,
+                Err(e) => {
+                    eprintln!("Failed to inspect ISO: {}", e);
+                    std::process::exit(2);
+                }
+            };
+            let distro = linux_persistence::detect_linux_distro(iso_path).unwrap_or_else(|e| {
+                eprintln!("Could not identify distribution: {}", e);
+                None
+            });
+            let mut report = utils::detect_report(IsoType::Linux);
+            report["distro"] = serde_json::json!(distro.as_ref().map(|d| d.name.as_str()));
+            report["version"] = serde_json::json!(distro.as_ref().and_then(|d| d.version.as_deref()));
+            report["persistence"] = serde_json::json!(persistence.name());
+            report
+        }
+        None => {
+            eprintln!("Could not detect ISO type for {}", iso_path);
+            std::process::exit(2);
+        }
+    };
+    println!("{}", report);
 }
@@ -0,0 +1,277 @@
+//! Integrity checks of source images: a quick size check and full SHA-256 verification
+
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::error::{IoResultExt, UsbCreatorError, UsbCreatorResult};
+
+/// Read size used while hashing; large enough to keep the disk streaming
+const HASH_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Normalize a user-supplied checksum to 64 lowercase hex digits.
+/// Accepts `sha256sum` output (`<hash>  <file>`) as well as a bare hash.
+pub fn normalize_sha256(text: &str) -> UsbCreatorResult<String> {
+    let hash = text.split_whitespace().next().unwrap_or("").to_ascii_lowercase();
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(UsbCreatorError::validation_error(format!(
+            "'{}' is not a SHA-256 checksum (expected 64 hex digits)",
+            text.trim()
+        )));
+    }
+    Ok(hash)
+}
+
+/// Checksum file found next to an image (`<image>.sha256`, `.sha256sum` or `.md5`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SidecarChecksum {
+    /// SHA-256 of the image read from `file`
+    Sha256 { file: PathBuf, hash: String },
+    /// An MD5 list; verification only supports SHA-256, so it is reported but not used
+    Md5 { file: PathBuf },
+}
+
+/// Hash for `image_name` in the text of a checksum file. Takes `sha256sum`/`md5sum` lines
+/// (`<hash>  <name>`, `<hash> *<name>`), BSD tag lines (`SHA256 (<name>) = <hash>`) and a file
+/// holding a bare hash; a line naming another file is skipped. `hash_len` is the digest length in
+/// hex digits.
+fn hash_for_image(contents: &str, image_name: &str, hash_len: usize) -> Option<String> {
+    let is_hash = |text: &str| text.len() == hash_len && text.chars().all(|c| c.is_ascii_hexdigit());
+    let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'));
+    let single = lines.clone().count() == 1;
+    lines.find_map(|line| {
+        let (hash, name) = match line.split_once(" (").filter(|_| line.contains(") = ")) {
+            Some((_, tagged)) => {
+                let (name, hash) = tagged.rsplit_once(") = ")?;
+                (hash.trim(), Some(name))
+            }
+            None => {
+                let mut fields = line.splitn(2, char::is_whitespace);
+                (fields.next()?, fields.next().map(|name| name.trim().trim_start_matches('*')))
+            }
+        };
+        // Lists often carry the path the image was built under; compare the file names only
+        let names_image = match name {
+            Some(name) => name.rsplit('/').next() == Some(image_name),
+            None => single,
+        };
+        (names_image && is_hash(hash)).then(|| hash.to_ascii_lowercase())
+    })
+}
+
+/// Look for a checksum file published next to `image` and read the image's hash from it
+pub fn find_sidecar_checksum(image: &Path) -> Option<SidecarChecksum> {
+    let image_name = image.file_name()?.to_str()?;
+    for extension in ["sha256", "sha256sum", "md5"] {
+        let file = image.with_file_name(format!("{}.{}", image_name, extension));
+        let Ok(contents) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        if extension == "md5" {
+            if hash_for_image(&contents, image_name, 32).is_some() {
+                return Some(SidecarChecksum::Md5 { file });
+            }
+        } else if let Some(hash) = hash_for_image(&contents, image_name, 64) {
+            return Some(SidecarChecksum::Sha256 { file, hash });
+        }
+    }
+    None
+}
+
+/// Published image size, with the slack implied by how precisely it was written
+/// (`4.89GB` covers 4.885-4.895 GB, a plain byte count must match exactly)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedSize {
+    pub bytes: u64,
+    pub tolerance_bytes: u64,
+}
+
+/// Parse a published size such as `4.89GB`, `5000 MiB` or `4892463104`
+pub fn parse_expected_size(text: &str) -> UsbCreatorResult<ExpectedSize> {
+    let text = text.trim();
+    let invalid = || {
+        UsbCreatorError::validation_error(format!(
+            "'{}' is not a file size (e.g. 4.89GB, 5000MiB or a byte count)",
+            text
+        ))
+    };
+    let split = text.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(text.len());
+    let (number, unit) = (text[..split].trim(), text[split..].trim());
+    let unit_bytes: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    let decimals = number.split_once('.').map_or(0, |(_, fraction)| fraction.len()) as i32;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || c == '.') || (unit_bytes == 1 && decimals > 0) {
+        return Err(invalid());
+    }
+    let value: f64 = number.parse().map_err(|_| invalid())?;
+    let unit_bytes = unit_bytes as f64;
+    Ok(ExpectedSize {
+        bytes: (value * unit_bytes).round() as u64,
+        tolerance_bytes: if unit_bytes == 1.0 { 0 } else { (unit_bytes * 0.5 / 10f64.powi(decimals)).round() as u64 },
+    })
+}
+
+/// Result of comparing a file's size with the published one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeCheck {
+    Matches { actual: u64 },
+    /// Typical of an interrupted download
+    Smaller { actual: u64, expected: u64 },
+    Larger { actual: u64, expected: u64 },
+}
+
+impl SizeCheck {
+    pub fn is_match(&self) -> bool {
+        matches!(self, SizeCheck::Matches { .. })
+    }
+
+    /// Line for the log, e.g. "Size matches published (4.89 GB)"
+    pub fn message(&self) -> String {
+        let gb = |bytes: u64| format!("{:.2} GB", bytes as f64 / 1e9);
+        match *self {
+            SizeCheck::Matches { actual } => format!("Size matches published ({})", gb(actual)),
+            SizeCheck::Smaller { actual, expected } => format!(
+                "Warning: file is smaller than expected ({} of {}), possibly incomplete",
+                gb(actual),
+                gb(expected)
+            ),
+            SizeCheck::Larger { actual, expected } => format!(
+                "Warning: file is larger than expected ({} instead of {}); it may be a different image",
+                gb(actual),
+                gb(expected)
+            ),
+        }
+    }
+}
+
+/// Compare the size of `path` with a published size; instant, unlike a full hash
+pub fn check_file_size(path: &str, expected: ExpectedSize) -> UsbCreatorResult<SizeCheck> {
+    let actual = std::fs::metadata(path).with_context(format!("Failed to read size of {}", path))?.len();
+    Ok(if actual.abs_diff(expected.bytes) <= expected.tolerance_bytes {
+        SizeCheck::Matches { actual }
+    } else if actual < expected.bytes {
+        SizeCheck::Smaller { actual, expected: expected.bytes }
+    } else {
+        SizeCheck::Larger { actual, expected: expected.bytes }
+    })
+}
+
+/// Whole percent of `done` out of `total`, shared by the CLI and GUI progress displays
+pub fn progress_percent(done: u64, total: u64) -> u64 {
+    (done * 100).checked_div(total).unwrap_or(100).min(100)
+}
+
+/// Hex SHA-256 digest of a file; `progress` gets (bytes hashed, total bytes) after every chunk
+pub fn sha256_file(path: &Path, progress: &mut dyn FnMut(u64, u64)) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; HASH_CHUNK_BYTES];
+    let mut done = 0u64;
+    progress(0, total);
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        done += read as u64;
+        progress(done, total);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Check `path` against an expected SHA-256, reporting (bytes hashed, total bytes) to `progress`;
+/// a mismatch yields `UsbCreatorError::ChecksumMismatch`
+pub fn verify_sha256(path: &str, expected: &str, progress: &mut dyn FnMut(u64, u64)) -> UsbCreatorResult<()> {
+    let expected = normalize_sha256(expected)?;
+    let actual = sha256_file(Path::new(path), progress).with_context(format!("Failed to hash {}", path))?;
+    if actual != expected {
+        return Err(UsbCreatorError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn normalizes_sha256sum_output() {
+        let line = format!("{}  ubuntu-24.04-desktop-amd64.iso\n", ABC_SHA256.to_uppercase());
+        assert_eq!(normalize_sha256(&line).unwrap(), ABC_SHA256);
+        assert!(normalize_sha256("deadbeef").is_err());
+        assert!(normalize_sha256("").is_err());
+    }
+
+    #[test]
+    fn reads_the_image_hash_from_a_sidecar_file() {
+        let name = "ubuntu-24.04-desktop-amd64.iso";
+        assert_eq!(hash_for_image(&format!("{}\n", ABC_SHA256.to_uppercase()), name, 64).as_deref(), Some(ABC_SHA256));
+        let list = format!("{}  other.iso\n{} *build/{}\n", "1".repeat(64), ABC_SHA256, name);
+        assert_eq!(hash_for_image(&list, name, 64).as_deref(), Some(ABC_SHA256));
+        assert_eq!(hash_for_image(&format!("SHA256 ({}) = {}", name, ABC_SHA256), name, 64).as_deref(), Some(ABC_SHA256));
+        assert_eq!(hash_for_image(&format!("{}  other.iso", ABC_SHA256), name, 64), None);
+        assert_eq!(hash_for_image("900150983cd24fb0d6963f7d28e17f72  x.iso", "x.iso", 64), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join(name);
+        assert_eq!(find_sidecar_checksum(&image), None);
+        std::fs::write(dir.path().join(format!("{}.md5", name)), format!("900150983cd24fb0d6963f7d28e17f72  {}", name)).unwrap();
+        assert!(matches!(find_sidecar_checksum(&image), Some(SidecarChecksum::Md5 { .. })));
+        let file = dir.path().join(format!("{}.sha256sum", name));
+        std::fs::write(&file, format!("{}  {}\n", ABC_SHA256, name)).unwrap();
+        assert_eq!(find_sidecar_checksum(&image), Some(SidecarChecksum::Sha256 { file, hash: ABC_SHA256.to_string() }));
+    }
+
+    #[test]
+    fn size_check_allows_rounding_of_published_size() {
+        let published = parse_expected_size("4.89GB").unwrap();
+        assert_eq!(published, ExpectedSize { bytes: 4_890_000_000, tolerance_bytes: 5_000_000 });
+        assert_eq!(parse_expected_size("3 MiB").unwrap(), ExpectedSize { bytes: 3 << 20, tolerance_bytes: 1 << 19 });
+        assert_eq!(parse_expected_size("1024").unwrap().tolerance_bytes, 0);
+        assert!(parse_expected_size("4.89 TB").is_err());
+        assert!(parse_expected_size("12.5").is_err());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"abc").unwrap();
+        let path = file.path().to_str().unwrap();
+        assert!(check_file_size(path, parse_expected_size("3").unwrap()).unwrap().is_match());
+        let short = check_file_size(path, parse_expected_size("4").unwrap()).unwrap();
+        assert_eq!(short, SizeCheck::Smaller { actual: 3, expected: 4 });
+        assert!(short.message().contains("possibly incomplete"));
+    }
+
+    #[test]
+    fn verifies_file_digest() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"abc").unwrap();
+        let path = file.path().to_str().unwrap();
+        let mut reports = Vec::new();
+        assert!(verify_sha256(path, ABC_SHA256, &mut |done, total| reports.push((done, total))).is_ok());
+        assert_eq!(reports, vec![(0, 3), (3, 3)]);
+        assert_eq!(progress_percent(1, 3), 33);
+        assert_eq!(progress_percent(0, 0), 100);
+
+        let wrong = "0".repeat(64);
+        match verify_sha256(path, &wrong, &mut |_, _| {}) {
+            Err(UsbCreatorError::ChecksumMismatch { expected, actual }) => {
+                assert_eq!(expected, wrong);
+                assert_eq!(actual, ABC_SHA256);
+            }
+            other => panic!("expected checksum mismatch, got {:?}", other),
+        }
+    }
+}
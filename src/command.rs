@@ -0,0 +1,96 @@
+//! Seam between the flows and the external tools they drive (`parted`, `lsblk`, `blkid`...), so
+//! the parsing of their output can be tested against canned output
+
+use std::io;
+use std::process::{Command, Output};
+
+/// Runs an external program to completion and captures its output
+pub trait CommandRunner {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output>;
+}
+
+/// Runs the real programs from `PATH`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        Command::new(program).args(args).output()
+    }
+}
+
+/// Answers with canned output keyed by the full command line (`program arg1 arg2`); any
+/// command without an answer fails as if the program were not installed
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockRunner {
+    responses: Vec<(String, Output)>,
+    calls: std::cell::RefCell<Vec<String>>,
+}
+
+#[cfg(test)]
+impl MockRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `command_line` with a zero exit status and `stdout`
+    pub fn with_output(self, command_line: &str, stdout: &str) -> Self {
+        self.with_response(command_line, 0, stdout, "")
+    }
+
+    /// Answer `command_line` with exit status 1 and `stderr`
+    pub fn with_failure(self, command_line: &str, stderr: &str) -> Self {
+        self.with_response(command_line, 1, "", stderr)
+    }
+
+    fn with_response(mut self, command_line: &str, code: i32, stdout: &str, stderr: &str) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+        self.responses.push((
+            command_line.to_string(),
+            Output {
+                status: std::process::ExitStatus::from_raw(code << 8),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+            },
+        ));
+        self
+    }
+
+    /// Command lines run so far, in order
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for MockRunner {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        let command_line = std::iter::once(program).chain(args.iter().copied()).collect::<Vec<_>>().join(" ");
+        self.calls.borrow_mut().push(command_line.clone());
+        self.responses
+            .iter()
+            .find(|(line, _)| *line == command_line)
+            .map(|(_, output)| output.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no canned output for `{}`", command_line)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_runner_answers_known_command_lines_only() {
+        let runner = MockRunner::new()
+            .with_output("blockdev --getsz /dev/sdz", "30031872\n")
+            .with_failure("parted -ms /dev/sdz print", "Error: unrecognised disk label");
+        let output = runner.output("blockdev", &["--getsz", "/dev/sdz"]).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"30031872\n");
+        let failed = runner.output("parted", &["-ms", "/dev/sdz", "print"]).unwrap();
+        assert_eq!(failed.status.code(), Some(1));
+        assert!(runner.output("lsblk", &["/dev/sdz"]).is_err());
+        assert_eq!(runner.calls().len(), 3);
+    }
+}
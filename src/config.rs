@@ -70,6 +70,9 @@ pub mod temp {
 
     /// Install partition mount directory name
     pub const INSTALL_MOUNT_DIR: &str = "install";
+
+    /// EFI tool partition mount directory name
+    pub const TOOL_MOUNT_DIR: &str = "tools";
 }
 
 /// GUI configuration
@@ -33,6 +33,12 @@ pub enum UsbCreatorError {
     /// Validation errors
     ValidationError(String),
 
+    /// Source image does not match its published SHA-256
+    ChecksumMismatch { expected: String, actual: String },
+
+    /// Requested persistence does not fit; `max_mb` is the largest size that would
+    InsufficientSpace { requested_mb: u64, max_mb: u64 },
+
     /// Generic errors with context
     Generic(String),
 }
@@ -51,6 +57,14 @@ impl fmt::Display for UsbCreatorError {
             UsbCreatorError::ConfigError(msg) => write!(f, "Configuration error: {}", msg),
             UsbCreatorError::PermissionError(msg) => write!(f, "Permission error: {}", msg),
             UsbCreatorError::ValidationError(msg) => write!(f, "Validation error: {}", msg),
+            UsbCreatorError::ChecksumMismatch { expected, actual } => {
+                write!(f, "Checksum mismatch: expected {}, got {}", expected, actual)
+            }
+            UsbCreatorError::InsufficientSpace { requested_mb, max_mb } => write!(
+                f,
+                "Not enough space for {} MB of persistence; up to {} MB is available",
+                requested_mb, max_mb
+            ),
             UsbCreatorError::Generic(msg) => write!(f, "Error: {}", msg),
         }
     }
@@ -135,4 +149,32 @@ impl UsbCreatorError {
     pub fn generic(msg: impl Into<String>) -> Self {
         UsbCreatorError::Generic(msg.into())
     }
+
+    /// Failure to create a temporary directory under `dir`, usually a full or read-only /tmp
+    pub fn temp_dir_failed(dir: &std::path::Path, err: io::Error) -> Self {
+        UsbCreatorError::Io(
+            err,
+            format!(
+                "Could not create a temporary directory in {}; free some space there or set TMPDIR to a directory with room",
+                dir.display()
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_dir_error_names_directory_and_tmpdir() {
+        let err = UsbCreatorError::temp_dir_failed(
+            std::path::Path::new("/tmp"),
+            io::Error::other("No space left on device"),
+        );
+        let text = err.to_string();
+        assert!(text.contains("/tmp"));
+        assert!(text.contains("TMPDIR"));
+        assert!(text.contains("No space left on device"));
+    }
 }
\ No newline at end of file
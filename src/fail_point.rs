@@ -0,0 +1,55 @@
+//! Failure injection for exercising cleanup paths. `MAJUSB_FAIL_AT=<step>[,<step>...]` makes the
+//! named steps of a write fail as if their tool had, so a run against a scratch stick can check
+//! that mounts are released, loop devices detached and partial partitions rolled back. Not meant
+//! for users; unset, a check is one environment lookup.
+
+use std::io;
+
+/// Environment variable naming the steps to fail
+pub const FAIL_AT_ENV: &str = "MAJUSB_FAIL_AT";
+
+/// Steps that can be failed
+pub const STEPS: [&str; 12] = [
+    "wipe",
+    "partition",
+    "mkfs_fat32",
+    "mkfs_ntfs",
+    "mount_boot",
+    "copy_boot",
+    "mount_install",
+    "copy_install",
+    "dd",
+    "persistence_mkpart",
+    "persistence_mkfs",
+    "data_partition",
+];
+
+/// Whether the comma-separated `list` names `step`
+fn names_step(list: &str, step: &str) -> bool {
+    list.split(',').any(|name| name.trim() == step)
+}
+
+/// Fail `step` when `MAJUSB_FAIL_AT` names it; call it right before the step's tool runs
+pub fn check(step: &str) -> io::Result<()> {
+    debug_assert!(STEPS.contains(&step), "unknown fail point {}", step);
+    match std::env::var(FAIL_AT_ENV) {
+        Ok(list) if names_step(&list, step) => {
+            Err(io::Error::other(format!("{} failed (simulated by {}={})", step, FAIL_AT_ENV, list)))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fail_list_names_whole_steps_only() {
+        assert!(names_step("mkfs_ntfs", "mkfs_ntfs"));
+        assert!(names_step("wipe, copy_install", "copy_install"));
+        assert!(!names_step("mkfs_ntfs", "mkfs_fat32"));
+        assert!(!names_step("copy", "copy_boot"));
+        assert!(!names_step("", "dd"));
+    }
+}
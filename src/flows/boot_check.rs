@@ -0,0 +1,137 @@
+//! Quick bootability check of a written stick: the FAT partitions firmware reads must hold an EFI
+//! loader, and every loader found must be a PE image of an EFI application for the architecture
+//! its name claims. Far cheaper than reading the device back, it catches the common "does not boot
+//! because the boot file is missing" case.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::flows::efi_tool::{efi_loader_name, TOOL_PARTITION_NAME};
+use crate::flows::media_scan::{list_partitions, with_mounted_partition, ScannedPartition};
+use crate::utils::resolve_device_path;
+
+/// Loaders firmware or its boot entries start, relative to the partition root. The fallback
+/// `BOOT*.EFI` names must match the image's architecture; the Windows boot manager may be any.
+const LOADER_PATHS: [&str; 4] = [
+    "EFI/BOOT/BOOTX64.EFI",
+    "EFI/BOOT/BOOTIA32.EFI",
+    "EFI/BOOT/BOOTAA64.EFI",
+    "EFI/Microsoft/Boot/bootmgfw.efi",
+];
+
+/// Loaders found on the media, as "<partition>: <path>", and what is wrong with the others
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootCheck {
+    pub loaders: Vec<String>,
+    pub problems: Vec<String>,
+}
+
+impl BootCheck {
+    /// At least one valid loader and no broken one
+    pub fn passed(&self) -> bool {
+        !self.loaders.is_empty() && self.problems.is_empty()
+    }
+
+    /// One line for logs and warnings, e.g. "no EFI loader on any FAT partition"
+    pub fn summary(&self) -> String {
+        if !self.problems.is_empty() {
+            format!("broken EFI boot files: {}", self.problems.join("; "))
+        } else if self.loaders.is_empty() {
+            "no EFI loader on any FAT partition".to_string()
+        } else {
+            format!("EFI loaders present: {}", self.loaders.join(", "))
+        }
+    }
+
+    /// Check the loaders under the root of the partition called `name`
+    fn check_tree(&mut self, name: &str, root: &Path) {
+        for rel in LOADER_PATHS {
+            let Some(path) = find_ignoring_case(root, rel) else {
+                continue;
+            };
+            let expected = rel.rsplit('/').next().filter(|file| file.starts_with("BOOT"));
+            let result = fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|image| efi_loader_name(&image))
+                .and_then(|loader| match expected {
+                    Some(expected) if expected != loader => Err(format!("it is built for the architecture of {}", loader)),
+                    _ => Ok(()),
+                });
+            match result {
+                Ok(()) => self.loaders.push(format!("{}: {}", name, rel)),
+                Err(reason) => self.problems.push(format!("{}: {}: {}", name, rel, reason)),
+            }
+        }
+    }
+}
+
+/// `rel` under `root`, matching each component without regard to case the way FAT does (ISO and
+/// copied trees spell `EFI/BOOT/BOOTX64.EFI` in any mix of cases)
+fn find_ignoring_case(root: &Path, rel: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in rel.split('/') {
+        let entry = fs::read_dir(&path)
+            .ok()?
+            .flatten()
+            .find(|entry| entry.file_name().to_string_lossy().eq_ignore_ascii_case(component))?;
+        path = entry.path();
+    }
+    path.is_file().then_some(path)
+}
+
+/// Partitions firmware looks for loaders on; the EFI tool partition holds a loader of its own and
+/// would hide a missing one on the boot partition
+fn checked(partition: &ScannedPartition) -> bool {
+    partition.fstype.as_deref() == Some("vfat") && partition.label.as_deref() != Some(TOOL_PARTITION_NAME)
+}
+
+/// Mount the FAT partitions of `device` read-only and check the EFI loaders on them
+pub fn check_boot_files(device: &str, log: &mut dyn Write) -> io::Result<BootCheck> {
+    let device = &resolve_device_path(device);
+    let _ = std::process::Command::new("udevadm").arg("settle").status();
+    let mut check = BootCheck::default();
+    for partition in list_partitions(device)?.iter().filter(|part| checked(part)) {
+        writeln!(log, "Checking EFI boot files on {}...", partition.path)?;
+        let name = partition.label.clone().unwrap_or_else(|| partition.path.clone());
+        if let Err(e) = with_mounted_partition(partition, true, |root| {
+            check.check_tree(&name, root);
+            Ok(())
+        }) {
+            check.problems.push(format!("{}: {}", name, e));
+        }
+    }
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flows::efi_tool::pe_image;
+
+    #[test]
+    fn finds_loaders_in_any_case_and_checks_their_architecture() {
+        let root = tempfile::tempdir().unwrap();
+        let mut check = BootCheck::default();
+        check.check_tree("BOOT", root.path());
+        assert!(!check.passed());
+        assert_eq!(check.summary(), "no EFI loader on any FAT partition");
+
+        fs::create_dir_all(root.path().join("efi/boot")).unwrap();
+        fs::write(root.path().join("efi/boot/bootx64.efi"), pe_image(0x8664, 10)).unwrap();
+        let mut check = BootCheck::default();
+        check.check_tree("BOOT", root.path());
+        assert!(check.passed());
+        assert_eq!(check.loaders, ["BOOT: EFI/BOOT/BOOTX64.EFI"]);
+
+        fs::write(root.path().join("efi/boot/BOOTAA64.EFI"), pe_image(0x8664, 10)).unwrap();
+        fs::create_dir_all(root.path().join("efi/Microsoft/Boot")).unwrap();
+        fs::write(root.path().join("efi/Microsoft/Boot/bootmgfw.efi"), b"truncated").unwrap();
+        let mut check = BootCheck::default();
+        check.check_tree("BOOT", root.path());
+        assert!(!check.passed());
+        assert_eq!(check.problems.len(), 2);
+        assert!(check.problems[0].contains("architecture of BOOTX64.EFI"));
+        assert!(check.summary().contains("bootmgfw.efi: it is not a PE executable"));
+    }
+}
@@ -0,0 +1,120 @@
+//! Extra FAT partition carrying a standalone EFI tool (memtest86+, an EFI shell, ...) next to the
+//! Windows installer. The tool is stored under the removable-media loader path, so firmware boot
+//! menus list the partition as a separate entry.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::Command;
+
+/// GPT name and FAT label of the tool partition
+pub const TOOL_PARTITION_NAME: &str = "TOOLS";
+
+/// Largest tool image accepted; EFI tools are a few MB at most
+const MAX_TOOL_BYTES: u64 = 256 * 1024 * 1024;
+
+/// PE `Machine` values and the fallback loader name firmware looks for on that architecture
+const EFI_LOADER_NAMES: [(u16, &str); 3] = [(0x8664, "BOOTX64.EFI"), (0x014c, "BOOTIA32.EFI"), (0xaa64, "BOOTAA64.EFI")];
+
+/// PE optional header `Subsystem` of EFI applications
+const EFI_APPLICATION_SUBSYSTEM: u16 = 10;
+
+/// Fallback loader name for a PE/EFI application image, or why it is not one
+pub fn efi_loader_name(image: &[u8]) -> Result<&'static str, String> {
+    let u16_at = |offset: usize| image.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |offset: usize| image.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    if !image.starts_with(b"MZ") {
+        return Err("it is not a PE executable (no MZ header)".to_string());
+    }
+    let pe = u32_at(0x3c).ok_or("the DOS header is truncated")? as usize;
+    if image.get(pe..pe + 4) != Some(b"PE\0\0".as_slice()) {
+        return Err("it has no PE signature".to_string());
+    }
+    let machine = u16_at(pe + 4).ok_or("the PE header is truncated")?;
+    // COFF header (20 bytes) after the signature, then Subsystem at offset 68 of the optional header
+    let subsystem = u16_at(pe + 24 + 68).ok_or("the PE optional header is truncated")?;
+    if subsystem != EFI_APPLICATION_SUBSYSTEM {
+        return Err(format!("it is a PE executable but not an EFI application (subsystem {})", subsystem));
+    }
+    EFI_LOADER_NAMES
+        .iter()
+        .find(|(value, _)| *value == machine)
+        .map(|(_, name)| *name)
+        .ok_or_else(|| format!("its architecture (machine 0x{:04x}) has no EFI boot path", machine))
+}
+
+/// Check that `path` is an EFI application small enough for the tool partition; returns the
+/// loader name it is stored under
+pub fn validate_efi_tool(path: &Path) -> Result<&'static str, String> {
+    let meta = fs::metadata(path).map_err(|e| format!("Cannot read EFI tool {}: {}", path.display(), e))?;
+    if !meta.is_file() || meta.len() > MAX_TOOL_BYTES {
+        return Err(format!("EFI tool {} is not a file of at most {} MB", path.display(), MAX_TOOL_BYTES / 1024 / 1024));
+    }
+    let mut image = Vec::new();
+    fs::File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut image))
+        .map_err(|e| format!("Cannot read EFI tool {}: {}", path.display(), e))?;
+    efi_loader_name(&image).map_err(|reason| format!("{} cannot be used as an EFI tool: {}", path.display(), reason))
+}
+
+/// Size in MiB of the tool partition for an image of `image_bytes`: room for the FAT structures,
+/// never less than 32 MiB
+pub fn tool_partition_mb(image_bytes: u64) -> u64 {
+    (image_bytes.div_ceil(1024 * 1024) + 8).max(32)
+}
+
+/// Format `partition` as FAT with the `TOOLS` label and store `image` as its fallback loader,
+/// mounting it on `mount_dir` meanwhile
+pub fn install_tool(partition: &str, image: &Path, mount_dir: &Path, log: &mut dyn Write) -> io::Result<()> {
+    let loader = validate_efi_tool(image).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    writeln!(log, "Formatting {} as FAT for the EFI tool...", partition)?;
+    let output = Command::new("mkfs.vfat").args(["-n", TOOL_PARTITION_NAME, partition]).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("mkfs.vfat failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    fs::create_dir_all(mount_dir)?;
+    let status = Command::new("mount").arg(partition).arg(mount_dir).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("mount {} failed", partition)));
+    }
+    let boot_dir = mount_dir.join("EFI/BOOT");
+    let copied = fs::create_dir_all(&boot_dir).and_then(|_| fs::copy(image, boot_dir.join(loader)));
+    let _ = Command::new("umount").arg(mount_dir).status();
+    copied?;
+    writeln!(log, "Added {} to {} as EFI/BOOT/{}", image.display(), TOOL_PARTITION_NAME, loader)?;
+    Ok(())
+}
+
+/// Smallest PE image `efi_loader_name` accepts, for tests
+#[cfg(test)]
+pub(crate) fn pe_image(machine: u16, subsystem: u16) -> Vec<u8> {
+    let mut image = vec![0u8; 0x200];
+    image[..2].copy_from_slice(b"MZ");
+    image[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+    image[0x80..0x84].copy_from_slice(b"PE\0\0");
+    image[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+    image[0x80 + 24 + 68..0x80 + 24 + 70].copy_from_slice(&subsystem.to_le_bytes());
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn efi_applications_get_their_fallback_loader_name() {
+        assert_eq!(efi_loader_name(&pe_image(0x8664, 10)), Ok("BOOTX64.EFI"));
+        assert_eq!(efi_loader_name(&pe_image(0xaa64, 10)), Ok("BOOTAA64.EFI"));
+        // A Windows console program is PE but not EFI
+        assert!(efi_loader_name(&pe_image(0x8664, 3)).unwrap_err().contains("subsystem 3"));
+        assert!(efi_loader_name(&pe_image(0x01c4, 10)).unwrap_err().contains("0x01c4"));
+        assert!(efi_loader_name(b"\x7fELF").unwrap_err().contains("MZ"));
+        assert!(efi_loader_name(&pe_image(0x8664, 10)[..0x90]).is_err());
+    }
+
+    #[test]
+    fn tool_partition_leaves_room_for_fat() {
+        assert_eq!(tool_partition_mb(150_000), 32);
+        assert_eq!(tool_partition_mb(40 * 1024 * 1024), 48);
+    }
+}
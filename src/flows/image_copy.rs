@@ -0,0 +1,644 @@
+//! Rust-controlled copies between image files and block devices: paced writes that dd cannot
+//! shape, raw writes into a single partition, cloning a stick back into a (compressed) image and
+//! restoring such images. These loops can also be paused and resumed, which dd and rsync cannot.
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::flows::windows_flow::{ensure_not_system_device, unmount_device_mounts};
+use crate::utils::{get_device_logical_block_size, partition_path, read_partitions, resolve_device_path, PartitionInfo};
+
+/// Size of each read/write
+const CHUNK_BYTES: usize = 4 * 1024 * 1024;
+/// First bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Push written data to the device at least this often, so pacing reflects the device
+/// rather than the page cache
+const SYNC_EVERY_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Options shaping the copy
+#[derive(Debug, Clone, Default)]
+pub struct CopyOptions {
+    /// Maximum average write speed in MB/s; None copies as fast as the device allows
+    pub max_speed_mbps: Option<u64>,
+    /// Switch the caller can use to pause and resume the copy
+    pub pause: Option<PauseControl>,
+}
+
+/// Pause switch shared between the GUI and a running copy; clones control the same copy
+#[derive(Debug, Clone, Default)]
+pub struct PauseControl(Arc<(Mutex<bool>, Condvar)>);
+
+impl PauseControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold the copy before its next chunk
+    pub fn pause(&self) {
+        self.set(true);
+    }
+
+    pub fn resume(&self) {
+        self.set(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.0.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set(&self, paused: bool) {
+        let (state, changed) = &*self.0;
+        *state.lock().unwrap_or_else(PoisonError::into_inner) = paused;
+        changed.notify_all();
+    }
+
+    /// Block until resumed; returns how long that took
+    fn wait_until_resumed(&self) -> Duration {
+        let started = Instant::now();
+        let (state, changed) = &*self.0;
+        let paused = state.lock().unwrap_or_else(PoisonError::into_inner);
+        drop(changed.wait_while(paused, |paused| *paused).unwrap_or_else(PoisonError::into_inner));
+        started.elapsed()
+    }
+}
+
+/// Block while `pause` is set. What was written so far is pushed to `output` first, so the
+/// device sits idle instead of flushing its cache throughout the pause. Returns the time spent
+/// paused, which callers leave out of their speed figures.
+fn hold_if_paused(pause: Option<&PauseControl>, output: Option<&File>, done: u64, log: &mut dyn Write) -> io::Result<Duration> {
+    let Some(pause) = pause.filter(|pause| pause.is_paused()) else {
+        return Ok(Duration::ZERO);
+    };
+    if let Some(output) = output {
+        output.sync_data()?;
+    }
+    writeln!(log, "Paused after {} MB; the device is idle until the write is resumed", done / 1024 / 1024)?;
+    log.flush().ok();
+    let paused = pause.wait_until_resumed();
+    writeln!(log, "Resumed after {} s", paused.as_secs())?;
+    Ok(paused)
+}
+
+/// Writes `[PROGRESS] <phase>: ...` lines at most once per second
+struct ProgressReporter {
+    phase: &'static str,
+    total: u64,
+    started: Instant,
+    last_report: Option<Instant>,
+}
+
+impl ProgressReporter {
+    fn new(phase: &'static str, total: u64) -> Self {
+        Self { phase, total, started: Instant::now(), last_report: None }
+    }
+
+    /// Leave `paused` out of the reported speed
+    fn skip(&mut self, paused: Duration) {
+        self.started += paused;
+    }
+
+    fn report(&mut self, done: u64, log: &mut dyn Write) -> io::Result<()> {
+        let due = self.last_report.is_none_or(|last| last.elapsed() >= Duration::from_secs(1));
+        if !due && done != self.total {
+            return Ok(());
+        }
+        let elapsed = self.started.elapsed().as_secs_f64().max(0.001);
+        writeln!(
+            log,
+            "[PROGRESS] {}: {} / {} MB at {:.1} MB/s ({}%)",
+            self.phase,
+            done / 1024 / 1024,
+            self.total / 1024 / 1024,
+            done as f64 / 1024.0 / 1024.0 / elapsed,
+            (done * 100).checked_div(self.total).unwrap_or(100)
+        )?;
+        log.flush().ok();
+        self.last_report = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Time to wait before writing more so the average stays at or below `limit_bytes_per_sec`
+fn pacing_delay(bytes_done: u64, elapsed: Duration, limit_bytes_per_sec: u64) -> Duration {
+    if limit_bytes_per_sec == 0 {
+        return Duration::ZERO;
+    }
+    let earliest = Duration::from_secs_f64(bytes_done as f64 / limit_bytes_per_sec as f64);
+    earliest.saturating_sub(elapsed)
+}
+
+/// Copy `source` onto `device` in chunks, writing `[PROGRESS] write: ...` lines at most once
+/// per second. Returns the number of bytes written.
+pub fn copy_image(source: &str, device: &str, options: CopyOptions, log: &mut dyn Write) -> io::Result<u64> {
+    let device = &resolve_device_path(device);
+    let mut input = File::open(source)?;
+    let total = input.metadata()?.len();
+    let mut output = OpenOptions::new().write(true).open(device)?;
+    let limit = options.max_speed_mbps.map(|mbps| mbps.saturating_mul(1024 * 1024));
+    if let Some(mbps) = options.max_speed_mbps {
+        writeln!(log, "Write speed limited to {} MB/s", mbps)?;
+    }
+
+    let mut buf = vec![0u8; CHUNK_BYTES];
+    let mut written: u64 = 0;
+    let mut unsynced: u64 = 0;
+    let mut started = Instant::now();
+    let mut progress = ProgressReporter::new("write", total);
+    loop {
+        let paused = hold_if_paused(options.pause.as_ref(), Some(&output), written, log)?;
+        if !paused.is_zero() {
+            started += paused;
+            progress.skip(paused);
+            unsynced = 0;
+        }
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buf[..read])?;
+        written += read as u64;
+        unsynced += read as u64;
+        if unsynced >= SYNC_EVERY_BYTES {
+            output.sync_data()?;
+            unsynced = 0;
+        }
+        if let Some(limit) = limit {
+            std::thread::sleep(pacing_delay(written, started.elapsed(), limit));
+        }
+        progress.report(written, log)?;
+    }
+    output.sync_all()?;
+    Ok(written)
+}
+
+/// Where an image from a network share is copied before writing; disk-backed, unlike a tmpfs /tmp
+pub const LOCAL_COPY_DIR: &str = "/var/tmp/majusb";
+
+/// Copy `source` into `dest_dir` with `[PROGRESS] copy: ...` lines, so a stalling network share
+/// stalls this copy rather than the write. Returns the path of the copy.
+pub fn copy_image_locally(source: &str, dest_dir: &Path, log: &mut dyn Write) -> io::Result<String> {
+    let name = Path::new(source)
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file", source)))?;
+    std::fs::create_dir_all(dest_dir)?;
+    let dest = dest_dir.join(name);
+    let mut input = File::open(source)?;
+    let total = input.metadata()?.len();
+    let mut output = File::create(&dest)?;
+    writeln!(log, "Copying {} ({} MB) to {} before writing...", source, total / 1024 / 1024, dest.display())?;
+
+    let mut buf = vec![0u8; CHUNK_BYTES];
+    let mut copied: u64 = 0;
+    let mut progress = ProgressReporter::new("copy", total);
+    loop {
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buf[..read])?;
+        copied += read as u64;
+        progress.report(copied, log)?;
+    }
+    output.sync_all()?;
+    if copied != total {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("copied {} of {} bytes from {}", copied, total, source),
+        ));
+    }
+    Ok(dest.to_string_lossy().into_owned())
+}
+
+/// Check that `image_bytes` fit in `partition`, whose size is counted in `sector_size`-byte sectors
+pub fn check_fits_partition(image_bytes: u64, partition: &PartitionInfo, sector_size: u64) -> Result<(), String> {
+    let partition_bytes = partition.size_sectors * sector_size;
+    if image_bytes > partition_bytes {
+        return Err(format!(
+            "The image needs {} MB but partition {} only holds {} MB",
+            image_bytes.div_ceil(1024 * 1024),
+            partition.number,
+            partition_bytes / 1024 / 1024
+        ));
+    }
+    Ok(())
+}
+
+/// Copy `image` verbatim into partition `number` of `device`. The partition table and the other
+/// partitions are left alone; images larger than the partition are refused before writing.
+/// Returns the number of bytes written.
+pub fn write_image_to_partition(
+    image: &str,
+    device: &str,
+    number: u32,
+    options: CopyOptions,
+    log: &mut dyn Write,
+) -> io::Result<u64> {
+    let device = &resolve_device_path(device);
+    let partitions = read_partitions(device)?;
+    let partition = partitions.iter().find(|part| part.number == number).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} has no partition {}", device, number))
+    })?;
+    let image_bytes = File::open(image)?.metadata()?.len();
+    let sector_size = get_device_logical_block_size(device).unwrap_or(512);
+    check_fits_partition(image_bytes, partition, sector_size).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    ensure_not_system_device(device, log)?;
+    unmount_device_mounts(device, log)?;
+    let target = partition_path(device, number);
+    writeln!(log, "Writing {} MB into {} ({})", image_bytes / 1024 / 1024, target, partition.summary(sector_size))?;
+    let written = copy_image(image, &target, options, log)?;
+    writeln!(log, "Image written to {}; the other partitions were not touched", target)?;
+    Ok(written)
+}
+
+/// Destination of a cloned image: a plain (sparse) file or a gzip stream
+enum ImageSink {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl ImageSink {
+    /// Emit `len` zero bytes: a hole in plain files, literal zeros in the gzip stream
+    fn write_zeros(&mut self, len: u64, zeros: &[u8]) -> io::Result<()> {
+        match self {
+            ImageSink::Plain(file) => {
+                file.seek(SeekFrom::Current(len as i64))?;
+            }
+            ImageSink::Gzip(encoder) => {
+                let mut left = len;
+                while left > 0 {
+                    let n = left.min(zeros.len() as u64) as usize;
+                    encoder.write_all(&zeros[..n])?;
+                    left -= n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            ImageSink::Plain(file) => file.write_all(data),
+            ImageSink::Gzip(encoder) => encoder.write_all(data),
+        }
+    }
+
+    /// Finish the stream; `len` is the final size of a plain image (covers a trailing hole)
+    fn finish(self, len: u64) -> io::Result<()> {
+        let file = match self {
+            ImageSink::Plain(file) => {
+                file.set_len(len)?;
+                file
+            }
+            ImageSink::Gzip(encoder) => encoder.finish()?,
+        };
+        file.sync_all()
+    }
+}
+
+/// Read `device` back into an image at `dest`, gzip-compressed when `compress` is set.
+/// Zero-filled runs become holes in uncompressed images; with `trim` the zero-filled space at the
+/// end of the device is left out entirely. Returns the (uncompressed) image size in bytes.
+pub fn clone_device_to_image(
+    device: &str,
+    dest: &str,
+    compress: bool,
+    trim: bool,
+    log: &mut dyn Write,
+) -> io::Result<u64> {
+    let mut input = File::open(device)?;
+    // Block devices report a zero length in their metadata; seeking to the end works for both
+    let total = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
+    let output = File::create(dest)?;
+    let mut sink = if compress {
+        ImageSink::Gzip(GzEncoder::new(output, Compression::default()))
+    } else {
+        ImageSink::Plain(output)
+    };
+    writeln!(
+        log,
+        "Cloning {} ({} MB) to {}{}",
+        device,
+        total / 1024 / 1024,
+        dest,
+        if compress { " (gzip)" } else { "" }
+    )?;
+
+    let zeros = vec![0u8; CHUNK_BYTES];
+    let mut buf = vec![0u8; CHUNK_BYTES];
+    let mut read_total: u64 = 0;
+    // Zero bytes read but not yet emitted; dropped at the end when trimming
+    let mut pending_zeros: u64 = 0;
+    let mut progress = ProgressReporter::new("clone", total);
+    loop {
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        read_total += read as u64;
+        let chunk = &buf[..read];
+        match chunk.iter().rposition(|&b| b != 0) {
+            None => pending_zeros += read as u64,
+            Some(last) => {
+                sink.write_zeros(pending_zeros, &zeros)?;
+                sink.write_all(&chunk[..=last])?;
+                pending_zeros = (read - last - 1) as u64;
+            }
+        }
+        progress.report(read_total, log)?;
+    }
+    let image_len = if trim {
+        read_total - pending_zeros
+    } else {
+        sink.write_zeros(pending_zeros, &zeros)?;
+        read_total
+    };
+    sink.finish(image_len)?;
+    if image_len < read_total {
+        writeln!(
+            log,
+            "Trimmed {} MB of trailing empty space",
+            (read_total - image_len) / 1024 / 1024
+        )?;
+    }
+    writeln!(log, "Image saved: {} MB of device data", image_len / 1024 / 1024)?;
+    Ok(image_len)
+}
+
+/// Open an image for reading, decompressing it on the fly when it is gzip-compressed
+fn open_image(path: &str) -> io::Result<Box<dyn Read>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 2];
+    let is_gzip = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(if is_gzip { Box::new(GzDecoder::new(file)) } else { Box::new(file) })
+}
+
+/// Size of the data an image expands to. Compressed images are decompressed once, which also
+/// catches truncated or corrupt archives before the device is touched.
+fn image_size(path: &str) -> io::Result<u64> {
+    let mut reader = open_image(path)?;
+    io::copy(&mut reader, &mut io::sink())
+}
+
+/// Stream `reader` through SHA-256, copying each chunk to `output` when given.
+/// Returns the byte count and the hex digest.
+fn hash_copy(
+    reader: &mut dyn Read,
+    mut output: Option<&mut File>,
+    pause: Option<&PauseControl>,
+    progress: &mut ProgressReporter,
+    log: &mut dyn Write,
+) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_BYTES];
+    let mut done: u64 = 0;
+    let mut unsynced: u64 = 0;
+    loop {
+        let paused = hold_if_paused(pause, output.as_deref(), done, log)?;
+        if !paused.is_zero() {
+            progress.skip(paused);
+            unsynced = 0;
+        }
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        if let Some(output) = output.as_deref_mut() {
+            output.write_all(&buf[..read])?;
+            unsynced += read as u64;
+            if unsynced >= SYNC_EVERY_BYTES {
+                output.sync_data()?;
+                unsynced = 0;
+            }
+        }
+        done += read as u64;
+        progress.report(done, log)?;
+    }
+    if let Some(output) = output {
+        output.sync_all()?;
+    }
+    let digest = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    Ok((done, digest))
+}
+
+/// Flush `file` and evict its pages from the page cache, so the next read comes from the medium
+fn drop_cached_pages(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    file.sync_all()?;
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Write an `.img` or `.img.gz` onto `device`, decompressing on the fly, then read the device back
+/// and compare it with the image. Images larger than the device are refused before writing.
+/// Returns the number of bytes restored. Both passes stop between chunks while `pause` is set.
+pub fn restore_image_to_device(image: &str, device: &str, pause: Option<&PauseControl>, log: &mut dyn Write) -> io::Result<u64> {
+    let device = &resolve_device_path(device);
+    writeln!(log, "Checking image {}...", image)?;
+    let image_len = image_size(image)?;
+    ensure_not_system_device(device, log)?;
+    unmount_device_mounts(device, log)?;
+    let mut output = OpenOptions::new().read(true).write(true).open(device)?;
+    let device_len = output.seek(SeekFrom::End(0))?;
+    output.seek(SeekFrom::Start(0))?;
+    if image_len > device_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The image needs {} MB but {} only holds {} MB",
+                image_len / 1024 / 1024,
+                device,
+                device_len / 1024 / 1024
+            ),
+        ));
+    }
+    writeln!(log, "Restoring {} MB onto {}...", image_len / 1024 / 1024, device)?;
+    let mut progress = ProgressReporter::new("restore", image_len);
+    let (written, image_digest) = hash_copy(&mut open_image(image)?, Some(&mut output), pause, &mut progress, log)?;
+    drop(output);
+
+    writeln!(log, "Verifying restored data...")?;
+    let readback = File::open(device)?;
+    drop_cached_pages(&readback)?;
+    let mut readback = readback.take(written);
+    let mut progress = ProgressReporter::new("verify", written);
+    let (verified, device_digest) = hash_copy(&mut readback, None, pause, &mut progress, log)?;
+    if verified != written || device_digest != image_digest {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Verification failed: {} does not match the image (SHA-256 {} vs {})", device, device_digest, image_digest),
+        ));
+    }
+    writeln!(log, "Verification OK (SHA-256 {})", image_digest)?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacing_holds_writes_back_to_the_limit() {
+        let mb = 1024 * 1024;
+        // 20 MB at 10 MB/s must take 2s; after 0.5s there is 1.5s left to wait
+        assert_eq!(pacing_delay(20 * mb, Duration::from_millis(500), 10 * mb), Duration::from_millis(1500));
+        // Already slower than the cap: no delay
+        assert_eq!(pacing_delay(5 * mb, Duration::from_secs(1), 10 * mb), Duration::ZERO);
+        assert_eq!(pacing_delay(5 * mb, Duration::ZERO, 0), Duration::ZERO);
+    }
+
+    #[test]
+    fn copies_image_and_reports_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("image.iso");
+        let target = dir.path().join("device.img");
+        let data: Vec<u8> = (0..CHUNK_BYTES + 1234).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+        std::fs::write(&target, []).unwrap();
+
+        let mut log = Vec::new();
+        let written = copy_image(
+            source.to_str().unwrap(),
+            target.to_str().unwrap(),
+            CopyOptions::default(),
+            &mut log,
+        )
+        .unwrap();
+        assert_eq!(written, data.len() as u64);
+        assert_eq!(std::fs::read(&target).unwrap(), data);
+        assert!(String::from_utf8(log).unwrap().contains("(100%)"));
+
+        // An absurd cap saturates instead of overflowing and does not slow the copy
+        let options = CopyOptions { max_speed_mbps: Some(u64::MAX), ..Default::default() };
+        let written = copy_image(source.to_str().unwrap(), target.to_str().unwrap(), options, &mut Vec::new()).unwrap();
+        assert_eq!(written, data.len() as u64);
+    }
+
+    #[test]
+    fn paused_copy_waits_for_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("image.iso");
+        let target = dir.path().join("device.img");
+        std::fs::write(&source, vec![7u8; 1234]).unwrap();
+        std::fs::write(&target, []).unwrap();
+
+        let pause = PauseControl::new();
+        pause.pause();
+        let options = CopyOptions { pause: Some(pause.clone()), ..Default::default() };
+        let (source_path, target_path) = (source.to_str().unwrap().to_string(), target.to_str().unwrap().to_string());
+        let copy = std::thread::spawn(move || {
+            let mut log = Vec::new();
+            copy_image(&source_path, &target_path, options, &mut log).map(|written| (written, log))
+        });
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!copy.is_finished());
+        assert!(std::fs::read(&target).unwrap().is_empty());
+
+        pause.resume();
+        let (written, log) = copy.join().unwrap().unwrap();
+        assert_eq!(written, 1234);
+        let log = String::from_utf8(log).unwrap();
+        assert!(log.contains("Paused after 0 MB"));
+        assert!(log.contains("Resumed after"));
+    }
+
+    #[test]
+    fn copies_network_images_to_a_local_directory() {
+        let share = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let source = share.path().join("fedora.iso");
+        std::fs::write(&source, vec![3u8; 4096]).unwrap();
+
+        let mut log = Vec::new();
+        let copy = copy_image_locally(source.to_str().unwrap(), &local.path().join("cache"), &mut log).unwrap();
+        assert_eq!(copy, local.path().join("cache/fedora.iso").to_str().unwrap());
+        assert_eq!(std::fs::read(&copy).unwrap(), vec![3u8; 4096]);
+        assert!(String::from_utf8(log).unwrap().contains("[PROGRESS] copy:"));
+    }
+
+    #[test]
+    fn images_must_fit_the_target_partition() {
+        let partition = PartitionInfo {
+            number: 3,
+            start_sector: 2048,
+            end_sector: 2048 + 4095,
+            size_sectors: 4096,
+            fs_type: "ext4".to_string(),
+            name: "recovery".to_string(),
+            cluster_bytes: None,
+        };
+        assert_eq!(check_fits_partition(2 * 1024 * 1024, &partition, 512), Ok(()));
+        let err = check_fits_partition(2 * 1024 * 1024 + 1, &partition, 512).unwrap_err();
+        assert_eq!(err, "The image needs 3 MB but partition 3 only holds 2 MB");
+        // 4K-sector devices count the same sectors eight times larger
+        assert_eq!(check_fits_partition(16 * 1024 * 1024, &partition, 4096), Ok(()));
+    }
+
+    #[test]
+    fn clones_device_and_trims_trailing_zeros() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("device.img");
+        // Data, a zero gap that must survive, more data, then empty space to trim
+        let mut data = vec![7u8; 1000];
+        data.extend(vec![0u8; CHUNK_BYTES]);
+        data.extend(vec![9u8; 10]);
+        let used = data.len();
+        data.extend(vec![0u8; 2 * CHUNK_BYTES]);
+        std::fs::write(&device, &data).unwrap();
+        let device = device.to_str().unwrap();
+
+        let plain = dir.path().join("plain.img");
+        let len = clone_device_to_image(device, plain.to_str().unwrap(), false, true, &mut io::sink()).unwrap();
+        assert_eq!(len, used as u64);
+        assert_eq!(std::fs::read(&plain).unwrap(), &data[..used]);
+
+        let untrimmed = dir.path().join("full.img");
+        clone_device_to_image(device, untrimmed.to_str().unwrap(), false, false, &mut io::sink()).unwrap();
+        assert_eq!(std::fs::read(&untrimmed).unwrap(), data);
+
+        let gz = dir.path().join("plain.img.gz");
+        clone_device_to_image(device, gz.to_str().unwrap(), true, true, &mut io::sink()).unwrap();
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(File::open(&gz).unwrap()).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, &data[..used]);
+    }
+
+    #[test]
+    fn restores_compressed_image_and_refuses_oversized_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..CHUNK_BYTES + 4321).map(|i| (i % 253) as u8).collect();
+        let gz = dir.path().join("backup.img.gz");
+        let mut encoder = GzEncoder::new(File::create(&gz).unwrap(), Compression::fast());
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap();
+
+        // The "device" is larger than the image; the tail must be left alone
+        let device = dir.path().join("device.img");
+        std::fs::write(&device, vec![0xAAu8; data.len() + 512]).unwrap();
+        let written = restore_image_to_device(gz.to_str().unwrap(), device.to_str().unwrap(), None, &mut io::sink()).unwrap();
+        assert_eq!(written, data.len() as u64);
+        let restored = std::fs::read(&device).unwrap();
+        assert_eq!(&restored[..data.len()], &data[..]);
+        assert!(restored[data.len()..].iter().all(|&b| b == 0xAA));
+
+        let small = dir.path().join("small.img");
+        std::fs::write(&small, vec![0u8; 1024]).unwrap();
+        let err = restore_image_to_device(gz.to_str().unwrap(), small.to_str().unwrap(), None, &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(std::fs::read(&small).unwrap(), vec![0u8; 1024]);
+    }
+}
@@ -0,0 +1,1203 @@
+//! Description of a write request as chosen by the user, independent of the UI
+
+use crate::flows::boot_check::check_boot_files;
+use crate::flows::linux_autoinstall::AnswerFile;
+use crate::flows::linux_data_partition::{validate_data_partition, DataFilesystem, DataPartitionConfig};
+use crate::flows::linux_flow::LinuxWriteOptions;
+use crate::flows::media_manifest::write_media_manifest;
+use crate::flows::media_scan::{write_media_label, MediaLabel};
+use crate::checksum::{normalize_sha256, parse_expected_size};
+use crate::flows::windows_flow::{validate_esp_size_mb, validate_exclude_pattern, validate_fat32_sectors_per_cluster, WindowsFlowOptions};
+use crate::flows::linux_persistence::{PartitionTableType, PersistenceConfig, PersistenceType, RemountPolicy, MAX_RESERVED_PERCENT};
+use crate::flows::outcome::{record_warning, Warning, WarningKind};
+use crate::settings::CopyStrategy;
+use crate::utils::{caching_bridge_name, partition_path, thorough_flush};
+use crate::windows::unattend::UnattendFlags;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// How a Linux ISO is written to the device
+#[derive(Debug, Clone)]
+pub enum LinuxWriteMode {
+    /// Raw image copy with dd; the device is left exactly as the ISO lays it out
+    RawDd,
+    /// dd followed by a persistence partition in the remaining space
+    Persistent(PersistenceConfig),
+    /// The ISO's table replaced by a fresh `table` with its files copied onto FAT32 (UEFI boot
+    /// only), optionally followed by a persistence partition on the same table
+    Extracted {
+        table: PartitionTableType,
+        persistence: Option<PersistenceConfig>,
+    },
+}
+
+/// Kind of image a job writes, after detection and any user override
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Windows,
+    Linux,
+    /// Verbatim dd copy with no OS-specific handling
+    Raw,
+    /// Previously saved `.img`/`.img.gz` restored onto the device
+    DiskImage,
+}
+
+/// User override for the detected OS type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OsOverride {
+    #[default]
+    Auto,
+    ForceWindows,
+    ForceLinux,
+    ForceRaw,
+    ForceDiskImage,
+}
+
+impl OsOverride {
+    /// Map a GUI combo index (Auto / Windows / Linux / Raw / Disk image) to an override
+    pub fn from_index(index: u32) -> Self {
+        match index {
+            1 => OsOverride::ForceWindows,
+            2 => OsOverride::ForceLinux,
+            3 => OsOverride::ForceRaw,
+            4 => OsOverride::ForceDiskImage,
+            _ => OsOverride::Auto,
+        }
+    }
+
+    /// Resolve the image kind; forced values win over `detected` (`is_windows_iso` result)
+    pub fn resolve(self, detected: Option<bool>) -> Option<ImageKind> {
+        match self {
+            OsOverride::Auto => detected.map(|is_windows| {
+                if is_windows { ImageKind::Windows } else { ImageKind::Linux }
+            }),
+            OsOverride::ForceWindows => Some(ImageKind::Windows),
+            OsOverride::ForceLinux => Some(ImageKind::Linux),
+            OsOverride::ForceRaw => Some(ImageKind::Raw),
+            OsOverride::ForceDiskImage => Some(ImageKind::DiskImage),
+        }
+    }
+}
+
+/// Write mode selected for a job
+#[derive(Debug, Clone)]
+pub enum WriteMode {
+    /// Linux ISO written with dd, optionally followed by persistence
+    Linux {
+        mode: LinuxWriteMode,
+        fix_protective_mbr: bool,
+    },
+    /// Windows ISO written with the dual-partition (BOOT + ESD-USB) layout
+    Windows {
+        bypass: Option<UnattendFlags>,
+        cluster_bytes: u64,
+        /// FAT32 sectors per cluster for BOOT; None picks it from the device block size
+        fat32_sectors_per_cluster: Option<u64>,
+        /// `$OEM$` folder copied to `sources/$OEM$` on the INSTALL partition
+        oem_folder: Option<String>,
+        /// Size in MB of a dedicated ESP replacing the 1 GiB BOOT partition
+        esp_mb: Option<u64>,
+        /// Extra rsync `--exclude` patterns for the INSTALL copy
+        exclude: Vec<String>,
+        /// Copy the boot files only, for checking that a machine lists the stick (cannot install)
+        boot_test: bool,
+        /// Windows 7/8 layout: one active NTFS partition on an MBR table, booted by BIOS
+        legacy: bool,
+        /// EFI application (e.g. memtest86+) given its own bootable FAT partition
+        tool_efi: Option<String>,
+    },
+    /// Windows ISO written directly with dd (not recommended)
+    WindowsDirectDd,
+    /// Any image copied verbatim with dd, bypassing OS detection
+    RawImage,
+    /// Image copied verbatim into one existing partition; the partition table and the other
+    /// partitions are left alone
+    RawPartition { number: u32 },
+    /// Saved disk image (optionally gzip-compressed) restored and verified
+    RestoreImage,
+}
+
+/// True for file names of saved disk images (`.img`, `.raw`, optionally gzip-compressed)
+pub fn is_disk_image(path: &str) -> bool {
+    let name = path.to_ascii_lowercase();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    name.ends_with(".img") || name.ends_with(".raw")
+}
+
+/// A fully specified write request: source image, target device and mode
+#[derive(Debug, Clone)]
+pub struct WriteJob {
+    pub iso_path: String,
+    pub device: String,
+    pub mode: WriteMode,
+    /// SHA-256 the source image must match before anything is written
+    pub expected_sha256: Option<String>,
+    /// Published size of the source image, checked before the (much slower) hash
+    pub expected_size: Option<String>,
+    /// Write speed cap in MB/s for modes that copy the image verbatim (Linux and raw)
+    pub max_speed_mbps: Option<u64>,
+    /// Kickstart or preseed for an unattended install (Linux mode only)
+    pub answer_file: Option<AnswerFile>,
+    /// exFAT/FAT32 storage partition added after the ISO (Linux mode only)
+    pub data_partition: Option<DataPartitionConfig>,
+    /// Flush the device's buffers and read back its ends after the write, for bridges that cache writes
+    pub thorough_flush: bool,
+    /// Name and description stored on the media so a later scan can identify it
+    pub media_label: Option<MediaLabel>,
+    /// Store SHA-256 hashes of the written files on the media for `cli_helper verify-media`
+    pub checksum_manifest: bool,
+    /// Check after the write that the media holds a valid EFI loader (see `boot_check`)
+    pub verify_boot: bool,
+    /// Erase the device even when it holds a Ventoy or syslinux multiboot layout
+    pub overwrite_multiboot: bool,
+    /// ISO contents staged once for a batch of Windows writes (see `windows_flow::stage_iso`)
+    pub staged_iso: Option<PathBuf>,
+    /// Whole-file or delta copies in the Windows flow
+    pub copy_strategy: CopyStrategy,
+}
+
+impl WriteJob {
+    pub fn new(iso_path: impl Into<String>, device: impl Into<String>, mode: WriteMode) -> Self {
+        Self {
+            iso_path: iso_path.into(),
+            device: device.into(),
+            mode,
+            expected_sha256: None,
+            expected_size: None,
+            max_speed_mbps: None,
+            answer_file: None,
+            data_partition: None,
+            thorough_flush: false,
+            media_label: None,
+            checksum_manifest: false,
+            verify_boot: false,
+            overwrite_multiboot: false,
+            staged_iso: None,
+            copy_strategy: CopyStrategy::FirstWrite,
+        }
+    }
+
+    /// The same job aimed at another device (for writing several sticks at once)
+    pub fn with_device(&self, device: impl Into<String>) -> Self {
+        Self {
+            device: device.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Require the source image to match `sha256` (normalized hex) before writing
+    pub fn with_expected_sha256(mut self, sha256: Option<String>) -> Self {
+        self.expected_sha256 = sha256;
+        self
+    }
+
+    /// Compare the source image with a published size (as accepted by `parse_expected_size`)
+    pub fn with_expected_size(mut self, size: Option<String>) -> Self {
+        self.expected_size = size;
+        self
+    }
+
+    /// Cap the image copy at `mbps` MB/s (None or 0 for unlimited)
+    pub fn with_max_speed(mut self, mbps: Option<u64>) -> Self {
+        self.max_speed_mbps = mbps.filter(|&mbps| mbps > 0);
+        self
+    }
+
+    /// Put `answer` on the media for an unattended install (None for an interactive one)
+    pub fn with_answer_file(mut self, answer: Option<AnswerFile>) -> Self {
+        self.answer_file = answer.filter(|answer| !answer.path().is_empty());
+        self
+    }
+
+    /// Add a storage partition after a Linux ISO (None for none)
+    pub fn with_data_partition(mut self, data: Option<DataPartitionConfig>) -> Self {
+        self.data_partition = data;
+        self
+    }
+
+    /// Read the Windows flow's files from a staged copy of the ISO instead of mounting it
+    pub fn with_staged_iso(mut self, root: Option<PathBuf>) -> Self {
+        self.staged_iso = root;
+        self
+    }
+
+    /// Tune the Windows flow's copies for a first write or a rewrite of the same stick
+    pub fn with_copy_strategy(mut self, strategy: CopyStrategy) -> Self {
+        self.copy_strategy = strategy;
+        self
+    }
+
+    /// Force cached writes out of the device after writing (see `thorough_flush`)
+    pub fn with_thorough_flush(mut self, enabled: bool) -> Self {
+        self.thorough_flush = enabled;
+        self
+    }
+
+    /// Allow erasing an existing multiboot stick (the user confirmed losing its ISOs)
+    pub fn with_overwrite_multiboot(mut self, enabled: bool) -> Self {
+        self.overwrite_multiboot = enabled;
+        self
+    }
+
+    /// Hash the written files and store the manifest on the media (see `checksum_manifest`)
+    pub fn with_checksum_manifest(mut self, enabled: bool) -> Self {
+        self.checksum_manifest = enabled;
+        self
+    }
+
+    /// Check the written media's EFI boot files once the write is done (see `verify_boot`)
+    pub fn with_verify_boot(mut self, enabled: bool) -> Self {
+        self.verify_boot = enabled;
+        self
+    }
+
+    /// Name the media (None or an empty name for no label file); the image file name is added
+    pub fn with_media_label(mut self, label: Option<MediaLabel>) -> Self {
+        self.media_label = label.filter(|label| !label.name.trim().is_empty()).map(|label| MediaLabel {
+            image: std::path::Path::new(&self.iso_path).file_name().map(|name| name.to_string_lossy().into_owned()),
+            ..label
+        });
+        self
+    }
+
+    /// Node the image is written to: the device, or one of its partitions
+    pub fn target(&self) -> String {
+        match self.mode {
+            WriteMode::RawPartition { number } => partition_path(&self.device, number),
+            _ => self.device.clone(),
+        }
+    }
+
+    /// Message for a write that failed after it started changing the target: what state the target
+    /// is left in and how to recover
+    pub fn partial_write_notice(&self) -> String {
+        match self.mode {
+            WriteMode::RawPartition { number } => format!(
+                "Partition {} of {} may be partially written and its contents are not usable; the other partitions were not touched. Run the write again to recreate it.",
+                number, self.device
+            ),
+            _ => format!(
+                "{} may be partially written: its partitions and files are in an inconsistent state and it will not boot. Run the write again to recreate it, or format it to use it as plain storage.",
+                self.device
+            ),
+        }
+    }
+
+    /// Last step after a successful write: the label file and checksum manifest, the EFI boot file
+    /// check and thorough flush when requested, and a warning when the device sits behind a bridge known to acknowledge
+    /// writes before storing them
+    pub fn finalize(&self, log: &mut dyn Write, warnings: &mut Vec<Warning>) -> io::Result<()> {
+        if let Some(label) = &self.media_label {
+            // Another partition of the device may hold the label file; it is not ours to touch
+            let result = match self.mode {
+                WriteMode::RawPartition { .. } => Err(io::Error::other("only one partition of the device was written")),
+                _ => write_media_label(&self.device, label, log).map(|_| ()),
+            };
+            if let Err(e) = result {
+                let warning = Warning::new(WarningKind::Label, format!("the name \"{}\" was not stored: {}", label.name, e));
+                record_warning(log, warnings, warning)?;
+            }
+        }
+        if self.checksum_manifest {
+            let result = match self.mode {
+                WriteMode::RawPartition { .. } => Err(io::Error::other("only one partition of the device was written")),
+                _ => write_media_manifest(&self.device, log).map(|_| ()),
+            };
+            if let Err(e) = result {
+                let warning = Warning::new(WarningKind::Manifest, format!("the checksum manifest was not stored: {}", e));
+                record_warning(log, warnings, warning)?;
+            }
+        }
+        if self.verify_boot {
+            let result = match self.mode {
+                WriteMode::RawPartition { .. } => Err(io::Error::other("only one partition of the device was written")),
+                WriteMode::Windows { legacy: true, .. } => Err(io::Error::other("the legacy layout boots through BIOS only")),
+                _ => check_boot_files(&self.device, log),
+            };
+            match result {
+                Ok(check) if check.passed() => writeln!(log, "Boot check passed: {}", check.summary())?,
+                Ok(check) => {
+                    let warning = Warning::new(WarningKind::BootFiles, format!("{} may not boot through UEFI: {}", self.device, check.summary()));
+                    record_warning(log, warnings, warning)?;
+                }
+                Err(e) => writeln!(log, "Skipping the EFI boot file check: {}", e)?,
+            }
+        }
+        let bridge = caching_bridge_name(&self.device);
+        if self.thorough_flush {
+            if let Err(e) = thorough_flush(&self.device, log) {
+                let warning = Warning::new(WarningKind::WriteCache, format!("thorough flush of {} failed: {}", self.device, e));
+                record_warning(log, warnings, warning)?;
+            }
+        } else if let Some(name) = bridge {
+            let warning = Warning::new(
+                WarningKind::WriteCache,
+                format!(
+                    "{} is behind a {} bridge, which may still hold data after sync; eject it before unplugging, or enable the thorough flush",
+                    self.device, name
+                ),
+            );
+            record_warning(log, warnings, warning)?;
+        }
+        Ok(())
+    }
+
+    /// Plan for the confirmation dialog, from facts the caller measured about image and device
+    pub fn plan(&self, image_label: impl Into<String>, image_bytes: u64, device_bytes: Option<u64>) -> WritePlan {
+        let persistence = match &self.mode {
+            WriteMode::Linux { mode: LinuxWriteMode::Persistent(config), .. }
+            | WriteMode::Linux { mode: LinuxWriteMode::Extracted { persistence: Some(config), .. }, .. } => {
+                Some((config.size_mb, config.label.clone()))
+            }
+            _ => None,
+        };
+        WritePlan {
+            device: self.target(),
+            device_bytes,
+            image_label: image_label.into(),
+            image_bytes,
+            persistence,
+            note: None,
+        }
+    }
+
+    /// True for modes that copy the image verbatim with dd
+    pub fn is_raw_dd(&self) -> bool {
+        matches!(
+            self.mode,
+            WriteMode::Linux { mode: LinuxWriteMode::RawDd, .. }
+                | WriteMode::WindowsDirectDd
+                | WriteMode::RawImage
+                | WriteMode::RawPartition { .. }
+        )
+    }
+
+    /// True when the image goes through the Rust copy loop, which can be paused: restores,
+    /// writes into one partition and speed-capped dd-style writes. dd, rsync and the Windows
+    /// flow run to completion.
+    pub fn can_pause(&self) -> bool {
+        match &self.mode {
+            WriteMode::RawPartition { .. } | WriteMode::RestoreImage => true,
+            WriteMode::Linux { mode: LinuxWriteMode::Extracted { .. }, .. } => false,
+            WriteMode::Linux { .. } | WriteMode::RawImage => self.max_speed_mbps.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Options for the dd-based Linux flow, or None for Windows jobs
+    pub fn linux_options(&self) -> Option<LinuxWriteOptions> {
+        match &self.mode {
+            WriteMode::Linux { mode, fix_protective_mbr } => Some(LinuxWriteOptions {
+                persistence: match mode {
+                    LinuxWriteMode::RawDd => None,
+                    LinuxWriteMode::Persistent(config) => Some(config.clone()),
+                    LinuxWriteMode::Extracted { persistence, .. } => persistence.clone(),
+                },
+                repartition: match mode {
+                    LinuxWriteMode::Extracted { table, .. } => Some(*table),
+                    _ => None,
+                },
+                fix_protective_mbr: *fix_protective_mbr,
+                max_speed_mbps: self.max_speed_mbps,
+                answer_file: self.answer_file.clone(),
+                pause: None,
+                data_partition: self.data_partition,
+            }),
+            WriteMode::RawImage => Some(LinuxWriteOptions {
+                max_speed_mbps: self.max_speed_mbps,
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Options for the dual-partition Windows flow, or None for other modes
+    pub fn windows_options(&self) -> Option<WindowsFlowOptions> {
+        match &self.mode {
+            WriteMode::Windows { bypass, cluster_bytes, fat32_sectors_per_cluster, oem_folder, esp_mb, exclude, boot_test, legacy, tool_efi } => {
+                Some(WindowsFlowOptions {
+                    bypass: *bypass,
+                    cluster_bytes: Some(*cluster_bytes),
+                    fat32_sectors_per_cluster: *fat32_sectors_per_cluster,
+                    oem_folder: oem_folder.as_ref().map(PathBuf::from),
+                    esp_mb: *esp_mb,
+                    exclude: exclude.clone(),
+                    boot_test: *boot_test,
+                    legacy: *legacy,
+                    tool_efi: tool_efi.as_ref().map(PathBuf::from),
+                    staged_iso: self.staged_iso.clone(),
+                    copy_strategy: self.copy_strategy,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Explain why the chosen mode contradicts OS detection (`is_windows_iso` result), if it does.
+    /// Explicit raw/dd choices are not reported; they already carry their own warning.
+    pub fn detection_conflict(&self, detected: Option<bool>) -> Option<String> {
+        match (&self.mode, detected) {
+            (WriteMode::Linux { .. }, Some(true)) => Some(
+                "This ISO was detected as Windows, but Linux (dd) mode is selected. \
+                 A Windows ISO written with dd usually does not boot on UEFI systems."
+                    .to_string(),
+            ),
+            (WriteMode::Windows { .. }, Some(false)) => Some(
+                "This ISO was detected as Linux, but the Windows dual-partition mode is selected. \
+                 The stick will not boot a Linux installer."
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Human-readable mode line for the log
+    pub fn describe_mode(&self) -> String {
+        match &self.mode {
+            WriteMode::Linux { mode: LinuxWriteMode::RawDd, .. } => {
+                "Linux (raw write with dd, persistence: disabled)".to_string()
+            }
+            WriteMode::Linux { mode: LinuxWriteMode::Persistent(config), .. } => format!(
+                "Linux (dd + persistence, type: {:?}, size: {} MB, table: {:?})",
+                config.persistence_type, config.size_mb, config.partition_table
+            ),
+            WriteMode::Linux { mode: LinuxWriteMode::Extracted { table, persistence }, .. } => format!(
+                "Linux (new {:?} table, ISO files copied to FAT32, UEFI only, persistence: {})",
+                table,
+                persistence.as_ref().map_or("disabled".to_string(), |config| format!("{} MB", config.size_mb))
+            ),
+            WriteMode::Windows { boot_test: true, .. } => {
+                "Windows boot test (boot files only, cannot install)".to_string()
+            }
+            WriteMode::Windows { legacy: true, cluster_bytes, .. } => {
+                format!("Windows 7/8 legacy (MBR, single NTFS partition, BIOS boot, cluster size: {} bytes)", cluster_bytes)
+            }
+            WriteMode::Windows { cluster_bytes, .. } => {
+                format!("Windows (dual-partition, cluster size: {} bytes)", cluster_bytes)
+            }
+            WriteMode::WindowsDirectDd => "Windows (direct dd mode)".to_string(),
+            WriteMode::RawImage => "Raw image (dd, detection overridden)".to_string(),
+            WriteMode::RawPartition { .. } => {
+                format!("Raw image into partition {} only (dd, partition table kept)", self.target())
+            }
+            WriteMode::RestoreImage => "Restore disk image (decompressed on the fly, verified)".to_string(),
+        }
+    }
+
+    /// Arguments for an equivalent `cli_helper` run, without the program name
+    pub fn to_cli_args(&self) -> Vec<String> {
+        let mut args = vec![self.iso_path.clone(), self.device.clone()];
+        match &self.mode {
+            WriteMode::Windows { bypass, cluster_bytes, fat32_sectors_per_cluster, oem_folder, esp_mb, exclude, boot_test, legacy, tool_efi } => {
+                args.push(cluster_bytes.to_string());
+                args.push("--type=windows".to_string());
+                if let Some(sectors) = fat32_sectors_per_cluster {
+                    args.push(format!("--fat32-sectors={}", sectors));
+                }
+                if let Some(folder) = oem_folder {
+                    args.push(format!("--oem-folder={}", folder));
+                }
+                if let Some(size_mb) = esp_mb {
+                    args.push(format!("--esp-size={}", size_mb));
+                }
+                for pattern in exclude {
+                    args.push(format!("--exclude={}", pattern));
+                }
+                if *boot_test {
+                    args.push("--boot-test".to_string());
+                }
+                if *legacy {
+                    args.push("--legacy".to_string());
+                }
+                if let Some(tool) = tool_efi {
+                    args.push(format!("--tool-efi={}", tool));
+                }
+                let flags = bypass.unwrap_or(UnattendFlags::empty());
+                for (flag, name) in BYPASS_OPTIONS {
+                    if flags.contains(flag) {
+                        args.push(name.to_string());
+                    }
+                }
+            }
+            WriteMode::WindowsDirectDd => {
+                args.push("--type=windows".to_string());
+                args.push("--use-dd-mode".to_string());
+            }
+            WriteMode::Linux { mode, fix_protective_mbr } => {
+                args.push("--type=linux".to_string());
+                if *fix_protective_mbr {
+                    args.push("--fix-pmbr".to_string());
+                }
+                let persistence = match mode {
+                    LinuxWriteMode::RawDd => None,
+                    LinuxWriteMode::Persistent(config) => Some(config),
+                    LinuxWriteMode::Extracted { table, persistence } => {
+                        args.push(format!("--repartition={}", table_name(*table)));
+                        persistence.as_ref()
+                    }
+                };
+                if let Some(config) = persistence {
+                    args.push("--persistence".to_string());
+                    args.push(format!("--persistence-size={}", config.size_mb));
+                    args.push(format!("--persistence-type={}", config.persistence_type.name()));
+                    args.push(format!("--persistence-label={}", config.label));
+                    args.push(format!("--table={}", table_name(config.partition_table)));
+                    if let Some(start) = config.start_sector {
+                        args.push(format!("--persistence-start={}", start));
+                    }
+                    for param in &config.kernel_params {
+                        args.push(format!("--kernel-param={}", param));
+                    }
+                    if config.remount != RemountPolicy::default() {
+                        args.push(format!("--remount={}", config.remount.name()));
+                    }
+                    if config.reserved_percent != 0 {
+                        args.push(format!("--persistence-reserved={}", config.reserved_percent));
+                    }
+                }
+            }
+            WriteMode::RawImage => args.push("--type=raw".to_string()),
+            WriteMode::RawPartition { number } => {
+                args.push("--type=raw".to_string());
+                args.push(format!("--partition={}", number));
+            }
+            WriteMode::RestoreImage => args.push("--type=image".to_string()),
+        }
+        if let Some(hash) = &self.expected_sha256 {
+            args.push(format!("--sha256={}", hash));
+        }
+        if let Some(size) = &self.expected_size {
+            args.push(format!("--expected-size={}", size));
+        }
+        if let Some(mbps) = self.max_speed_mbps {
+            args.push(format!("--max-speed={}", mbps));
+        }
+        if self.thorough_flush {
+            args.push("--thorough-flush".to_string());
+        }
+        if self.overwrite_multiboot {
+            args.push("--overwrite-multiboot".to_string());
+        }
+        if self.checksum_manifest {
+            args.push("--manifest".to_string());
+        }
+        if self.verify_boot {
+            args.push("--verify-boot".to_string());
+        }
+        if self.copy_strategy == CopyStrategy::Rewrite {
+            args.push("--rewrite".to_string());
+        }
+        if let Some(label) = &self.media_label {
+            args.push(format!("--name={}", label.name));
+            if !label.description.is_empty() {
+                args.push(format!("--description={}", label.description));
+            }
+        }
+        if let Some(data) = &self.data_partition {
+            args.push(format!("--data-partition={}", data.size_mb));
+            if data.filesystem != DataFilesystem::default() {
+                args.push(format!("--data-fs={}", data.filesystem.name()));
+            }
+        }
+        match &self.answer_file {
+            Some(AnswerFile::Kickstart(path)) => args.push(format!("--kickstart={}", path)),
+            Some(AnswerFile::Preseed(path)) => args.push(format!("--preseed={}", path)),
+            Some(AnswerFile::Autoinstall { user_data, meta_data }) => {
+                args.push(format!("--autoinstall={}", user_data));
+                if let Some(path) = meta_data {
+                    args.push(format!("--meta-data={}", path));
+                }
+            }
+            None => {}
+        }
+        args
+    }
+
+    /// Shell command line that reproduces this job with `cli_helper`
+    pub fn to_cli_command(&self) -> String {
+        let mut command = String::from("cli_helper");
+        for arg in self.to_cli_args() {
+            command.push(' ');
+            command.push_str(&shell_quote(&arg));
+        }
+        command
+    }
+
+    /// Parse `cli_helper` arguments (without the program name): `<iso> <device> [cluster_bytes]`
+    /// followed by options. `detect` decides Windows vs Linux when no `--type` is given.
+    pub fn from_cli_args(args: &[String], detect: impl FnOnce(&str) -> Option<bool>) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut options: Vec<(&str, Option<&str>)> = Vec::new();
+        for arg in args {
+            match arg.strip_prefix("--") {
+                Some(option) => match option.split_once('=') {
+                    Some((name, value)) => options.push((name, Some(value))),
+                    None => options.push((option, None)),
+                },
+                None => positional.push(arg.as_str()),
+            }
+        }
+        let [iso_path, device, rest @ ..] = positional.as_slice() else {
+            return Err("expected <iso_path> <usb_device>".to_string());
+        };
+        let cluster_bytes = match rest {
+            [] => 4096,
+            [cluster] => cluster.parse().map_err(|_| format!("invalid cluster size '{}'", cluster))?,
+            _ => return Err(format!("unexpected argument '{}'", rest[1])),
+        };
+
+        let has = |name: &str| options.iter().any(|(n, _)| *n == name);
+        let value = |name: &str| -> Result<Option<&str>, String> {
+            match options.iter().rev().find(|(n, _)| *n == name) {
+                Some((_, Some(v))) => Ok(Some(*v)),
+                Some((_, None)) => Err(format!("--{} needs a value", name)),
+                None => Ok(None),
+            }
+        };
+        let number = |name: &str| -> Result<Option<u64>, String> {
+            value(name)?
+                .map(|v| v.parse().map_err(|_| format!("invalid number for --{}: '{}'", name, v)))
+                .transpose()
+        };
+
+        const KNOWN: [&str; 41] = [
+            "type", "use-dd-mode", "bypass-tpm", "bypass-secure-boot", "bypass-ram", "fix-pmbr",
+            "persistence", "persistence-size", "persistence-type", "persistence-label",
+            "persistence-start", "table", "kernel-param", "sha256", "cluster", "max-speed",
+            "kickstart", "preseed", "autoinstall", "meta-data", "remount",
+            "fat32-sectors", "expected-size", "oem-folder", "esp-size", "thorough-flush",
+            "name", "description", "partition", "exclude", "repartition", "overwrite-multiboot",
+            "boot-test", "persistence-reserved", "manifest", "rewrite", "legacy", "tool-efi",
+            "verify-boot", "data-partition", "data-fs",
+        ];
+        if let Some((unknown, _)) = options.iter().find(|(n, _)| !KNOWN.contains(n)) {
+            return Err(format!("unknown option --{}", unknown));
+        }
+        let cluster_bytes = number("cluster")?.unwrap_or(cluster_bytes);
+
+        let kind = match value("type")? {
+            Some("windows") => ImageKind::Windows,
+            Some("linux") => ImageKind::Linux,
+            Some("raw") => ImageKind::Raw,
+            Some("image") => ImageKind::DiskImage,
+            Some(other) => return Err(format!("unknown image type '{}' (windows, linux, raw or image)", other)),
+            None if is_disk_image(iso_path) => ImageKind::DiskImage,
+            None if detect(iso_path) == Some(true) => ImageKind::Windows,
+            None => ImageKind::Linux,
+        };
+
+        if kind != ImageKind::Raw && has("partition") {
+            return Err("--partition only applies to --type=raw".to_string());
+        }
+        if (kind != ImageKind::Windows || has("use-dd-mode")) && has("exclude") {
+            return Err("--exclude only applies to the dual-partition Windows mode".to_string());
+        }
+        if (kind != ImageKind::Windows || has("use-dd-mode")) && has("boot-test") {
+            return Err("--boot-test only applies to the dual-partition Windows mode".to_string());
+        }
+        let mode = match kind {
+            ImageKind::Raw => match number("partition")? {
+                None => WriteMode::RawImage,
+                Some(number @ 1..=128) => WriteMode::RawPartition { number: number as u32 },
+                Some(number) => return Err(format!("invalid partition number {}", number)),
+            },
+            ImageKind::DiskImage => WriteMode::RestoreImage,
+            ImageKind::Windows if has("use-dd-mode") => WriteMode::WindowsDirectDd,
+            ImageKind::Windows => {
+                let mut flags = UnattendFlags::empty();
+                for (flag, name) in BYPASS_OPTIONS {
+                    if has(&name[2..]) {
+                        flags |= flag;
+                    }
+                }
+                let fat32_sectors_per_cluster = number("fat32-sectors")?;
+                if let Some(sectors) = fat32_sectors_per_cluster {
+                    validate_fat32_sectors_per_cluster(sectors)?;
+                }
+                let esp_mb = number("esp-size")?;
+                if let Some(size_mb) = esp_mb {
+                    validate_esp_size_mb(size_mb)?;
+                }
+                let exclude: Vec<String> = options
+                    .iter()
+                    .filter(|(n, _)| *n == "exclude")
+                    .map(|(_, v)| v.unwrap_or_default().to_string())
+                    .collect();
+                for pattern in &exclude {
+                    validate_exclude_pattern(pattern)?;
+                }
+                let legacy = has("legacy");
+                if legacy && (esp_mb.is_some() || has("boot-test") || has("tool-efi")) {
+                    return Err("--legacy writes a single partition and cannot be combined with --esp-size, --boot-test or --tool-efi".to_string());
+                }
+                WriteMode::Windows {
+                    bypass: if flags.is_empty() { None } else { Some(flags) },
+                    cluster_bytes,
+                    fat32_sectors_per_cluster,
+                    oem_folder: value("oem-folder")?.map(str::to_string),
+                    esp_mb,
+                    exclude,
+                    boot_test: has("boot-test"),
+                    legacy,
+                    tool_efi: value("tool-efi")?.map(str::to_string),
+                }
+            }
+            ImageKind::Linux => {
+                let table = |name: &str| -> Result<Option<PartitionTableType>, String> {
+                    match value(name)? {
+                        None => Ok(None),
+                        Some("gpt") => Ok(Some(PartitionTableType::Gpt)),
+                        Some("mbr") => Ok(Some(PartitionTableType::Mbr)),
+                        Some(other) => Err(format!("unknown partition table '{}' (gpt or mbr)", other)),
+                    }
+                };
+                let repartition = table("repartition")?;
+                if repartition.is_some() && (has("fix-pmbr") || has("persistence-start")) {
+                    return Err("--fix-pmbr and --persistence-start describe the ISO's layout and do not apply with --repartition".to_string());
+                }
+                let defaults = PersistenceConfig::default();
+                let persistence = if has("persistence") {
+                    Some(PersistenceConfig {
+                        enabled: true,
+                        size_mb: number("persistence-size")?.unwrap_or(defaults.size_mb),
+                        persistence_type: value("persistence-type")?
+                            .map(PersistenceType::from_name)
+                            .unwrap_or(defaults.persistence_type),
+                        label: value("persistence-label")?.map(str::to_string).unwrap_or(defaults.label),
+                        partition_table: match (table("table")?, repartition) {
+                            (Some(chosen), Some(new_table)) if chosen != new_table => {
+                                return Err("--table must match --repartition".to_string());
+                            }
+                            (chosen, new_table) => chosen.or(new_table).unwrap_or(PartitionTableType::Gpt),
+                        },
+                        start_sector: number("persistence-start")?,
+                        kernel_params: options
+                            .iter()
+                            .filter(|(n, _)| *n == "kernel-param")
+                            .filter_map(|(_, v)| v.map(str::to_string))
+                            .collect(),
+                        remount: match value("remount")? {
+                            None => defaults.remount,
+                            Some(name) => RemountPolicy::from_name(name)
+                                .ok_or_else(|| format!("unknown remount policy '{}' (never, on-failure or always)", name))?,
+                        },
+                        reserved_percent: match number("persistence-reserved")? {
+                            None => defaults.reserved_percent,
+                            Some(percent) if percent <= u64::from(MAX_RESERVED_PERCENT) => percent as u8,
+                            Some(percent) => {
+                                return Err(format!("--persistence-reserved must be 0-{}, got {}", MAX_RESERVED_PERCENT, percent));
+                            }
+                        },
+                    })
+                } else {
+                    None
+                };
+                let mode = match (repartition, persistence) {
+                    (Some(table), persistence) => LinuxWriteMode::Extracted { table, persistence },
+                    (None, Some(config)) => LinuxWriteMode::Persistent(config),
+                    (None, None) => LinuxWriteMode::RawDd,
+                };
+                WriteMode::Linux { mode, fix_protective_mbr: has("fix-pmbr") }
+            }
+        };
+
+        let answer_file = match (value("kickstart")?, value("preseed")?, value("autoinstall")?) {
+            (Some(path), None, None) => Some(AnswerFile::Kickstart(path.to_string())),
+            (None, Some(path), None) => Some(AnswerFile::Preseed(path.to_string())),
+            (None, None, Some(path)) => Some(AnswerFile::Autoinstall {
+                user_data: path.to_string(),
+                meta_data: value("meta-data")?.map(str::to_string),
+            }),
+            (None, None, None) => None,
+            _ => return Err("only one of --kickstart, --preseed and --autoinstall can be given".to_string()),
+        };
+        if has("meta-data") && !matches!(answer_file, Some(AnswerFile::Autoinstall { .. })) {
+            return Err("--meta-data needs --autoinstall".to_string());
+        }
+        if answer_file.is_some() && !matches!(mode, WriteMode::Linux { .. }) {
+            return Err("answer files (--kickstart, --preseed, --autoinstall) only apply to Linux installer images".to_string());
+        }
+
+        let data_partition = match (number("data-partition")?, value("data-fs")?) {
+            (Some(size_mb), filesystem) => {
+                let filesystem = match filesystem {
+                    None => DataFilesystem::default(),
+                    Some(name) => DataFilesystem::from_name(name).ok_or_else(|| format!("unknown data partition filesystem '{}' (exfat or fat32)", name))?,
+                };
+                let config = DataPartitionConfig { size_mb, filesystem };
+                validate_data_partition(&config)?;
+                Some(config)
+            }
+            (None, Some(_)) => return Err("--data-fs needs --data-partition".to_string()),
+            (None, None) => None,
+        };
+        if data_partition.is_some() && !matches!(mode, WriteMode::Linux { .. }) {
+            return Err("--data-partition only applies to Linux images".to_string());
+        }
+
+        let media_label = match (value("name")?, value("description")?) {
+            (Some(name), description) => Some(MediaLabel {
+                name: name.to_string(),
+                description: description.unwrap_or_default().to_string(),
+                image: None,
+            }),
+            (None, Some(_)) => return Err("--description needs --name".to_string()),
+            (None, None) => None,
+        };
+        let expected_sha256 = value("sha256")?
+            .map(|hash| normalize_sha256(hash).map_err(|e| e.to_string()))
+            .transpose()?;
+        let expected_size = value("expected-size")?.map(str::to_string);
+        if let Some(size) = &expected_size {
+            parse_expected_size(size).map_err(|e| e.to_string())?;
+        }
+        Ok(WriteJob::new(*iso_path, *device, mode)
+            .with_expected_sha256(expected_sha256)
+            .with_expected_size(expected_size)
+            .with_max_speed(number("max-speed")?)
+            .with_answer_file(answer_file)
+            .with_data_partition(data_partition)
+            .with_thorough_flush(has("thorough-flush"))
+            .with_overwrite_multiboot(has("overwrite-multiboot"))
+            .with_checksum_manifest(has("manifest"))
+            .with_verify_boot(has("verify-boot"))
+            .with_copy_strategy(if has("rewrite") { CopyStrategy::Rewrite } else { CopyStrategy::FirstWrite })
+            .with_media_label(media_label))
+    }
+}
+
+/// Everything a job will do to the device, summarized for the confirmation dialog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WritePlan {
+    pub device: String,
+    /// Device capacity in bytes, when it could be read
+    pub device_bytes: Option<u64>,
+    /// Distribution name and version when known, else the image file name
+    pub image_label: String,
+    pub image_bytes: u64,
+    /// Persistence partition created after the write: (size in MB, filesystem label)
+    pub persistence: Option<(u64, String)>,
+    /// Extra remark shown below the plan (e.g. a degraded USB link)
+    pub note: Option<String>,
+}
+
+impl WritePlan {
+    /// Space left unallocated once the image and persistence are on the device
+    pub fn free_bytes_after(&self) -> Option<u64> {
+        let persistence_bytes = self.persistence.as_ref().map_or(0, |(size_mb, _)| size_mb * 1024 * 1024);
+        self.device_bytes
+            .map(|device| device.saturating_sub(self.image_bytes).saturating_sub(persistence_bytes))
+    }
+
+    /// One-sentence description, e.g. "Write Ubuntu 24.04 (3.4 GB) to /dev/sdb, then create
+    /// 2.8 GB ext4 persistence (label: writable), leaving 0.9 GB free."
+    pub fn summary(&self) -> String {
+        let mut text = format!("Write {} ({}) to {}", self.image_label, format_gb(self.image_bytes), self.device);
+        match (&self.persistence, self.free_bytes_after()) {
+            (Some((size_mb, label)), free) => {
+                text.push_str(&format!(
+                    ", then create {} ext4 persistence (label: {})",
+                    format_gb(size_mb * 1024 * 1024),
+                    label
+                ));
+                if let Some(free) = free {
+                    text.push_str(&format!(", leaving {} free", format_gb(free)));
+                }
+            }
+            (None, _) => {
+                if let Some(device) = self.device_bytes {
+                    text.push_str(&format!(" ({})", format_gb(device)));
+                }
+            }
+        }
+        text.push('.');
+        text
+    }
+}
+
+/// `cli_helper` name of a partition table type
+fn table_name(table: PartitionTableType) -> &'static str {
+    match table {
+        PartitionTableType::Gpt => "gpt",
+        PartitionTableType::Mbr => "mbr",
+    }
+}
+
+/// Decimal gigabytes with one decimal, the unit USB sticks are sold in
+fn format_gb(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / 1e9)
+}
+
+/// Bypass flags and their `cli_helper` switches
+pub const BYPASS_OPTIONS: [(UnattendFlags, &str); 3] = [
+    (UnattendFlags::BYPASS_TPM, "--bypass-tpm"),
+    (UnattendFlags::BYPASS_SECURE_BOOT, "--bypass-secure-boot"),
+    (UnattendFlags::BYPASS_RAM, "--bypass-ram"),
+];
+
+/// Quote an argument for POSIX shells when it contains anything beyond safe characters
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_write_notice_names_what_was_touched() {
+        let job = WriteJob::new("/tmp/distro.iso", "/dev/sdz", WriteMode::RawImage);
+        let notice = job.partial_write_notice();
+        assert!(notice.starts_with("/dev/sdz may be partially written"));
+        assert!(notice.contains("Run the write again"));
+        let partition = job.with_device("/dev/nvme1n1");
+        let partition = WriteJob { mode: WriteMode::RawPartition { number: 2 }, ..partition };
+        assert!(partition.partial_write_notice().starts_with("Partition 2 of /dev/nvme1n1"));
+    }
+
+    #[test]
+    fn raw_linux_job_has_no_persistence() {
+        let job = WriteJob::new(
+            "/tmp/distro.iso",
+            "/dev/sdz",
+            WriteMode::Linux { mode: LinuxWriteMode::RawDd, fix_protective_mbr: true },
+        );
+        assert!(job.is_raw_dd());
+        let options = job.linux_options().unwrap();
+        assert!(options.persistence.is_none());
+        assert!(options.fix_protective_mbr);
+        assert!(options.max_speed_mbps.is_none());
+    }
+
+    #[test]
+    fn persistent_linux_job_carries_config() {
+        let job = WriteJob::new(
+            "/tmp/distro.iso",
+            "/dev/sdz",
+            WriteMode::Linux {
+                mode: LinuxWriteMode::Persistent(PersistenceConfig { enabled: true, ..Default::default() }),
+                fix_protective_mbr: false,
+            },
+        );
+        assert!(!job.is_raw_dd());
+        assert!(job.linux_options().unwrap().persistence.is_some());
+        assert!(!job.can_pause());
+        let capped = job.clone().with_max_speed(Some(15));
+        assert_eq!(capped.linux_options().unwrap().max_speed_mbps, Some(15));
+        assert!(capped.can_pause());
+        assert!(capped.with_max_speed(Some(0)).max_speed_mbps.is_none());
+        assert!(job.describe_mode().contains("persistence"));
+    }
+
+    #[test]
+    fn forced_override_wins_over_detection() {
+        assert_eq!(OsOverride::Auto.resolve(Some(true)), Some(ImageKind::Windows));
+        assert_eq!(OsOverride::Auto.resolve(None), None);
+        assert_eq!(OsOverride::ForceWindows.resolve(Some(false)), Some(ImageKind::Windows));
+        assert_eq!(OsOverride::ForceLinux.resolve(Some(true)), Some(ImageKind::Linux));
+        assert_eq!(OsOverride::from_index(3).resolve(None), Some(ImageKind::Raw));
+    }
+
+    #[test]
+    fn reports_mode_contradicting_detection() {
+        let linux = WriteJob::new(
+            "/tmp/win.iso",
+            "/dev/sdz",
+            WriteMode::Linux { mode: LinuxWriteMode::RawDd, fix_protective_mbr: false },
+        );
+        assert!(linux.detection_conflict(Some(true)).is_some());
+        assert!(linux.detection_conflict(Some(false)).is_none());
+        assert!(linux.detection_conflict(None).is_none());
+
+        let windows = WriteJob::new("/tmp/x.iso", "/dev/sdz", WriteMode::Windows {
+            bypass: None,
+            cluster_bytes: 4096,
+            fat32_sectors_per_cluster: None,
+            oem_folder: None,
+            esp_mb: None,
+            exclude: Vec::new(),
+            boot_test: false,
+            legacy: false,
+            tool_efi: None,
+        });
+        assert_eq!(windows.windows_options().unwrap().cluster_bytes, Some(4096));
+        assert!(linux.windows_options().is_none());
+        let staged = windows.with_device("/dev/sdy").with_staged_iso(Some(PathBuf::from("/mnt/tmp/staged")));
+        assert_eq!(staged.windows_options().unwrap().staged_iso, Some(PathBuf::from("/mnt/tmp/staged")));
+        assert!(windows.detection_conflict(Some(false)).is_some());
+        let raw = WriteJob::new("/tmp/win.iso", "/dev/sdz", WriteMode::RawImage);
+        assert!(raw.detection_conflict(Some(true)).is_none());
+    }
+
+    #[test]
+    fn cli_args_round_trip() {
+        let persistent = WriteJob::new(
+            "/home/me/My ISOs/fedora.iso",
+            "/dev/sdz",
+            WriteMode::Linux {
+                mode: LinuxWriteMode::Persistent(PersistenceConfig {
+                    enabled: true,
+                    size_mb: 8192,
+                    persistence_type: PersistenceType::OverlayFS,
+                    partition_table: PartitionTableType::Mbr,
+                    start_sector: Some(6_402_048),
+                    kernel_params: vec!["toram".to_string()],
+                    remount: RemountPolicy::Always,
+                    reserved_percent: 1,
+                    ..Default::default()
+                }),
+                fix_protective_mbr: true,
+            },
+        )
+        .with_expected_sha256(Some("a".repeat(64)))
+        .with_expected_size(Some("4.89GB".to_string()))
+        .with_max_speed(Some(20))
+        .with_answer_file(Some(AnswerFile::Kickstart("/home/me/ks.cfg".to_string())))
+        .with_data_partition(Some(DataPartitionConfig { size_mb: 8192, filesystem: DataFilesystem::Fat32 }))
+        .with_thorough_flush(true)
+        .with_checksum_manifest(true)
+        .with_verify_boot(true)
+        .with_media_label(Some(MediaLabel {
+            name: "Fedora rescue".to_string(),
+            description: "Workstation 40, 8 GB persistence".to_string(),
+            image: None,
+        }));
+        let preseeded = WriteJob::new(
+            "/tmp/debian.iso",
+            "/dev/sdz",
+            WriteMode::Linux { mode: LinuxWriteMode::RawDd, fix_protective_mbr: false },
+        )
+        .with_answer_file(Some(AnswerFile::Preseed("/tmp/preseed.cfg".to_string())));
+        let autoinstall = WriteJob::new(
+            "/tmp/ubuntu-server.iso",
+            "/dev/sdz",
+            WriteMode::Linux { mode: LinuxWriteMode::RawDd, fix_protective_mbr: false },
+        )
+        .with_answer_file(Some(AnswerFile::Autoinstall {
+            user_data: "/tmp/user-data".to_string(),
+            meta_data: Some("/tmp/meta-data".to_string()),
+        }));
+        let windows = WriteJob::new(
+            "/tmp/win.iso",
+            "/dev/sdz",
+            WriteMode::Windows {
+                bypass: Some(UnattendFlags::BYPASS_TPM | UnattendFlags::BYPASS_RAM),
+                cluster_bytes: 65536,
+                fat32_sectors_per_cluster: Some(8),
+                oem_folder: Some("/home/me/$OEM$".to_string()),
+                esp_mb: Some(1024),
+                exclude: vec!["sources/??-??/".to_string(), "support".to_string()],
+                boot_test: true,
+                legacy: false,
+                tool_efi: Some("/home/me/mt86plus.efi".to_string()),
+            },
+        )
+        .with_copy_strategy(CopyStrategy::Rewrite);
+        let legacy_args: Vec<String> = ["/tmp/win7.iso", "/dev/sdz", "--type=windows", "--legacy"].iter().map(|s| s.to_string()).collect();
+        let legacy = WriteJob::from_cli_args(&legacy_args, |_| panic!("type is explicit")).unwrap();
+        assert!(legacy.windows_options().unwrap().legacy);
+        assert_eq!(persistent.media_label.as_ref().and_then(|label| label.image.as_deref()), Some("fedora.iso"));
+        let restore = WriteJob::new("/tmp/backup.img.gz", "/dev/sdz", WriteMode::RestoreImage);
+        assert_eq!(restore.with_device("/dev/sdy").to_cli_args()[1], "/dev/sdy");
+        let into_partition = WriteJob::new("/tmp/recovery.img", "/dev/nvme0n1", WriteMode::RawPartition { number: 4 });
+        assert_eq!(into_partition.target(), "/dev/nvme0n1p4");
+        let raw = WriteJob::new("/tmp/x.img", "/dev/sdz", WriteMode::RawImage).with_overwrite_multiboot(true);
+        let extracted = WriteJob::new(
+            "/tmp/fedora.iso",
+            "/dev/sdz",
+            WriteMode::Linux {
+                mode: LinuxWriteMode::Extracted {
+                    table: PartitionTableType::Mbr,
+                    persistence: Some(PersistenceConfig {
+                        enabled: true,
+                        partition_table: PartitionTableType::Mbr,
+                        ..Default::default()
+                    }),
+                },
+                fix_protective_mbr: false,
+            },
+        );
+        let options = extracted.linux_options().unwrap();
+        assert_eq!(options.repartition, Some(PartitionTableType::Mbr));
+        assert!(options.persistence.is_some());
+        for job in [persistent, preseeded, autoinstall, windows, legacy, restore, raw, into_partition, extracted] {
+            let args = job.to_cli_args();
+            let parsed = WriteJob::from_cli_args(&args, |_| panic!("type is explicit")).unwrap();
+            assert_eq!(parsed.to_cli_args(), args);
+        }
+    }
+
+    #[test]
+    fn cli_args_fall_back_to_detection_and_reject_unknown_options() {
+        let args: Vec<String> = ["/tmp/win.iso", "/dev/sdz", "--bypass-tpm"].iter().map(|s| s.to_string()).collect();
+        let job = WriteJob::from_cli_args(&args, |_| Some(true)).unwrap();
+        assert!(matches!(job.mode, WriteMode::Windows { bypass: Some(_), cluster_bytes: 4096, fat32_sectors_per_cluster: None, oem_folder: None, esp_mb: None, .. }));
+
+        let args: Vec<String> = ["/tmp/backup.IMG.gz", "/dev/sdz"].iter().map(|s| s.to_string()).collect();
+        let job = WriteJob::from_cli_args(&args, |_| panic!("disk images skip detection")).unwrap();
+        assert!(matches!(job.mode, WriteMode::RestoreImage));
+        assert!(!is_disk_image("/tmp/distro.iso"));
+
+        let legacy_with_esp: Vec<String> = ["/tmp/win7.iso", "/dev/sdz", "--legacy", "--esp-size=1024"].iter().map(|s| s.to_string()).collect();
+        assert!(WriteJob::from_cli_args(&legacy_with_esp, |_| Some(true)).is_err());
+
+        let kickstart_on_windows: Vec<String> = ["/tmp/win.iso", "/dev/sdz", "--kickstart=/tmp/ks.cfg"].iter().map(|s| s.to_string()).collect();
+        assert!(WriteJob::from_cli_args(&kickstart_on_windows, |_| Some(true)).is_err());
+        let data_on_windows: Vec<String> = ["/tmp/win.iso", "/dev/sdz", "--data-partition=4096"].iter().map(|s| s.to_string()).collect();
+        assert!(WriteJob::from_cli_args(&data_on_windows, |_| Some(true)).is_err());
+        // A lone data partition must not fall back to the plain dd write, which never adds it
+        let data_only: Vec<String> = ["/tmp/x.iso", "/dev/sdz", "--type=linux", "--data-partition=4096"].iter().map(|s| s.to_string()).collect();
+        let data_job = WriteJob::from_cli_args(&data_only, |_| None).unwrap();
+        assert!(data_job.linux_options().unwrap().needs_extra_steps());
+
+        let partition_on_linux: Vec<String> = ["/tmp/x.iso", "/dev/sdz", "--type=linux", "--partition=2"].iter().map(|s| s.to_string()).collect();
+        assert!(WriteJob::from_cli_args(&partition_on_linux, |_| None).is_err());
+        let partition_zero: Vec<String> = ["/tmp/x.img", "/dev/sdz", "--type=raw", "--partition=0"].iter().map(|s| s.to_string()).collect();
+        assert!(WriteJob::from_cli_args(&partition_zero, |_| None).is_err());
+
+        let bad: Vec<String> = ["/tmp/x.iso", "/dev/sdz", "--frobnicate"].iter().map(|s| s.to_string()).collect();
+        assert!(WriteJob::from_cli_args(&bad, |_| None).is_err());
+    }
+
+    #[test]
+    fn plan_summarizes_persistence_and_remaining_space() {
+        let job = WriteJob::new(
+            "/tmp/ubuntu.iso",
+            "/dev/sdb",
+            WriteMode::Linux {
+                mode: LinuxWriteMode::Persistent(PersistenceConfig {
+                    enabled: true,
+                    size_mb: 2670,
+                    label: "writable".to_string(),
+                    ..Default::default()
+                }),
+                fix_protective_mbr: false,
+            },
+        );
+        let plan = job.plan("Ubuntu 24.04", 3_400_000_000, Some(7_100_000_000));
+        assert_eq!(
+            plan.summary(),
+            "Write Ubuntu 24.04 (3.4 GB) to /dev/sdb, then create 2.8 GB ext4 persistence (label: writable), leaving 0.9 GB free."
+        );
+
+        let raw = WriteJob::new("/tmp/x.img", "/dev/sdb", WriteMode::RawImage).plan("x.img", 1_000_000_000, None);
+        assert_eq!(raw.summary(), "Write x.img (1.0 GB) to /dev/sdb.");
+        assert_eq!(raw.free_bytes_after(), None);
+
+        let partition = WriteJob::new("/tmp/x.img", "/dev/sdb", WriteMode::RawPartition { number: 3 })
+            .plan("x.img", 1_000_000_000, Some(2_000_000_000));
+        assert_eq!(partition.summary(), "Write x.img (1.0 GB) to /dev/sdb3 (2.0 GB).");
+    }
+
+    #[test]
+    fn quotes_arguments_for_the_shell() {
+        let job = WriteJob::new("/tmp/it's here.iso", "/dev/sdz", WriteMode::RawImage);
+        assert_eq!(job.to_cli_command(), "cli_helper '/tmp/it'\\''s here.iso' /dev/sdz --type=raw");
+    }
+}
@@ -0,0 +1,526 @@
+//! Unattended installs: copy a user-provided answer file (Fedora/RHEL kickstart, Debian preseed,
+//! Ubuntu autoinstall user-data) onto written installer media and point the installer at it.
+
+use crate::error::{UsbCreatorError, UsbCreatorResult};
+use crate::flows::outcome::{record_warning, Warning, WarningKind};
+use crate::flows::linux_persistence::{
+    append_partition, build_partition_path, create_temp_dir, detect_bootloader_configs, inject_kernel_params,
+    with_mounted_iso, Bootloader, PartitionTableType, RemountPolicy,
+};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+/// Name of the kickstart file at the root of the boot partition
+pub const KICKSTART_FILE_NAME: &str = "ks.cfg";
+/// Name d-i looks for at the root of its initrd
+const PRESEED_FILE_NAME: &str = "preseed.cfg";
+/// cpio archive holding the preseed, loaded by GRUB as an extra initrd
+const PRESEED_ARCHIVE_NAME: &str = "preseed.cpio";
+/// d-i reads /preseed.cfg from the initrd on its own; these make it skip every question the
+/// preseed answers. `preseed/file=/cdrom/...` is not an option: on written hybrid media /cdrom
+/// is the read-only ISO9660 partition.
+const PRESEED_KERNEL_PARAMS: [&str; 2] = ["auto=true", "priority=critical"];
+/// Filesystem label cloud-init's NoCloud datasource looks for
+const CIDATA_LABEL: &str = "CIDATA";
+/// Size of the NoCloud partition; user-data and meta-data are a few KB
+const CIDATA_SIZE_MB: u64 = 32;
+/// `autoinstall` skips Subiquity's confirmation prompt; `ds=nocloud` limits cloud-init to the
+/// CIDATA partition
+const AUTOINSTALL_KERNEL_PARAMS: [&str; 2] = ["autoinstall", "ds=nocloud"];
+/// Directories holding the d-i kernel and initrd, per architecture
+const DEBIAN_INSTALLER_DIRS: &[&str] = &["install.amd", "install.a64", "install.386", "install"];
+/// Answer files are plain text; anything larger is almost certainly the wrong file and
+/// would not fit in the slack of the installer's EFI partition anyway
+const MAX_ANSWER_FILE_BYTES: u64 = 512 * 1024;
+
+/// Installer on a Linux ISO, which decides the answer file format it reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallerFamily {
+    /// Fedora/RHEL Anaconda (kickstart)
+    Anaconda,
+    /// Debian installer, also used by older Ubuntu alternate/server images (preseed)
+    DebianInstaller,
+    /// Ubuntu 20.04+ Subiquity (cloud-init autoinstall)
+    Subiquity,
+}
+
+impl InstallerFamily {
+    pub fn description(self) -> &'static str {
+        match self {
+            InstallerFamily::Anaconda => "Fedora/RHEL installer",
+            InstallerFamily::DebianInstaller => "Debian installer",
+            InstallerFamily::Subiquity => "Ubuntu Server installer (autoinstall)",
+        }
+    }
+}
+
+/// Identify the installer on an ISO; None for live images and unknown layouts
+pub fn detect_installer_family(iso_path: &str) -> Option<InstallerFamily> {
+    with_mounted_iso(iso_path, |root| Ok(installer_family_for_root(root))).ok().flatten()
+}
+
+fn installer_family_for_root(root: &Path) -> Option<InstallerFamily> {
+    if root.join("images").join("install.img").is_file() {
+        return Some(InstallerFamily::Anaconda);
+    }
+    if debian_installer_dir(root).is_some() {
+        return Some(InstallerFamily::DebianInstaller);
+    }
+    let casper = root.join("casper");
+    if casper.join("installer.squashfs").is_file() || casper.join("install-sources.yaml").is_file() {
+        return Some(InstallerFamily::Subiquity);
+    }
+    None
+}
+
+/// Directory holding the d-i `vmlinuz` and `initrd.gz`, relative to the ISO root
+fn debian_installer_dir(root: &Path) -> Option<&'static str> {
+    DEBIAN_INSTALLER_DIRS
+        .iter()
+        .copied()
+        .find(|dir| root.join(dir).join("vmlinuz").is_file() && root.join(dir).join("initrd.gz").is_file())
+}
+
+/// User-provided answer file for an unattended install
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnswerFile {
+    /// Fedora/RHEL kickstart, copied as ks.cfg and passed with `inst.ks=`
+    Kickstart(String),
+    /// Debian preseed, packed into an extra initrd loaded by a dedicated GRUB entry
+    Preseed(String),
+    /// Ubuntu cloud-init autoinstall, written to a NoCloud (CIDATA) partition. Without an
+    /// explicit meta-data file, a `meta-data` next to the user-data is used, else an empty one.
+    Autoinstall { user_data: String, meta_data: Option<String> },
+}
+
+impl AnswerFile {
+    /// Answer file of the format `family` reads, None when it has no supported format
+    pub fn for_family(family: InstallerFamily, path: impl Into<String>) -> Option<Self> {
+        match family {
+            InstallerFamily::Anaconda => Some(AnswerFile::Kickstart(path.into())),
+            InstallerFamily::DebianInstaller => Some(AnswerFile::Preseed(path.into())),
+            InstallerFamily::Subiquity => Some(AnswerFile::Autoinstall { user_data: path.into(), meta_data: None }),
+        }
+    }
+
+    /// The answer file itself (the user-data for autoinstall)
+    pub fn path(&self) -> &str {
+        match self {
+            AnswerFile::Kickstart(path) | AnswerFile::Preseed(path) => path,
+            AnswerFile::Autoinstall { user_data, .. } => user_data,
+        }
+    }
+
+    /// Installer that reads this kind of answer file
+    pub fn family(&self) -> InstallerFamily {
+        match self {
+            AnswerFile::Kickstart(_) => InstallerFamily::Anaconda,
+            AnswerFile::Preseed(_) => InstallerFamily::DebianInstaller,
+            AnswerFile::Autoinstall { .. } => InstallerFamily::Subiquity,
+        }
+    }
+
+    /// Human-readable kind for logs and errors
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AnswerFile::Kickstart(_) => "Kickstart",
+            AnswerFile::Preseed(_) => "Preseed",
+            AnswerFile::Autoinstall { .. } => "Autoinstall",
+        }
+    }
+
+    /// meta-data file written next to the user-data, None for an empty one
+    fn meta_data_path(&self) -> Option<String> {
+        let AnswerFile::Autoinstall { user_data, meta_data } = self else {
+            return None;
+        };
+        meta_data.clone().or_else(|| {
+            let sibling = Path::new(user_data).with_file_name("meta-data");
+            sibling.is_file().then(|| sibling.to_string_lossy().to_string())
+        })
+    }
+}
+
+/// Check that `path` is a readable, reasonably sized text file
+pub fn validate_answer_file(path: &str) -> UsbCreatorResult<()> {
+    let metadata = fs::metadata(path)
+        .map_err(|e| UsbCreatorError::validation_error(format!("Answer file {}: {}", path, e)))?;
+    if !metadata.is_file() {
+        return Err(UsbCreatorError::validation_error(format!("Answer file {} is not a regular file", path)));
+    }
+    if metadata.len() == 0 {
+        return Err(UsbCreatorError::validation_error(format!("Answer file {} is empty", path)));
+    }
+    if metadata.len() > MAX_ANSWER_FILE_BYTES {
+        return Err(UsbCreatorError::validation_error(format!(
+            "Answer file {} is {} KB; answer files larger than {} KB are not supported",
+            path,
+            metadata.len() / 1024,
+            MAX_ANSWER_FILE_BYTES / 1024
+        )));
+    }
+    Ok(())
+}
+
+/// Check the answer file (and, for autoinstall, that its YAML parses) before anything is written
+pub fn validate_answer(answer: &AnswerFile) -> UsbCreatorResult<()> {
+    validate_answer_file(answer.path())?;
+    if let AnswerFile::Autoinstall { user_data, .. } = answer {
+        let read = |path: &str| {
+            fs::read_to_string(path).map_err(|e| UsbCreatorError::validation_error(format!("Cannot read {}: {}", path, e)))
+        };
+        let meta_data = match answer.meta_data_path() {
+            Some(path) => read(&path)?,
+            None => String::new(),
+        };
+        validate_autoinstall_yaml(&read(user_data)?, &meta_data)?;
+    }
+    Ok(())
+}
+
+/// user-data must be a YAML mapping with an `autoinstall` section; meta-data a mapping or empty
+pub fn validate_autoinstall_yaml(user_data: &str, meta_data: &str) -> UsbCreatorResult<()> {
+    let parsed: serde_yaml::Value = serde_yaml::from_str(user_data)
+        .map_err(|e| UsbCreatorError::validation_error(format!("user-data is not valid YAML: {}", e)))?;
+    if parsed.get("autoinstall").is_none_or(|section| !section.is_mapping()) {
+        return Err(UsbCreatorError::validation_error(
+            "user-data has no `autoinstall:` section; Subiquity would ignore it",
+        ));
+    }
+    let meta: serde_yaml::Value = serde_yaml::from_str(meta_data)
+        .map_err(|e| UsbCreatorError::validation_error(format!("meta-data is not valid YAML: {}", e)))?;
+    if !meta.is_null() && !meta.is_mapping() {
+        return Err(UsbCreatorError::validation_error("meta-data must be a YAML mapping (or empty)"));
+    }
+    Ok(())
+}
+
+/// Check the answer file and that `iso_path` holds the installer that reads it
+pub fn validate_for_iso(answer: &AnswerFile, iso_path: &str) -> UsbCreatorResult<()> {
+    validate_answer(answer)?;
+    match detect_installer_family(iso_path) {
+        Some(family) if family == answer.family() => Ok(()),
+        Some(InstallerFamily::Subiquity) => Err(UsbCreatorError::validation_error(format!(
+            "{} uses cloud-init autoinstall; {} files are not read by this installer",
+            iso_path,
+            answer.kind().to_lowercase()
+        ))),
+        _ => Err(UsbCreatorError::validation_error(format!(
+            "{} files need a {} ISO; {} is not one",
+            answer.kind(),
+            answer.family().description(),
+            iso_path
+        ))),
+    }
+}
+
+/// Kernel parameter telling Anaconda to load the kickstart from the partition with `uuid`
+pub fn kickstart_kernel_param(uuid: &str) -> String {
+    format!("inst.ks=hd:UUID={}:/{}", uuid, KICKSTART_FILE_NAME)
+}
+
+/// Put the answer file on a freshly written installer and make the boot menu use it. Kickstart
+/// and preseed go to the writable (FAT) boot partition; the ISO9660 partition is read-only, so
+/// legacy BIOS boot keeps the interactive installer. Autoinstall gets its own CIDATA partition.
+pub fn install_answer_file(
+    usb_device: &str,
+    iso_path: &str,
+    answer: &AnswerFile,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
+) -> UsbCreatorResult<()> {
+    validate_answer(answer)?;
+    let _ = Command::new("sync").status();
+
+    match answer {
+        AnswerFile::Kickstart(path) => {
+            let (partition, uuid) = find_fat_partition(usb_device)?;
+            with_mounted_partition(&partition, |root| {
+                fs::copy(path, root.join(KICKSTART_FILE_NAME))
+                    .map_err(|e| UsbCreatorError::Io(e, format!("Failed to copy the kickstart file to {}", partition)))
+            })?;
+            let _ = writeln!(log, "[AUTOINSTALL] Copied {} to {} as /{}", path, partition, KICKSTART_FILE_NAME);
+            let param = kickstart_kernel_param(&uuid);
+            if inject_kernel_params(usb_device, std::slice::from_ref(&param)) > 0 {
+                let _ = writeln!(log, "[AUTOINSTALL] Added {} to the UEFI boot entries", param);
+            } else {
+                let warning = Warning::new(
+                    WarningKind::AnswerFile,
+                    format!("No boot entry could be given {}; the installer will not load the kickstart", param),
+                );
+                record_warning(log, warnings, warning)?;
+            }
+        }
+        AnswerFile::Preseed(path) => {
+            let (partition, uuid) = find_fat_partition(usb_device)?;
+            let kernel_dir = with_mounted_iso(iso_path, |root| Ok(debian_installer_dir(root)))?.ok_or_else(|| {
+                UsbCreatorError::validation_error(format!("No Debian installer kernel found on {}", iso_path))
+            })?;
+            let preseed = fs::read(path).map_err(|e| UsbCreatorError::Io(e, format!("Failed to read {}", path)))?;
+            let archive = cpio_archive(PRESEED_FILE_NAME, &preseed);
+            with_mounted_partition(&partition, |root| {
+                fs::write(root.join(PRESEED_ARCHIVE_NAME), &archive)
+                    .map_err(|e| UsbCreatorError::Io(e, format!("Failed to copy the preseed to {}", partition)))?;
+                let grub_configs: Vec<_> = detect_bootloader_configs(root)
+                    .into_iter()
+                    .filter(|(bootloader, _)| *bootloader == Bootloader::Grub)
+                    .map(|(_, path)| path)
+                    .collect();
+                if grub_configs.is_empty() {
+                    return Err(UsbCreatorError::validation_error(format!(
+                        "No GRUB config on {}; the preseed boot entry could not be added",
+                        partition
+                    )));
+                }
+                let entry = preseed_menu_entry(kernel_dir, &uuid);
+                for config in grub_configs {
+                    let contents = fs::read_to_string(&config)?;
+                    if let Some(updated) = insert_menu_entry(&contents, &entry) {
+                        fs::write(&config, updated)?;
+                    }
+                }
+                Ok(())
+            })?;
+            let _ = writeln!(
+                log,
+                "[AUTOINSTALL] Added an \"Automated install (preseed)\" boot entry using {} ({})",
+                path,
+                PRESEED_KERNEL_PARAMS.join(" ")
+            );
+        }
+        AnswerFile::Autoinstall { user_data, .. } => {
+            let meta_data = match answer.meta_data_path() {
+                Some(path) => fs::read(&path).map_err(|e| UsbCreatorError::Io(e, format!("Failed to read {}", path)))?,
+                None => Vec::new(),
+            };
+            let partition = append_partition(usb_device, PartitionTableType::Gpt, CIDATA_SIZE_MB, None, CIDATA_LABEL, RemountPolicy::default(), warnings)?;
+            let output = Command::new("mkfs.vfat")
+                .args(["-n", CIDATA_LABEL, &partition])
+                .output()
+                .map_err(|e| UsbCreatorError::Io(e, "Failed to spawn mkfs.vfat".to_string()))?;
+            if !output.status.success() {
+                return Err(UsbCreatorError::command_failed("mkfs.vfat", String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            with_mounted_partition(&partition, |root| {
+                fs::copy(user_data, root.join("user-data"))?;
+                fs::write(root.join("meta-data"), &meta_data)?;
+                Ok(())
+            })?;
+            let _ = writeln!(log, "[AUTOINSTALL] Wrote user-data and meta-data to {} ({})", partition, CIDATA_LABEL);
+            let params: Vec<String> = AUTOINSTALL_KERNEL_PARAMS.iter().map(|param| param.to_string()).collect();
+            if inject_kernel_params(usb_device, &params) > 0 {
+                let _ = writeln!(log, "[AUTOINSTALL] Added {} to the boot entries", params.join(" "));
+            } else {
+                let _ = writeln!(
+                    log,
+                    "[AUTOINSTALL] Boot menu is read-only; the installer will ask for confirmation before autoinstalling"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// First FAT partition among the two an installer ISO lays out, with its filesystem UUID
+fn find_fat_partition(usb_device: &str) -> UsbCreatorResult<(String, String)> {
+    let partition = [1, 2]
+        .into_iter()
+        .map(|number| build_partition_path(usb_device, number))
+        .find(|part| blkid_value(part, "TYPE").as_deref() == Some("vfat"))
+        .ok_or_else(|| {
+            UsbCreatorError::validation_error(format!(
+                "No writable FAT boot partition found on {} to hold the answer file",
+                usb_device
+            ))
+        })?;
+    let uuid = blkid_value(&partition, "UUID")
+        .ok_or_else(|| UsbCreatorError::validation_error(format!("Could not read the UUID of {}", partition)))?;
+    Ok((partition, uuid))
+}
+
+/// Mount `partition` read-write on a temporary directory for the duration of `update`
+fn with_mounted_partition<T>(
+    partition: &str,
+    update: impl FnOnce(&Path) -> UsbCreatorResult<T>,
+) -> UsbCreatorResult<T> {
+    let mount_dir = create_temp_dir()?;
+    let output = Command::new("mount")
+        .arg(partition)
+        .arg(mount_dir.path())
+        .output()
+        .map_err(|e| UsbCreatorError::Io(e, "Failed to spawn mount".to_string()))?;
+    if !output.status.success() {
+        return Err(UsbCreatorError::command_failed("mount", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let result = update(mount_dir.path());
+    let _ = Command::new("umount").arg(mount_dir.path()).status();
+    result
+}
+
+/// Single blkid tag of a partition, None when blkid has no value for it
+fn blkid_value(partition: &str, tag: &str) -> Option<String> {
+    let output = Command::new("blkid").args(["-o", "value", "-s", tag, partition]).output().ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(value).filter(|v| output.status.success() && !v.is_empty())
+}
+
+/// GRUB entry booting d-i from the ISO with the preseed archive from the partition `uuid`
+/// appended as a second initrd (the kernel unpacks concatenated cpio archives in order)
+fn preseed_menu_entry(kernel_dir: &str, uuid: &str) -> String {
+    format!(
+        "menuentry \"Automated install (preseed)\" {{\n\
+         \tsearch --no-floppy --file --set=root /{dir}/vmlinuz\n\
+         \tsearch --no-floppy --fs-uuid --set=answers {uuid}\n\
+         \tlinux /{dir}/vmlinuz {params} ---\n\
+         \tinitrd /{dir}/initrd.gz ($answers)/{archive}\n\
+         }}\n",
+        dir = kernel_dir,
+        uuid = uuid,
+        params = PRESEED_KERNEL_PARAMS.join(" "),
+        archive = PRESEED_ARCHIVE_NAME,
+    )
+}
+
+/// Insert `entry` ahead of the first menu entry or sourced config so it becomes the default.
+/// Returns None when the config already carries a preseed entry.
+fn insert_menu_entry(contents: &str, entry: &str) -> Option<String> {
+    if contents.contains(PRESEED_ARCHIVE_NAME) {
+        return None;
+    }
+    let mut updated = String::with_capacity(contents.len() + entry.len());
+    let mut inserted = false;
+    for line in contents.lines() {
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        if !inserted && matches!(keyword, "menuentry" | "submenu" | "source" | "configfile") {
+            updated.push_str(entry);
+            inserted = true;
+        }
+        updated.push_str(line);
+        updated.push('\n');
+    }
+    if !inserted {
+        updated.push_str(entry);
+    }
+    Some(updated)
+}
+
+/// Single-file cpio archive in the "newc" format the kernel unpacks into its initramfs
+fn cpio_archive(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut archive = Vec::with_capacity(data.len() + 512);
+    push_cpio_entry(&mut archive, 1, name, 0o100644, data);
+    push_cpio_entry(&mut archive, 0, "TRAILER!!!", 0, &[]);
+    archive
+}
+
+fn push_cpio_entry(archive: &mut Vec<u8>, inode: u32, name: &str, mode: u32, data: &[u8]) {
+    let fields = [inode, mode, 0, 0, 1, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+    archive.extend_from_slice(b"070701");
+    for field in fields {
+        archive.extend_from_slice(format!("{:08X}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    pad_to_four(archive);
+    archive.extend_from_slice(data);
+    pad_to_four(archive);
+}
+
+fn pad_to_four(archive: &mut Vec<u8>) {
+    while !archive.len().is_multiple_of(4) {
+        archive.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kickstart_param_points_at_partition_uuid() {
+        assert_eq!(kickstart_kernel_param("1A2B-3C4D"), "inst.ks=hd:UUID=1A2B-3C4D:/ks.cfg");
+    }
+
+    #[test]
+    fn answer_file_must_exist_and_have_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let ks = dir.path().join("ks.cfg");
+        let ks_path = ks.to_str().unwrap();
+        assert!(validate_answer_file(ks_path).is_err());
+        fs::write(&ks, "").unwrap();
+        assert!(validate_answer_file(ks_path).is_err());
+        fs::write(&ks, "text\nlang en_US.UTF-8\n").unwrap();
+        assert!(validate_answer_file(ks_path).is_ok());
+    }
+
+    #[test]
+    fn installer_family_is_detected_from_iso_layout() {
+        let touch = |root: &Path, file: &str| {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        };
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(installer_family_for_root(dir.path()), None);
+
+        let fedora = tempfile::tempdir().unwrap();
+        touch(fedora.path(), "images/install.img");
+        assert_eq!(installer_family_for_root(fedora.path()), Some(InstallerFamily::Anaconda));
+
+        let debian = tempfile::tempdir().unwrap();
+        touch(debian.path(), "install.amd/vmlinuz");
+        touch(debian.path(), "install.amd/initrd.gz");
+        assert_eq!(installer_family_for_root(debian.path()), Some(InstallerFamily::DebianInstaller));
+        assert_eq!(debian_installer_dir(debian.path()), Some("install.amd"));
+
+        let ubuntu = tempfile::tempdir().unwrap();
+        touch(ubuntu.path(), "casper/install-sources.yaml");
+        assert_eq!(installer_family_for_root(ubuntu.path()), Some(InstallerFamily::Subiquity));
+    }
+
+    #[test]
+    fn autoinstall_yaml_needs_an_autoinstall_section() {
+        let user_data = "#cloud-config\nautoinstall:\n  version: 1\n  identity:\n    hostname: box\n";
+        assert!(validate_autoinstall_yaml(user_data, "").is_ok());
+        assert!(validate_autoinstall_yaml(user_data, "instance-id: box-1\n").is_ok());
+        assert!(validate_autoinstall_yaml("#cloud-config\nusers: []\n", "").is_err());
+        assert!(validate_autoinstall_yaml("autoinstall: [unclosed\n", "").is_err());
+        assert!(validate_autoinstall_yaml(user_data, "- not\n- a mapping\n").is_err());
+    }
+
+    #[test]
+    fn autoinstall_picks_up_sibling_meta_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_data = dir.path().join("user-data");
+        fs::write(&user_data, "autoinstall:\n  version: 1\n").unwrap();
+        let answer = AnswerFile::for_family(InstallerFamily::Subiquity, user_data.to_str().unwrap()).unwrap();
+        assert_eq!(answer.meta_data_path(), None);
+        fs::write(dir.path().join("meta-data"), "").unwrap();
+        assert_eq!(answer.meta_data_path(), Some(dir.path().join("meta-data").to_string_lossy().to_string()));
+        assert!(validate_answer(&answer).is_ok());
+    }
+
+    #[test]
+    fn preseed_entry_goes_before_the_existing_menu_once() {
+        let stub = "search --file --set=root /.disk/id/abc\nset prefix=($root)/boot/grub\nsource $prefix/x86_64-efi/grub.cfg\n";
+        let entry = preseed_menu_entry("install.amd", "1A2B-3C4D");
+        let updated = insert_menu_entry(stub, &entry).unwrap();
+        let entry_at = updated.find("menuentry \"Automated install (preseed)\"").unwrap();
+        assert!(updated.find("set prefix").unwrap() < entry_at);
+        assert!(entry_at < updated.find("source $prefix").unwrap());
+        assert!(updated.contains("\tinitrd /install.amd/initrd.gz ($answers)/preseed.cpio\n"));
+        assert!(insert_menu_entry(&updated, &entry).is_none());
+    }
+
+    #[test]
+    fn cpio_archive_uses_newc_layout() {
+        let archive = cpio_archive("preseed.cfg", b"d-i debian-installer/locale string en_US\n");
+        assert!(archive.starts_with(b"070701"));
+        assert_eq!(&archive[110..121], b"preseed.cfg");
+        assert_eq!(archive.len() % 4, 0);
+        let text = String::from_utf8_lossy(&archive);
+        assert!(text.contains("d-i debian-installer/locale"));
+        assert!(text.contains("TRAILER!!!"));
+    }
+}
@@ -0,0 +1,151 @@
+//! Plain storage partition added after a Linux ISO is written: exFAT or FAT32 in the free space
+//! behind the ISO (and persistence), so the live stick doubles as a drive for files shared with
+//! other machines. It reuses the persistence code that grows the GPT and appends the next
+//! partition, without any union-mount setup or boot parameters.
+
+use std::io::Write;
+use std::process::Command;
+
+use crate::command::SystemRunner;
+use crate::error::{UsbCreatorError, UsbCreatorResult};
+use crate::fail_point;
+use crate::flows::linux_persistence::{append_partition, detect_partition_table_type, with_remount_context, PartitionTableType, RemountPolicy};
+use crate::flows::outcome::{record_warning, Warning, WarningKind};
+use crate::utils::missing_program_error;
+
+/// Filesystem label and GPT name of the data partition; short enough for FAT
+pub const DATA_PARTITION_LABEL: &str = "DATA";
+
+/// Smallest data partition: FAT32 needs 65525 clusters
+pub const MIN_DATA_PARTITION_MB: u64 = 64;
+
+/// Filesystem of the data partition
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DataFilesystem {
+    /// No 4 GiB file limit; Windows, macOS and current Linux read it natively
+    #[default]
+    Exfat,
+    /// Readable everywhere, including old systems, cameras and TVs; files up to 4 GiB
+    Fat32,
+}
+
+impl DataFilesystem {
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            DataFilesystem::Exfat => "exfat",
+            DataFilesystem::Fat32 => "fat32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "exfat" => Some(DataFilesystem::Exfat),
+            "fat32" | "vfat" => Some(DataFilesystem::Fat32),
+            _ => None,
+        }
+    }
+
+    /// mkfs program and arguments formatting `partition` with the data label
+    fn mkfs(self, partition: &str) -> (&'static str, Vec<&str>) {
+        match self {
+            DataFilesystem::Exfat => ("mkfs.exfat", vec!["-L", DATA_PARTITION_LABEL, partition]),
+            DataFilesystem::Fat32 => ("mkfs.vfat", vec!["-F", "32", "-n", DATA_PARTITION_LABEL, partition]),
+        }
+    }
+
+    /// Partition type other systems look for before mounting: Microsoft basic data on GPT,
+    /// exFAT/NTFS (0x07) or FAT32 LBA (0x0c) on MBR
+    fn partition_type(self, table: PartitionTableType) -> &'static str {
+        match (table, self) {
+            (PartitionTableType::Gpt, _) => "0700",
+            (PartitionTableType::Mbr, DataFilesystem::Exfat) => "7",
+            (PartitionTableType::Mbr, DataFilesystem::Fat32) => "c",
+        }
+    }
+}
+
+/// Size and filesystem of the data partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataPartitionConfig {
+    pub size_mb: u64,
+    pub filesystem: DataFilesystem,
+}
+
+/// Reject sizes too small to format
+pub fn validate_data_partition(config: &DataPartitionConfig) -> Result<(), String> {
+    if config.size_mb < MIN_DATA_PARTITION_MB {
+        return Err(format!("the data partition needs at least {} MB, got {} MB", MIN_DATA_PARTITION_MB, config.size_mb));
+    }
+    Ok(())
+}
+
+/// Run a helper, reporting its stderr when it fails
+fn run(program: &str, args: &[&str]) -> UsbCreatorResult<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| UsbCreatorError::Io(missing_program_error(program, e), format!("Failed to run {}", program)))?;
+    if !output.status.success() {
+        return Err(UsbCreatorError::command_failed(program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+/// Append the data partition after the last partition of a freshly written `usb_device` and
+/// format it. Returns the partition node.
+pub fn create_data_partition(
+    usb_device: &str,
+    config: &DataPartitionConfig,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
+) -> UsbCreatorResult<String> {
+    validate_data_partition(config).map_err(UsbCreatorError::validation_error)?;
+    writeln!(log, "Adding a {} MB {} data partition...", config.size_mb, config.filesystem.name())?;
+    fail_point::check("data_partition")?;
+    let table = detect_partition_table_type(&SystemRunner, usb_device)?;
+    let partition = append_partition(usb_device, table, config.size_mb, None, DATA_PARTITION_LABEL, RemountPolicy::default(), warnings)?;
+
+    let (program, args) = config.filesystem.mkfs(&partition);
+    writeln!(log, "Formatting {} with {}...", partition, program)?;
+    run(program, &args).map_err(|e| with_remount_context(usb_device, e))?;
+
+    // Without a data type Windows and macOS leave a Linux-typed partition unmounted
+    let number = partition
+        .strip_prefix(usb_device)
+        .map(|suffix| suffix.trim_start_matches('p'))
+        .unwrap_or_default();
+    let type_code = config.filesystem.partition_type(table);
+    let typed = match table {
+        PartitionTableType::Gpt => run("sgdisk", &["-t", &format!("{}:{}", number, type_code), usb_device]),
+        PartitionTableType::Mbr => run("sfdisk", &["--part-type", usb_device, number, type_code]),
+    };
+    if let Err(e) = typed {
+        let warning = Warning::new(
+            WarningKind::Partitioning,
+            format!("{} keeps a Linux partition type, so other systems may not mount it: {}", partition, e),
+        );
+        record_warning(log, warnings, warning)?;
+    }
+    let _ = Command::new("partprobe").arg(usb_device).status();
+    let _ = Command::new("sync").status();
+    writeln!(log, "Data partition {} ({}) ready", partition, DATA_PARTITION_LABEL)?;
+    Ok(partition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_filesystems_get_matching_partition_types() {
+        assert_eq!(DataFilesystem::from_name("exFAT"), Some(DataFilesystem::Exfat));
+        assert_eq!(DataFilesystem::from_name("fat32"), Some(DataFilesystem::Fat32));
+        assert_eq!(DataFilesystem::from_name("ntfs"), None);
+        assert_eq!(DataFilesystem::Fat32.partition_type(PartitionTableType::Mbr), "c");
+        assert_eq!(DataFilesystem::Exfat.partition_type(PartitionTableType::Gpt), "0700");
+        assert_eq!(DataFilesystem::Fat32.mkfs("/dev/sdb3").1, ["-F", "32", "-n", "DATA", "/dev/sdb3"]);
+        let tiny = DataPartitionConfig { size_mb: 16, filesystem: DataFilesystem::Fat32 };
+        assert!(validate_data_partition(&tiny).unwrap_err().contains("at least 64 MB"));
+    }
+}
@@ -0,0 +1,295 @@
+//! Linux write that replaces the ISO's own partition table: the device gets a fresh GPT or MBR
+//! table with one FAT32 partition holding the ISO's files. Only UEFI boot is set up, but the
+//! table type is the user's choice and a persistence partition can follow on either.
+
+use crate::fail_point;
+use crate::flows::linux_persistence::{detect_bootloader_configs, wait_for_block_node, PartitionTableType};
+use crate::flows::outcome::{record_warning, Warning, WarningKind};
+use crate::flows::windows_flow::{ensure_not_system_device, unmount_device_mounts};
+use crate::settings::Tuning;
+use crate::utils::{flush_with_progress, get_device_size_bytes, missing_program_error, mount_iso_readable, partition_path, with_remount_hint};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Largest file FAT32 can hold
+const FAT32_MAX_FILE_BYTES: u64 = 4 * 1024 * 1024 * 1024 - 1;
+/// Removable-media loaders in `EFI/BOOT`, one of which UEFI firmware boots
+const EFI_LOADERS: [&str; 3] = ["bootx64.efi", "bootia32.efi", "bootaa64.efi"];
+/// Room kept on the data partition beyond the ISO files when persistence follows it
+const DATA_MARGIN_MB: u64 = 256;
+/// Longest FAT volume label
+const FAT_LABEL_MAX: usize = 11;
+
+/// FAT volume label for the copied ISO: its own label when it is a valid FAT label, otherwise
+/// an uppercased, sanitized and truncated form of it ("LIVE" when nothing is left)
+pub fn fat_volume_label(iso_label: &str) -> String {
+    let label: String = iso_label
+        .trim()
+        .chars()
+        .filter(char::is_ascii)
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .take(FAT_LABEL_MAX)
+        .collect();
+    if label.trim_matches('_').is_empty() { "LIVE".to_string() } else { label }
+}
+
+/// Total size of the files under the mounted ISO `root`. Fails when a file is too large for
+/// FAT32 or when there is no removable-media EFI loader to boot.
+pub fn check_contents(root: &Path) -> Result<u64, String> {
+    fn walk(dir: &Path, total: &mut u64) -> Result<(), String> {
+        let entries = fs::read_dir(dir).map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let Ok(meta) = fs::metadata(&path) else { continue };
+            if meta.is_dir() {
+                walk(&path, total)?;
+            } else if meta.len() > FAT32_MAX_FILE_BYTES {
+                return Err(format!(
+                    "{} is {} MB, too large for FAT32; use the dd mode for this image",
+                    path.display(),
+                    meta.len() / 1_000_000
+                ));
+            } else {
+                *total += meta.len();
+            }
+        }
+        Ok(())
+    }
+    let child = |dir: &Path, name: &str| {
+        fs::read_dir(dir).ok()?.filter_map(|e| e.ok()).map(|e| e.path()).find(|path| {
+            path.file_name().is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name))
+        })
+    };
+    let has_loader = child(root, "efi")
+        .and_then(|efi| child(&efi, "boot"))
+        .is_some_and(|boot| EFI_LOADERS.iter().any(|loader| child(&boot, loader).is_some()));
+    if !has_loader {
+        return Err("The ISO has no EFI/BOOT loader, so a copied stick would not boot; use the dd mode".to_string());
+    }
+    let mut total = 0;
+    walk(root, &mut total)?;
+    Ok(total)
+}
+
+/// Size in MiB of the data partition: the whole device, or the ISO files plus 10% and
+/// `DATA_MARGIN_MB` when a persistence partition will follow
+fn data_partition_mb(contents_bytes: u64, device_bytes: u64, leave_room: bool) -> Result<Option<u64>, String> {
+    let contents_mb = contents_bytes.div_ceil(1024 * 1024);
+    let needed_mb = contents_mb + contents_mb / 10 + DATA_MARGIN_MB;
+    // 1 MiB alignment gap in front, and the backup GPT at the end
+    let device_mb = (device_bytes / (1024 * 1024)).saturating_sub(2);
+    if needed_mb > device_mb {
+        return Err(format!(
+            "The ISO files need {} MB but the device only has {} MB",
+            needed_mb, device_mb
+        ));
+    }
+    Ok(leave_room.then_some(needed_mb))
+}
+
+/// Replace whole-word occurrences of `old` with `new` (a label next to letters, digits, `-` or
+/// `_` is part of a longer name). Returns None when there is none.
+fn replace_label(contents: &str, old: &str, new: &str) -> Option<String> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    let mut out = String::with_capacity(contents.len());
+    let mut copied = 0;
+    for (index, _) in contents.match_indices(old) {
+        let before = contents[..index].chars().next_back();
+        let after = contents[index + old.len()..].chars().next();
+        if before.is_some_and(is_word) || after.is_some_and(is_word) {
+            continue;
+        }
+        out.push_str(&contents[copied..index]);
+        out.push_str(new);
+        copied = index + old.len();
+    }
+    let changed = copied > 0;
+    out.push_str(&contents[copied..]);
+    changed.then_some(out)
+}
+
+/// Point the boot configs under `root` at the new volume label (GRUB `search` lines and
+/// `CDLABEL=`/`archisolabel=` style parameters). Returns how many files changed.
+fn relabel_boot_configs(root: &Path, old: &str, new: &str) -> io::Result<usize> {
+    let mut updated = 0;
+    for (_, path) in detect_bootloader_configs(root) {
+        if let Ok(contents) = fs::read_to_string(&path)
+            && let Some(relabeled) = replace_label(&contents, old, new)
+        {
+            fs::write(&path, relabeled)?;
+            updated += 1;
+        }
+    }
+    Ok(updated)
+}
+
+/// Volume label of an ISO file, or an empty string when blkid cannot read one
+fn iso_volume_label(iso_path: &str) -> String {
+    Command::new("blkid")
+        .args(["-o", "value", "-s", "LABEL", iso_path])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Run `program` and turn a non-zero exit into an error carrying its stderr
+fn run(program: &str, args: &[&str]) -> io::Result<()> {
+    let output = Command::new(program).args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Wipe `usb_device`, create a new `table` with one FAT32 partition and copy the files of
+/// `iso_path` onto it. With `leave_room` the partition only takes what the files need, so a
+/// persistence partition can be appended afterwards.
+pub fn write_iso_contents(
+    iso_path: &str,
+    usb_device: &str,
+    table: PartitionTableType,
+    leave_room: bool,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
+) -> io::Result<()> {
+    let iso_mount = tempfile::tempdir()?;
+    mount_iso_readable(iso_path, iso_mount.path(), "loop")
+        .map_err(|e| io::Error::other(format!("Could not mount {}: {}", iso_path, e)))?;
+    let iso_root = scopeguard::guard(iso_mount.path().to_path_buf(), |dir| {
+        let _ = Command::new("umount").arg(&dir).status();
+    });
+    let contents_bytes = check_contents(&iso_root).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let data_mb = data_partition_mb(contents_bytes, get_device_size_bytes(usb_device)?, leave_room)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let iso_label = iso_volume_label(iso_path);
+    let fat_label = fat_volume_label(&iso_label);
+
+    ensure_not_system_device(usb_device, log)?;
+    unmount_device_mounts(usb_device, log)?;
+
+    let (label_type, boot_flag) = match table {
+        PartitionTableType::Gpt => ("gpt", "esp"),
+        PartitionTableType::Mbr => ("msdos", "boot"),
+    };
+    let end = data_mb.map(|mb| format!("{}MiB", mb + 1)).unwrap_or_else(|| "100%".to_string());
+    writeln!(log, "Repartitioning {} with a new {} table (the ISO's own table is not kept)...", usb_device, label_type)?;
+    fail_point::check("wipe")?;
+    run("wipefs", &["-a", usb_device])?;
+    fail_point::check("partition")?;
+    run("parted", &["-s", usb_device, "mklabel", label_type, "mkpart", "primary", "fat32", "1MiB", &end, "set", "1", boot_flag, "on"])
+        .map_err(|e| io::Error::other(with_remount_hint(usb_device, e.to_string())))?;
+    let _ = Command::new("partprobe").arg(usb_device).status();
+    let partition = partition_path(usb_device, 1);
+    wait_for_block_node(&partition, Duration::from_secs(Tuning::for_device(usb_device).partition_node_timeout_secs))
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    writeln!(log, "Formatting {} as FAT32 (label {})...", partition, fat_label)?;
+    fail_point::check("mkfs_fat32")?;
+    run("mkfs.vfat", &["-F", "32", "-n", &fat_label, &partition])
+        .map_err(|e| io::Error::other(with_remount_hint(usb_device, e.to_string())))?;
+
+    let data_mount = tempfile::tempdir()?;
+    run("mount", &[&partition, &data_mount.path().to_string_lossy()])?;
+    let data_root = scopeguard::guard(data_mount.path().to_path_buf(), |dir| {
+        let _ = Command::new("umount").arg(&dir).status();
+    });
+    writeln!(log, "Copying {} MB of ISO files...", contents_bytes / 1_000_000)?;
+    // FAT has no symlinks or permissions: copy link targets and keep only times
+    fail_point::check("copy_install")?;
+    let status = Command::new("rsync")
+        .args(["-r", "-t", "-L", "--modify-window=2", "--info=progress2"])
+        .arg(format!("{}/", iso_root.display()))
+        .arg(format!("{}/", data_root.display()))
+        .status()
+        .map_err(|e| missing_program_error("rsync", e))?;
+    if !status.success() {
+        return Err(io::Error::other(format!("rsync failed ({})", status)));
+    }
+
+    if !iso_label.is_empty() && iso_label != fat_label {
+        match relabel_boot_configs(&data_root, &iso_label, &fat_label) {
+            Ok(0) => {}
+            Ok(count) => writeln!(log, "Boot entries now look for label {} instead of {} ({} file(s))", fat_label, iso_label, count)?,
+            Err(e) => {
+                let warning = Warning::new(
+                    WarningKind::Label,
+                    format!("Boot entries still refer to label {} ({}); the live system may not find its files", iso_label, e),
+                );
+                record_warning(log, warnings, warning)?;
+            }
+        }
+    }
+    flush_with_progress(log)?;
+    drop(data_root);
+    drop(iso_root);
+    writeln!(log, "ISO files copied to {}; only UEFI boot is set up (use the dd mode for legacy BIOS)", partition)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_labels_fit_fat() {
+        assert_eq!(fat_volume_label("ARCH_202410"), "ARCH_202410");
+        assert_eq!(fat_volume_label("Fedora-WS-Live-40-1-14"), "FEDORA-WS-L");
+        assert_eq!(fat_volume_label("Ubuntu 24.04 LTS amd64"), "UBUNTU_24_0");
+        assert_eq!(fat_volume_label(" ?? "), "LIVE");
+        assert_eq!(fat_volume_label(""), "LIVE");
+    }
+
+    #[test]
+    fn contents_need_an_efi_loader_and_fat32_sized_files() {
+        let iso = tempfile::tempdir().unwrap();
+        fs::create_dir_all(iso.path().join("LiveOS")).unwrap();
+        fs::write(iso.path().join("LiveOS/squashfs.img"), vec![0u8; 1000]).unwrap();
+        assert!(check_contents(iso.path()).unwrap_err().contains("EFI/BOOT"));
+
+        fs::create_dir_all(iso.path().join("EFI/BOOT")).unwrap();
+        fs::write(iso.path().join("EFI/BOOT/BOOTX64.EFI"), vec![0u8; 24]).unwrap();
+        assert_eq!(check_contents(iso.path()).unwrap(), 1024);
+
+        let big = fs::File::create(iso.path().join("LiveOS/rootfs.img")).unwrap();
+        big.set_len(FAT32_MAX_FILE_BYTES + 1).unwrap();
+        assert!(check_contents(iso.path()).unwrap_err().contains("too large for FAT32"));
+    }
+
+    #[test]
+    fn data_partition_leaves_room_only_for_persistence() {
+        let gib = 1024 * 1024 * 1024;
+        assert_eq!(data_partition_mb(2 * gib, 16 * gib, false), Ok(None));
+        assert_eq!(data_partition_mb(2 * gib, 16 * gib, true), Ok(Some(2048 + 204 + DATA_MARGIN_MB)));
+        assert!(data_partition_mb(4 * gib, 4 * gib, false).is_err());
+    }
+
+    #[test]
+    fn labels_are_replaced_as_whole_words() {
+        let grub = "search --no-floppy --set=root -l 'Fedora-WS-Live-40'\n\
+                    linux /images/pxeboot/vmlinuz root=live:CDLABEL=Fedora-WS-Live-40 rd.live.image\n\
+                    menuentry 'Fedora-WS-Live-40-test' {}\n";
+        let relabeled = replace_label(grub, "Fedora-WS-Live-40", "FEDORA-WS-L").unwrap();
+        assert!(relabeled.contains("-l 'FEDORA-WS-L'"));
+        assert!(relabeled.contains("CDLABEL=FEDORA-WS-L rd.live.image"));
+        assert!(relabeled.contains("'Fedora-WS-Live-40-test'"));
+        assert!(replace_label("nothing here", "ARCH_2024", "ARCH").is_none());
+
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("boot/grub")).unwrap();
+        fs::write(root.path().join("boot/grub/grub.cfg"), grub).unwrap();
+        assert_eq!(relabel_boot_configs(root.path(), "Fedora-WS-Live-40", "FEDORA-WS-L").unwrap(), 1);
+        assert_eq!(relabel_boot_configs(root.path(), "Fedora-WS-Live-40", "FEDORA-WS-L").unwrap(), 0);
+    }
+}
@@ -1,8 +1,79 @@
 use crate::error::UsbCreatorError;
-use crate::flows::linux_persistence::{create_persistence_partition, validate_persistence_config, PersistenceConfig};
-use std::io::{self, Write};
+use crate::flows::image_copy::{copy_image, CopyOptions, PauseControl};
+use crate::flows::linux_extract::write_iso_contents;
+use crate::flows::linux_persistence::{create_persistence_partition_with_recovery, maybe_expand_gpt, validate_persistence_config, PartitionTableType, PersistenceConfig};
+use crate::flows::linux_data_partition::{create_data_partition, validate_data_partition, DataPartitionConfig};
+use crate::flows::linux_autoinstall::{install_answer_file, validate_for_iso, AnswerFile};
+use crate::fail_point;
+use crate::flows::outcome::{record_warning, Warning, WarningKind};
+use crate::utils::{check_iso_readable, flush_with_progress, get_device_logical_block_size, get_device_size_bytes, log_partition_geometry, resolve_device_path};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::process::Command;
 
+/// MBR partition type used by a GPT protective MBR
+const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+/// Offset of the first MBR partition entry
+const MBR_ENTRIES_OFFSET: usize = 446;
+/// Size of a single MBR partition entry
+const MBR_ENTRY_SIZE: usize = 16;
+
+/// Options for the Linux (dd) write flow
+#[derive(Debug, Clone, Default)]
+pub struct LinuxWriteOptions {
+    /// Optional persistence partition to create after the write
+    pub persistence: Option<PersistenceConfig>,
+    /// Inspect and regenerate the GPT protective MBR after dd
+    pub fix_protective_mbr: bool,
+    /// Replace the ISO's partition table with a new one of this type and copy the ISO files
+    /// instead of dd (UEFI boot only)
+    pub repartition: Option<PartitionTableType>,
+    /// Cap on the write speed in MB/s; the image is then copied by the paced Rust loop instead of dd
+    pub max_speed_mbps: Option<u64>,
+    /// Kickstart, preseed or Ubuntu autoinstall answer file to put on installer media for an
+    /// unattended install
+    pub answer_file: Option<AnswerFile>,
+    /// Pause switch for the paced copy (dd and the file copy cannot be paused)
+    pub pause: Option<PauseControl>,
+    /// Plain exFAT/FAT32 storage partition added after the ISO (and persistence)
+    pub data_partition: Option<DataPartitionConfig>,
+}
+
+impl LinuxWriteOptions {
+    /// Whether the write needs `write_iso_to_usb_with_options` rather than a plain dd (the
+    /// protective MBR repair can follow either)
+    pub fn needs_extra_steps(&self) -> bool {
+        self.persistence.is_some()
+            || self.repartition.is_some()
+            || self.max_speed_mbps.is_some()
+            || self.answer_file.is_some()
+            || self.data_partition.is_some()
+    }
+}
+
+/// Non-fatal results of a Linux write
+#[derive(Debug, Clone, Default)]
+pub struct LinuxWriteReport {
+    /// Issues the user should know about (e.g. persistence skipped)
+    pub warnings: Vec<Warning>,
+    /// Largest persistence size that would have fit, when persistence was skipped for lack of space
+    pub persistence_max_mb: Option<u64>,
+}
+
+/// State of the protective MBR found on a device after writing a hybrid ISO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectiveMbrStatus {
+    /// No GPT header present; the image uses a plain MBR layout
+    NotGpt,
+    /// A single 0xEE entry spans the whole device
+    Valid,
+    /// GPT header present but no 0xEE entry in the MBR
+    Missing,
+    /// 0xEE entry present alongside other MBR entries (hybrid MBR)
+    Hybrid,
+    /// 0xEE entry does not cover the device (typically sized to the ISO image)
+    WrongSize { recorded: u64, expected: u64 },
+}
 
 /// Write the ISO file to the USB device using dd (requires root)
 pub fn write_iso_to_usb(iso_path: &str, usb_device: &str, log: &mut dyn Write) -> io::Result<()> {
@@ -16,6 +87,26 @@ pub fn write_iso_to_usb_with_persistence(
     log: &mut dyn Write,
     persistence: Option<PersistenceConfig>,
 ) -> io::Result<()> {
+    let options = LinuxWriteOptions {
+        persistence,
+        ..Default::default()
+    };
+    write_iso_to_usb_with_options(iso_path, usb_device, &options, log).map(|_| ())
+}
+
+/// Write the ISO file to the USB device using dd with the given options.
+/// Returns non-fatal warnings (e.g. persistence skipped) collected during the write.
+pub fn write_iso_to_usb_with_options(
+    iso_path: &str,
+    usb_device: &str,
+    options: &LinuxWriteOptions,
+    log: &mut dyn Write,
+) -> io::Result<LinuxWriteReport> {
+    let usb_device = &resolve_device_path(usb_device);
+    crate::quirks::log_device_quirk(usb_device, log)?;
+    check_iso_readable(iso_path)?;
+    let persistence = options.persistence.clone();
+    let mut report = LinuxWriteReport::default();
     if persistence.is_some() {
         writeln!(log, "[PERSISTENCE] Persistence requested. Will add partition after write.")?;
     } else {
@@ -25,28 +116,195 @@ pub fn write_iso_to_usb_with_persistence(
     if let Some(config) = &persistence {
         validate_persistence_config(config).map_err(to_io_error)?;
     }
+    if let Some(answer) = &options.answer_file {
+        validate_for_iso(answer, iso_path).map_err(to_io_error)?;
+    }
+    if let Some(data) = &options.data_partition {
+        validate_data_partition(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
 
-    let status = Command::new("dd")
-        .arg(format!("if={}", iso_path))
-        .arg(format!("of={}", usb_device))
-        .arg("bs=4M")
-        .arg("status=progress")
-        .arg("oflag=sync")
-        .status()?;
+    if options.repartition.is_none() {
+        fail_point::check("dd")?;
+    }
+    let copied = match (options.repartition, options.max_speed_mbps) {
+        (Some(table), _) => {
+            write_iso_contents(iso_path, usb_device, table, persistence.is_some(), log, &mut report.warnings)?;
+            true
+        }
+        (None, Some(mbps)) => {
+            let copy_options = CopyOptions { max_speed_mbps: Some(mbps), pause: options.pause.clone() };
+            // Keep the cause (no space, I/O error, short read) instead of a generic "dd failed"
+            copy_image(iso_path, usb_device, copy_options, log)
+                .map_err(|e| io::Error::new(e.kind(), format!("Image copy to {} failed: {}", usb_device, e)))?;
+            true
+        }
+        (None, None) => Command::new("dd")
+            .arg(format!("if={}", iso_path))
+            .arg(format!("of={}", usb_device))
+            .arg("bs=4M")
+            .arg("status=progress")
+            .arg("oflag=sync")
+            .status()?
+            .success(),
+    };
 
-    if status.success() {
+    if copied {
         writeln!(log, "ISO written successfully to {}", usb_device)?;
+        flush_with_progress(log)?;
+        if options.fix_protective_mbr && options.repartition.is_none() {
+            fix_protective_mbr(usb_device, log, &mut report.warnings)?;
+        }
+        if let Some(answer) = &options.answer_file
+            && let Err(e) = install_answer_file(usb_device, iso_path, answer, log, &mut report.warnings)
+        {
+            let warning = Warning::new(WarningKind::AnswerFile, format!("{} file not installed: {}", answer.kind(), e));
+            record_warning(log, &mut report.warnings, warning)?;
+        }
         if let Some(config) = persistence {
             writeln!(log, "[PERSISTENCE] Starting persistence partition creation...")?;
-            create_persistence_partition(usb_device, &config).map_err(to_io_error)?;
+            match create_persistence_partition_with_recovery(usb_device, &config, log, &mut report.warnings) {
+                Ok(()) => {}
+                // The ISO itself is bootable; report validation failures as a skipped persistence.
+                Err(UsbCreatorError::ValidationError(msg)) => {
+                    let warning = Warning::new(WarningKind::Persistence, format!("Persistence skipped: {}", msg));
+                    record_warning(log, &mut report.warnings, warning)?;
+                }
+                Err(e @ UsbCreatorError::InsufficientSpace { max_mb, .. }) => {
+                    let warning = Warning::new(WarningKind::Persistence, format!("Persistence skipped: {}", e));
+                    record_warning(log, &mut report.warnings, warning)?;
+                    report.persistence_max_mb = Some(max_mb);
+                }
+                Err(e) => return Err(to_io_error(e)),
+            }
         }
-        Ok(())
+        // Like persistence, the storage partition is an extra: the ISO boots without it
+        if let Some(data) = &options.data_partition
+            && let Err(e) = create_data_partition(usb_device, data, log, &mut report.warnings)
+        {
+            let warning = Warning::new(WarningKind::DataPartition, format!("Data partition skipped: {}", e));
+            record_warning(log, &mut report.warnings, warning)?;
+        }
+        log_partition_geometry(usb_device, log)?;
+        Ok(report)
     } else {
         writeln!(log, "Failed to write ISO to {}", usb_device)?;
         Err(io::Error::new(io::ErrorKind::Other, "dd failed"))
     }
 }
 
+/// Inspect the protective MBR after dd and regenerate it when it does not match the GPT.
+///
+/// Some UEFI firmwares refuse to boot hybrid ISOs whose protective MBR is missing,
+/// mixed with other entries, or only spans the ISO image. The backup GPT header is
+/// relocated first (sgdisk), then a single 0xEE entry covering the device is written.
+/// Returns `Ok(true)` when a repair was performed; non-fatal issues go to `warnings`.
+pub fn fix_protective_mbr(usb_device: &str, log: &mut dyn Write, warnings: &mut Vec<Warning>) -> io::Result<bool> {
+    let _ = Command::new("sync").status();
+    let sector_size = get_device_logical_block_size(usb_device).unwrap_or(512);
+    let status = read_protective_mbr_status(usb_device, sector_size)?;
+    writeln!(log, "[PMBR] Protective MBR status on {}: {:?}", usb_device, status)?;
+    match status {
+        ProtectiveMbrStatus::NotGpt => {
+            writeln!(log, "[PMBR] No GPT header found; skipping protective MBR repair.")?;
+            return Ok(false);
+        }
+        ProtectiveMbrStatus::Valid => {
+            writeln!(log, "[PMBR] Protective MBR is valid; nothing to do.")?;
+            return Ok(false);
+        }
+        ProtectiveMbrStatus::Hybrid => {
+            writeln!(log, "[PMBR] Hybrid MBR detected; replacing it with a protective MBR (legacy BIOS boot may stop working).")?;
+        }
+        _ => {}
+    }
+
+    // Move the backup GPT header to the end of the device so the 0xEE entry can span it.
+    maybe_expand_gpt(usb_device, warnings).map_err(to_io_error)?;
+
+    let total_sectors = get_device_size_bytes(usb_device)? / sector_size;
+    write_protective_mbr(usb_device, total_sectors)?;
+    let _ = Command::new("sync").status();
+    let _ = Command::new("partprobe").arg(usb_device).status();
+
+    match read_protective_mbr_status(usb_device, sector_size)? {
+        ProtectiveMbrStatus::Valid => {
+            writeln!(log, "[PMBR] Protective MBR regenerated on {}.", usb_device)?;
+            Ok(true)
+        }
+        other => Err(io::Error::other(format!(
+            "Protective MBR still invalid after repair: {:?}",
+            other
+        ))),
+    }
+}
+
+/// Classify the MBR sector given the following GPT header sector and the device size in sectors.
+pub fn inspect_protective_mbr(mbr: &[u8], gpt_header: &[u8], total_sectors: u64) -> ProtectiveMbrStatus {
+    if gpt_header.len() < 8 || &gpt_header[..8] != b"EFI PART" {
+        return ProtectiveMbrStatus::NotGpt;
+    }
+    if mbr.len() < 512 || mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return ProtectiveMbrStatus::Missing;
+    }
+
+    let entries: Vec<&[u8]> = (0..4)
+        .map(|i| &mbr[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE..MBR_ENTRIES_OFFSET + (i + 1) * MBR_ENTRY_SIZE])
+        .collect();
+    let used: Vec<&&[u8]> = entries.iter().filter(|e| e[4] != 0x00).collect();
+    let protective: Vec<&&[u8]> = used.iter().copied().filter(|e| e[4] == PROTECTIVE_MBR_TYPE).collect();
+
+    if protective.is_empty() {
+        return ProtectiveMbrStatus::Missing;
+    }
+    if used.len() > 1 {
+        return ProtectiveMbrStatus::Hybrid;
+    }
+
+    let entry = protective[0];
+    let start = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as u64;
+    let recorded = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as u64;
+    let expected = total_sectors.saturating_sub(1).min(u32::MAX as u64);
+    if start != 1 || recorded != expected {
+        return ProtectiveMbrStatus::WrongSize { recorded, expected };
+    }
+    ProtectiveMbrStatus::Valid
+}
+
+/// Build a protective MBR entry spanning `total_sectors` into `mbr`, keeping the boot code intact.
+fn build_protective_mbr(mbr: &mut [u8], total_sectors: u64) {
+    let size = total_sectors.saturating_sub(1).min(u32::MAX as u64) as u32;
+    for byte in &mut mbr[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + 4 * MBR_ENTRY_SIZE] {
+        *byte = 0;
+    }
+    let entry = &mut mbr[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + MBR_ENTRY_SIZE];
+    entry[0] = 0x00; // not bootable
+    entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]); // CHS of LBA 1
+    entry[4] = PROTECTIVE_MBR_TYPE;
+    entry[5..8].copy_from_slice(&[0xFF, 0xFF, 0xFF]); // CHS beyond addressable range
+    entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&size.to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+}
+
+fn read_protective_mbr_status(usb_device: &str, sector_size: u64) -> io::Result<ProtectiveMbrStatus> {
+    let mut buf = vec![0u8; (sector_size * 2) as usize];
+    let mut file = fs::File::open(usb_device)?;
+    file.read_exact(&mut buf)?;
+    let total_sectors = get_device_size_bytes(usb_device)? / sector_size;
+    let (mbr, gpt) = buf.split_at(sector_size as usize);
+    Ok(inspect_protective_mbr(mbr, gpt, total_sectors))
+}
+
+fn write_protective_mbr(usb_device: &str, total_sectors: u64) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(usb_device)?;
+    let mut mbr = [0u8; 512];
+    file.read_exact(&mut mbr)?;
+    build_protective_mbr(&mut mbr, total_sectors);
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&mbr)?;
+    file.sync_all()
+}
 
 // Helper for verbose step output
 fn print_step(step: usize, total: usize, msg: &str) {
@@ -65,9 +323,11 @@ fn to_io_error(err: UsbCreatorError) -> io::Error {
 
 /// Streaming version: print log lines directly to stdout and flush after each
 pub fn write_iso_to_usb_stream(iso_path: &str, usb_device: &str, cluster_bytes: u64) -> io::Result<()> {
+    check_iso_readable(iso_path)?;
     let total_steps = 5;
     let mut step = 1;
     print_step(step, total_steps, "Wiping old partition table (wipefs)...");
+    fail_point::check("wipe").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
     let status = Command::new("wipefs")
         .arg("-a")
         .arg(usb_device)
@@ -82,6 +342,7 @@ pub fn write_iso_to_usb_stream(iso_path: &str, usb_device: &str, cluster_bytes:
     print_step(step, total_steps, &format!("Writing ISO to USB with dd (this may take a while)..."));
     use std::process::{Command, Stdio};
     use std::io::{BufRead, BufReader, Write};
+    fail_point::check("dd").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
     let mut child = Command::new("dd")
         .arg(format!("if={}", iso_path))
         .arg(format!("of={}", usb_device))
@@ -132,10 +393,69 @@ pub fn write_iso_to_usb_stream(iso_path: &str, usb_device: &str, cluster_bytes:
     }
     step += 1;
     print_step(step, total_steps, "Syncing data to disk...");
-    let _ = Command::new("sync").status();
+    flush_with_progress(&mut std::io::stdout())?;
+    let _ = log_partition_geometry(usb_device, &mut std::io::stdout());
     step += 1;
     print_step(step, total_steps, "Finalizing...");
     step += 1;
     print_step(step, total_steps, "Linux USB creation completed.");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flows::linux_data_partition::DataFilesystem;
+
+    #[test]
+    fn extra_partitions_need_the_full_flow() {
+        assert!(!LinuxWriteOptions::default().needs_extra_steps());
+        assert!(!LinuxWriteOptions { fix_protective_mbr: true, ..Default::default() }.needs_extra_steps());
+        let data = DataPartitionConfig { size_mb: 4096, filesystem: DataFilesystem::Exfat };
+        assert!(LinuxWriteOptions { data_partition: Some(data), ..Default::default() }.needs_extra_steps());
+    }
+
+    fn gpt_header() -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..8].copy_from_slice(b"EFI PART");
+        header
+    }
+
+    fn mbr_with_entries(entries: &[(u8, u32, u32)]) -> Vec<u8> {
+        let mut mbr = vec![0u8; 512];
+        for (i, (kind, start, size)) in entries.iter().enumerate() {
+            let off = MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE;
+            mbr[off + 4] = *kind;
+            mbr[off + 8..off + 12].copy_from_slice(&start.to_le_bytes());
+            mbr[off + 12..off + 16].copy_from_slice(&size.to_le_bytes());
+        }
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+        mbr
+    }
+
+    #[test]
+    fn detects_protective_mbr_sized_to_iso_image() {
+        let mbr = mbr_with_entries(&[(0xEE, 1, 6_000_000)]);
+        let status = inspect_protective_mbr(&mbr, &gpt_header(), 60_000_000);
+        assert_eq!(status, ProtectiveMbrStatus::WrongSize { recorded: 6_000_000, expected: 59_999_999 });
+    }
+
+    #[test]
+    fn detects_hybrid_and_missing_protective_entries() {
+        let hybrid = mbr_with_entries(&[(0x00, 0, 0), (0xEE, 1, 100), (0xEF, 200, 8192)]);
+        assert_eq!(inspect_protective_mbr(&hybrid, &gpt_header(), 1000), ProtectiveMbrStatus::Hybrid);
+        let missing = mbr_with_entries(&[(0x17, 0, 900), (0xEF, 200, 100)]);
+        assert_eq!(inspect_protective_mbr(&missing, &gpt_header(), 1000), ProtectiveMbrStatus::Missing);
+        assert_eq!(inspect_protective_mbr(&missing, &[0u8; 512], 1000), ProtectiveMbrStatus::NotGpt);
+    }
+
+    #[test]
+    fn rebuilt_protective_mbr_is_valid_and_keeps_boot_code() {
+        let mut mbr = mbr_with_entries(&[(0x17, 0, 900), (0xEF, 200, 100)]);
+        mbr[0] = 0xEB;
+        build_protective_mbr(&mut mbr, 1000);
+        assert_eq!(mbr[0], 0xEB);
+        assert_eq!(inspect_protective_mbr(&mbr, &gpt_header(), 1000), ProtectiveMbrStatus::Valid);
+    }
+}
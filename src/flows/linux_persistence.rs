@@ -1,16 +1,25 @@
 //! Linux persistence support for USB bootable drives
 
+use crate::command::{CommandRunner, SystemRunner};
 use crate::error::{UsbCreatorError, UsbCreatorResult};
+use crate::fail_point;
+use crate::flows::outcome::{record_warning, Warning, WarningKind};
+use crate::flows::volume_label::{sanitize_label, LabelFs};
+use crate::settings::Tuning;
+use crate::utils::{find_free_regions, read_partitions, remount_hint, sync_watching_writeback, FreeRegion, GPT_BACKUP_SECTORS};
 use scopeguard;
+use std::cell::{Cell, RefCell};
 use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::thread;
 use std::time::Duration;
 use tempfile;
 
 const SAFETY_MARGIN_MB: u64 = 512;
-const TABLE_REFRESH_ATTEMPTS: usize = 5;
+const TABLE_REFRESH_FAILED: &str =
+    "Kernel did not refresh partition table after write; aborting persistence creation";
 
 /// Configuration for Linux persistence
 #[derive(Debug, Clone)]
@@ -25,6 +34,54 @@ pub struct PersistenceConfig {
     pub label: String,
     /// Desired partition table type (GPT or MBR)
     pub partition_table: PartitionTableType,
+    /// Start sector of a user-chosen free region; None appends after the last partition
+    pub start_sector: Option<u64>,
+    /// Extra kernel parameters added to the boot entries on top of the per-type defaults
+    pub kernel_params: Vec<String>,
+    /// Whether partitions unmounted before repartitioning are mounted again afterwards
+    pub remount: RemountPolicy,
+    /// Share of the ext4 blocks reserved for root (`mkfs.ext4 -m`); the usual 5% is wasted on
+    /// a removable persistence partition, so the default is 0
+    pub reserved_percent: u8,
+}
+
+/// What to do with partitions of the target device that had to be unmounted before repartitioning
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemountPolicy {
+    /// Leave them unmounted
+    Never,
+    /// Remount only when the operation fails before the partition table was touched
+    #[default]
+    OnFailure,
+    /// Always remount, even after the table changed underneath them
+    Always,
+}
+
+impl RemountPolicy {
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            RemountPolicy::Never => "never",
+            RemountPolicy::OnFailure => "on-failure",
+            RemountPolicy::Always => "always",
+        }
+    }
+
+    /// Inverse of `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        [RemountPolicy::Never, RemountPolicy::OnFailure, RemountPolicy::Always]
+            .into_iter()
+            .find(|policy| policy.name() == name)
+    }
+
+    /// Whether to remount once the operation ends, given whether the partition table was modified
+    pub fn should_remount(self, table_modified: bool) -> bool {
+        match self {
+            RemountPolicy::Never => false,
+            RemountPolicy::OnFailure => !table_modified,
+            RemountPolicy::Always => true,
+        }
+    }
 }
 
 /// Types of persistence support
@@ -38,6 +95,26 @@ pub enum PersistenceType {
     Custom(String),
 }
 
+impl PersistenceType {
+    /// Short lowercase name, as used in machine-readable output
+    pub fn name(&self) -> &str {
+        match self {
+            PersistenceType::Casper => "casper",
+            PersistenceType::OverlayFS => "overlayfs",
+            PersistenceType::Custom(name) => name,
+        }
+    }
+
+    /// Inverse of `name`; unknown names become `Custom`
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "casper" => PersistenceType::Casper,
+            "overlayfs" => PersistenceType::OverlayFS,
+            other => PersistenceType::Custom(other.to_string()),
+        }
+    }
+}
+
 /// Supported partition table types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PartitionTableType {
@@ -45,6 +122,61 @@ pub enum PartitionTableType {
     Mbr,
 }
 
+impl PersistenceConfig {
+    /// Default kernel parameters for the persistence type followed by the user-supplied ones
+    pub fn effective_kernel_params(&self) -> Vec<String> {
+        let mut params = default_kernel_params(&self.persistence_type, &self.label);
+        for param in &self.kernel_params {
+            if !params.contains(param) {
+                params.push(param.clone());
+            }
+        }
+        params
+    }
+}
+
+/// Kernel parameters a persistence type needs to find its partition
+pub fn default_kernel_params(persistence_type: &PersistenceType, label: &str) -> Vec<String> {
+    match persistence_type {
+        // casper only looks for persistence when booted with `persistent`
+        PersistenceType::Casper => vec!["persistent".to_string()],
+        // dracut live images (Fedora) take the overlay device from rd.live.overlay
+        PersistenceType::OverlayFS => vec![format!("rd.live.overlay=LABEL={}", label)],
+        PersistenceType::Custom(_) => Vec::new(),
+    }
+}
+
+/// Check that a user-supplied kernel parameter is a single `name` or `name=value` token
+pub fn validate_kernel_param(param: &str) -> UsbCreatorResult<()> {
+    if param.is_empty() || param.len() > 256 {
+        return Err(UsbCreatorError::validation_error(format!(
+            "Kernel parameter must be 1-256 characters: '{}'",
+            param
+        )));
+    }
+    if param.starts_with('=') {
+        return Err(UsbCreatorError::validation_error(format!(
+            "Kernel parameter has no name: '{}'",
+            param
+        )));
+    }
+    if let Some(bad) = param
+        .chars()
+        .find(|c| !c.is_ascii_graphic() || matches!(c, '"' | '\'' | '`' | ';' | '\\' | '$'))
+    {
+        return Err(UsbCreatorError::validation_error(format!(
+            "Kernel parameter '{}' contains invalid character {:?}",
+            param, bad
+        )));
+    }
+    Ok(())
+}
+
+/// Split a whitespace-separated parameter string as typed in the GUI
+pub fn parse_kernel_params(text: &str) -> Vec<String> {
+    text.split_whitespace().map(str::to_string).collect()
+}
+
 impl Default for PersistenceConfig {
     fn default() -> Self {
         Self {
@@ -53,87 +185,334 @@ impl Default for PersistenceConfig {
             persistence_type: PersistenceType::Casper,
             label: "persistence".to_string(),
             partition_table: PartitionTableType::Gpt,
+            start_sector: None,
+            kernel_params: Vec::new(),
+            remount: RemountPolicy::default(),
+            reserved_percent: 0,
+        }
+    }
+}
+
+/// Highest reserved-blocks percentage `mkfs.ext4 -m` accepts
+pub const MAX_RESERVED_PERCENT: u8 = 50;
+/// A persistence filesystem with less room than this fills up within a session of updates
+const MIN_PERSISTENCE_AVAILABLE_MB: u64 = 256;
+/// Fewer free inodes than this run out before the space does once packages get installed
+const MIN_PERSISTENCE_FREE_INODES: u64 = 16_384;
+
+/// Room on a freshly formatted ext4 filesystem, read from its superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ext4Space {
+    /// Bytes ordinary users can write: free blocks minus the ones reserved for root
+    pub available_bytes: u64,
+    /// Bytes kept for root by the reserved-blocks percentage
+    pub reserved_bytes: u64,
+    /// Inodes left for new files and directories
+    pub free_inodes: u64,
+}
+
+impl Ext4Space {
+    /// Read the superblock summary printed by `tune2fs -l`
+    pub fn parse_tune2fs(output: &str) -> Option<Self> {
+        let field = |name: &str| {
+            output.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == name).then(|| value.trim().parse::<u64>().ok()).flatten()
+            })
+        };
+        let block_size = field("Block size")?;
+        let free_blocks = field("Free blocks")?;
+        let reserved_blocks = field("Reserved block count")?;
+        Some(Self {
+            available_bytes: free_blocks.saturating_sub(reserved_blocks) * block_size,
+            reserved_bytes: reserved_blocks * block_size,
+            free_inodes: field("Free inodes")?,
+        })
+    }
+
+    /// Why the filesystem is unusually tight for persistence, or None when it is not
+    pub fn constraint(&self) -> Option<String> {
+        let mut reasons = Vec::new();
+        if self.available_bytes < MIN_PERSISTENCE_AVAILABLE_MB * 1024 * 1024 {
+            reasons.push(format!("only {} MB are available", self.available_bytes / 1024 / 1024));
+        }
+        if self.free_inodes < MIN_PERSISTENCE_FREE_INODES {
+            reasons.push(format!("only {} inodes are free, so installing packages may run out of inodes first", self.free_inodes));
         }
+        (!reasons.is_empty()).then(|| format!("the persistence partition is tight: {}", reasons.join("; ")))
     }
 }
 
-/// Create persistence partition on USB drive after ISO writing
-pub fn create_persistence_partition(
+fn read_ext4_space(runner: &dyn CommandRunner, partition: &str) -> UsbCreatorResult<Ext4Space> {
+    let output = run_command_with_output_via(runner, "tune2fs", &["-l", partition])?;
+    Ext4Space::parse_tune2fs(&output)
+        .ok_or_else(|| UsbCreatorError::generic(format!("unexpected tune2fs output for {}", partition)))
+}
+
+/// Phases of persistence creation, reported as progress once the image is on the device
+const PERSISTENCE_PHASES: [&str; 5] = [
+    "Creating partition",
+    "Formatting ext4",
+    "Updating boot configuration",
+    "Configuring persistence",
+    "Flushing changes",
+];
+
+/// `[PROGRESS]` line for the start of phase `index`, or for completion past the last phase
+fn persistence_progress_line(index: usize) -> String {
+    let total = PERSISTENCE_PHASES.len();
+    match PERSISTENCE_PHASES.get(index) {
+        Some(phase) => format!(
+            "[PROGRESS] persistence: {} (step {}/{}) ({}%)",
+            phase,
+            index + 1,
+            total,
+            index * 100 / total
+        ),
+        None => "[PROGRESS] persistence: done (100%)".to_string(),
+    }
+}
+
+/// `[PROGRESS]` line for the final flush, `drained` percent of the way through: the flush fills
+/// the last phase's share of the bar, so the write does not look stuck at its very end
+fn persistence_flush_line(remaining_bytes: u64, drained: u8) -> String {
+    let total = PERSISTENCE_PHASES.len();
+    let start = (total - 1) * 100 / total;
+    format!(
+        "[PROGRESS] persistence: {}, {} MB left (step {}/{}) ({}%)",
+        PERSISTENCE_PHASES[total - 1],
+        remaining_bytes / 1024 / 1024,
+        total,
+        total,
+        start + usize::from(drained.min(100)) * (100 - start) / 100
+    )
+}
+
+/// Copy of `config` whose label is valid for ext4, recording a warning for each change.
+/// The same label goes to mkfs, the partition name and the kernel parameters, so it is fixed once.
+fn with_sanitized_label(
+    config: &PersistenceConfig,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
+) -> UsbCreatorResult<PersistenceConfig> {
+    let (label, label_warnings) = sanitize_label(&config.label, LabelFs::Ext4);
+    for warning in label_warnings {
+        record_warning(log, warnings, warning)?;
+    }
+    Ok(PersistenceConfig { label, ..config.clone() })
+}
+
+/// Create persistence partition on USB drive after ISO writing; `config.label` must already be
+/// sanitized. Each phase is announced on `log` as a `[PROGRESS]` line; non-fatal issues go to `warnings`.
+fn create_persistence_partition(
     usb_device: &str,
     config: &PersistenceConfig,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
 ) -> UsbCreatorResult<()> {
-    if !config.enabled {
-        return Ok(());
+    writeln!(log, "{}", persistence_progress_line(0))?;
+    println!("[PERSISTENCE] Creating {}MB persistence partition...", config.size_mb);
+    let partition_path = append_partition(
+        usb_device,
+        config.partition_table,
+        config.size_mb,
+        config.start_sector,
+        &config.label,
+        config.remount,
+        warnings,
+    )?;
+
+    writeln!(log, "{}", persistence_progress_line(1))?;
+    println!("[PERSISTENCE] Formatting persistence partition as ext4...");
+    let formatted = fail_point::check("persistence_mkfs").map_err(UsbCreatorError::from).and_then(|()| run_command("mkfs.ext4", &[
+        "-L", &config.label,
+        "-m", &config.reserved_percent.to_string(),
+        "-F",  // Force creation
+        &partition_path
+    ]));
+    if let Err(e) = formatted {
+        println!("[PERSISTENCE] ERROR while formatting persistence partition: {}", e);
+        return Err(with_remount_context(usb_device, e));
+    }
+    match read_ext4_space(&SystemRunner, &partition_path) {
+        Ok(space) => {
+            writeln!(
+                log,
+                "Persistence filesystem: {} MB available, {} free inodes, {} MB reserved for root ({}%)",
+                space.available_bytes / 1024 / 1024,
+                space.free_inodes,
+                space.reserved_bytes / 1024 / 1024,
+                config.reserved_percent
+            )?;
+            if let Some(constraint) = space.constraint() {
+                record_warning(log, warnings, Warning::new(WarningKind::Persistence, constraint))?;
+            }
+        }
+        Err(e) => writeln!(log, "Could not read the free space of {}: {}", partition_path, e)?,
     }
 
-    println!("[PERSISTENCE] Creating {}MB persistence partition...", config.size_mb);
+    // Point the live system at the new partition (per-type defaults plus user parameters)
+    writeln!(log, "{}", persistence_progress_line(2))?;
+    let kernel_params = config.effective_kernel_params();
+    if !kernel_params.is_empty() {
+        inject_kernel_params(usb_device, &kernel_params);
+    }
+
+    // Final settle to make the new partition visible
+    let _ = Command::new("sync").status();
+    let _ = run_command("partprobe", &[usb_device]);
+    settle_udev();
+
+    writeln!(log, "{}", persistence_progress_line(3))?;
+    println!("[PERSISTENCE] Setting up persistence configuration...");
+
+    // Configure persistence based on type
+    match &config.persistence_type {
+        PersistenceType::Casper => setup_casper_persistence(&partition_path, config)?,
+        PersistenceType::OverlayFS => setup_overlayfs_persistence(&partition_path, config)?,
+        PersistenceType::Custom(method) => setup_custom_persistence(&partition_path, config, method)?,
+    }
+
+    // Slow sticks can take many seconds to take the cached writes; show them draining
+    writeln!(log, "{}", persistence_progress_line(4))?;
+    println!("[PERSISTENCE] Flushing changes to {}...", usb_device);
+    let flushed = sync_watching_writeback(&mut |remaining, percent| {
+        writeln!(log, "{}", persistence_flush_line(remaining, percent))?;
+        log.flush()
+    });
+    if let Err(e) = flushed {
+        println!("[PERSISTENCE] Warning: flush progress unavailable ({}); running sync", e);
+        let _ = Command::new("sync").status();
+    }
+
+    // Refresh partition table so the OS sees the new partition
+    let _ = run_command("partprobe", &[usb_device]);
+
+    writeln!(log, "{}", persistence_progress_line(PERSISTENCE_PHASES.len()))?;
+    println!("Linux persistence setup completed successfully!");
+    Ok(())
+}
 
+/// Append a partition of `size_mb` (at `start_sector_hint`, or after the last partition) to a
+/// freshly written device, wait for its node and name it `name` on GPT. Returns the node path.
+/// Partitions unmounted on the way are restored according to `remount`; non-fatal issues go to `warnings`.
+pub(crate) fn append_partition(
+    usb_device: &str,
+    requested_table: PartitionTableType,
+    size_mb: u64,
+    start_sector_hint: Option<u64>,
+    name: &str,
+    remount: RemountPolicy,
+    warnings: &mut Vec<Warning>,
+) -> UsbCreatorResult<String> {
     // Ensure kernel has flushed caches and re-read partition table after dd
     let _ = Command::new("sync").status();
     let _ = Command::new("partprobe").arg(usb_device).status();
     settle_udev();
-    thread::sleep(Duration::from_millis(500));
+    thread::sleep(Tuning::for_device(usb_device).settle(500));
     refresh_partition_table(usb_device)?;
 
     // Detect existing partition table; if it differs from user selection, log and continue with detected type
-    let current_table = detect_partition_table_type(usb_device)?;
-    if current_table != config.partition_table {
+    let current_table = detect_partition_table_type(&SystemRunner, usb_device)?;
+    if current_table != requested_table {
         println!(
             "[PERSISTENCE] Requested table {:?} but detected {:?}. Proceeding with detected table.",
-            config.partition_table, current_table
+            requested_table, current_table
         );
     }
     let effective_table = current_table;
 
     // Ensure nothing is mounted from the target device before we repartition
     let previously_mounted = unmount_device_partitions(usb_device)?;
+    // Once the table changes, the old mounts describe a layout that is being rewritten and remounting
+    // them only races udev; before that, a failure leaves the device as it was, so restore them
+    let table_modified = Cell::new(false);
+    // Shared with the remount guard, which runs after the rest of this function is done with it
+    let warnings = RefCell::new(warnings);
     // Keep remount best-effort but do not let it mask failures; only if we actually unmounted something
     let _remount_guard = scopeguard::guard(previously_mounted, |mounts: Vec<(String, String)>| {
         if mounts.is_empty() {
             return;
         }
+        if !remount.should_remount(table_modified.get()) {
+            println!("[PERSISTENCE] Leaving {} partition(s) of {} unmounted.", mounts.len(), usb_device);
+            return;
+        }
         println!("[PERSISTENCE] Remounting previously mounted partitions (best effort)...");
         for (dev, mp) in mounts {
             println!("[PERSISTENCE] Remounting {} to {}", dev, mp);
-            let _ = Command::new("mount").args([dev.as_str(), mp.as_str()]).status();
+            if !Command::new("mount").args([dev.as_str(), mp.as_str()]).status().is_ok_and(|status| status.success()) {
+                println!("[PERSISTENCE] Warning: could not remount {} at {}.", dev, mp);
+                warnings.borrow_mut().push(Warning::new(
+                    WarningKind::Remount,
+                    format!("{} could not be mounted again at {}", dev, mp),
+                ));
+            }
         }
     });
 
     // For GPT-based layouts, expand GPT to the end of the device
     if effective_table == PartitionTableType::Gpt {
-        maybe_expand_gpt(usb_device)?;
+        table_modified.set(true);
+        maybe_expand_gpt(usb_device, &mut warnings.borrow_mut())?;
         let _ = run_command("partprobe", &[usb_device]);
         settle_udev();
-        thread::sleep(Duration::from_millis(500));
+        thread::sleep(Tuning::for_device(usb_device).settle(500));
         refresh_partition_table(usb_device)?;
     }
 
     // Find the next available partition number
-    let partition_number = find_next_partition_number(usb_device)?;
+    let partition_number = find_next_partition_number(&SystemRunner, usb_device)?;
     let partition_path = build_partition_path(usb_device, partition_number);
 
-    // Calculate partition start (we need to find where the existing partitions end)
-    let start_sector = find_next_available_sector(usb_device)?;
+    // Calculate partition start: the chosen free region, or after the last existing partition
     let total_sectors = get_total_sectors(usb_device)?;
-    ensure_free_space(usb_device, start_sector, total_sectors, config.size_mb)?;
-    let end_sector = start_sector + (config.size_mb * 2048).saturating_sub(1); // 512-byte sectors
+    let start_sector = match start_sector_hint {
+        Some(start) => {
+            println!("[PERSISTENCE] Using user-selected placement at sector {}.", start);
+            ensure_region_fits(usb_device, start, total_sectors, size_mb)?;
+            start
+        }
+        None => {
+            let start = find_next_available_sector(&SystemRunner, usb_device)?;
+            ensure_free_space(usb_device, start, total_sectors, size_mb)?;
+            start
+        }
+    };
+    let (end_sector, shortened) = persistence_end_sector(start_sector, size_mb, total_sectors)?;
+    if shortened {
+        let actual_mb = (end_sector - start_sector + 1) / 2048;
+        println!(
+            "[PERSISTENCE] Warning: {} MB from sector {} would reach the backup GPT area; ending at sector {} ({} MB).",
+            size_mb, start_sector, end_sector, actual_mb
+        );
+        warnings.borrow_mut().push(Warning::new(
+            WarningKind::Partitioning,
+            format!(
+                "Persistence partition on {} was shortened to {} MB (requested {} MB) to keep clear of the end of the device",
+                usb_device, actual_mb, size_mb
+            ),
+        ));
+    }
 
     // One more settle before creating the partition to avoid racing table updates
     let _ = Command::new("sync").status();
     let _ = run_command("partprobe", &[usb_device]);
     settle_udev();
-    thread::sleep(Duration::from_millis(300));
+    thread::sleep(Tuning::for_device(usb_device).settle(300));
 
     println!("[PERSISTENCE] Creating new partition {} ({}s-{}s)...", partition_number, start_sector, end_sector);
+    table_modified.set(true);
 
     // Create new partition
-    if let Err(e) = run_command("parted", &[
+    let created = fail_point::check("persistence_mkpart").map_err(UsbCreatorError::from).and_then(|()| run_command("parted", &[
         "-s", usb_device, "mkpart", "primary",
         &format!("{}s", start_sector),
         &format!("{}s", end_sector)
-    ]) {
+    ]));
+    if let Err(e) = created {
         println!("[PERSISTENCE] ERROR while creating partition: {}", e);
-        return Err(e);
+        return Err(with_remount_context(usb_device, e));
     }
 
     // Unmount again in case desktop automount raced after mkpart
@@ -148,7 +527,7 @@ pub fn create_persistence_partition(
     let _ = Command::new("hdparm").args(["-z", usb_device]).status();
     let _ = Command::new("sync").status();
     settle_udev();
-    thread::sleep(Duration::from_millis(500));
+    thread::sleep(Tuning::for_device(usb_device).settle(500));
     if let Err(e) = wait_for_partition_node(&partition_path, usb_device) {
         println!("[PERSISTENCE] Partition node still missing after mkpart: {}. Trying sfdisk append fallback (MBR only)...", e);
         if effective_table == PartitionTableType::Mbr {
@@ -160,7 +539,7 @@ pub fn create_persistence_partition(
             let _ = Command::new("partx").args(["-u", usb_device]).status();
             let _ = Command::new("blockdev").args(["--rereadpt", usb_device]).status();
             settle_udev();
-            thread::sleep(Duration::from_millis(600));
+            thread::sleep(Tuning::for_device(usb_device).settle(600));
             wait_for_partition_node(&partition_path, usb_device)?;
         } else {
             return Err(e);
@@ -178,47 +557,63 @@ pub fn create_persistence_partition(
         }
     } else {
         println!("[PERSISTENCE] GPT detected; skipping LBA flag (not applicable).");
+        // GPT-aware tools identify partitions by name rather than filesystem label
+        println!("[PERSISTENCE] Naming GPT partition {} '{}'...", partition_number, name);
+        if let Err(e) = run_command("sgdisk", &["-c", &format!("{}:{}", partition_number, name), usb_device]) {
+            println!("[PERSISTENCE] Warning: could not set GPT partition name: {}. Continuing.", e);
+            warnings.borrow_mut().push(Warning::new(
+                WarningKind::Partitioning,
+                format!("Partition {} was not named '{}': {}", partition_path, name, e),
+            ));
+        }
     }
 
-    println!("[PERSISTENCE] Formatting persistence partition as ext4...");
-    if let Err(e) = run_command("mkfs.ext4", &[
-        "-L", &config.label,
-        "-F",  // Force creation
-        &partition_path
-    ]) {
-        println!("[PERSISTENCE] ERROR while formatting persistence partition: {}", e);
-        return Err(e);
-    }
+    // parted/sgdisk re-read the table after setting the flag or name, and udev briefly removes
+    // and re-adds the partition nodes; formatting right away can hit a missing node
+    wait_for_block_node(&partition_path, Duration::from_secs(Tuning::for_device(usb_device).partition_node_timeout_secs))?;
 
-    // Add overlay kernel param for Fedora-style overlay if applicable
-    if matches!(config.persistence_type, PersistenceType::OverlayFS) {
-        inject_overlay_kernel_params(usb_device, &config.label);
+    // parted may round the end to its own alignment; report a partition smaller than asked for
+    let expected_sectors = end_sector - start_sector + 1;
+    match get_total_sectors(&partition_path) {
+        Ok(actual_sectors) if actual_sectors < expected_sectors => {
+            println!(
+                "[PERSISTENCE] Warning: {} has {} sectors, {} were requested.",
+                partition_path, actual_sectors, expected_sectors
+            );
+            warnings.borrow_mut().push(Warning::new(
+                WarningKind::Partitioning,
+                format!(
+                    "Persistence partition {} is {} MB instead of the requested {} MB (rounded by parted)",
+                    partition_path,
+                    actual_sectors / 2048,
+                    expected_sectors / 2048
+                ),
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => println!("[PERSISTENCE] Warning: could not read the size of {}: {}", partition_path, e),
     }
 
-    // Final settle to make the new partition visible
-    let _ = Command::new("sync").status();
-    let _ = run_command("partprobe", &[usb_device]);
-    settle_udev();
-
-    println!("[PERSISTENCE] Setting up persistence configuration...");
+    Ok(partition_path)
+}
 
-    // Configure persistence based on type
-    match &config.persistence_type {
-        PersistenceType::Casper => setup_casper_persistence(&partition_path, config)?,
-        PersistenceType::OverlayFS => setup_overlayfs_persistence(&partition_path, config)?,
-        PersistenceType::Custom(method) => setup_custom_persistence(&partition_path, config, method)?,
+/// Last sector of a `size_mb` partition starting at `start_sector`, pulled back when it would reach
+/// the sectors reserved for the backup GPT. The flag tells whether it was shortened.
+fn persistence_end_sector(start_sector: u64, size_mb: u64, total_sectors: u64) -> UsbCreatorResult<(u64, bool)> {
+    let last_usable = total_sectors.saturating_sub(GPT_BACKUP_SECTORS + 1);
+    let requested_end = start_sector + (size_mb * 2048).saturating_sub(1); // 512-byte sectors
+    if start_sector >= last_usable {
+        return Err(UsbCreatorError::validation_error(format!(
+            "Persistence cannot start at sector {}: the device ends at sector {}",
+            start_sector, last_usable
+        )));
     }
-
-    // Refresh partition table so the OS sees the new partition
-    let _ = run_command("partprobe", &[usb_device]);
-
-    println!("Linux persistence setup completed successfully!");
-    Ok(())
+    Ok((requested_end.min(last_usable), requested_end > last_usable))
 }
 
 /// Find the next available partition number for a device
-fn find_next_partition_number(device: &str) -> UsbCreatorResult<u32> {
-    let output = run_command_with_output("lsblk", &["-ln", "-o", "NAME", device])?;
+fn find_next_partition_number(runner: &dyn CommandRunner, device: &str) -> UsbCreatorResult<u32> {
+    let output = run_command_with_output_via(runner, "lsblk", &["-ln", "-o", "NAME", device])?;
     let device_name = device.trim_start_matches("/dev/");
     let mut max_number = 0;
 
@@ -238,31 +633,73 @@ fn find_next_partition_number(device: &str) -> UsbCreatorResult<u32> {
     Ok(max_number + 1)
 }
 
+/// One row of `parted -ms unit s print free`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PartedRow {
+    /// A partition; on MBR the extended partition spans its logical partitions
+    Partition { number: u32, start: u64, end: u64 },
+    /// Unallocated space, including gaps inside an extended partition
+    Free { start: u64, end: u64 },
+}
+
+/// Partition and free-space rows of `parted -ms unit s print free`; the `BYT;` and device
+/// header lines are skipped
+fn parse_parted_rows(output: &str) -> Vec<PartedRow> {
+    let sectors = |field: &str| field.trim_end_matches('s').parse::<u64>().ok();
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim().trim_end_matches(';').split(':').collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            let number = fields[0].parse::<u32>().ok()?;
+            let (start, end) = (sectors(fields[1])?, sectors(fields[2])?);
+            Some(if fields.get(4) == Some(&"free") {
+                PartedRow::Free { start, end }
+            } else {
+                PartedRow::Partition { number, start, end }
+            })
+        })
+        .collect()
+}
+
+/// 1 MiB-aligned start of the largest free region past `last_end`, the end of the last
+/// partition. Free space inside an extended partition or before the last partition is ignored.
+fn largest_free_start_after(rows: &[PartedRow], last_end: u64) -> Option<u64> {
+    rows.iter()
+        .filter_map(|row| match *row {
+            PartedRow::Free { start, end } if end > last_end => {
+                let aligned = align_to_2048(start.max(last_end + 1));
+                (aligned <= end).then_some((aligned, end - aligned + 1))
+            }
+            _ => None,
+        })
+        .max_by_key(|&(_, size)| size)
+        .map(|(start, _)| start)
+}
+
 /// Find the next available sector for partition creation
-fn find_next_available_sector(device: &str) -> UsbCreatorResult<u64> {
+fn find_next_available_sector(runner: &dyn CommandRunner, device: &str) -> UsbCreatorResult<u64> {
     let mut max_sector = 2048; // Start after first MB
 
-    // Try parted first
-    if let Ok(output) = run_command_with_output("parted", &[
-        "-ms", device, "unit", "s", "print"
+    // Try parted first; free-space rows are kept apart so they never count as partition ends
+    let mut rows = Vec::new();
+    if let Ok(output) = run_command_with_output_via(runner, "parted", &[
+        "-ms", device, "unit", "s", "print", "free"
     ]) {
         println!("[PERSISTENCE] Parsing existing partitions from parted output...");
-        for line in output.lines() {
-            if line.starts_with(|c: char| c.is_ascii_digit()) {
-                let parts: Vec<&str> = line.split(':').collect();
-                if parts.len() >= 3 {
-                    let end_raw = parts[2].trim_end_matches('s');
-                    if let Ok(end_sector) = end_raw.parse::<u64>() {
-                        max_sector = max_sector.max(end_sector);
-                        println!("[PERSISTENCE] Found partition ending at sector {}", end_sector);
-                    }
-                }
+        rows = parse_parted_rows(&output);
+        for row in &rows {
+            if let PartedRow::Partition { number, end, .. } = *row {
+                max_sector = max_sector.max(end);
+                println!("[PERSISTENCE] Found partition {} ending at sector {}", number, end);
             }
         }
     }
 
-    // Fallback: use lsblk start+sectors for any child entries
-    if let Ok(output) = run_command_with_output("lsblk", &["-b", "-n", "-o", "NAME,START,SECTORS,TYPE", device]) {
+    // Fallback: use lsblk start+sectors for any child entries (list output, so names carry no tree prefix)
+    if let Ok(output) = run_command_with_output_via(runner, "lsblk", &["-b", "-ln", "-o", "NAME,START,SECTORS,TYPE", device]) {
         let device_name = device.trim_start_matches("/dev/");
         for line in output.lines() {
             let cols: Vec<&str> = line.split_whitespace().collect();
@@ -284,7 +721,13 @@ fn find_next_available_sector(device: &str) -> UsbCreatorResult<u64> {
         }
     }
 
-    let next = align_to_2048(max_sector + 1);
+    let next = match largest_free_start_after(&rows, max_sector) {
+        Some(start) => {
+            println!("[PERSISTENCE] parted reports free space from sector {} after the last partition", start);
+            start
+        }
+        None => align_to_2048(max_sector + 1),
+    };
     println!(
         "[PERSISTENCE] Next available start sector chosen: {} (after max end {})",
         next, max_sector
@@ -305,9 +748,9 @@ fn align_to_2048(sector: u64) -> u64 {
 }
 
 /// Detect current partition table type via parted -ms print
-fn detect_partition_table_type(device: &str) -> UsbCreatorResult<PartitionTableType> {
+pub(crate) fn detect_partition_table_type(runner: &dyn CommandRunner, device: &str) -> UsbCreatorResult<PartitionTableType> {
     // Try parted first
-    if let Ok(output) = run_command_with_output("parted", &["-ms", device, "unit", "s", "print"]) {
+    if let Ok(output) = run_command_with_output_via(runner, "parted", &["-ms", device, "unit", "s", "print"]) {
         for line in output.lines() {
             if line.starts_with("/dev/") {
                 let parts: Vec<&str> = line.split(':').collect();
@@ -324,7 +767,7 @@ fn detect_partition_table_type(device: &str) -> UsbCreatorResult<PartitionTableT
     }
 
     // Fallback: lsblk PTTYPE
-    if let Ok(output) = run_command_with_output("lsblk", &["-dn", "-o", "PTTYPE", device]) {
+    if let Ok(output) = run_command_with_output_via(runner, "lsblk", &["-dn", "-o", "PTTYPE", device]) {
         let pttype = output.trim().to_lowercase();
         if pttype.contains("gpt") {
             return Ok(PartitionTableType::Gpt);
@@ -334,7 +777,7 @@ fn detect_partition_table_type(device: &str) -> UsbCreatorResult<PartitionTableT
     }
 
     // Fallback: blkid PTTYPE
-    if let Ok(output) = run_command_with_output("blkid", &["-s", "PTTYPE", "-o", "value", device]) {
+    if let Ok(output) = run_command_with_output_via(runner, "blkid", &["-s", "PTTYPE", "-o", "value", device]) {
         let pttype = output.trim().to_lowercase();
         if pttype.contains("gpt") {
             return Ok(PartitionTableType::Gpt);
@@ -349,11 +792,16 @@ fn detect_partition_table_type(device: &str) -> UsbCreatorResult<PartitionTableT
 }
 
 /// Build partition path that works for /dev/sdX and /dev/nvmeXpY devices
-fn build_partition_path(device: &str, partition_number: u32) -> String {
-    if device.chars().last().map(|c| c.is_ascii_digit()).unwrap_or(false) {
-        format!("{}p{}", device, partition_number)
-    } else {
-        format!("{}{}", device, partition_number)
+pub(crate) fn build_partition_path(device: &str, partition_number: u32) -> String {
+    crate::utils::partition_path(device, partition_number)
+}
+
+/// `error` of a partitioning or formatting step on `usb_device`, turned into a mount error naming
+/// the partition when a desktop automounter mounted one again between steps
+pub(crate) fn with_remount_context(usb_device: &str, error: UsbCreatorError) -> UsbCreatorError {
+    match remount_hint(usb_device) {
+        Some(hint) => UsbCreatorError::MountError(format!("{}: {}", error, hint)),
+        None => error,
     }
 }
 
@@ -381,7 +829,7 @@ fn unmount_device_partitions(device: &str) -> UsbCreatorResult<Vec<(String, Stri
     }
     // Give the kernel/udev a moment to release the device
     settle_udev();
-    thread::sleep(Duration::from_millis(200));
+    thread::sleep(Tuning::for_device(device).settle(200));
     Ok(mounts)
 }
 
@@ -397,24 +845,52 @@ fn ensure_free_space(device: &str, start_sector: u64, total_sectors: u64, size_m
     }
     let needed = size_mb + SAFETY_MARGIN_MB;
     if free_mb < needed {
-        return Err(UsbCreatorError::validation_error(
-            format!(
-                "Persistence size {} MB exceeds available space {} MB ({} MB safety margin)",
-                size_mb, free_mb, SAFETY_MARGIN_MB
-            ),
-        ));
+        return Err(UsbCreatorError::InsufficientSpace {
+            requested_mb: size_mb,
+            max_mb: free_mb - SAFETY_MARGIN_MB,
+        });
     }
     Ok(())
 }
 
+/// Ensure a user-chosen placement lies entirely inside one free region of the device
+fn ensure_region_fits(device: &str, start_sector: u64, total_sectors: u64, size_mb: u64) -> UsbCreatorResult<()> {
+    let partitions = read_partitions(device)
+        .map_err(|e| UsbCreatorError::Io(e, format!("Failed to read partitions on {}", device)))?;
+    let end_sector = start_sector + (size_mb * 2048).saturating_sub(1);
+    let regions = find_free_regions(&partitions, total_sectors, 1);
+    match regions.iter().find(|region| region.contains(start_sector, start_sector)) {
+        Some(region) if region.contains(start_sector, end_sector) => Ok(()),
+        // The placement is free but the region ends too early: report what would fit there
+        Some(region) => Err(UsbCreatorError::InsufficientSpace {
+            requested_mb: size_mb,
+            max_mb: (region.end_sector - start_sector + 1) / 2048,
+        }),
+        None => Err(UsbCreatorError::validation_error(format!(
+            "Selected placement {}s-{}s on {} overlaps an existing partition or the end of the device",
+            start_sector, end_sector, device
+        ))),
+    }
+}
+
+/// Free regions where persistence could be placed once `iso_path` is written to `usb_device`.
+/// The ISO's own partition table (read from the image file) is laid over the device size.
+pub fn list_persistence_regions(iso_path: &str, usb_device: &str) -> UsbCreatorResult<Vec<FreeRegion>> {
+    let partitions = read_partitions(iso_path)
+        .map_err(|e| UsbCreatorError::Io(e, format!("Failed to read partition table of {}", iso_path)))?;
+    let total_sectors = get_total_sectors(usb_device)?;
+    Ok(find_free_regions(&partitions, total_sectors, (SAFETY_MARGIN_MB + 1) * 2048))
+}
+
 /// Refresh partition table with retries to avoid races right after dd
 fn refresh_partition_table(device: &str) -> UsbCreatorResult<()> {
-    for attempt in 1..=TABLE_REFRESH_ATTEMPTS {
-        println!("[PERSISTENCE] Refreshing partition table (attempt {}/{})...", attempt, TABLE_REFRESH_ATTEMPTS);
+    let attempts = Tuning::for_device(device).table_refresh_attempts;
+    for attempt in 1..=attempts {
+        println!("[PERSISTENCE] Refreshing partition table (attempt {}/{})...", attempt, attempts);
         let _ = Command::new("sync").status();
         let _ = Command::new("partprobe").arg(device).status();
         settle_udev();
-        thread::sleep(Duration::from_millis(300));
+        thread::sleep(Tuning::for_device(device).settle(300));
         // Probe with parted print; success means kernel sees the table
         match run_command_with_output("parted", &["-ms", device, "unit", "s", "print"]) {
             Ok(_) => return Ok(()),
@@ -428,46 +904,158 @@ fn refresh_partition_table(device: &str) -> UsbCreatorResult<()> {
             }
         }
     }
-    Err(UsbCreatorError::validation_error(
-        "Kernel did not refresh partition table after write; aborting persistence creation",
-    ))
+    Err(UsbCreatorError::validation_error(TABLE_REFRESH_FAILED))
+}
+
+/// Create the persistence partition, recovering once from a stale partition table.
+/// When the kernel never picks up the new table (some USB controllers need a bus reset),
+/// the device is rescanned (or powered off and re-enumerated) and creation is retried.
+pub fn create_persistence_partition_with_recovery(
+    usb_device: &str,
+    config: &PersistenceConfig,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
+) -> UsbCreatorResult<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    // Sanitize up front so a retry does not record the label warnings a second time
+    let config = &with_sanitized_label(config, log, warnings)?;
+    match create_persistence_partition(usb_device, config, log, warnings) {
+        Err(e) if is_table_refresh_failure(&e) => {
+            println!("[PERSISTENCE] Partition table probe failed; attempting device rescan and retry...");
+            if let Err(rescan_err) = rescan_device(usb_device) {
+                println!("[PERSISTENCE] Device rescan failed: {}", rescan_err);
+                return Err(e);
+            }
+            create_persistence_partition(usb_device, config, log, warnings)
+        }
+        other => other,
+    }
+}
+
+fn is_table_refresh_failure(err: &UsbCreatorError) -> bool {
+    matches!(err, UsbCreatorError::ValidationError(msg) if msg == TABLE_REFRESH_FAILED)
+}
+
+/// Force the kernel to re-enumerate a USB device so it presents a fresh partition table.
+/// Tries a SCSI rescan via sysfs first, then falls back to `udisksctl power-off`.
+fn rescan_device(device: &str) -> UsbCreatorResult<()> {
+    let name = device.trim_start_matches("/dev/");
+    let rescan_path = format!("/sys/block/{}/device/rescan", name);
+    let _ = Command::new("sync").status();
+
+    match fs::write(&rescan_path, "1") {
+        Ok(()) => {
+            println!("[PERSISTENCE] Triggered SCSI rescan via {}", rescan_path);
+        }
+        Err(e) => {
+            println!(
+                "[PERSISTENCE] SCSI rescan unavailable ({}); powering off {} for re-enumeration...",
+                e, device
+            );
+            run_command("udisksctl", &["power-off", "-b", device])?;
+            // The node disappears on power-off; wait for it to go away before waiting for it to return
+            for _ in 0..10 {
+                if !std::path::Path::new(device).exists() {
+                    break;
+                }
+                thread::sleep(Tuning::for_device(device).settle(500));
+            }
+        }
+    }
+
+    wait_for_reenumeration(device)
+}
+
+/// Wait until the block device node exists again after a rescan or power cycle
+fn wait_for_reenumeration(device: &str) -> UsbCreatorResult<()> {
+    let timeout_secs = Tuning::for_device(device).reenumeration_timeout_secs;
+    for attempt in 1..=timeout_secs {
+        if std::path::Path::new(device).exists() {
+            settle_udev();
+            thread::sleep(Tuning::for_device(device).settle(500));
+            println!("[PERSISTENCE] {} is present again after rescan.", device);
+            return Ok(());
+        }
+        println!(
+            "[PERSISTENCE] Waiting for {} to re-enumerate ({}/{}s)...",
+            device, attempt, timeout_secs
+        );
+        thread::sleep(Duration::from_secs(1));
+    }
+    Err(UsbCreatorError::partition_error(format!(
+        "{} did not re-appear within {}s after rescan; unplug and reconnect the drive, then retry",
+        device, timeout_secs
+    )))
 }
 
 /// Wait for partition node to appear after mkpart
 fn wait_for_partition_node(partition_path: &str, device: &str) -> UsbCreatorResult<()> {
-    for attempt in 1..=TABLE_REFRESH_ATTEMPTS * 3 {
-        if std::path::Path::new(partition_path).exists() {
+    let attempts = Tuning::for_device(device).table_refresh_attempts * 3;
+    for attempt in 1..=attempts {
+        if is_block_device(partition_path) {
             return Ok(());
         }
         println!(
             "[PERSISTENCE] Waiting for {} to appear (attempt {}/{})...",
-            partition_path, attempt, TABLE_REFRESH_ATTEMPTS * 3
+            partition_path, attempt, attempts
         );
         let _ = Command::new("partprobe").arg(device).status();
         let _ = Command::new("partx").args(["-u", device]).status();
         let _ = Command::new("blockdev").args(["--rereadpt", device]).status();
         let _ = Command::new("hdparm").args(["-z", device]).status();
         settle_udev();
-        thread::sleep(Duration::from_millis(600));
+        thread::sleep(Tuning::for_device(device).settle(600));
     }
     Err(UsbCreatorError::validation_error(format!(
         "Partition node {} did not appear after creation",
         partition_path
     )))
 }
+
+/// Poll (with `udevadm settle`) until `partition_path` is an openable block device, for at most
+/// `timeout`. Unlike `wait_for_partition_node` this does not poke the partition table again.
+pub(crate) fn wait_for_block_node(partition_path: &str, timeout: Duration) -> UsbCreatorResult<()> {
+    let started = std::time::Instant::now();
+    loop {
+        settle_udev();
+        if is_block_device(partition_path) {
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            return Err(UsbCreatorError::partition_error(format!(
+                "{} was created but its device node did not appear within {}s; replug the device and try again",
+                partition_path,
+                timeout.as_secs()
+            )));
+        }
+        println!("[PERSISTENCE] Waiting for {} before formatting...", partition_path);
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// True when `path` is a block device that can be opened (udev may create the node early)
+fn is_block_device(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path).is_ok_and(|meta| meta.file_type().is_block_device()) && fs::File::open(path).is_ok()
+}
+
 /// Try to relocate the GPT backup header to the end of the device (best effort).
 /// This is needed for hybrid ISOs whose backup GPT sits at the end of the image,
 /// leaving free space unreachable until the header is moved.
-fn maybe_expand_gpt(device: &str) -> UsbCreatorResult<()> {
+pub(crate) fn maybe_expand_gpt(device: &str, warnings: &mut Vec<Warning>) -> UsbCreatorResult<()> {
     match Command::new("sgdisk").args(["-e", device]).output() {
         Ok(output) => {
             if output.status.success() {
                 println!("[PERSISTENCE] Expanded GPT to end of device.");
             } else {
-                println!(
-                    "[PERSISTENCE] Warning: sgdisk -e failed ({}). Continuing.",
-                    String::from_utf8_lossy(&output.stderr).trim()
-                );
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                println!("[PERSISTENCE] Warning: sgdisk -e failed ({}). Continuing.", stderr.trim());
+                warnings.push(Warning::new(
+                    WarningKind::Partitioning,
+                    format!("GPT backup header was not moved to the end of {}: {}", device, stderr.trim()),
+                ));
             }
         }
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -534,7 +1122,7 @@ fn append_partition_with_sfdisk(device: &str, start_sector: u64, end_sector: u64
 
 /// Setup Casper persistence (Ubuntu/Debian)
 fn setup_casper_persistence(partition_path: &str, _config: &PersistenceConfig) -> UsbCreatorResult<()> {
-    let mount_dir = tempfile::tempdir()?;
+    let mount_dir = create_temp_dir()?;
 
     // Mount the persistence partition
     run_command("mount", &[partition_path, mount_dir.path().to_str().unwrap()])?;
@@ -565,7 +1153,7 @@ fn setup_casper_persistence(partition_path: &str, _config: &PersistenceConfig) -
 
 /// Setup OverlayFS persistence
 fn setup_overlayfs_persistence(partition_path: &str, _config: &PersistenceConfig) -> UsbCreatorResult<()> {
-    let mount_dir = tempfile::tempdir()?;
+    let mount_dir = create_temp_dir()?;
 
     // Mount the persistence partition
     run_command("mount", &[partition_path, mount_dir.path().to_str().unwrap()])?;
@@ -592,52 +1180,163 @@ fn setup_overlayfs_persistence(partition_path: &str, _config: &PersistenceConfig
 
 /// Inject kernel parameters for overlay persistence (Fedora/OverlayFS) if boot configs are writable.
 pub fn inject_overlay_kernel_params(usb_device: &str, overlay_label: &str) {
+    inject_kernel_params(
+        usb_device,
+        &default_kernel_params(&PersistenceType::OverlayFS, overlay_label),
+    );
+}
+
+/// Boot loader whose config files carry the kernel command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bootloader {
+    /// `linux`/`linuxefi`/`linux16` lines in grub.cfg
+    Grub,
+    /// `options` lines in loader/entries/*.conf
+    SystemdBoot,
+    /// `APPEND` lines in isolinux/syslinux configs
+    Syslinux,
+}
+
+/// Directories that may hold GRUB configs on live media
+const GRUB_DIRS: &[&str] = &["boot/grub", "boot/grub2", "EFI/BOOT", "EFI/fedora", "EFI/ubuntu", "EFI/debian"];
+/// Directories that may hold isolinux/syslinux configs on live media
+const SYSLINUX_DIRS: &[&str] = &["isolinux", "syslinux", "boot/isolinux", "boot/syslinux", ""];
+
+/// Find the boot loader config files under a mounted boot partition, tagged with their loader.
+pub fn detect_bootloader_configs(root: &Path) -> Vec<(Bootloader, PathBuf)> {
+    let mut found = Vec::new();
+
+    // systemd-boot: one entry file per boot option
+    if let Ok(entries) = fs::read_dir(root.join("loader").join("entries")) {
+        let mut confs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .collect();
+        confs.sort();
+        found.extend(confs.into_iter().map(|path| (Bootloader::SystemdBoot, path)));
+    }
+
+    for dir in GRUB_DIRS {
+        for name in ["grub.cfg", "grub2.cfg", "loopback.cfg"] {
+            let path = root.join(dir).join(name);
+            if path.is_file() {
+                found.push((Bootloader::Grub, path));
+            }
+        }
+    }
+
+    // syslinux menus are often split across included files (e.g. archiso_sys-linux.cfg)
+    for dir in SYSLINUX_DIRS {
+        let dir_path = root.join(dir);
+        let is_syslinux_dir = dir_path.join("isolinux.cfg").is_file() || dir_path.join("syslinux.cfg").is_file();
+        if !is_syslinux_dir {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&dir_path) else { continue };
+        let mut cfgs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "cfg"))
+            .filter(|path| !path.file_name().is_some_and(|name| name == "grub.cfg" || name == "loopback.cfg"))
+            .collect();
+        cfgs.sort();
+        found.extend(cfgs.into_iter().map(|path| (Bootloader::Syslinux, path)));
+    }
+
+    found
+}
+
+/// Append `params` to the kernel command lines of the boot configs found on the first two partitions.
+/// Returns how many config files were updated (read-only ISO9660 configs cannot be).
+pub fn inject_kernel_params(usb_device: &str, params: &[String]) -> usize {
     let candidate_parts = [build_partition_path(usb_device, 1), build_partition_path(usb_device, 2)];
-    let candidate_configs = [
-        "EFI/BOOT/grub.cfg",
-        "EFI/fedora/grub.cfg",
-        "EFI/BOOT/grub2.cfg",
-        "isolinux/isolinux.cfg",
-        "syslinux/isolinux.cfg",
-        "syslinux/syslinux.cfg",
-        "isolinux.cfg",
-    ];
-    let param = format!("rd.live.overlay=LABEL={}", overlay_label);
+    let mut updated_count = 0;
 
     for part in candidate_parts.iter() {
-        let mnt = match tempfile::tempdir() {
+        let mnt = match create_temp_dir() {
             Ok(dir) => dir,
-            Err(_) => continue,
+            Err(e) => {
+                println!("[PERSISTENCE] Skipping boot config update on {}: {}", part, e);
+                continue;
+            }
         };
         if run_command("mount", &[part.as_str(), mnt.path().to_str().unwrap()]).is_err() {
             continue;
         }
-        for cfg in candidate_configs.iter() {
-            let path = mnt.path().join(cfg);
-            if !path.exists() {
-                continue;
-            }
-            if let Ok(contents) = fs::read_to_string(&path) {
-                if contents.contains(&param) {
-                    continue;
-                }
-                let mut new_lines = Vec::new();
-                for line in contents.lines() {
-                    if line.trim_start().starts_with("linux") || line.trim_start().starts_with("linuxefi") {
-                        new_lines.push(format!("{} {}", line, param));
-                    } else if line.trim_start().starts_with("append") {
-                        new_lines.push(format!("{} {}", line, param));
-                    } else {
-                        new_lines.push(line.to_string());
+        let configs = detect_bootloader_configs(mnt.path());
+        if configs.is_empty() {
+            println!("[PERSISTENCE] No boot loader configs found on {}", part);
+        }
+        for (bootloader, path) in configs {
+            let shown = path.strip_prefix(mnt.path()).unwrap_or(&path).display().to_string();
+            if let Ok(contents) = fs::read_to_string(&path)
+                && let Some(updated) = append_kernel_params(bootloader, &contents, params)
+            {
+                match fs::write(&path, updated) {
+                    Ok(()) => {
+                        updated_count += 1;
+                        println!(
+                            "[PERSISTENCE] Added kernel parameters [{}] to {} ({:?})",
+                            params.join(" "),
+                            shown,
+                            bootloader
+                        )
                     }
-                }
-                if fs::write(&path, new_lines.join("\n")).is_ok() {
-                    println!("[PERSISTENCE] Added overlay kernel parameter to {}", path.display());
+                    Err(e) => println!("[PERSISTENCE] Could not update {}: {}", shown, e),
                 }
             }
         }
         let _ = run_command("umount", &[part.as_str()]);
     }
+    updated_count
+}
+
+/// True for lines holding the kernel command line in the given loader's syntax
+fn is_cmdline_line(bootloader: Bootloader, trimmed: &str) -> bool {
+    let keyword = trimmed.split_whitespace().next().unwrap_or("");
+    match bootloader {
+        Bootloader::Grub => matches!(keyword, "linux" | "linuxefi" | "linux16"),
+        Bootloader::SystemdBoot => keyword == "options",
+        // syslinux `LINUX` names the kernel image; the command line lives in `APPEND`
+        Bootloader::Syslinux => keyword.eq_ignore_ascii_case("append"),
+    }
+}
+
+/// Append the missing `params` to every kernel command line in `contents`. systemd-boot
+/// entries without an `options` line get one. Returns None when nothing needed changes.
+fn append_kernel_params(bootloader: Bootloader, contents: &str, params: &[String]) -> Option<String> {
+    let mut changed = false;
+    let mut saw_cmdline = false;
+    let mut new_lines = Vec::new();
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if is_cmdline_line(bootloader, trimmed) {
+            saw_cmdline = true;
+            let present: Vec<&str> = trimmed.split_whitespace().collect();
+            let missing: Vec<&str> = params
+                .iter()
+                .map(String::as_str)
+                .filter(|param| !present.contains(param))
+                .collect();
+            if !missing.is_empty() {
+                changed = true;
+                new_lines.push(format!("{} {}", line.trim_end(), missing.join(" ")));
+                continue;
+            }
+        }
+        new_lines.push(line.to_string());
+    }
+    if bootloader == Bootloader::SystemdBoot && !saw_cmdline && !params.is_empty() {
+        changed = true;
+        new_lines.push(format!("options {}", params.join(" ")));
+    }
+    if !changed {
+        return None;
+    }
+    let mut updated = new_lines.join("\n");
+    if contents.ends_with('\n') || !saw_cmdline {
+        updated.push('\n');
+    }
+    Some(updated)
 }
 
 /// Setup custom persistence method
@@ -646,7 +1345,7 @@ fn setup_custom_persistence(
     config: &PersistenceConfig,
     method: &str,
 ) -> UsbCreatorResult<()> {
-    let mount_dir = tempfile::tempdir()?;
+    let mount_dir = create_temp_dir()?;
 
     // Mount the persistence partition
     run_command("mount", &[partition_path, mount_dir.path().to_str().unwrap()])?;
@@ -667,40 +1366,203 @@ fn setup_custom_persistence(
 
 /// Detect the appropriate persistence type for a Linux ISO
 pub fn detect_persistence_type(iso_path: &str) -> UsbCreatorResult<PersistenceType> {
-    let mount_dir = tempfile::tempdir()?;
-
-    // Mount ISO temporarily to check for distribution type
-    run_command("mount", &[
-        "-o", "loop,ro", iso_path, mount_dir.path().to_str().unwrap()
-    ])?;
-
-    let _cleanup = scopeguard::guard((), |_| {
-        let _ = run_command("umount", &[mount_dir.path().to_str().unwrap()]);
-    });
-
-    // Check for distribution-specific markers
-    let mount_path = mount_dir.path();
+    with_mounted_iso(iso_path, |mount_path| Ok(persistence_type_for_root(mount_path)))
+}
 
+/// Pick the persistence mechanism from the markers found at the ISO root
+pub(crate) fn persistence_type_for_root(mount_path: &Path) -> PersistenceType {
     // Ubuntu/Debian detection
     if mount_path.join("casper").exists()
         || mount_path.join("disk").join("casper").exists()
         || mount_path.join(".disk").exists()
     {
-        return Ok(PersistenceType::Casper);
+        return PersistenceType::Casper;
     }
 
     // Fedora detection
     if mount_path.join("LiveOS").exists() || mount_path.join("isolinux").exists() {
-        return Ok(PersistenceType::OverlayFS);
+        return PersistenceType::OverlayFS;
     }
 
     // Arch detection
     if mount_path.join("arch").exists() || mount_path.join("airootfs").exists() {
-        return Ok(PersistenceType::OverlayFS);
+        return PersistenceType::OverlayFS;
     }
 
     // Default to OverlayFS for unknown distributions
-    Ok(PersistenceType::OverlayFS)
+    PersistenceType::OverlayFS
+}
+
+/// Distribution name and version read from a Linux ISO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinuxDistroInfo {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+/// Identify the distribution on a Linux ISO from `.disk/info`, `arch/version` or the volume label.
+/// Returns Ok(None) when the ISO carries none of these.
+pub fn detect_linux_distro(iso_path: &str) -> UsbCreatorResult<Option<LinuxDistroInfo>> {
+    let from_files = with_mounted_iso(iso_path, |mount_path| Ok(distro_for_root(mount_path)))?;
+    if from_files.is_some() {
+        return Ok(from_files);
+    }
+
+    // Fedora and others only identify themselves through the volume label (e.g. Fedora-WS-Live-40-1-14)
+    let output = Command::new("blkid")
+        .args(["-o", "value", "-s", "LABEL", iso_path])
+        .output()
+        .map_err(|e| UsbCreatorError::Io(e, "Failed to spawn blkid".to_string()))?;
+    Ok(parse_distro_string(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Distribution named by `.disk/info` or `arch/version` under an ISO (or written partition) root
+pub(crate) fn distro_for_root(root: &Path) -> Option<LinuxDistroInfo> {
+    if let Ok(info) = fs::read_to_string(root.join(".disk").join("info")) {
+        return parse_distro_string(&info);
+    }
+    let version = fs::read_to_string(root.join("arch").join("version")).ok()?;
+    Some(LinuxDistroInfo {
+        name: "Arch Linux".to_string(),
+        version: Some(version.trim().to_string()).filter(|v| !v.is_empty()),
+    })
+}
+
+/// Split a release string such as `Ubuntu 24.04 LTS "Noble Numbat" - Release amd64` into
+/// name and version: the name is everything before the first token starting with a digit.
+fn parse_distro_string(text: &str) -> Option<LinuxDistroInfo> {
+    let line = text.lines().next()?.trim();
+    let mut name_parts = Vec::new();
+    let mut version = None;
+    for token in line.split(|c: char| c.is_whitespace() || c == '-' || c == '_') {
+        if token.is_empty() {
+            continue;
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            version = Some(token.to_string());
+            break;
+        }
+        if token.starts_with('"') {
+            break;
+        }
+        name_parts.push(token);
+    }
+    if name_parts.is_empty() {
+        return None;
+    }
+    Some(LinuxDistroInfo { name: name_parts.join(" "), version })
+}
+
+/// How an ISO's x86_64 UEFI loader will fare on a machine with Secure Boot enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureBootSupport {
+    /// Signed shim chain-loading GRUB: boots with Secure Boot on
+    SignedShim,
+    /// Signed loader without shim; boots only if its key is enrolled
+    SignedLoader,
+    /// Unsigned loader: Secure Boot must be disabled
+    Unsigned,
+    /// No `EFI/BOOT/BOOTX64.EFI`; the image boots in legacy BIOS mode only
+    NoUefiLoader,
+}
+
+impl SecureBootSupport {
+    /// Short text for the OS label
+    pub fn description(self) -> &'static str {
+        match self {
+            SecureBootSupport::SignedShim => "Secure Boot: supported (signed shim)",
+            SecureBootSupport::SignedLoader => "Secure Boot: signed loader without shim",
+            SecureBootSupport::Unsigned => "Secure Boot: not supported (unsigned loader)",
+            SecureBootSupport::NoUefiLoader => "Secure Boot: no UEFI loader",
+        }
+    }
+}
+
+/// Inspect the mounted ISO's `EFI/BOOT` directory for a signed shim
+pub fn check_secure_boot_support(iso_path: &str) -> UsbCreatorResult<SecureBootSupport> {
+    with_mounted_iso(iso_path, |root| Ok(secure_boot_support_for_root(root)))
+}
+
+fn secure_boot_support_for_root(root: &Path) -> SecureBootSupport {
+    // ISO9660 without Rock Ridge may present names in either case
+    let Some(boot_dir) = find_entry_ignore_case(root, "EFI").and_then(|efi| find_entry_ignore_case(&efi, "BOOT"))
+    else {
+        return SecureBootSupport::NoUefiLoader;
+    };
+    let Some(loader) = find_entry_ignore_case(&boot_dir, "BOOTX64.EFI") else {
+        return SecureBootSupport::NoUefiLoader;
+    };
+    let Ok(bytes) = fs::read(&loader) else {
+        return SecureBootSupport::NoUefiLoader;
+    };
+    if !pe_has_signature(&bytes) {
+        return SecureBootSupport::Unsigned;
+    }
+    // shim always sits next to the GRUB it chain-loads and embeds its own name
+    let has_grub = find_entry_ignore_case(&boot_dir, "GRUBX64.EFI").is_some();
+    let is_shim = bytes.windows(9).any(|window| window == b"UEFI SHIM");
+    if has_grub && is_shim {
+        SecureBootSupport::SignedShim
+    } else {
+        SecureBootSupport::SignedLoader
+    }
+}
+
+fn find_entry_ignore_case(dir: &Path, name: &str) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_name().to_string_lossy().eq_ignore_ascii_case(name))
+        .map(|entry| entry.path())
+}
+
+/// True when a PE image carries an Authenticode certificate table (data directory 4)
+fn pe_has_signature(bytes: &[u8]) -> bool {
+    let read_u16 = |at: usize| bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let read_u32 = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    if bytes.get(..2) != Some(b"MZ") {
+        return false;
+    }
+    let Some(pe_offset) = read_u32(0x3c).map(|offset| offset as usize) else {
+        return false;
+    };
+    if bytes.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+        return false;
+    }
+    // Optional header follows the 4-byte signature and the 20-byte COFF header
+    let optional = pe_offset + 24;
+    let directories = match read_u16(optional) {
+        Some(0x10b) => optional + 96,
+        Some(0x20b) => optional + 112,
+        _ => return false,
+    };
+    read_u32(directories + 4 * 8 + 4).is_some_and(|size| size > 0)
+}
+
+/// Create a temporary mount point, with an actionable error when the temp directory is unusable
+pub(crate) fn create_temp_dir() -> UsbCreatorResult<tempfile::TempDir> {
+    tempfile::tempdir().map_err(|e| UsbCreatorError::temp_dir_failed(&std::env::temp_dir(), e))
+}
+
+/// Mount an ISO read-only on a temporary directory for the duration of `inspect`.
+/// Runs quietly so headless callers can keep stdout machine-readable.
+pub(crate) fn with_mounted_iso<T>(
+    iso_path: &str,
+    inspect: impl FnOnce(&Path) -> UsbCreatorResult<T>,
+) -> UsbCreatorResult<T> {
+    crate::utils::check_iso_readable(iso_path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => UsbCreatorError::PermissionError(e.to_string()),
+        _ => UsbCreatorError::IsoDetectionError(e.to_string()),
+    })?;
+    let mount_dir = create_temp_dir()?;
+    crate::utils::mount_iso_readable(iso_path, mount_dir.path(), "loop")
+        .map_err(|stderr| UsbCreatorError::command_failed("mount", &stderr))?;
+
+    let _cleanup = scopeguard::guard((), |_| {
+        let _ = Command::new("umount").arg(mount_dir.path()).status();
+    });
+
+    inspect(mount_dir.path())
 }
 
 /// Validate persistence configuration
@@ -728,6 +1590,17 @@ pub fn validate_persistence_config(config: &PersistenceConfig) -> UsbCreatorResu
         ));
     }
 
+    if config.reserved_percent > MAX_RESERVED_PERCENT {
+        return Err(UsbCreatorError::validation_error(format!(
+            "Reserved space cannot exceed {}%",
+            MAX_RESERVED_PERCENT
+        )));
+    }
+
+    for param in &config.kernel_params {
+        validate_kernel_param(param)?;
+    }
+
     Ok(())
 }
 
@@ -787,10 +1660,13 @@ fn run_command(cmd: &str, args: &[&str]) -> UsbCreatorResult<()> {
 }
 
 fn run_command_with_output(cmd: &str, args: &[&str]) -> UsbCreatorResult<String> {
+    run_command_with_output_via(&SystemRunner, cmd, args)
+}
+
+fn run_command_with_output_via(runner: &dyn CommandRunner, cmd: &str, args: &[&str]) -> UsbCreatorResult<String> {
     println!("[PERSISTENCE] Running command: {} {}", cmd, args.join(" "));
-    let output = Command::new(cmd)
-        .args(args)
-        .output()
+    let output = runner
+        .output(cmd, args)
         .map_err(|e| UsbCreatorError::Io(e, format!("Failed to spawn {}", cmd)))?;
 
     if output.status.success() {
@@ -807,3 +1683,316 @@ fn run_command_with_output(cmd: &str, args: &[&str]) -> UsbCreatorResult<String>
         Err(UsbCreatorError::command_failed(cmd, stderr.trim()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::MockRunner;
+
+    const PARTED_PRINT: &str = "parted -ms /dev/sdz unit s print";
+    const PARTED_GPT: &str = "BYT;\n\
+        /dev/sdz:30031872s:scsi:512:512:gpt:SanDisk Cruzer Blade:;\n\
+        1:64s:5425151s:5425088s::ISO9660:hidden, msftdata;\n\
+        2:5425152s:5433343s:8192s:fat16:Appended2:boot, esp;\n";
+    const PARTED_PRINT_FREE: &str = "parted -ms /dev/sdz unit s print free";
+    const PARTED_GPT_FREE: &str = "BYT;\n\
+        /dev/sdz:30031872s:scsi:512:512:gpt:SanDisk Cruzer Blade:;\n\
+        1:34s:63s:30s:free;\n\
+        1:64s:5425151s:5425088s::ISO9660:hidden, msftdata;\n\
+        2:5425152s:5433343s:8192s:fat16:Appended2:boot, esp;\n\
+        1:5433344s:30031838s:24598495s:free;\n";
+    const PARTED_MSDOS_EXTENDED_FREE: &str = "BYT;\n\
+        /dev/sdz:30031872s:scsi:512:512:msdos:SanDisk Cruzer Blade:;\n\
+        1:32s:2047s:2016s:free;\n\
+        1:2048s:4196351s:4194304s:fat32::boot, lba;\n\
+        2:4196352s:12584959s:8388608s:::lba;\n\
+        5:4198400s:8392703s:4194304s:ext4::;\n\
+        1:8392704s:12584959s:4192256s:free;\n\
+        1:12584960s:30031871s:17446912s:free;\n";
+    const PARTED_MSDOS: &str = "BYT;\n\
+        /dev/sdz:30031872s:scsi:512:512:msdos:SanDisk Cruzer Blade:;\n\
+        1:0s:4173823s:4173824s:::boot, hidden;\n\
+        2:4173824s:4194303s:20480s:fat16::esp;\n";
+
+    #[test]
+    fn next_sector_comes_from_parted_and_falls_back_to_lsblk() {
+        let runner = MockRunner::new().with_output(PARTED_PRINT_FREE, PARTED_GPT_FREE);
+        assert_eq!(find_next_available_sector(&runner, "/dev/sdz").unwrap(), 5_433_344);
+
+        let runner = MockRunner::new()
+            .with_failure(PARTED_PRINT_FREE, "Error: /dev/sdz: unrecognised disk label")
+            .with_output(
+                "lsblk -b -ln -o NAME,START,SECTORS,TYPE /dev/sdz",
+                "sdz           30031872 disk\nsdz1        0  4173824 part\nsdz2  4173824    20480 part\n",
+            );
+        assert_eq!(find_next_available_sector(&runner, "/dev/sdz").unwrap(), 4_194_304);
+
+        // Nothing to parse: the first aligned sector past the reserved first MiB
+        assert_eq!(find_next_available_sector(&MockRunner::new(), "/dev/sdz").unwrap(), 4096);
+    }
+
+    #[test]
+    fn free_rows_and_extended_partitions_do_not_move_the_start() {
+        let rows = parse_parted_rows(PARTED_MSDOS_EXTENDED_FREE);
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[2], PartedRow::Partition { number: 2, start: 4_196_352, end: 12_584_959 });
+        assert_eq!(rows[4], PartedRow::Free { start: 8_392_704, end: 12_584_959 });
+
+        // The gap inside the extended partition cannot hold a new primary partition
+        let runner = MockRunner::new().with_output(PARTED_PRINT_FREE, PARTED_MSDOS_EXTENDED_FREE);
+        assert_eq!(find_next_available_sector(&runner, "/dev/sdz").unwrap(), 12_584_960);
+
+        // Unaligned free space is rounded up to the next MiB, and the largest region wins
+        let rows = [
+            PartedRow::Partition { number: 1, start: 64, end: 5_000_000 },
+            PartedRow::Free { start: 5_000_001, end: 5_001_000 },
+            PartedRow::Partition { number: 2, start: 5_001_001, end: 5_100_000 },
+            PartedRow::Free { start: 5_100_001, end: 6_000_000 },
+        ];
+        assert_eq!(largest_free_start_after(&rows, 5_100_000), Some(5_101_568));
+        assert_eq!(largest_free_start_after(&rows[..3], 5_100_000), None);
+    }
+
+    #[test]
+    fn next_partition_number_follows_the_highest_existing_one() {
+        let runner = MockRunner::new().with_output("lsblk -ln -o NAME /dev/sdz", "sdz\nsdz1\nsdz2\n");
+        assert_eq!(find_next_partition_number(&runner, "/dev/sdz").unwrap(), 3);
+        let runner = MockRunner::new().with_output("lsblk -ln -o NAME /dev/nvme0n1", "nvme0n1\nnvme0n1p1\nnvme0n1p4\n");
+        assert_eq!(find_next_partition_number(&runner, "/dev/nvme0n1").unwrap(), 5);
+        assert!(find_next_partition_number(&MockRunner::new(), "/dev/sdz").is_err());
+    }
+
+    #[test]
+    fn partition_table_type_tries_parted_lsblk_then_blkid() {
+        let runner = MockRunner::new().with_output(PARTED_PRINT, PARTED_GPT);
+        assert_eq!(detect_partition_table_type(&runner, "/dev/sdz").unwrap(), PartitionTableType::Gpt);
+        let runner = MockRunner::new().with_output(PARTED_PRINT, PARTED_MSDOS);
+        assert_eq!(detect_partition_table_type(&runner, "/dev/sdz").unwrap(), PartitionTableType::Mbr);
+
+        let runner = MockRunner::new()
+            .with_failure(PARTED_PRINT, "Error: /dev/sdz: unrecognised disk label")
+            .with_output("lsblk -dn -o PTTYPE /dev/sdz", "\n")
+            .with_output("blkid -s PTTYPE -o value /dev/sdz", "gpt\n");
+        assert_eq!(detect_partition_table_type(&runner, "/dev/sdz").unwrap(), PartitionTableType::Gpt);
+        assert_eq!(
+            runner.calls(),
+            [PARTED_PRINT, "lsblk -dn -o PTTYPE /dev/sdz", "blkid -s PTTYPE -o value /dev/sdz"]
+        );
+
+        // Unknown layouts are treated as the MBR of a hybrid ISO
+        assert_eq!(detect_partition_table_type(&MockRunner::new(), "/dev/sdz").unwrap(), PartitionTableType::Mbr);
+    }
+
+
+    #[test]
+    fn persistence_phases_advance_the_progress_bar() {
+        let fractions: Vec<f64> = (0..=PERSISTENCE_PHASES.len())
+            .map(|index| crate::utils::parse_progress_line(&persistence_progress_line(index)).unwrap().0)
+            .collect();
+        assert_eq!(fractions, vec![0.0, 0.2, 0.4, 0.6, 0.8, 1.0]);
+        let (_, label) = crate::utils::parse_progress_line(&persistence_progress_line(1)).unwrap();
+        assert_eq!(label, "persistence: Formatting ext4 (step 2/5) (20%)");
+
+        // The flush moves the bar through the last phase
+        let (fraction, label) = crate::utils::parse_progress_line(&persistence_flush_line(300 << 20, 0)).unwrap();
+        assert_eq!((fraction, label.as_str()), (0.8, "persistence: Flushing changes, 300 MB left (step 5/5) (80%)"));
+        assert_eq!(crate::utils::parse_progress_line(&persistence_flush_line(0, 50)).unwrap().0, 0.9);
+        assert_eq!(crate::utils::parse_progress_line(&persistence_flush_line(0, 100)).unwrap().0, 1.0);
+    }
+
+    /// Minimal PE32+ header whose certificate table has `cert_size` bytes
+    fn fake_pe(cert_size: u32, marker: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 0x200];
+        bytes[..2].copy_from_slice(b"MZ");
+        bytes[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        bytes[0x80..0x84].copy_from_slice(b"PE\0\0");
+        bytes[0x98..0x9a].copy_from_slice(&0x20bu16.to_le_bytes());
+        let cert_dir = 0x98 + 112 + 4 * 8;
+        bytes[cert_dir + 4..cert_dir + 8].copy_from_slice(&cert_size.to_le_bytes());
+        bytes.extend_from_slice(marker);
+        bytes
+    }
+
+    #[test]
+    fn secure_boot_support_requires_signed_shim_next_to_grub() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(secure_boot_support_for_root(root.path()), SecureBootSupport::NoUefiLoader);
+
+        let boot = root.path().join("EFI").join("boot");
+        fs::create_dir_all(&boot).unwrap();
+        fs::write(boot.join("bootx64.efi"), fake_pe(0, b"UEFI SHIM")).unwrap();
+        assert_eq!(secure_boot_support_for_root(root.path()), SecureBootSupport::Unsigned);
+
+        fs::write(boot.join("bootx64.efi"), fake_pe(0x800, b"UEFI SHIM")).unwrap();
+        assert_eq!(secure_boot_support_for_root(root.path()), SecureBootSupport::SignedLoader);
+
+        fs::write(boot.join("grubx64.efi"), fake_pe(0x800, b"")).unwrap();
+        assert_eq!(secure_boot_support_for_root(root.path()), SecureBootSupport::SignedShim);
+        assert!(!pe_has_signature(b"MZ"));
+    }
+
+    #[test]
+    fn insufficient_space_reports_largest_fitting_size() {
+        // 4096 MB free after the ISO: 4096 - 512 MB margin fits
+        let total_sectors = 4096 * 2048;
+        assert!(ensure_free_space("/dev/sdz", 0, total_sectors, 3584).is_ok());
+        match ensure_free_space("/dev/sdz", 0, total_sectors, 8192) {
+            Err(UsbCreatorError::InsufficientSpace { requested_mb, max_mb }) => {
+                assert_eq!((requested_mb, max_mb), (8192, 3584));
+            }
+            other => panic!("expected insufficient space, got {:?}", other),
+        }
+        assert!(matches!(
+            ensure_free_space("/dev/sdz", 0, 256 * 2048, 100),
+            Err(UsbCreatorError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn only_table_refresh_failures_trigger_rescan() {
+        assert!(is_table_refresh_failure(&UsbCreatorError::validation_error(TABLE_REFRESH_FAILED)));
+        assert!(!is_table_refresh_failure(&UsbCreatorError::validation_error("Persistence size too large")));
+        assert!(!is_table_refresh_failure(&UsbCreatorError::partition_error(TABLE_REFRESH_FAILED)));
+    }
+
+    #[test]
+    fn remount_policy_restores_only_untouched_layouts_by_default() {
+        assert!(RemountPolicy::default().should_remount(false));
+        assert!(!RemountPolicy::default().should_remount(true));
+        assert!(RemountPolicy::Always.should_remount(true));
+        assert!(!RemountPolicy::Never.should_remount(false));
+    }
+
+    #[test]
+    fn reads_ext4_space_and_flags_tight_filesystems() {
+        const TUNE2FS: &str = "tune2fs 1.47.0 (5-Feb-2023)\n\
+            Filesystem volume name:   persistence\n\
+            Inode count:              32768\n\
+            Block count:              131072\n\
+            Reserved block count:     6553\n\
+            Free blocks:              116327\n\
+            Free inodes:              32757\n\
+            Block size:               4096\n";
+        let runner = MockRunner::new().with_output("tune2fs -l /dev/sdz3", TUNE2FS);
+        let space = read_ext4_space(&runner, "/dev/sdz3").unwrap();
+        assert_eq!(space, Ext4Space { available_bytes: 109_774 * 4096, reserved_bytes: 6553 * 4096, free_inodes: 32757 });
+        assert_eq!(space.constraint(), None);
+
+        let tight = Ext4Space { available_bytes: 100 * 1024 * 1024, reserved_bytes: 0, free_inodes: 4000 };
+        let message = tight.constraint().unwrap();
+        assert!(message.contains("only 100 MB are available"));
+        assert!(message.contains("only 4000 inodes are free"));
+        assert!(read_ext4_space(&MockRunner::new().with_output("tune2fs -l /dev/sdz3", "garbage"), "/dev/sdz3").is_err());
+
+        let config = PersistenceConfig { enabled: true, reserved_percent: 60, ..Default::default() };
+        assert!(validate_persistence_config(&config).is_err());
+    }
+
+    #[test]
+    fn user_kernel_params_extend_type_defaults() {
+        let config = PersistenceConfig {
+            persistence_type: PersistenceType::OverlayFS,
+            kernel_params: vec!["toram".to_string(), "rd.live.overlay=LABEL=persistence".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(config.effective_kernel_params(), vec!["rd.live.overlay=LABEL=persistence", "toram"]);
+        assert!(validate_kernel_param("nomodeset").is_ok());
+        assert!(validate_kernel_param("quiet;reboot").is_err());
+        assert!(validate_kernel_param("=value").is_err());
+        assert!(validate_kernel_param("").is_err());
+    }
+
+    #[test]
+    fn appends_only_missing_params_to_kernel_lines() {
+        let cfg = "menuentry 'Live' {\n  linux /vmlinuz quiet persistent\n  initrd /initrd\n}\n";
+        let params = vec!["persistent".to_string(), "toram".to_string()];
+        let updated = append_kernel_params(Bootloader::Grub, cfg, &params).unwrap();
+        assert!(updated.contains("  linux /vmlinuz quiet persistent toram\n"));
+        assert!(updated.ends_with("}\n"));
+        assert!(append_kernel_params(Bootloader::Grub, &updated, &params).is_none());
+    }
+
+    #[test]
+    fn injects_with_each_loader_syntax() {
+        let params = vec!["toram".to_string()];
+
+        let entry = "title Arch Linux\nlinux /arch/boot/vmlinuz\noptions archisobasedir=arch\n";
+        let updated = append_kernel_params(Bootloader::SystemdBoot, entry, &params).unwrap();
+        assert!(updated.contains("options archisobasedir=arch toram\n"));
+        assert!(updated.contains("linux /arch/boot/vmlinuz\n"));
+
+        let bare = "title Live\nlinux /vmlinuz\n";
+        let updated = append_kernel_params(Bootloader::SystemdBoot, bare, &params).unwrap();
+        assert!(updated.ends_with("options toram\n"));
+
+        let syslinux = "LABEL arch\nLINUX /arch/boot/vmlinuz\nAPPEND archisobasedir=arch\n";
+        let updated = append_kernel_params(Bootloader::Syslinux, syslinux, &params).unwrap();
+        assert!(updated.contains("APPEND archisobasedir=arch toram\n"));
+        assert!(updated.contains("LINUX /arch/boot/vmlinuz\n"));
+    }
+
+    #[test]
+    fn detects_bootloader_config_files() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("loader/entries")).unwrap();
+        fs::write(root.path().join("loader/entries/01-live.conf"), "title Live\n").unwrap();
+        fs::create_dir_all(root.path().join("boot/grub")).unwrap();
+        fs::write(root.path().join("boot/grub/grub.cfg"), "").unwrap();
+        fs::create_dir_all(root.path().join("syslinux")).unwrap();
+        fs::write(root.path().join("syslinux/syslinux.cfg"), "").unwrap();
+        fs::write(root.path().join("syslinux/archiso_sys-linux.cfg"), "").unwrap();
+
+        let found: Vec<(Bootloader, String)> = detect_bootloader_configs(root.path())
+            .into_iter()
+            .map(|(loader, path)| (loader, path.strip_prefix(root.path()).unwrap().display().to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Bootloader::SystemdBoot, "loader/entries/01-live.conf".to_string()),
+                (Bootloader::Grub, "boot/grub/grub.cfg".to_string()),
+                (Bootloader::Syslinux, "syslinux/archiso_sys-linux.cfg".to_string()),
+                (Bootloader::Syslinux, "syslinux/syslinux.cfg".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_distro_release_strings() {
+        let ubuntu = parse_distro_string("Ubuntu 24.04 LTS \"Noble Numbat\" - Release amd64 (20240424)\n").unwrap();
+        assert_eq!(ubuntu.name, "Ubuntu");
+        assert_eq!(ubuntu.version.as_deref(), Some("24.04"));
+
+        let debian = parse_distro_string("Debian GNU/Linux 12.5.0 \"Bookworm\" - Official amd64").unwrap();
+        assert_eq!(debian.name, "Debian GNU/Linux");
+        assert_eq!(debian.version.as_deref(), Some("12.5.0"));
+
+        let fedora = parse_distro_string("Fedora-WS-Live-40-1-14").unwrap();
+        assert_eq!(fedora.name, "Fedora WS Live");
+        assert_eq!(fedora.version.as_deref(), Some("40"));
+
+        assert!(parse_distro_string("").is_none());
+    }
+
+    #[test]
+    fn missing_partition_node_times_out_with_a_clear_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let regular_file = dir.path().join("sdz3");
+        fs::write(&regular_file, b"").unwrap();
+        assert!(!is_block_device(regular_file.to_str().unwrap()));
+        assert!(!is_block_device("/dev/null"));
+
+        let missing = dir.path().join("sdz4");
+        let err = wait_for_block_node(missing.to_str().unwrap(), Duration::ZERO).unwrap_err();
+        assert!(matches!(&err, UsbCreatorError::PartitionError(msg) if msg.contains("sdz4") && msg.contains("did not appear")));
+    }
+
+    #[test]
+    fn end_sector_stays_clear_of_the_backup_gpt() {
+        // 30031872 sectors: the last usable one is 30031837
+        assert_eq!(persistence_end_sector(5_435_392, 4096, 30_031_872).unwrap(), (13_824_000 - 1, false));
+        assert_eq!(persistence_end_sector(5_435_392, 12_010, 30_031_872).unwrap(), (30_031_837, true));
+        assert_eq!(persistence_end_sector(5_435_392, 12_009, 30_031_872).unwrap(), (30_029_823, false));
+        assert!(persistence_end_sector(30_031_837, 1, 30_031_872).is_err());
+    }
+}
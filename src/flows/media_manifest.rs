@@ -0,0 +1,212 @@
+//! SHA-256 manifest of the files written to a stick, stored next to the label file so the media
+//! can later be checked for tampering with `cli_helper verify-media`. Every partition with a
+//! filesystem is covered except ext partitions, which hold persistence data that changes by design.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::checksum::sha256_file;
+use crate::flows::media_scan::{label_partition, list_partitions, with_mounted_partition, ScannedPartition};
+use crate::utils::resolve_device_path;
+
+/// Manifest file, relative to the root of the partition that holds it
+pub const MANIFEST_FILE: &str = ".majusb/manifest.json";
+
+/// Directory of our own bookkeeping files, left out of the manifest since it changes after the write
+const BOOKKEEPING_DIR: &str = ".majusb";
+
+/// Hashes of one partition's files
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionManifest {
+    /// Position of the partition on the device, starting at 1 (device names differ between machines)
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Path relative to the partition root, with `/` separators, to hex SHA-256
+    pub files: BTreeMap<String, String>,
+}
+
+impl PartitionManifest {
+    /// Label or position, e.g. "BOOT" or "partition 2"
+    pub fn name(&self) -> String {
+        self.label.clone().unwrap_or_else(|| format!("partition {}", self.index))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaManifest {
+    pub partitions: Vec<PartitionManifest>,
+}
+
+/// Differences between a manifest and the media, as "<partition>: <path>"
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestCheck {
+    pub files: usize,
+    pub missing: Vec<String>,
+    pub changed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl ManifestCheck {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty() && self.added.is_empty()
+    }
+
+    /// Compare the recorded hashes of one partition with those found on it now
+    fn compare(&mut self, recorded: &PartitionManifest, found: &BTreeMap<String, String>) {
+        let name = recorded.name();
+        self.files += recorded.files.len();
+        for (path, hash) in &recorded.files {
+            match found.get(path) {
+                None => self.missing.push(format!("{}: {}", name, path)),
+                Some(actual) if actual != hash => self.changed.push(format!("{}: {}", name, path)),
+                Some(_) => {}
+            }
+        }
+        self.added.extend(
+            found
+                .keys()
+                .filter(|path| !recorded.files.contains_key(*path))
+                .map(|path| format!("{}: {}", name, path)),
+        );
+    }
+}
+
+/// Partitions the manifest covers: ones with a filesystem that is not ext2/3/4
+fn covered(partition: &ScannedPartition) -> bool {
+    partition.fstype.as_deref().is_some_and(|fs| !fs.starts_with("ext"))
+}
+
+/// SHA-256 of every regular file under `root`, skipping our bookkeeping directory and symlinks
+pub fn hash_tree(root: &Path) -> io::Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let kind = entry.file_type()?;
+            if kind.is_dir() {
+                if !(dir == root && entry.file_name() == BOOKKEEPING_DIR) {
+                    pending.push(path);
+                }
+            } else if kind.is_file() {
+                let rel = path.strip_prefix(root).unwrap_or(&path);
+                let rel = rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+                hashes.insert(rel, sha256_file(&path, &mut |_, _| {})?);
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// Hash the files of every covered partition of `device`, mounting them read-only
+pub fn build_manifest(device: &str, log: &mut dyn Write) -> io::Result<MediaManifest> {
+    let mut manifest = MediaManifest::default();
+    for (position, partition) in list_partitions(device)?.iter().enumerate() {
+        if !covered(partition) {
+            continue;
+        }
+        writeln!(log, "Hashing files on {}...", partition.path)?;
+        let files = with_mounted_partition(partition, true, hash_tree)?;
+        writeln!(log, "  {} files", files.len())?;
+        manifest.partitions.push(PartitionManifest {
+            index: position + 1,
+            label: partition.label.clone(),
+            files,
+        });
+    }
+    Ok(manifest)
+}
+
+/// Hash the written media and store the manifest on its largest writable partition (the one that
+/// also holds the label file). Returns that partition.
+pub fn write_media_manifest(device: &str, log: &mut dyn Write) -> io::Result<String> {
+    let device = &resolve_device_path(device);
+    let _ = std::process::Command::new("udevadm").arg("settle").status();
+    let manifest = build_manifest(device, log)?;
+    let partitions = list_partitions(device)?;
+    let partition = label_partition(&partitions).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no writable partition on {} for the manifest", device))
+    })?;
+    let files: usize = manifest.partitions.iter().map(|part| part.files.len()).sum();
+    writeln!(log, "Writing checksums of {} files to {}...", files, partition.path)?;
+    let text = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+    with_mounted_partition(partition, false, |root| {
+        let path = root.join(MANIFEST_FILE);
+        fs::create_dir_all(path.parent().unwrap_or(root))?;
+        fs::write(path, text.as_bytes())
+    })?;
+    Ok(partition.path.clone())
+}
+
+/// Re-hash the media and compare it with the manifest stored on it
+pub fn verify_media(device: &str, log: &mut dyn Write) -> io::Result<ManifestCheck> {
+    let device = &resolve_device_path(device);
+    let partitions = list_partitions(device)?;
+    let manifest = partitions
+        .iter()
+        .filter(|part| part.fstype.is_some())
+        .find_map(|part| {
+            with_mounted_partition(part, true, |root| fs::read_to_string(root.join(MANIFEST_FILE))).ok()
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no {} on {}", MANIFEST_FILE, device)))?;
+    let manifest: MediaManifest = serde_json::from_str(&manifest).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a valid manifest: {}", MANIFEST_FILE, e))
+    })?;
+    let mut check = ManifestCheck::default();
+    for recorded in &manifest.partitions {
+        let partition = recorded.index.checked_sub(1).and_then(|i| partitions.get(i)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} is no longer on {}", recorded.name(), device))
+        })?;
+        writeln!(log, "Checking {} ({})...", recorded.name(), partition.path)?;
+        let found = with_mounted_partition(partition, true, hash_tree)?;
+        check.compare(recorded, &found);
+    }
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, rel: &str, content: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn hashes_files_and_reports_changes() {
+        let root = tempfile::tempdir().unwrap();
+        write(root.path(), "bootmgr", "abc");
+        write(root.path(), "efi/boot/bootx64.efi", "loader");
+        write(root.path(), ".majusb/label.json", "{}");
+        let files = hash_tree(root.path()).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["bootmgr", "efi/boot/bootx64.efi"]);
+        assert_eq!(files["bootmgr"], "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        let recorded = PartitionManifest { index: 1, label: Some("BOOT".to_string()), files };
+        let manifest = MediaManifest { partitions: vec![recorded.clone()] };
+        let text = serde_json::to_string(&manifest).unwrap();
+        assert_eq!(serde_json::from_str::<MediaManifest>(&text).unwrap(), manifest);
+
+        let mut check = ManifestCheck::default();
+        check.compare(&recorded, &hash_tree(root.path()).unwrap());
+        assert!(check.is_clean());
+        assert_eq!(check.files, 2);
+
+        write(root.path(), "bootmgr", "tampered");
+        write(root.path(), "autorun.inf", "x");
+        fs::remove_file(root.path().join("efi/boot/bootx64.efi")).unwrap();
+        let mut check = ManifestCheck::default();
+        check.compare(&recorded, &hash_tree(root.path()).unwrap());
+        assert_eq!(check.changed, ["BOOT: bootmgr"]);
+        assert_eq!(check.missing, ["BOOT: efi/boot/bootx64.efi"]);
+        assert_eq!(check.added, ["BOOT: autorun.inf"]);
+        assert_eq!(PartitionManifest { index: 2, ..Default::default() }.name(), "partition 2");
+    }
+}
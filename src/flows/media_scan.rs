@@ -0,0 +1,512 @@
+//! Identify what an already-written stick holds ("Windows 11 install media", "Ubuntu live with
+//! persistence", ...) by mounting its partitions read-only and looking for the markers that image
+//! detection uses on ISOs. Sticks can also carry a name chosen when they were written, stored in
+//! `.majusb/label.json` on a writable partition.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+use crate::flows::linux_persistence::{distro_for_root, persistence_type_for_root, LinuxDistroInfo};
+use crate::flows::syslinux_multiboot::{ISO_DIR, MULTIBOOT_LABEL, SYSLINUX_DIR};
+use crate::utils::{resolve_device_path, LINUX_ROOT_MARKERS};
+use crate::windows::wim::{InstallImage, WimEditor, WindowsFlavor};
+
+/// Label file, relative to the root of the partition that holds it
+pub const LABEL_FILE: &str = ".majusb/label.json";
+
+/// Filesystems the label file can be written to
+const WRITABLE_FSTYPES: [&str; 6] = ["vfat", "exfat", "ntfs", "ext2", "ext3", "ext4"];
+
+/// Label Ventoy gives the small FAT partition holding its EFI loader
+const VENTOY_EFI_LABEL: &str = "VTOYEFI";
+
+/// Name and description the user gave a stick when writing it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaLabel {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// File name of the image written to the stick
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// What kind of media the device holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Windows,
+    Linux,
+    Multiboot,
+    Unknown,
+}
+
+/// One partition of the scanned device, as reported by lsblk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ScannedPartition {
+    pub path: String,
+    pub size_bytes: u64,
+    pub fstype: Option<String>,
+    pub label: Option<String>,
+    /// Where the partition is already mounted; such partitions are inspected in place
+    #[serde(skip)]
+    pub mount_point: Option<String>,
+}
+
+/// Persistence partition found next to a live system
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PersistenceSummary {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub partition: String,
+    pub size_bytes: u64,
+}
+
+/// Result of scanning a device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MediaReport {
+    #[serde(rename = "type")]
+    pub kind: MediaKind,
+    /// One line for people, e.g. "Ubuntu 24.04 live (casper) with 2.8 GB persistence"
+    pub description: String,
+    pub distro: Option<String>,
+    pub version: Option<String>,
+    /// Windows editions in the install image (needs wimlib-imagex)
+    pub editions: Vec<String>,
+    /// Name and description stored on the stick when it was written
+    pub label: Option<MediaLabel>,
+    pub persistence: Option<PersistenceSummary>,
+    pub partitions: Vec<ScannedPartition>,
+}
+
+/// Multiboot layout already on a stick, recognised from partition labels alone so it can be
+/// checked without mounting anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultibootLayout {
+    /// Ventoy: ISOs anywhere on the data partition, listed by Ventoy at boot
+    Ventoy { data_partition: ScannedPartition },
+    /// Syslinux menu drive created by `cli_helper multiboot`, ISOs under `isos/`
+    Syslinux { partition: ScannedPartition },
+}
+
+impl MultibootLayout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MultibootLayout::Ventoy { .. } => "Ventoy",
+            MultibootLayout::Syslinux { .. } => "syslinux",
+        }
+    }
+
+    /// Partition new ISOs are copied to
+    pub fn data_partition(&self) -> &ScannedPartition {
+        match self {
+            MultibootLayout::Ventoy { data_partition } => data_partition,
+            MultibootLayout::Syslinux { partition } => partition,
+        }
+    }
+}
+
+/// Ventoy puts its exFAT (or NTFS, FAT32, ext4...) data partition first and a 32 MB `VTOYEFI`
+/// partition after it; our own multiboot drive is a single FAT32 partition labelled `MULTIBOOT`
+pub fn detect_multiboot_layout(partitions: &[ScannedPartition]) -> Option<MultibootLayout> {
+    let is_ventoy_efi = |part: &ScannedPartition| part.label.as_deref() == Some(VENTOY_EFI_LABEL);
+    if partitions.iter().any(is_ventoy_efi) {
+        return partitions
+            .iter()
+            .find(|part| !is_ventoy_efi(part) && part.fstype.is_some())
+            .map(|part| MultibootLayout::Ventoy { data_partition: part.clone() });
+    }
+    partitions
+        .iter()
+        .find(|part| part.fstype.as_deref() == Some("vfat") && part.label.as_deref() == Some(MULTIBOOT_LABEL))
+        .map(|part| MultibootLayout::Syslinux { partition: part.clone() })
+}
+
+/// Multiboot layout on `device`, if any (lsblk only, nothing is mounted)
+pub fn find_multiboot_layout(device: &str) -> io::Result<Option<MultibootLayout>> {
+    Ok(detect_multiboot_layout(&list_partitions(device)?))
+}
+
+/// Markers found at the root of one partition
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct RootContent {
+    windows: bool,
+    /// Client or Server, from the install image metadata
+    flavor: Option<WindowsFlavor>,
+    editions: Vec<String>,
+    /// Persistence mechanism of the live system, when the partition holds one
+    live: Option<String>,
+    distro: Option<LinuxDistroInfo>,
+    /// Number of ISOs when the partition is a syslinux multiboot drive
+    multiboot_isos: Option<usize>,
+    label: Option<MediaLabel>,
+}
+
+/// Mount each partition of `device` read-only (or use its existing mount) and describe the media
+pub fn scan_device(device: &str) -> io::Result<MediaReport> {
+    let device = &resolve_device_path(device);
+    let partitions = list_partitions(device)?;
+    let contents = partitions.iter().map(inspect_partition).collect();
+    Ok(summarize(partitions, contents))
+}
+
+/// Partitions of `device` with their filesystems and current mounts
+pub(crate) fn list_partitions(device: &str) -> io::Result<Vec<ScannedPartition>> {
+    let output = Command::new("lsblk")
+        .args(["-lnbp", "-P", "-o", "NAME,TYPE,SIZE,FSTYPE,LABEL,MOUNTPOINT", device])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "lsblk failed on {}: {}",
+            device,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_lsblk_partitions(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Run `f` on the root of `partition`: its existing mount, or a temporary one (read-only when
+/// `read_only` is set) that is undone afterwards
+pub(crate) fn with_mounted_partition<T>(
+    partition: &ScannedPartition,
+    read_only: bool,
+    f: impl FnOnce(&Path) -> io::Result<T>,
+) -> io::Result<T> {
+    if let Some(mount_point) = partition.mount_point.as_deref().filter(|mp| !mp.is_empty()) {
+        return f(Path::new(mount_point));
+    }
+    let mount_dir = tempfile::tempdir()?;
+    let options = if read_only { "ro" } else { "rw" };
+    let output = Command::new("mount")
+        .args(["-o", options, &partition.path])
+        .arg(mount_dir.path())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "mount {} failed: {}",
+            partition.path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let result = f(mount_dir.path());
+    let _ = Command::new("umount").arg(mount_dir.path()).status();
+    result
+}
+
+/// Inspect one partition; partitions that cannot be mounted count as empty
+fn inspect_partition(partition: &ScannedPartition) -> RootContent {
+    if partition.fstype.is_none() {
+        return RootContent::default();
+    }
+    with_mounted_partition(partition, true, |root| Ok(inspect_root(root))).unwrap_or_default()
+}
+
+/// Look for multiboot, Windows setup and Linux live markers under `root`
+fn inspect_root(root: &Path) -> RootContent {
+    let mut content = RootContent {
+        label: fs::read_to_string(root.join(LABEL_FILE))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok()),
+        ..RootContent::default()
+    };
+    let iso_dir = root.join(ISO_DIR);
+    if root.join(SYSLINUX_DIR).join("syslinux.cfg").is_file() && iso_dir.is_dir() {
+        let isos = fs::read_dir(&iso_dir).map_or(0, |entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().extension().is_some_and(|ext| ext.eq_ignore_ascii_case("iso")))
+                .count()
+        });
+        content.multiboot_isos = Some(isos);
+        return content;
+    }
+    let sources = root.join("sources");
+    if sources.is_dir() {
+        let install_image = InstallImage::find(&sources).ok().flatten();
+        content.windows = install_image.is_some()
+            || sources.join("boot.wim").is_file()
+            || root.join("bootmgr").is_file();
+        content.flavor = install_image.as_ref().and_then(|image| image.flavor().ok());
+        if let Some(image) = install_image.filter(|_| WimEditor::has_wimlib()) {
+            content.editions = image.editor().image_names().unwrap_or_default();
+        }
+        if content.windows {
+            return content;
+        }
+    }
+    if LINUX_ROOT_MARKERS.iter().any(|marker| root.join(marker).exists()) {
+        content.live = Some(persistence_type_for_root(root).name().to_string());
+        content.distro = distro_for_root(root);
+    }
+    content
+}
+
+/// Combine the per-partition findings into one report
+fn summarize(partitions: Vec<ScannedPartition>, contents: Vec<RootContent>) -> MediaReport {
+    let mut report = MediaReport {
+        kind: MediaKind::Unknown,
+        description: "Unknown".to_string(),
+        distro: None,
+        version: None,
+        editions: Vec::new(),
+        label: contents.iter().find_map(|content| content.label.clone()),
+        persistence: None,
+        partitions: Vec::new(),
+    };
+    if let Some(MultibootLayout::Ventoy { data_partition }) = detect_multiboot_layout(&partitions) {
+        report.kind = MediaKind::Multiboot;
+        report.description = format!("Ventoy multiboot (ISOs on {})", data_partition.path);
+    } else if let Some(isos) = contents.iter().find_map(|content| content.multiboot_isos) {
+        report.kind = MediaKind::Multiboot;
+        report.description = format!("Syslinux multiboot ({} ISO{})", isos, if isos == 1 { "" } else { "s" });
+    } else if contents.iter().any(|content| content.windows) {
+        report.kind = MediaKind::Windows;
+        report.editions = contents.iter().flat_map(|content| content.editions.clone()).collect();
+        let labels: Vec<&str> = partitions.iter().filter_map(|part| part.label.as_deref()).collect();
+        // Edition names start with the product, e.g. "Windows 11 Pro" or "Windows Server 2022 Standard"
+        let product = report.editions.first().and_then(|edition| {
+            let words: Vec<&str> = edition.split_whitespace().take(2).collect();
+            (words.len() == 2 && words[0] == "Windows").then(|| words.join(" "))
+        });
+        let flavor = contents.iter().find_map(|content| content.flavor).unwrap_or(WindowsFlavor::Client);
+        report.description = format!("{} install media", product.as_deref().unwrap_or(flavor.product_name()));
+        if !labels.is_empty() {
+            report.description.push_str(&format!(" ({})", labels.join("+")));
+        }
+    } else if let Some((index, live)) =
+        contents.iter().enumerate().find_map(|(index, content)| Some((index, content.live.clone()?)))
+    {
+        report.kind = MediaKind::Linux;
+        let distro = contents[index].distro.clone();
+        report.distro = distro.as_ref().map(|d| d.name.clone());
+        report.version = distro.as_ref().and_then(|d| d.version.clone());
+        let name = match (&report.distro, &report.version) {
+            (Some(name), Some(version)) => format!("{} {}", name, version),
+            (Some(name), None) => name.clone(),
+            _ => "Linux".to_string(),
+        };
+        report.description = format!("{} live ({})", name, live);
+        // Persistence lives on an ext partition that is not itself part of the live image
+        let persistence = partitions.iter().zip(&contents).find(|(part, content)| {
+            part.fstype.as_deref().is_some_and(|fs| fs.starts_with("ext")) && content.live.is_none()
+        });
+        if let Some((part, _)) = persistence {
+            report.description.push_str(&format!(
+                " with {:.1} GB persistence",
+                part.size_bytes as f64 / 1_000_000_000.0
+            ));
+            report.persistence = Some(PersistenceSummary {
+                kind: live,
+                partition: part.path.clone(),
+                size_bytes: part.size_bytes,
+            });
+        }
+    }
+    if let Some(label) = &report.label {
+        report.description = format!("{}: {}", label.name, report.description);
+    }
+    report.partitions = partitions;
+    report
+}
+
+/// Partition the label file goes to: the largest writable one. The small EFI image inside a
+/// hybrid ISO is skipped, since changing it would alter the written image.
+pub(crate) fn label_partition(partitions: &[ScannedPartition]) -> Option<&ScannedPartition> {
+    let hybrid_iso = partitions.iter().any(|part| part.fstype.as_deref() == Some("iso9660"));
+    partitions
+        .iter()
+        .filter(|part| part.fstype.as_deref().is_some_and(|fs| WRITABLE_FSTYPES.contains(&fs)))
+        .filter(|part| !(hybrid_iso && part.fstype.as_deref() == Some("vfat")))
+        .max_by_key(|part| part.size_bytes)
+}
+
+/// Store `label` on the largest writable partition of `device`. Returns that partition.
+pub fn write_media_label(device: &str, label: &MediaLabel, log: &mut dyn Write) -> io::Result<String> {
+    let _ = Command::new("udevadm").arg("settle").status();
+    let partitions = list_partitions(device)?;
+    let partition = label_partition(&partitions).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no writable partition on {} for the label", device))
+    })?;
+    writeln!(log, "Writing label \"{}\" to {}...", label.name, partition.path)?;
+    let text = serde_json::to_string_pretty(label).map_err(io::Error::other)?;
+    with_mounted_partition(partition, false, |root| {
+        let path = root.join(LABEL_FILE);
+        fs::create_dir_all(path.parent().unwrap_or(root))?;
+        fs::write(path, text.as_bytes())
+    })?;
+    Ok(partition.path.clone())
+}
+
+/// Partitions from `lsblk -lnbp -P -o NAME,TYPE,SIZE,FSTYPE,LABEL,MOUNTPOINT`.
+/// A device written from an ISO without a partition table reports its filesystem on the disk itself,
+/// which then stands in for the single partition.
+fn parse_lsblk_partitions(output: &str) -> Vec<ScannedPartition> {
+    let mut disk = None;
+    let mut partitions = Vec::new();
+    for line in output.lines() {
+        let mut kind = String::new();
+        let mut partition = ScannedPartition::default();
+        for (key, value) in lsblk_pairs(line) {
+            let value = Some(value).filter(|v| !v.is_empty());
+            match key {
+                "NAME" => partition.path = value.unwrap_or_default(),
+                "TYPE" => kind = value.unwrap_or_default(),
+                "SIZE" => partition.size_bytes = value.and_then(|v| v.parse().ok()).unwrap_or(0),
+                "FSTYPE" => partition.fstype = value,
+                "LABEL" => partition.label = value,
+                "MOUNTPOINT" => partition.mount_point = value,
+                _ => {}
+            }
+        }
+        match kind.as_str() {
+            "part" => partitions.push(partition),
+            "disk" if partition.fstype.is_some() => disk = Some(partition),
+            _ => {}
+        }
+    }
+    if partitions.is_empty() {
+        partitions.extend(disk);
+    }
+    partitions
+}
+
+/// `KEY="value"` pairs of one `lsblk -P` line, with `\xNN` escapes decoded
+fn lsblk_pairs(line: &str) -> Vec<(&str, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = line.trim();
+    while let Some((key, after)) = rest.split_once("=\"") {
+        let Some((value, tail)) = after.split_once('"') else {
+            break;
+        };
+        pairs.push((key.trim(), unescape_lsblk(value)));
+        rest = tail;
+    }
+    pairs
+}
+
+fn unescape_lsblk(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let raw = value.as_bytes();
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'\\' && raw.get(i + 1) == Some(&b'x')
+            && let Some(byte) = value.get(i + 2..i + 4).and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            bytes.push(byte);
+            i += 4;
+            continue;
+        }
+        bytes.push(raw[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(root: &Path, rel: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+    }
+
+    #[test]
+    fn parses_lsblk_pairs() {
+        let output = "NAME=\"/dev/sdb\" TYPE=\"disk\" SIZE=\"16008609792\" FSTYPE=\"\" LABEL=\"\" MOUNTPOINT=\"\"\n\
+            NAME=\"/dev/sdb1\" TYPE=\"part\" SIZE=\"1073741824\" FSTYPE=\"vfat\" LABEL=\"BOOT\" MOUNTPOINT=\"\"\n\
+            NAME=\"/dev/sdb2\" TYPE=\"part\" SIZE=\"14934867968\" FSTYPE=\"ntfs\" LABEL=\"ESD-USB\" MOUNTPOINT=\"/run/media/me/ESD\\x20USB\"\n";
+        let partitions = parse_lsblk_partitions(output);
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions[0].label.as_deref(), Some("BOOT"));
+        assert_eq!(partitions[1].size_bytes, 14934867968);
+        assert_eq!(partitions[1].mount_point.as_deref(), Some("/run/media/me/ESD USB"));
+
+        let whole_disk = "NAME=\"/dev/sdc\" TYPE=\"disk\" SIZE=\"8000000000\" FSTYPE=\"iso9660\" LABEL=\"ARCH_202410\" MOUNTPOINT=\"\"\n";
+        assert_eq!(parse_lsblk_partitions(whole_disk)[0].path, "/dev/sdc");
+    }
+
+    #[test]
+    fn recognises_windows_and_live_media() {
+        let boot = tempfile::tempdir().unwrap();
+        touch(boot.path(), "bootmgr");
+        touch(boot.path(), "sources/boot.wim");
+        let install = tempfile::tempdir().unwrap();
+        touch(install.path(), "sources/install.swm");
+        let partition = |path: &str, fstype: &str, label: &str, size_bytes: u64| ScannedPartition {
+            path: path.to_string(),
+            size_bytes,
+            fstype: Some(fstype.to_string()),
+            label: Some(label.to_string()),
+            mount_point: None,
+        };
+
+        let windows = summarize(
+            vec![partition("/dev/sdb1", "vfat", "BOOT", 1 << 30), partition("/dev/sdb2", "ntfs", "ESD-USB", 15 << 30)],
+            vec![inspect_root(boot.path()), inspect_root(install.path())],
+        );
+        assert_eq!(windows.kind, MediaKind::Windows);
+        assert_eq!(windows.description, "Windows install media (BOOT+ESD-USB)");
+
+        assert_eq!(label_partition(&windows.partitions).unwrap().path, "/dev/sdb2");
+
+        let live = tempfile::tempdir().unwrap();
+        fs::create_dir_all(live.path().join("casper")).unwrap();
+        fs::create_dir_all(live.path().join(".disk")).unwrap();
+        fs::write(live.path().join(".disk/info"), "Ubuntu 24.04 LTS \"Noble Numbat\" - Release amd64").unwrap();
+        let ubuntu = summarize(
+            vec![partition("/dev/sdc1", "iso9660", "Ubuntu", 6_000_000_000), partition("/dev/sdc3", "ext4", "writable", 2_800_000_000)],
+            vec![inspect_root(live.path()), RootContent::default()],
+        );
+        assert_eq!(ubuntu.kind, MediaKind::Linux);
+        assert_eq!(ubuntu.description, "Ubuntu 24.04 live (casper) with 2.8 GB persistence");
+        assert_eq!(ubuntu.persistence.unwrap().partition, "/dev/sdc3");
+
+        let esp = partition("/dev/sdc2", "vfat", "ESP", 5_000_000);
+        assert_eq!(label_partition(std::slice::from_ref(&esp)).unwrap().path, "/dev/sdc2");
+        let iso = partition("/dev/sdc1", "iso9660", "Ubuntu", 6_000_000_000);
+        assert!(label_partition(&[iso.clone(), esp]).is_none());
+
+        let label = MediaLabel { name: "Rescue".to_string(), description: String::new(), image: None };
+        touch(live.path(), LABEL_FILE);
+        fs::write(live.path().join(LABEL_FILE), serde_json::to_string(&label).unwrap()).unwrap();
+        let named = summarize(vec![iso], vec![inspect_root(live.path())]);
+        assert_eq!(named.description, "Rescue: Ubuntu 24.04 live (casper)");
+        assert_eq!(named.label, Some(label));
+
+        let empty = tempfile::tempdir().unwrap();
+        let unknown = summarize(vec![partition("/dev/sdd1", "exfat", "DATA", 1 << 30)], vec![inspect_root(empty.path())]);
+        assert_eq!(unknown.kind, MediaKind::Unknown);
+        assert!(serde_json::to_string(&unknown).unwrap().starts_with("{\"type\":\"unknown\""));
+    }
+
+    #[test]
+    fn recognises_existing_multiboot_layouts() {
+        let output = "NAME=\"/dev/sdb\" TYPE=\"disk\" SIZE=\"64023257088\" FSTYPE=\"\" LABEL=\"\" MOUNTPOINT=\"\"\n\
+            NAME=\"/dev/sdb1\" TYPE=\"part\" SIZE=\"63989170176\" FSTYPE=\"exfat\" LABEL=\"Ventoy\" MOUNTPOINT=\"/run/media/me/Ventoy\"\n\
+            NAME=\"/dev/sdb2\" TYPE=\"part\" SIZE=\"33554432\" FSTYPE=\"vfat\" LABEL=\"VTOYEFI\" MOUNTPOINT=\"\"\n";
+        let ventoy = parse_lsblk_partitions(output);
+        let layout = detect_multiboot_layout(&ventoy).unwrap();
+        assert_eq!(layout.name(), "Ventoy");
+        assert_eq!(layout.data_partition().path, "/dev/sdb1");
+        let report = summarize(ventoy, vec![RootContent::default(), RootContent::default()]);
+        assert_eq!(report.kind, MediaKind::Multiboot);
+        assert_eq!(report.description, "Ventoy multiboot (ISOs on /dev/sdb1)");
+
+        let partition = |fstype: &str, label: &str| ScannedPartition {
+            path: "/dev/sdc1".to_string(),
+            size_bytes: 16 << 30,
+            fstype: Some(fstype.to_string()),
+            label: Some(label.to_string()),
+            mount_point: None,
+        };
+        let syslinux = detect_multiboot_layout(&[partition("vfat", MULTIBOOT_LABEL)]).unwrap();
+        assert!(matches!(syslinux, MultibootLayout::Syslinux { .. }));
+        assert!(detect_multiboot_layout(&[partition("vfat", "UBUNTU")]).is_none());
+        assert!(detect_multiboot_layout(&[partition("exfat", "Ventoy")]).is_none());
+    }
+}
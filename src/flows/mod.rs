@@ -1,3 +1,16 @@
+pub mod boot_check;
+pub mod efi_tool;
+pub mod image_copy;
+pub mod job;
+pub mod linux_autoinstall;
+pub mod linux_data_partition;
+pub mod linux_extract;
 pub mod linux_flow;
 pub mod linux_persistence;
+pub mod media_manifest;
+pub mod media_scan;
+pub mod outcome;
+pub mod preflight;
+pub mod syslinux_multiboot;
+pub mod volume_label;
 pub mod windows_flow;
@@ -0,0 +1,175 @@
+//! Structured result of a write operation, reported by the GUI worker
+
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::error::UsbCreatorError;
+use crate::flows::windows_flow::WindowsFlowMetrics;
+
+/// Area of the write a non-fatal issue came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// Persistence partition was skipped or only partly set up
+    Persistence,
+    /// Partition table repair or naming did not fully succeed
+    Partitioning,
+    /// Partitions unmounted for the write could not be mounted again
+    Remount,
+    /// Filesystem creation fell back to defaults
+    Formatting,
+    /// Kickstart, preseed or autoinstall data was not put on the media
+    AnswerFile,
+    /// Windows Setup bypass options were not applied
+    Bypass,
+    /// Device runs slower than it could (e.g. USB 3 stick on a USB 2 link)
+    UsbSpeed,
+    /// Writes may still be cached by the USB bridge after the final sync
+    WriteCache,
+    /// The name and description could not be stored on the media
+    Label,
+    /// A volume label was changed to fit its filesystem
+    VolumeLabel,
+    /// The checksum manifest could not be stored on the media
+    Manifest,
+    /// The post-write check found no valid EFI loader on the media
+    BootFiles,
+    /// The storage partition after a Linux ISO was not created
+    DataPartition,
+}
+
+/// Non-fatal issue collected during a write and shown once it completes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(kind: WarningKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into() }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Log `warning` as a "Warning:" line and add it to `warnings`
+pub fn record_warning(log: &mut dyn Write, warnings: &mut Vec<Warning>, warning: Warning) -> io::Result<()> {
+    writeln!(log, "Warning: {}", warning)?;
+    warnings.push(warning);
+    Ok(())
+}
+
+/// Final result of a write: success flag, optional metrics, non-fatal warnings and the hard error.
+#[derive(Debug, Default)]
+pub struct WriteOutcome {
+    /// Whether the media was written successfully
+    pub success: bool,
+    /// Metrics captured by the Windows flow (None for flows without instrumentation)
+    pub metrics: Option<WindowsFlowMetrics>,
+    /// Non-fatal issues the user should know about (e.g. persistence skipped)
+    pub warnings: Vec<Warning>,
+    /// Hard failure that aborted the write
+    pub error: Option<UsbCreatorError>,
+    /// Largest persistence size that would fit, when persistence was skipped for lack of space
+    pub persistence_max_mb: Option<u64>,
+    /// What a failure left on the device, once the write had started changing it
+    pub device_state: Option<String>,
+}
+
+impl WriteOutcome {
+    /// Successful write with optional metrics and warnings
+    pub fn succeeded(metrics: Option<WindowsFlowMetrics>, warnings: Vec<Warning>) -> Self {
+        Self {
+            success: true,
+            metrics,
+            warnings,
+            error: None,
+            persistence_max_mb: None,
+            device_state: None,
+        }
+    }
+
+    /// Failed write carrying the error that aborted it
+    pub fn failed(error: UsbCreatorError) -> Self {
+        Self {
+            success: false,
+            metrics: None,
+            warnings: Vec::new(),
+            error: Some(error),
+            persistence_max_mb: None,
+            device_state: None,
+        }
+    }
+
+    /// Build an outcome from a flow result, attaching `context` to IO errors
+    pub fn from_io_result(
+        result: io::Result<(Option<WindowsFlowMetrics>, Vec<Warning>)>,
+        context: &str,
+    ) -> Self {
+        match result {
+            Ok((metrics, warnings)) => Self::succeeded(metrics, warnings),
+            Err(e) => Self::failed(UsbCreatorError::Io(e, context.to_string())),
+        }
+    }
+
+    /// True when the write succeeded but produced warnings
+    pub fn has_warnings(&self) -> bool {
+        !self.warnings.is_empty()
+    }
+
+    /// One-line summary suitable for status labels
+    pub fn summary(&self) -> String {
+        match (&self.error, self.success) {
+            (Some(e), _) => format!("Failed: {}", e),
+            (None, false) => "Failed".to_string(),
+            (None, true) if self.has_warnings() => {
+                let count = self.warnings.len();
+                format!("Completed with {} warning{}", count, if count == 1 { "" } else { "s" })
+            }
+            (None, true) => "Completed".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn successful_outcome_reports_warnings_separately() {
+        let outcome = WriteOutcome::from_io_result(
+            Ok((None, vec![Warning::new(WarningKind::Persistence, "Persistence skipped: not enough space")])),
+            "Linux ISO write",
+        );
+        assert!(outcome.success);
+        assert!(outcome.error.is_none());
+        assert!(outcome.has_warnings());
+        assert_eq!(outcome.summary(), "Completed with 1 warning");
+    }
+
+    #[test]
+    fn recorded_warnings_are_logged_and_collected() {
+        let mut log = Vec::new();
+        let mut warnings = Vec::new();
+        record_warning(&mut log, &mut warnings, Warning::new(WarningKind::Remount, "could not remount /dev/sdb1")).unwrap();
+        record_warning(&mut log, &mut warnings, Warning::new(WarningKind::UsbSpeed, "running at USB 2.0 speed")).unwrap();
+        assert_eq!(String::from_utf8(log).unwrap().lines().next(), Some("Warning: could not remount /dev/sdb1"));
+        assert_eq!(warnings[1].kind, WarningKind::UsbSpeed);
+        assert_eq!(WriteOutcome::succeeded(None, warnings).summary(), "Completed with 2 warnings");
+    }
+
+    #[test]
+    fn failed_outcome_keeps_typed_error() {
+        let outcome = WriteOutcome::from_io_result(
+            Err(io::Error::other("dd failed")),
+            "Linux ISO write",
+        );
+        assert!(!outcome.success);
+        assert!(matches!(outcome.error, Some(UsbCreatorError::Io(_, ref ctx)) if ctx == "Linux ISO write"));
+        assert!(outcome.summary().contains("dd failed"));
+    }
+}
@@ -0,0 +1,401 @@
+//! Checks run before any write, collected into one report: tools on the host, the source image,
+//! and the target device. The GUI shows the report in a dialog and the CLI prints it; a failed
+//! item stops the write, a warning only needs to be acknowledged.
+
+use std::io;
+
+use crate::checksum::{check_file_size, parse_expected_size, SizeCheck};
+use crate::flows::job::{is_disk_image, LinuxWriteMode, WriteJob, WriteMode};
+use crate::flows::linux_data_partition::DataFilesystem;
+use crate::flows::volume_label::{sanitize_label, LabelFs};
+use crate::flows::windows_flow::ensure_not_system_device;
+use crate::utils::{backing_disks, check_iso_readable, device_risk_reasons, get_device_size_bytes, live_system_disks, network_filesystem, validate_image_file};
+
+/// Outcome of one check, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        }
+    }
+}
+
+/// One line of the report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightItem {
+    /// Short name of the check, e.g. "Free space"
+    pub check: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// Every check for one job, in a fixed order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreflightReport {
+    pub items: Vec<PreflightItem>,
+}
+
+impl PreflightReport {
+    /// Worst status of all items
+    pub fn status(&self) -> CheckStatus {
+        self.items.iter().map(|item| item.status).max().unwrap_or(CheckStatus::Pass)
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.status() == CheckStatus::Fail
+    }
+
+    /// Items at `status`
+    pub fn items_with(&self, status: CheckStatus) -> impl Iterator<Item = &PreflightItem> {
+        self.items.iter().filter(move |item| item.status == status)
+    }
+
+    /// One line per item, e.g. "[FAIL] Free space: the image needs 6.2 GB but /dev/sdb holds 4.0 GB"
+    pub fn lines(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .map(|item| format!("[{}] {}: {}", item.status.label(), item.check, item.detail))
+            .collect()
+    }
+
+    fn push(&mut self, check: &'static str, status: CheckStatus, detail: impl Into<String>) {
+        self.items.push(PreflightItem { check, status, detail: detail.into() });
+    }
+}
+
+/// What was measured about the host, the image and the device; `evaluate` turns it into a report
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreflightFacts {
+    /// Programs the job runs that are not on `PATH`
+    pub missing_tools: Vec<String>,
+    /// Why the image cannot be read or used
+    pub image_error: Option<String>,
+    /// Problem worth mentioning that does not stop the write (e.g. a sparse download)
+    pub image_warning: Option<String>,
+    /// Bytes the image occupies once written; None when unknown (compressed images, devices)
+    pub image_bytes: Option<u64>,
+    /// Comparison with the published size, when the job carries one
+    pub size_check: Option<Result<SizeCheck, String>>,
+    /// sysfs `removable` flag of the device
+    pub removable: Option<bool>,
+    pub device_bytes: Option<u64>,
+    /// Capacity of the node written to (the partition for partition writes)
+    pub target_bytes: Option<u64>,
+    /// sysfs `ro` flag: the device or its write-protect switch refuses writes
+    pub read_only: bool,
+    /// Why the device looks like it hosts the running system
+    pub system_disk: Option<String>,
+    /// The device is the live USB this system booted from
+    pub live_system: bool,
+    /// The source image is stored on the device
+    pub holds_image: bool,
+    /// Network share the image is read from (e.g. "nfs4 share at /mnt/nas")
+    pub network_source: Option<String>,
+}
+
+/// Programs the flow for `job` runs, besides the ones every flow uses
+pub fn required_tools(job: &WriteJob) -> Vec<&'static str> {
+    let mut tools = vec!["lsblk"];
+    match &job.mode {
+        WriteMode::Windows { legacy: true, .. } => {
+            tools.extend(["parted", "wipefs", "mkfs.ntfs", "ms-sys", "rsync", "mount", "umount"]);
+        }
+        WriteMode::Windows { .. } => {
+            tools.extend(["parted", "wipefs", "mkfs.vfat", "mkfs.ntfs", "rsync", "mount", "umount"]);
+        }
+        WriteMode::WindowsDirectDd => tools.push("dd"),
+        WriteMode::Linux { mode, fix_protective_mbr } => {
+            match mode {
+                LinuxWriteMode::RawDd => tools.extend(["dd", "wipefs"]),
+                LinuxWriteMode::Persistent(_) => tools.extend(["dd", "wipefs", "parted", "mkfs.ext4", "sgdisk", "mount", "umount"]),
+                LinuxWriteMode::Extracted { persistence, .. } => {
+                    tools.extend(["wipefs", "parted", "mkfs.vfat", "rsync", "mount", "umount"]);
+                    if persistence.is_some() {
+                        tools.extend(["mkfs.ext4", "sgdisk"]);
+                    }
+                }
+            }
+            // The protective MBR repair moves the backup GPT; the data partition is appended and
+            // typed like persistence
+            let mut extra = Vec::new();
+            if *fix_protective_mbr {
+                extra.push("sgdisk");
+            }
+            if let Some(data) = &job.data_partition {
+                extra.extend(["parted", "sgdisk"]);
+                extra.push(match data.filesystem {
+                    DataFilesystem::Exfat => "mkfs.exfat",
+                    DataFilesystem::Fat32 => "mkfs.vfat",
+                });
+            }
+            if job.answer_file.is_some() {
+                extra.push("mkfs.vfat");
+            }
+            for tool in extra {
+                if !tools.contains(&tool) {
+                    tools.push(tool);
+                }
+            }
+        }
+        WriteMode::RawImage | WriteMode::RawPartition { .. } | WriteMode::RestoreImage => {}
+    }
+    tools
+}
+
+/// Measure everything the checks need for `job`
+pub fn gather_facts(job: &WriteJob) -> PreflightFacts {
+    let target = job.target();
+    let dev_name = job.device.trim_start_matches("/dev/");
+    let sys_flag = |name: &str| {
+        std::fs::read_to_string(format!("/sys/block/{}/{}", dev_name, name))
+            .ok()
+            .map(|flag| flag.trim() == "1")
+    };
+    let (image_error, image_warning) = match check_iso_readable(&job.iso_path) {
+        Err(e) => (Some(e.to_string()), None),
+        Ok(()) => (None, validate_image_file(&job.iso_path).ok().flatten()),
+    };
+    // A compressed disk image only reveals its size once it has been decompressed
+    let compressed = is_disk_image(&job.iso_path) && job.iso_path.to_ascii_lowercase().ends_with(".gz");
+    let image_bytes = std::fs::metadata(&job.iso_path)
+        .ok()
+        .filter(|meta| meta.is_file() && !compressed)
+        .map(|meta| meta.len());
+    let size_check = job
+        .expected_size
+        .as_deref()
+        .and_then(|size| parse_expected_size(size).ok())
+        .map(|expected| check_file_size(&job.iso_path, expected).map_err(|e| e.to_string()));
+    PreflightFacts {
+        missing_tools: required_tools(job)
+            .into_iter()
+            .filter(|tool| which::which(tool).is_err())
+            .map(str::to_string)
+            .collect(),
+        image_error,
+        image_warning,
+        image_bytes,
+        size_check,
+        removable: sys_flag("removable"),
+        device_bytes: get_device_size_bytes(&job.device).ok(),
+        target_bytes: get_device_size_bytes(&target).ok(),
+        read_only: sys_flag("ro").unwrap_or(false),
+        system_disk: ensure_not_system_device(&job.device, &mut io::sink()).err().map(|e| e.to_string()),
+        live_system: live_system_disks().contains(&job.device),
+        holds_image: backing_disks(&job.iso_path).contains(&job.device),
+        network_source: network_filesystem(&job.iso_path),
+    }
+}
+
+fn format_gb(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / 1e9)
+}
+
+/// Judge the measured facts for `job`
+pub fn evaluate(job: &WriteJob, facts: &PreflightFacts) -> PreflightReport {
+    use CheckStatus::*;
+    let mut report = PreflightReport::default();
+    let target = job.target();
+
+    if facts.missing_tools.is_empty() {
+        report.push("Tools", Pass, "all required programs are installed");
+    } else {
+        report.push("Tools", Fail, format!("missing {}", facts.missing_tools.join(", ")));
+    }
+
+    match (&facts.image_error, &facts.image_warning) {
+        (Some(error), _) => report.push("Image", Fail, error.clone()),
+        (None, Some(warning)) => report.push("Image", Warn, warning.clone()),
+        (None, None) => report.push("Image", Pass, format!("{} is readable", job.iso_path)),
+    }
+    match &facts.size_check {
+        Some(Ok(check)) if check.is_match() => report.push("Published size", Pass, check.message()),
+        Some(Ok(check)) => report.push("Published size", Warn, check.message().trim_start_matches("Warning: ").to_string()),
+        Some(Err(e)) => report.push("Published size", Warn, e.clone()),
+        None => {}
+    }
+
+    let persistence = job.plan("", 0, None).persistence;
+    let persistence_bytes = persistence.as_ref().map_or(0, |(size_mb, _)| size_mb * 1024 * 1024);
+    match (facts.image_bytes, facts.target_bytes) {
+        (Some(image), Some(capacity)) if image + persistence_bytes > capacity => report.push(
+            "Free space",
+            Fail,
+            format!("the write needs {} but {} holds {}", format_gb(image + persistence_bytes), target, format_gb(capacity)),
+        ),
+        (Some(image), Some(capacity)) => report.push(
+            "Free space",
+            Pass,
+            format!("{} of {} on {}", format_gb(image + persistence_bytes), format_gb(capacity), target),
+        ),
+        (None, Some(_)) => report.push("Free space", Pass, "checked while writing (image size known only after reading it)"),
+        (_, None) => report.push("Free space", Warn, format!("the size of {} could not be read", target)),
+    }
+
+    if let Some((_, label)) = &persistence {
+        let (fixed, changes) = sanitize_label(label, LabelFs::Ext4);
+        if !changes.is_empty() {
+            let changes: Vec<String> = changes.iter().map(|warning| warning.to_string()).collect();
+            report.push("Volume label", Warn, format!("persistence will be labelled {}: {}", fixed, changes.join("; ")));
+        }
+    }
+
+    if facts.read_only {
+        report.push("Write protection", Fail, format!("{} is read-only; check the write-protect switch", job.device));
+    } else {
+        report.push("Write protection", Pass, format!("{} accepts writes", job.device));
+    }
+
+    if let Some(reason) = &facts.system_disk {
+        report.push("System disk", Fail, reason.clone());
+    } else if facts.live_system {
+        report.push("System disk", Fail, format!("{} is the live USB this system is running from", job.device));
+    } else {
+        report.push("System disk", Pass, format!("{} is not in use by the running system", job.device));
+    }
+
+    if facts.holds_image {
+        report.push("Source image", Fail, format!("{} contains the source image; writing to it would destroy it", job.device));
+    } else if let Some(share) = &facts.network_source {
+        report.push(
+            "Source image",
+            Warn,
+            format!("the image is read from a {}; a stalled network stalls the write, so copy it to a local disk first", share),
+        );
+    } else {
+        report.push("Source image", Pass, "the image is stored on another disk");
+    }
+
+    if matches!(job.mode, WriteMode::RawPartition { .. }) {
+        report.push("Device", Pass, format!("only {} is written", target));
+    } else {
+        let reasons = device_risk_reasons(facts.removable, facts.device_bytes);
+        if reasons.is_empty() {
+            report.push("Device", Pass, format!("{} looks like a removable stick", job.device));
+        } else {
+            report.push("Device", Warn, reasons.join("; "));
+        }
+    }
+    report
+}
+
+/// Run every check for `job`
+pub fn run_preflight(job: &WriteJob) -> PreflightReport {
+    evaluate(job, &gather_facts(job))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flows::linux_persistence::PersistenceConfig;
+    use crate::flows::linux_data_partition::DataPartitionConfig;
+
+    fn healthy() -> PreflightFacts {
+        PreflightFacts {
+            image_bytes: Some(3_000_000_000),
+            removable: Some(true),
+            device_bytes: Some(16_000_000_000),
+            target_bytes: Some(16_000_000_000),
+            ..PreflightFacts::default()
+        }
+    }
+
+    #[test]
+    fn healthy_job_passes_every_check() {
+        let job = WriteJob::new("/tmp/distro.iso", "/dev/sdz", WriteMode::RawImage);
+        let report = evaluate(&job, &healthy());
+        assert_eq!(report.status(), CheckStatus::Pass);
+        assert!(report.lines().iter().any(|line| line == "[PASS] Free space: 3.0 GB of 16.0 GB on /dev/sdz"));
+    }
+
+    #[test]
+    fn blocking_problems_fail_and_doubts_warn() {
+        let job = WriteJob::new(
+            "/tmp/distro.iso",
+            "/dev/sdz",
+            WriteMode::Linux {
+                mode: LinuxWriteMode::Persistent(PersistenceConfig { enabled: true, size_mb: 14_000, ..Default::default() }),
+                fix_protective_mbr: false,
+            },
+        );
+        let facts = PreflightFacts {
+            missing_tools: vec!["mkfs.ext4".to_string()],
+            read_only: true,
+            holds_image: true,
+            removable: Some(false),
+            ..healthy()
+        };
+        let report = evaluate(&job, &facts);
+        assert!(report.has_failures());
+        let failed: Vec<&str> = report.items_with(CheckStatus::Fail).map(|item| item.check).collect();
+        assert_eq!(failed, ["Tools", "Free space", "Write protection", "Source image"]);
+        let warned: Vec<&str> = report.items_with(CheckStatus::Warn).map(|item| item.check).collect();
+        assert_eq!(warned, ["Device"]);
+
+        let sparse = PreflightFacts { image_warning: Some("mostly holes".to_string()), target_bytes: None, ..healthy() };
+        let report = evaluate(&WriteJob::new("/tmp/distro.iso", "/dev/sdz", WriteMode::RawImage), &sparse);
+        assert_eq!(report.status(), CheckStatus::Warn);
+        assert_eq!(report.items_with(CheckStatus::Warn).count(), 2);
+
+        let remote = PreflightFacts { network_source: Some("nfs4 share at /mnt/nas".to_string()), ..healthy() };
+        let report = evaluate(&WriteJob::new("/mnt/nas/distro.iso", "/dev/sdz", WriteMode::RawImage), &remote);
+        let warned: Vec<&str> = report.items_with(CheckStatus::Warn).map(|item| item.check).collect();
+        assert_eq!(warned, ["Source image"]);
+    }
+
+    #[test]
+    fn persistence_label_changes_are_announced() {
+        let config = PersistenceConfig { enabled: true, size_mb: 4096, label: "my persistent home".to_string(), ..Default::default() };
+        let job = WriteJob::new(
+            "/tmp/distro.iso",
+            "/dev/sdz",
+            WriteMode::Linux { mode: LinuxWriteMode::Persistent(config), fix_protective_mbr: false },
+        );
+        let report = evaluate(&job, &healthy());
+        let label = report.items_with(CheckStatus::Warn).find(|item| item.check == "Volume label").unwrap();
+        assert!(label.detail.starts_with("persistence will be labelled my persistent ho:"));
+    }
+
+    #[test]
+    fn tools_follow_the_write_mode() {
+        let windows_job = |legacy| {
+            WriteJob::new(
+                "/tmp/win.iso",
+                "/dev/sdz",
+                WriteMode::Windows {
+                    bypass: None,
+                    cluster_bytes: 4096,
+                    fat32_sectors_per_cluster: None,
+                    oem_folder: None,
+                    esp_mb: None,
+                    exclude: Vec::new(),
+                    boot_test: false,
+                    legacy,
+                    tool_efi: None,
+                },
+            )
+        };
+        let windows = windows_job(false);
+        assert!(required_tools(&windows).contains(&"mkfs.ntfs"));
+        let legacy = windows_job(true);
+        assert!(required_tools(&legacy).contains(&"ms-sys"));
+        assert!(!required_tools(&legacy).contains(&"mkfs.vfat"));
+        let raw = WriteJob::new("/tmp/x.img", "/dev/sdz", WriteMode::RawImage);
+        assert_eq!(required_tools(&raw), ["lsblk"]);
+
+        let linux = WriteJob::new("/tmp/x.iso", "/dev/sdz", WriteMode::Linux { mode: LinuxWriteMode::RawDd, fix_protective_mbr: false })
+            .with_data_partition(Some(DataPartitionConfig { size_mb: 4096, filesystem: DataFilesystem::Exfat }));
+        let tools = required_tools(&linux);
+        assert!(tools.contains(&"mkfs.exfat") && tools.contains(&"sgdisk"));
+        assert!(!tools.contains(&"mkfs.vfat"));
+    }
+}
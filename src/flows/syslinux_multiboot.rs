@@ -0,0 +1,426 @@
+//! BIOS multiboot media: several ISOs copied to one FAT32 partition, booted from a syslinux menu
+//! that hands the chosen ISO to memdisk
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::flows::media_scan::{find_multiboot_layout, with_mounted_partition, MultibootLayout};
+use crate::flows::windows_flow::{ensure_not_system_device, unmount_device_mounts};
+use crate::utils::{check_iso_readable, flush_with_progress, get_device_size_bytes, partition_path, resolve_device_path};
+
+/// Volume label of the multiboot partition
+pub const MULTIBOOT_LABEL: &str = "MULTIBOOT";
+/// Directory on the partition holding the copied ISOs
+pub(crate) const ISO_DIR: &str = "isos";
+/// Directory on the partition holding syslinux, its modules and the menu
+pub(crate) const SYSLINUX_DIR: &str = "syslinux";
+/// FAT32 cannot store files of 4 GiB or more
+const FAT32_MAX_FILE_BYTES: u64 = 4 * 1024 * 1024 * 1024 - 1;
+/// Room left for the partition table, FAT structures and syslinux
+const OVERHEAD_BYTES: u64 = 64 * 1024 * 1024;
+/// Where distributions install the BIOS syslinux files
+const SYSLINUX_SEARCH_DIRS: [&str; 6] = [
+    "/usr/lib/syslinux/bios",
+    "/usr/lib/syslinux/modules/bios",
+    "/usr/lib/syslinux/mbr",
+    "/usr/lib/syslinux",
+    "/usr/share/syslinux",
+    "/usr/lib/SYSLINUX",
+];
+/// COM32 modules the menu needs at boot (syslinux installs ldlinux.c32 itself)
+const MENU_MODULES: [&str; 3] = ["menu.c32", "libutil.c32", "libcom32.c32"];
+
+/// Syslinux files copied to (or written on) the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslinuxFiles {
+    /// 440-byte boot code written to the MBR
+    pub mbr: PathBuf,
+    /// memdisk kernel that boots an ISO from RAM
+    pub memdisk: PathBuf,
+    /// Menu module and the libraries it loads
+    pub modules: Vec<PathBuf>,
+}
+
+/// One ISO on the multiboot partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultibootEntry {
+    /// ISO on the host
+    pub source: PathBuf,
+    /// File name under `isos/` on the partition
+    pub file_name: String,
+    /// Text shown in the boot menu
+    pub label: String,
+}
+
+/// First file named `name` in `dirs`
+fn find_in(dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    dirs.iter().map(|dir| dir.join(name)).find(|path| path.is_file())
+}
+
+/// Locate the syslinux files in `dirs`
+pub fn find_syslinux_files_in(dirs: &[PathBuf]) -> Result<SyslinuxFiles, String> {
+    let require = |name: &str| {
+        find_in(dirs, name).ok_or_else(|| {
+            format!("syslinux file {} not found; install syslinux (BIOS modules) and retry", name)
+        })
+    };
+    Ok(SyslinuxFiles {
+        mbr: require("mbr.bin")?,
+        memdisk: require("memdisk")?,
+        modules: MENU_MODULES.iter().map(|name| require(name)).collect::<Result<_, _>>()?,
+    })
+}
+
+/// Locate the syslinux files installed on this system
+pub fn find_syslinux_files() -> Result<SyslinuxFiles, String> {
+    let dirs: Vec<PathBuf> = SYSLINUX_SEARCH_DIRS.iter().map(PathBuf::from).collect();
+    find_syslinux_files_in(&dirs)
+}
+
+/// File name for the ISO in `slot` (1-based): numbered to keep the menu order and reduced to
+/// characters that are safe in syslinux paths
+fn slot_file_name(slot: usize, iso: &Path) -> String {
+    let name = iso.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let safe: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    format!("{:02}-{}", slot, safe)
+}
+
+/// Menu entries for `isos`, in order
+pub fn plan_entries(isos: &[String]) -> Vec<MultibootEntry> {
+    plan_entries_from(1, isos)
+}
+
+/// Menu entries for `isos`, numbered from `first_slot`
+fn plan_entries_from(first_slot: usize, isos: &[String]) -> Vec<MultibootEntry> {
+    isos.iter()
+        .enumerate()
+        .map(|(index, iso)| {
+            let source = PathBuf::from(iso);
+            let label = source
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| iso.clone());
+            MultibootEntry { file_name: slot_file_name(first_slot + index, &source), source, label }
+        })
+        .collect()
+}
+
+/// Entries for the ISOs already under `isos/` on a multiboot drive, in menu order. The menu
+/// label is recovered from the file name, minus its slot number.
+pub fn existing_entries(iso_dir: &Path) -> io::Result<Vec<MultibootEntry>> {
+    let mut names: Vec<String> = fs::read_dir(iso_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.to_ascii_lowercase().ends_with(".iso"))
+        .collect();
+    names.sort();
+    Ok(names
+        .into_iter()
+        .map(|file_name| {
+            let stem = &file_name[..file_name.len() - ".iso".len()];
+            let label = match stem.split_once('-') {
+                Some((slot, rest)) if slot.chars().all(|c| c.is_ascii_digit()) => rest,
+                _ => stem,
+            };
+            MultibootEntry { source: iso_dir.join(&file_name), label: label.to_string(), file_name }
+        })
+        .collect())
+}
+
+/// Check ISOs added to an existing drive: readable, within `max_file_bytes` when the filesystem
+/// has a limit, not already present under the same name and within `free_bytes` together
+pub fn validate_additions(
+    entries: &[MultibootEntry],
+    dest_dir: &Path,
+    free_bytes: u64,
+    max_file_bytes: Option<u64>,
+) -> Result<u64, String> {
+    if entries.is_empty() {
+        return Err("no ISOs selected to add".to_string());
+    }
+    let mut total = 0;
+    for entry in entries {
+        let path = entry.source.to_string_lossy();
+        check_iso_readable(&path).map_err(|e| e.to_string())?;
+        let bytes = fs::metadata(&entry.source).map_err(|e| format!("{}: {}", path, e))?.len();
+        if max_file_bytes.is_some_and(|max| bytes > max) {
+            return Err(format!("{} is larger than 4 GiB and cannot be stored on FAT32", path));
+        }
+        if dest_dir.join(&entry.file_name).exists() {
+            return Err(format!("{} is already on the drive", entry.file_name));
+        }
+        total += bytes;
+    }
+    if total > free_bytes {
+        return Err(format!(
+            "the ISOs need {} MB but only {} MB are free on the drive",
+            total / 1024 / 1024,
+            free_bytes / 1024 / 1024
+        ));
+    }
+    Ok(total)
+}
+
+/// Space available to unprivileged writers on the filesystem mounted at `root`
+pub(crate) fn free_bytes(root: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(root.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Check that every ISO can be read, fits on FAT32 and that all of them fit on the device
+pub fn validate_entries(entries: &[MultibootEntry], device_bytes: u64) -> Result<u64, String> {
+    if entries.is_empty() {
+        return Err("no ISOs selected for the multiboot drive".to_string());
+    }
+    let mut total = OVERHEAD_BYTES;
+    for entry in entries {
+        let path = entry.source.to_string_lossy();
+        check_iso_readable(&path).map_err(|e| e.to_string())?;
+        let bytes = fs::metadata(&entry.source).map_err(|e| format!("{}: {}", path, e))?.len();
+        if bytes > FAT32_MAX_FILE_BYTES {
+            return Err(format!("{} is larger than 4 GiB and cannot be stored on FAT32", path));
+        }
+        total += bytes;
+    }
+    if total > device_bytes {
+        return Err(format!(
+            "the ISOs need {} MB but the device holds {} MB",
+            total / 1024 / 1024,
+            device_bytes / 1024 / 1024
+        ));
+    }
+    Ok(total)
+}
+
+/// syslinux.cfg listing each entry; memdisk loads the selected ISO into RAM and boots it
+pub fn generate_syslinux_cfg(entries: &[MultibootEntry]) -> String {
+    let mut cfg = String::new();
+    cfg.push_str("UI menu.c32\n");
+    cfg.push_str("PROMPT 0\n");
+    cfg.push_str("TIMEOUT 300\n");
+    cfg.push_str("MENU TITLE MajUSB multiboot\n");
+    for (index, entry) in entries.iter().enumerate() {
+        cfg.push_str(&format!("\nLABEL iso{}\n", index + 1));
+        cfg.push_str(&format!("  MENU LABEL {}\n", entry.label));
+        cfg.push_str(&format!("  LINUX /{}/memdisk\n", SYSLINUX_DIR));
+        cfg.push_str(&format!("  INITRD /{}/{}\n", ISO_DIR, entry.file_name));
+        cfg.push_str("  APPEND iso raw\n");
+    }
+    cfg
+}
+
+/// Run `program` and turn a non-zero exit into an error carrying its stderr
+fn run(program: &str, args: &[&str]) -> io::Result<()> {
+    let output = Command::new(program).args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Copy memdisk, the menu modules and `syslinux.cfg` to the mounted partition
+pub fn write_boot_files(root: &Path, files: &SyslinuxFiles, entries: &[MultibootEntry]) -> io::Result<()> {
+    let dir = root.join(SYSLINUX_DIR);
+    fs::create_dir_all(&dir)?;
+    for file in std::iter::once(&files.memdisk).chain(&files.modules) {
+        if let Some(name) = file.file_name() {
+            fs::copy(file, dir.join(name))?;
+        }
+    }
+    fs::write(dir.join("syslinux.cfg"), generate_syslinux_cfg(entries))
+}
+
+/// Install syslinux on the (unmounted) `partition` and its boot code in the MBR of `device`
+pub fn install_bootloader(device: &str, partition: &str, files: &SyslinuxFiles) -> io::Result<()> {
+    run("syslinux", &["--install", "--directory", &format!("/{}", SYSLINUX_DIR), partition])?;
+    let mbr = format!("if={}", files.mbr.display());
+    run("dd", &["bs=440", "count=1", "conv=notrunc", &mbr, &format!("of={}", device)])?;
+    run("parted", &["-s", device, "set", "1", "boot", "on"])
+}
+
+/// Erase `usb_device` and create a syslinux multiboot drive holding `isos`
+pub fn write_multiboot_usb(isos: &[String], usb_device: &str, log: &mut dyn Write) -> io::Result<()> {
+    let usb_device = &resolve_device_path(usb_device);
+    let entries = plan_entries(isos);
+    let device_bytes = get_device_size_bytes(usb_device)?;
+    let total = validate_entries(&entries, device_bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let files = find_syslinux_files().map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+    writeln!(log, "Creating multiboot drive with {} ISO(s), {} MB in total", entries.len(), total / 1024 / 1024)?;
+
+    ensure_not_system_device(usb_device, log)?;
+    unmount_device_mounts(usb_device, log)?;
+
+    writeln!(log, "Partitioning {}...", usb_device)?;
+    run("wipefs", &["-a", usb_device])?;
+    run("parted", &["-s", usb_device, "mklabel", "msdos", "mkpart", "primary", "fat32", "1MiB", "100%"])?;
+    let _ = Command::new("partprobe").arg(usb_device).status();
+    let _ = Command::new("udevadm").arg("settle").status();
+    let partition = partition_path(usb_device, 1);
+
+    writeln!(log, "Formatting {} as FAT32...", partition)?;
+    run("mkfs.vfat", &["-F", "32", "-n", MULTIBOOT_LABEL, &partition])?;
+
+    let mount_dir = tempfile::tempdir()?;
+    run("mount", &[&partition, &mount_dir.path().to_string_lossy()])?;
+    let unmount = scopeguard::guard(mount_dir.path().to_path_buf(), |dir| {
+        let _ = Command::new("umount").arg(&dir).status();
+    });
+    let iso_dir = unmount.join(ISO_DIR);
+    fs::create_dir_all(&iso_dir)?;
+    for (index, entry) in entries.iter().enumerate() {
+        writeln!(log, "[{}/{}] Copying {}...", index + 1, entries.len(), entry.source.display())?;
+        fs::copy(&entry.source, iso_dir.join(&entry.file_name))?;
+    }
+    writeln!(log, "Writing syslinux menu...")?;
+    write_boot_files(&unmount, &files, &entries)?;
+    flush_with_progress(log)?;
+    drop(unmount);
+
+    writeln!(log, "Installing syslinux...")?;
+    install_bootloader(usb_device, &partition, &files)?;
+    let _ = Command::new("sync").status();
+    writeln!(log, "Multiboot drive ready: {} entries in the boot menu", entries.len())?;
+    Ok(())
+}
+
+/// Copy `isos` onto the Ventoy or syslinux multiboot layout already on `usb_device`, without
+/// repartitioning or formatting. Ventoy finds ISOs on its data partition by itself; the syslinux
+/// menu is rewritten to list the new entries after the existing ones. Returns the number added.
+pub fn add_isos_to_multiboot(isos: &[String], usb_device: &str, log: &mut dyn Write) -> io::Result<usize> {
+    let usb_device = &resolve_device_path(usb_device);
+    let layout = find_multiboot_layout(usb_device)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no Ventoy or multiboot layout; create one with `cli_helper multiboot` first", usb_device),
+        )
+    })?;
+    ensure_not_system_device(usb_device, log)?;
+    let partition = layout.data_partition();
+    writeln!(log, "Found {} multiboot layout, adding ISOs to {}", layout.name(), partition.path)?;
+    let max_file_bytes = (partition.fstype.as_deref() == Some("vfat")).then_some(FAT32_MAX_FILE_BYTES);
+
+    with_mounted_partition(partition, false, |root| {
+        let (dest_dir, existing) = match &layout {
+            MultibootLayout::Ventoy { .. } => (root.to_path_buf(), Vec::new()),
+            MultibootLayout::Syslinux { .. } => {
+                let iso_dir = root.join(ISO_DIR);
+                fs::create_dir_all(&iso_dir)?;
+                let existing = existing_entries(&iso_dir)?;
+                (iso_dir, existing)
+            }
+        };
+        let added: Vec<MultibootEntry> = match &layout {
+            // Ventoy lists files by name, so they keep the one they have
+            MultibootLayout::Ventoy { .. } => plan_entries(isos)
+                .into_iter()
+                .map(|entry| MultibootEntry {
+                    file_name: entry.source.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(entry.file_name),
+                    ..entry
+                })
+                .collect(),
+            MultibootLayout::Syslinux { .. } => plan_entries_from(existing.len() + 1, isos),
+        };
+        let total = validate_additions(&added, &dest_dir, free_bytes(root)?, max_file_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        writeln!(log, "Copying {} ISO(s), {} MB in total", added.len(), total / 1024 / 1024)?;
+        for (index, entry) in added.iter().enumerate() {
+            writeln!(log, "[{}/{}] Copying {}...", index + 1, added.len(), entry.source.display())?;
+            fs::copy(&entry.source, dest_dir.join(&entry.file_name))?;
+        }
+        if matches!(layout, MultibootLayout::Syslinux { .. }) {
+            writeln!(log, "Updating syslinux menu...")?;
+            let entries: Vec<MultibootEntry> = existing.into_iter().chain(added.iter().cloned()).collect();
+            fs::write(root.join(SYSLINUX_DIR).join("syslinux.cfg"), generate_syslinux_cfg(&entries))?;
+        }
+        flush_with_progress(log)?;
+        Ok(())
+    })?;
+    let _ = Command::new("sync").status();
+    writeln!(log, "Added {} ISO(s) to the {} drive", isos.len(), layout.name())?;
+    Ok(isos.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_lists_each_iso_through_memdisk() {
+        let entries = plan_entries(&["/home/me/ISOs/ubuntu 24.04.iso".to_string(), "/tmp/memtest.iso".to_string()]);
+        assert_eq!(entries[0].file_name, "01-ubuntu_24.04.iso");
+        assert_eq!(entries[0].label, "ubuntu 24.04");
+        assert_eq!(entries[1].file_name, "02-memtest.iso");
+
+        let cfg = generate_syslinux_cfg(&entries);
+        assert!(cfg.starts_with("UI menu.c32\n"));
+        assert!(cfg.contains("LABEL iso1\n  MENU LABEL ubuntu 24.04\n  LINUX /syslinux/memdisk\n  INITRD /isos/01-ubuntu_24.04.iso\n  APPEND iso raw\n"));
+        assert!(cfg.contains("LABEL iso2\n  MENU LABEL memtest\n"));
+    }
+
+    #[test]
+    fn syslinux_files_are_collected_across_directories() {
+        let bios = tempfile::tempdir().unwrap();
+        let mbr = tempfile::tempdir().unwrap();
+        let dirs = vec![bios.path().to_path_buf(), mbr.path().to_path_buf()];
+        for name in ["memdisk", "menu.c32", "libutil.c32", "libcom32.c32"] {
+            fs::write(bios.path().join(name), b"").unwrap();
+        }
+        assert!(find_syslinux_files_in(&dirs).unwrap_err().contains("mbr.bin"));
+
+        fs::write(mbr.path().join("mbr.bin"), [0u8; 440]).unwrap();
+        let files = find_syslinux_files_in(&dirs).unwrap();
+        assert_eq!(files.mbr, mbr.path().join("mbr.bin"));
+        assert_eq!(files.modules.len(), 3);
+    }
+
+    #[test]
+    fn entries_must_fit_on_the_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let iso = dir.path().join("small.iso");
+        fs::write(&iso, vec![0u8; 1024 * 1024]).unwrap();
+        let entries = plan_entries(&[iso.to_string_lossy().into_owned()]);
+        assert_eq!(validate_entries(&entries, 128 * 1024 * 1024), Ok(OVERHEAD_BYTES + 1024 * 1024));
+        assert!(validate_entries(&entries, 32 * 1024 * 1024).unwrap_err().contains("device holds 32 MB"));
+        assert!(validate_entries(&[], 128 * 1024 * 1024).is_err());
+    }
+
+    #[test]
+    fn additions_follow_the_existing_menu() {
+        let drive = tempfile::tempdir().unwrap();
+        let iso_dir = drive.path().join(ISO_DIR);
+        fs::create_dir_all(&iso_dir).unwrap();
+        for name in ["02-memtest.iso", "01-ubuntu_24.04.iso", "notes.txt"] {
+            fs::write(iso_dir.join(name), b"").unwrap();
+        }
+        let existing = existing_entries(&iso_dir).unwrap();
+        let names: Vec<&str> = existing.iter().map(|entry| entry.file_name.as_str()).collect();
+        assert_eq!(names, ["01-ubuntu_24.04.iso", "02-memtest.iso"]);
+        assert_eq!(existing[0].label, "ubuntu_24.04");
+
+        let host = tempfile::tempdir().unwrap();
+        let iso = host.path().join("debian.iso");
+        fs::write(&iso, vec![0u8; 1024 * 1024]).unwrap();
+        let added = plan_entries_from(existing.len() + 1, &[iso.to_string_lossy().into_owned()]);
+        assert_eq!(added[0].file_name, "03-debian.iso");
+        assert_eq!(validate_additions(&added, &iso_dir, 2 * 1024 * 1024, Some(FAT32_MAX_FILE_BYTES)), Ok(1024 * 1024));
+        assert!(validate_additions(&added, &iso_dir, 1024, None).unwrap_err().contains("only 0 MB are free"));
+
+        fs::write(iso_dir.join("03-debian.iso"), b"").unwrap();
+        assert!(validate_additions(&added, &iso_dir, u64::MAX, None).unwrap_err().contains("already on the drive"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Volume labels as each filesystem accepts them. `mkfs.vfat` rejects lowercase-only quirks and
+//! long names inconsistently across versions and `mkfs.ext4` silently truncates, so labels are
+//! normalized up front and every change is reported.
+
+use crate::flows::outcome::{Warning, WarningKind};
+
+/// Filesystem a label is written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelFs {
+    Fat,
+    Ntfs,
+    Ext4,
+}
+
+impl LabelFs {
+    pub fn name(self) -> &'static str {
+        match self {
+            LabelFs::Fat => "FAT",
+            LabelFs::Ntfs => "NTFS",
+            LabelFs::Ext4 => "ext4",
+        }
+    }
+
+    /// Longest label: characters for FAT and NTFS, bytes for ext4
+    pub fn max_len(self) -> usize {
+        match self {
+            LabelFs::Fat => 11,
+            LabelFs::Ntfs => 32,
+            LabelFs::Ext4 => 16,
+        }
+    }
+
+    fn allows(self, c: char) -> bool {
+        match self {
+            LabelFs::Fat => {
+                c.is_ascii_uppercase()
+                    || c.is_ascii_digit()
+                    || " !#$%&'()-@^_`{}~".contains(c)
+            }
+            LabelFs::Ntfs => !c.is_control() && !"\"*/:<>?\\|".contains(c),
+            LabelFs::Ext4 => !c.is_control(),
+        }
+    }
+}
+
+/// `label` as `fs` accepts it, with one warning per kind of change made (uppercased, characters
+/// replaced, truncated). The label is returned unchanged, with no warnings, when it already fits.
+pub fn sanitize_label(label: &str, fs: LabelFs) -> (String, Vec<Warning>) {
+    let mut changes = Vec::new();
+
+    let mut text = label.trim().to_string();
+    if text.len() != label.len() {
+        changes.push("surrounding spaces removed".to_string());
+    }
+
+    if fs == LabelFs::Fat && text.chars().any(|c| c.is_ascii_lowercase()) {
+        text = text.to_ascii_uppercase();
+        changes.push("uppercased".to_string());
+    }
+
+    let mut replaced = false;
+    text = text
+        .chars()
+        .map(|c| {
+            if fs.allows(c) {
+                c
+            } else {
+                replaced = true;
+                '_'
+            }
+        })
+        .collect();
+    if replaced {
+        changes.push(format!("unsupported characters replaced with '_' for {}", fs.name()));
+    }
+
+    let truncated = match fs {
+        LabelFs::Ext4 => {
+            let mut end = fs.max_len().min(text.len());
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text[..end].to_string()
+        }
+        LabelFs::Fat | LabelFs::Ntfs => text.chars().take(fs.max_len()).collect(),
+    };
+    if truncated != text {
+        let unit = if fs == LabelFs::Ext4 { "bytes" } else { "characters" };
+        changes.push(format!("truncated to {} ({} {} max for {})", truncated.trim_end(), fs.max_len(), unit, fs.name()));
+        // A trailing space left by the cut would be invisible in file managers
+        text = truncated.trim_end().to_string();
+    }
+
+    let warnings = changes
+        .into_iter()
+        .map(|change| Warning::new(WarningKind::VolumeLabel, format!("volume label \"{}\": {}", label, change)))
+        .collect();
+    (text, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(warnings: &[Warning]) -> Vec<&str> {
+        warnings.iter().map(|w| w.message.as_str()).collect()
+    }
+
+    #[test]
+    fn valid_labels_pass_through_without_warnings() {
+        assert_eq!(sanitize_label("BOOT", LabelFs::Fat), ("BOOT".to_string(), Vec::new()));
+        assert_eq!(sanitize_label("ESD-USB", LabelFs::Ntfs), ("ESD-USB".to_string(), Vec::new()));
+        assert_eq!(sanitize_label("persistence", LabelFs::Ext4), ("persistence".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn fat_labels_are_uppercased_replaced_and_truncated() {
+        let (label, warnings) = sanitize_label("Boot.Files:Windows", LabelFs::Fat);
+        assert_eq!(label, "BOOT_FILES_");
+        assert_eq!(
+            messages(&warnings),
+            [
+                "volume label \"Boot.Files:Windows\": uppercased",
+                "volume label \"Boot.Files:Windows\": unsupported characters replaced with '_' for FAT",
+                "volume label \"Boot.Files:Windows\": truncated to BOOT_FILES_ (11 characters max for FAT)",
+            ]
+        );
+        // The cut may land on a space, which is dropped
+        assert_eq!(sanitize_label("MY BOOT DISK", LabelFs::Fat).0, "MY BOOT DIS");
+        assert_eq!(sanitize_label("WINDOWS 11 X", LabelFs::Fat).0, "WINDOWS 11");
+    }
+
+    #[test]
+    fn ntfs_keeps_case_and_ext4_truncates_on_a_character_boundary() {
+        let (label, warnings) = sanitize_label("Install <Windows> 11", LabelFs::Ntfs);
+        assert_eq!(label, "Install _Windows_ 11");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::VolumeLabel);
+
+        let (label, warnings) = sanitize_label("données-persistantes", LabelFs::Ext4);
+        assert_eq!(label, "données-persist");
+        assert!(label.len() <= 16);
+        assert!(messages(&warnings)[0].contains("truncated to données-persist (16 bytes max for ext4)"));
+    }
+
+    #[test]
+    fn fixed_labels_of_the_flows_already_fit() {
+        for label in ["BOOT", "ESP", "MULTIBOOT", "CIDATA", "LIVE"] {
+            assert!(sanitize_label(label, LabelFs::Fat).1.is_empty(), "{}", label);
+        }
+        assert!(sanitize_label("ESD-USB", LabelFs::Ntfs).1.is_empty());
+    }
+}
@@ -1,12 +1,24 @@
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::io::{self, BufRead, Write};
-use std::time::Instant;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 
-use crate::utils::{get_device_optimal_block_size, has_ntfs3g, is_usb_device, parse_rsync_progress};
+use crate::flows::efi_tool::{install_tool, tool_partition_mb, validate_efi_tool, TOOL_PARTITION_NAME};
+use crate::flows::media_scan::{list_partitions, with_mounted_partition};
+use crate::flows::outcome::{record_warning, Warning, WarningKind};
+use crate::flows::syslinux_multiboot::free_bytes;
+use crate::flows::volume_label::{sanitize_label, LabelFs};
+use crate::fail_point;
+use crate::settings::Tuning;
+use crate::utils::{check_iso_readable, flush_with_progress, get_device_logical_block_size, get_device_optimal_block_size, log_partition_geometry, PartitionInfo, get_device_size_bytes, has_ntfs3_kernel_driver, has_ntfs3g, is_usb_device, missing_program_error, program_not_installed, with_remount_hint, parse_rsync_progress, resolve_device_path, scan_content, ContentScan};
 use crate::windows::unattend::{Architecture, UnattendFlags, UnattendGenerator};
-use crate::windows::wim::WimEditor;
-use tempfile::tempdir_in;
+use crate::windows::wim::{InstallImage, WimEditor};
+use crate::config::temp::{BOOT_MOUNT_DIR, INSTALL_MOUNT_DIR, ISO_MOUNT_DIR, MOUNT_BASE, TOOL_MOUNT_DIR};
+use crate::quirks::{device_quirk, log_device_quirk};
+use crate::settings::{configured_mount_base, CopyStrategy};
+use tempfile::{tempdir_in, TempDir};
 
 /// Metrics captured during the Windows USB creation flow.
 #[derive(Debug, Default, Clone)]
@@ -18,6 +30,95 @@ pub struct WindowsFlowMetrics {
     pub total_bytes: u64,
     pub avg_speed_mbps: f64,
     pub peak_speed_mbps: f64,
+    /// Driver used to mount the INSTALL (NTFS) partition
+    pub ntfs_driver: Option<NtfsDriver>,
+    /// Whether the ISO was loop-mounted or unpacked
+    pub iso_access: Option<IsoAccess>,
+    /// Partition geometry read back from the device after formatting
+    pub partitions: Vec<PartitionInfo>,
+    /// Logical sector size of the device, used to interpret `partitions`
+    pub sector_bytes: u64,
+}
+
+/// Options for the dual-partition Windows flow
+#[derive(Debug, Clone, Default)]
+pub struct WindowsFlowOptions {
+    /// Unattend bypass flags injected into boot.wim
+    pub bypass: Option<UnattendFlags>,
+    /// Requested NTFS cluster size for INSTALL; None derives it from the device block size
+    pub cluster_bytes: Option<u64>,
+    /// Requested `mkfs.vfat -s` sectors per cluster for BOOT; None derives it from the device block size
+    pub fat32_sectors_per_cluster: Option<u64>,
+    /// User `$OEM$` folder copied to `sources/$OEM$` on INSTALL for Windows Setup to apply
+    pub oem_folder: Option<PathBuf>,
+    /// Size in MB of a dedicated EFI System Partition holding only the EFI boot files, with
+    /// boot.wim and setup left on INSTALL; None keeps the 1 GiB BOOT partition
+    pub esp_mb: Option<u64>,
+    /// Extra rsync `--exclude` patterns for the INSTALL copy, checked with `validate_exclude_pattern`
+    pub exclude: Vec<String>,
+    /// Quick boot test: partition and copy the boot files only, leaving INSTALL empty. The stick
+    /// shows up in firmware boot menus but cannot install Windows.
+    pub boot_test: bool,
+    /// Windows 7/8 layout: a single active NTFS partition on an MBR table with BIOS boot code,
+    /// instead of the GPT dual-partition layout those setups can trip over
+    pub legacy: bool,
+    /// EFI application (e.g. memtest86+) put on a third FAT partition, listed by the firmware
+    /// boot menu next to the installer
+    pub tool_efi: Option<PathBuf>,
+    /// ISO contents already copied to local disk by `stage_iso`, read instead of mounting the ISO
+    pub staged_iso: Option<PathBuf>,
+    /// Whole-file copies for first writes or delta transfer for rewrites
+    pub copy_strategy: CopyStrategy,
+}
+
+/// Driver used to mount the NTFS INSTALL partition, in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NtfsDriver {
+    /// In-kernel ntfs3 driver (fastest for large copies)
+    Ntfs3,
+    /// FUSE ntfs-3g driver
+    Ntfs3g,
+    /// Whatever `mount` picks without an explicit type
+    Default,
+}
+
+impl NtfsDriver {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NtfsDriver::Ntfs3 => "ntfs3",
+            NtfsDriver::Ntfs3g => "ntfs-3g",
+            NtfsDriver::Default => "default",
+        }
+    }
+}
+
+/// Mount the NTFS partition, preferring ntfs3, then ntfs-3g, then a plain mount.
+/// Returns the driver that succeeded.
+fn mount_ntfs_partition(partition: &str, mountpoint: &str) -> io::Result<NtfsDriver> {
+    if has_ntfs3_kernel_driver() {
+        let status = Command::new("mount")
+            .args(["-t", "ntfs3", "-o", "noatime,nodiratime", partition, mountpoint])
+            .status()?;
+        if status.success() {
+            return Ok(NtfsDriver::Ntfs3);
+        }
+    }
+    if has_ntfs3g() {
+        let status = Command::new("mount")
+            .args(["-t", "ntfs-3g", "-o", "big_writes,async,noatime,nodiratime", partition, mountpoint])
+            .status()?;
+        if status.success() {
+            return Ok(NtfsDriver::Ntfs3g);
+        }
+    }
+    let status = Command::new("mount")
+        .args(["-o", "noatime,nodiratime", partition, mountpoint])
+        .status()?;
+    if status.success() {
+        Ok(NtfsDriver::Default)
+    } else {
+        Err(io::Error::other("mount INSTALL failed"))
+    }
 }
 
 fn log_metrics(metrics: &WindowsFlowMetrics, log: &mut dyn Write) -> io::Result<()> {
@@ -29,47 +130,1090 @@ fn log_metrics(metrics: &WindowsFlowMetrics, log: &mut dyn Write) -> io::Result<
     writeln!(log, "Total bytes copied : {} bytes", metrics.total_bytes)?;
     writeln!(log, "Average speed      : {:.2} MB/s", metrics.avg_speed_mbps)?;
     writeln!(log, "Peak speed         : {:.2} MB/s", metrics.peak_speed_mbps)?;
+    if let Some(driver) = metrics.ntfs_driver {
+        writeln!(log, "NTFS driver        : {}", driver.as_str())?;
+    }
+    match metrics.iso_access {
+        Some(IsoAccess::Extracted(extractor)) => {
+            writeln!(log, "ISO access         : unpacked with {} (no loop mount)", extractor.program())?
+        }
+        Some(IsoAccess::Staged) => writeln!(log, "ISO access         : staged copy shared by a batch")?,
+        _ => {}
+    }
+    for part in &metrics.partitions {
+        writeln!(log, "Partition          : {}", part.summary(metrics.sector_bytes))?;
+    }
     writeln!(log, "--------------------------------------")?;
     Ok(())
 }
 
+/// Exit code and error output of a failed rsync run
+#[derive(Debug)]
+struct RsyncFailure {
+    /// None when rsync could not be spawned or was killed by a signal
+    code: Option<i32>,
+    stderr: String,
+    /// rsync itself was not found
+    missing: bool,
+    /// rsync was stopped after making no progress for this many seconds
+    stalled_secs: Option<u64>,
+}
+
+impl RsyncFailure {
+    /// rsync could not be started or waited for
+    fn spawn(e: io::Error) -> Self {
+        RsyncFailure { code: None, missing: e.kind() == io::ErrorKind::NotFound, stderr: e.to_string(), stalled_secs: None }
+    }
+
+    /// rsync exited with a failure `code`
+    fn exited(code: Option<i32>, error_lines: &[String]) -> Self {
+        RsyncFailure { code, stderr: error_lines.join("\n"), missing: false, stalled_secs: None }
+    }
+
+    /// Error for the failed `label` copy; a missing rsync gets the command that installs it
+    fn into_error(self, label: &str) -> io::Error {
+        if self.missing {
+            program_not_installed("rsync")
+        } else {
+            io::Error::other(format!("rsync {} failed: {}", label, self))
+        }
+    }
+
+    /// Stalls on marginal sticks surface as I/O (11, 12), partial transfer (23) or timeout (30)
+    /// errors, or as a copy stopped for making no progress, and `--inplace` lets a rerun pick up
+    /// where the copy stopped. A full disk, a read-only remount or a vanished device will not recover.
+    fn is_transient(&self, device_present: bool) -> bool {
+        const FATAL_MARKERS: [&str; 3] = ["No space left on device", "Read-only file system", "No such device"];
+        device_present
+            && (self.stalled_secs.is_some() || matches!(self.code, Some(11 | 12 | 23 | 30)))
+            && !FATAL_MARKERS.iter().any(|marker| self.stderr.contains(marker))
+    }
+}
+
+impl std::fmt::Display for RsyncFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.stalled_secs, self.code) {
+            (Some(secs), _) => write!(f, "transfer stalled: no progress for {}s, the stick may be failing", secs)?,
+            (None, Some(code)) => write!(f, "rsync exited with code {}", code)?,
+            (None, None) => write!(f, "rsync did not complete")?,
+        }
+        let detail = self.stderr.trim();
+        if !detail.is_empty() {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run an rsync copy, rerunning it while failures look like a recoverable device stall.
+/// Attempts and the pause between them come from `Tuning`.
+fn rsync_with_retry<T>(
+    label: &str,
+    usb_device: &str,
+    log: &mut dyn Write,
+    mut run: impl FnMut() -> Result<T, RsyncFailure>,
+) -> io::Result<T> {
+    let tuning = Tuning::for_device(usb_device);
+    let mut attempt = 1;
+    loop {
+        match run() {
+            Ok(value) => return Ok(value),
+            Err(failure) if attempt < tuning.copy_attempts && failure.is_transient(Path::new(usb_device).exists()) => {
+                writeln!(
+                    log,
+                    "[RETRY] {} copy failed (attempt {}/{}): {}. Resuming in {}s...",
+                    label, attempt, tuning.copy_attempts, failure, tuning.copy_retry_delay_secs
+                )?;
+                log.flush()?;
+                std::thread::sleep(std::time::Duration::from_secs(tuning.copy_retry_delay_secs));
+                attempt += 1;
+            }
+            Err(failure) => return Err(failure.into_error(label)),
+        }
+    }
+}
+
+/// Keep the last lines rsync wrote to stderr that are not progress updates
+fn push_error_line(lines: &mut Vec<String>, line: String) {
+    if line.trim().is_empty() || parse_rsync_progress(&line).is_some() {
+        return;
+    }
+    if lines.len() == 5 {
+        lines.remove(0);
+    }
+    lines.push(line);
+}
+
+/// How often a running copy is checked for a stall
+const STALL_POLL: Duration = Duration::from_secs(1);
+
+/// Run rsync and collect its progress; see `watch_rsync` for the stall handling
 fn run_rsync_with_metrics(
     args: &[String],
+    stall_timeout: Option<Duration>,
     peak_speed: &mut f64,
-) -> io::Result<u64> {
-    let mut command = Command::new("rsync");
-    command.args(args);
-    command.stdout(std::process::Stdio::null());
-    command.stderr(std::process::Stdio::piped());
+) -> Result<u64, RsyncFailure> {
+    let child = Command::new("rsync")
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(RsyncFailure::spawn)?;
+    watch_rsync(child, stall_timeout, false, peak_speed)
+}
+
+/// Forward what `output` writes to `lines`, one message per `\n` or `\r` (progress lines are
+/// redrawn with `\r`), tagged with `is_stderr`
+fn forward_lines(output: impl std::io::Read + Send + 'static, is_stderr: bool, lines: std::sync::mpsc::Sender<(bool, String)>) {
+    std::thread::spawn(move || {
+        for segment in std::io::BufReader::new(output).split(b'\r').map_while(Result::ok) {
+            for line in segment.split(|&byte| byte == b'\n').filter(|line| !line.is_empty()) {
+                if lines.send((is_stderr, String::from_utf8_lossy(line).into_owned())).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
 
-    let mut child = command.spawn()?;
+/// Follow a running rsync until it exits: progress from stdout, error lines from stderr, both
+/// echoed to the terminal with `echo`. A dying stick can keep rsync blocked on a write without
+/// it ever failing, so once the byte count has not moved for `stall_timeout` the child is
+/// killed and the copy reported as stalled.
+fn watch_rsync(
+    mut child: std::process::Child,
+    stall_timeout: Option<Duration>,
+    echo: bool,
+    peak_speed: &mut f64,
+) -> Result<u64, RsyncFailure> {
     let mut transferred: u64 = 0;
+    let mut error_lines = Vec::new();
 
+    // Output is read on other threads so a silent rsync does not block the stall check
+    let (lines_tx, lines_rx) = std::sync::mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, false, lines_tx.clone());
+    }
     if let Some(stderr) = child.stderr.take() {
-        let reader = std::io::BufReader::new(stderr);
-        for line in reader.lines() {
-            if let Ok(line) = line {
+        forward_lines(stderr, true, lines_tx.clone());
+    }
+    drop(lines_tx);
+
+    let mut last_progress = Instant::now();
+    loop {
+        match lines_rx.recv_timeout(STALL_POLL) {
+            Ok((true, line)) => {
+                if echo {
+                    eprintln!("{}", line);
+                }
+                push_error_line(&mut error_lines, line);
+            }
+            Ok((false, line)) => {
+                if echo {
+                    print!("{}\r", line);
+                    let _ = io::stdout().flush();
+                }
                 if let Some((bytes, speed_mbps_opt)) = parse_rsync_progress(&line) {
-                    transferred = transferred.max(bytes);
-                    if let Some(speed) = speed_mbps_opt {
-                        if speed > *peak_speed {
-                            *peak_speed = speed;
-                        }
+                    if bytes > transferred {
+                        transferred = bytes;
+                        last_progress = Instant::now();
+                    }
+                    if let Some(speed) = speed_mbps_opt
+                        && speed > *peak_speed
+                    {
+                        *peak_speed = speed;
                     }
                 }
             }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        if let Some(limit) = stall_timeout
+            && last_progress.elapsed() >= limit
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            let mut failure = RsyncFailure::exited(None, &error_lines);
+            failure.stalled_secs = Some(limit.as_secs());
+            return Err(failure);
         }
     }
 
-    let status = child.wait()?;
+    let status = child.wait().map_err(RsyncFailure::spawn)?;
     if !status.success() {
-        return Err(io::Error::new(io::ErrorKind::Other, "rsync failed"));
+        return Err(RsyncFailure::exited(status.code(), &error_lines));
     }
 
     Ok(transferred)
 }
 
-fn ensure_not_system_device(device: &str, log: &mut dyn Write) -> io::Result<()> {
+/// Run rsync with its progress and errors echoed to the terminal, stopping it when it stalls
+fn run_rsync_streaming(args: &[String], stall_timeout: Option<Duration>) -> Result<(), RsyncFailure> {
+    let child = Command::new("rsync")
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(RsyncFailure::spawn)?;
+    watch_rsync(child, stall_timeout, true, &mut 0.0).map(|_| ())
+}
+
+/// Largest file FAT32 can store (4 GiB - 1 byte)
+const FAT32_MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+/// Find files destined for the FAT32 BOOT partition that exceed the FAT32 file size limit.
+/// The BOOT set is everything outside `sources/`, plus `sources/boot.wim`.
+fn find_oversized_boot_files(iso_root: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+    fn walk(dir: &Path, root: &Path, found: &mut Vec<(PathBuf, u64)>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if rel.as_os_str().eq_ignore_ascii_case("sources") {
+                    let boot_wim = path.join("boot.wim");
+                    if let Ok(meta) = fs::metadata(&boot_wim)
+                        && meta.len() > FAT32_MAX_FILE_SIZE
+                    {
+                        found.push((rel.join("boot.wim"), meta.len()));
+                    }
+                    continue;
+                }
+                walk(&path, root, found)?;
+            } else if file_type.is_file() {
+                let len = entry.metadata()?.len();
+                if len > FAT32_MAX_FILE_SIZE {
+                    found.push((rel, len));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut found = Vec::new();
+    walk(iso_root, iso_root, &mut found)?;
+    Ok(found)
+}
+
+/// Refuse media whose boot files cannot be stored on the FAT32 BOOT partition
+fn ensure_boot_files_fit_fat32(iso_root: &Path) -> io::Result<()> {
+    let oversized = find_oversized_boot_files(iso_root)?;
+    if oversized.is_empty() {
+        return Ok(());
+    }
+    let list = oversized
+        .iter()
+        .map(|(path, len)| format!("{} ({:.2} GiB)", path.display(), *len as f64 / (1u64 << 30) as f64))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Boot files exceed the FAT32 4 GiB file limit and cannot be copied to the BOOT partition: {}. \
+             Use direct dd mode or rebuild the media with smaller boot files.",
+            list
+        ),
+    ))
+}
+
+/// FAT32 needs at least this many clusters; below it mkfs.vfat refuses or falls back to FAT16
+const FAT32_MIN_CLUSTERS: u64 = 65_525;
+/// Largest cluster count a FAT32 table can address
+const FAT32_MAX_CLUSTERS: u64 = 0x0FFF_FFF5;
+/// Largest FAT32 cluster that Windows setup and UEFI firmwares reliably read
+const FAT32_MAX_CLUSTER_BYTES: u64 = 32 * 1024;
+/// Largest NTFS cluster accepted by every mkfs.ntfs release
+const NTFS_MAX_CLUSTER_BYTES: u64 = 64 * 1024;
+
+/// Cluster sizes used to format the BOOT (FAT32) and INSTALL (NTFS) partitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClusterSizes {
+    pub fat32_bytes: u64,
+    pub ntfs_bytes: u64,
+}
+
+/// Approximate FAT32 data clusters: 32 reserved sectors, then two 4-byte FAT entries per cluster
+fn fat32_cluster_count(partition_bytes: u64, cluster_bytes: u64, sector_bytes: u64) -> u64 {
+    partition_bytes.saturating_sub(32 * sector_bytes) / (cluster_bytes + 8)
+}
+
+/// Closest FAT32 cluster size to `requested` that mkfs.vfat accepts for the partition.
+/// Errors explain why no size fits.
+pub fn fit_fat32_cluster(partition_bytes: u64, requested: u64, sector_bytes: u64) -> Result<u64, String> {
+    if !requested.is_power_of_two() {
+        return Err(format!("FAT32 cluster size {} bytes is not a power of two", requested));
+    }
+    let mut cluster = requested.clamp(sector_bytes.min(FAT32_MAX_CLUSTER_BYTES), FAT32_MAX_CLUSTER_BYTES);
+    while fat32_cluster_count(partition_bytes, cluster, sector_bytes) < FAT32_MIN_CLUSTERS {
+        if cluster <= sector_bytes {
+            return Err(format!(
+                "a {} MB partition is too small for FAT32 with {}-byte sectors (at least {} clusters are required)",
+                partition_bytes / 1024 / 1024,
+                sector_bytes,
+                FAT32_MIN_CLUSTERS
+            ));
+        }
+        cluster /= 2;
+    }
+    while fat32_cluster_count(partition_bytes, cluster, sector_bytes) > FAT32_MAX_CLUSTERS {
+        if cluster >= FAT32_MAX_CLUSTER_BYTES {
+            return Err(format!(
+                "a {} MB partition is too large for FAT32 even with {} KB clusters",
+                partition_bytes / 1024 / 1024,
+                FAT32_MAX_CLUSTER_BYTES / 1024
+            ));
+        }
+        cluster *= 2;
+    }
+    Ok(cluster)
+}
+
+/// Closest NTFS cluster size to `requested` that mkfs.ntfs accepts for the partition.
+/// Errors explain which sizes to pick instead.
+pub fn fit_ntfs_cluster(partition_bytes: u64, requested: u64, sector_bytes: u64) -> Result<u64, String> {
+    if !requested.is_power_of_two() {
+        return Err(format!("NTFS cluster size {} bytes is not a power of two", requested));
+    }
+    if requested > NTFS_MAX_CLUSTER_BYTES {
+        return Err(format!(
+            "NTFS cluster size {} KB is larger than mkfs.ntfs accepts; choose {} KB or less",
+            requested / 1024,
+            NTFS_MAX_CLUSTER_BYTES / 1024
+        ));
+    }
+    // Clusters can never be smaller than a sector, and NTFS addresses at most 2^32 - 1 of them
+    let mut cluster = requested.max(sector_bytes);
+    while partition_bytes / cluster >= u64::from(u32::MAX) {
+        if cluster >= NTFS_MAX_CLUSTER_BYTES {
+            return Err(format!(
+                "a {} MB partition is too large for NTFS with {} KB clusters",
+                partition_bytes / 1024 / 1024,
+                NTFS_MAX_CLUSTER_BYTES / 1024
+            ));
+        }
+        cluster *= 2;
+    }
+    Ok(cluster)
+}
+
+/// Check the requested cluster sizes against the partitions just created, logging any
+/// adjustment. Sizes are passed through unchanged when the partitions cannot be measured.
+fn plan_cluster_sizes(usb_device: &str, requested: ClusterSizes, log: &mut dyn Write) -> io::Result<ClusterSizes> {
+    let sector_bytes = get_device_logical_block_size(usb_device).unwrap_or(512);
+    let (boot_bytes, install_bytes) = match (
+        get_device_size_bytes(&format!("{}1", usb_device)),
+        get_device_size_bytes(&format!("{}2", usb_device)),
+    ) {
+        (Ok(boot), Ok(install)) => (boot, install),
+        (Err(e), _) | (_, Err(e)) => {
+            writeln!(log, "Warning: could not measure partitions ({}); using requested cluster sizes", e)?;
+            return Ok(requested);
+        }
+    };
+    let invalid = |fs: &str, reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Cannot format {} partition: {}. Pick a different cluster size in Advanced options.", fs, reason),
+        )
+    };
+    let planned = ClusterSizes {
+        fat32_bytes: fit_fat32_cluster(boot_bytes, requested.fat32_bytes, sector_bytes)
+            .map_err(|reason| invalid("BOOT (FAT32)", reason))?,
+        ntfs_bytes: fit_ntfs_cluster(install_bytes, requested.ntfs_bytes, sector_bytes)
+            .map_err(|reason| invalid("INSTALL (NTFS)", reason))?,
+    };
+    if planned.fat32_bytes != requested.fat32_bytes {
+        writeln!(
+            log,
+            "Adjusted FAT32 cluster size from {} to {} bytes to fit the {} MB BOOT partition",
+            requested.fat32_bytes,
+            planned.fat32_bytes,
+            boot_bytes / 1024 / 1024
+        )?;
+    }
+    if planned.ntfs_bytes != requested.ntfs_bytes {
+        writeln!(
+            log,
+            "Adjusted NTFS cluster size from {} to {} bytes to fit the {} MB INSTALL partition",
+            requested.ntfs_bytes,
+            planned.ntfs_bytes,
+            install_bytes / 1024 / 1024
+        )?;
+    }
+    Ok(planned)
+}
+
+/// Cluster sizes derived from the device block size, each overridden by the user's choice when set
+fn requested_cluster_sizes(
+    block_size: u64,
+    sector_bytes: u64,
+    ntfs_override: Option<u64>,
+    fat32_sectors_override: Option<u64>,
+) -> ClusterSizes {
+    ClusterSizes {
+        fat32_bytes: fat32_sectors_override
+            .map(|sectors| sectors * sector_bytes)
+            .unwrap_or(block_size.clamp(512, FAT32_MAX_CLUSTER_BYTES)),
+        ntfs_bytes: ntfs_override.unwrap_or(block_size.clamp(512, NTFS_MAX_CLUSTER_BYTES)),
+    }
+}
+
+/// Subfolders Windows Setup recognizes in `$OEM$`: `$$` (Windows dir), `$1` (system drive),
+/// plus single drive letters
+fn is_oem_subfolder(name: &str) -> bool {
+    matches!(name, "$$" | "$1" | "$Docs" | "$Progs" | "Textmode")
+        || (name.len() == 1 && name.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+/// Check that `path` is a `$OEM$` folder: a directory with at least one subfolder Setup applies
+pub fn validate_oem_folder(path: &Path) -> Result<(), String> {
+    let entries = fs::read_dir(path).map_err(|e| format!("Cannot read $OEM$ folder {}: {}", path.display(), e))?;
+    let subfolders: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    if subfolders.iter().any(|name| is_oem_subfolder(name)) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} does not look like a $OEM$ folder: expected subfolders such as $$ (copied to the Windows folder) or $1 (copied to the system drive)",
+            path.display()
+        ))
+    }
+}
+
+/// rsync arguments copying the `$OEM$` folder to `sources/$OEM$` on the mounted INSTALL partition
+fn oem_copy_args(oem_folder: &Path, install_mount: &Path) -> Vec<String> {
+    vec![
+        "-r".to_string(),
+        "--times".to_string(),
+        format!("{}/", oem_folder.display()),
+        format!("{}/", install_mount.join("sources").join("$OEM$").display()),
+    ]
+}
+
+/// Copy the `$OEM$` folder to the mounted INSTALL partition
+fn copy_oem_folder(oem_folder: &Path, install_mount: &Path, log: &mut dyn Write) -> io::Result<()> {
+    writeln!(log, "Copying $OEM$ folder from {}...", oem_folder.display())?;
+    let status = Command::new("rsync")
+        .args(oem_copy_args(oem_folder, install_mount))
+        .status()
+        .map_err(|e| missing_program_error("rsync", e))?;
+    if !status.success() {
+        return Err(io::Error::other(format!("copying $OEM$ folder failed ({})", status)));
+    }
+    Ok(())
+}
+
+/// Check a user-chosen FAT32 sectors-per-cluster value (`mkfs.vfat -s` takes powers of two up to 128)
+pub fn validate_fat32_sectors_per_cluster(sectors: u64) -> Result<(), String> {
+    if sectors.is_power_of_two() && sectors <= 128 {
+        Ok(())
+    } else {
+        Err(format!("FAT32 sectors per cluster must be a power of two from 1 to 128, got {}", sectors))
+    }
+}
+
+/// Smallest and largest dedicated ESP offered. Below about 260 MB FAT32 needs sectors smaller
+/// than 4 KiB per cluster; the ESP also carries boot.wim, which `ensure_esp_fits` checks.
+pub const ESP_MIN_MB: u64 = 300;
+pub const ESP_MAX_MB: u64 = 4096;
+
+/// Check a user-chosen ESP size
+pub fn validate_esp_size_mb(size_mb: u64) -> Result<(), String> {
+    if (ESP_MIN_MB..=ESP_MAX_MB).contains(&size_mb) {
+        Ok(())
+    } else {
+        Err(format!("ESP size must be between {} and {} MB, got {}", ESP_MIN_MB, ESP_MAX_MB, size_mb))
+    }
+}
+
+/// Files Windows Setup cannot start or install without, relative to the ISO root
+const REQUIRED_SETUP_FILES: [&str; 9] = [
+    "bootmgr",
+    "bootmgr.efi",
+    "setup.exe",
+    "boot/bcd",
+    "efi/boot/bootx64.efi",
+    "sources/boot.wim",
+    "sources/install.wim",
+    "sources/install.esd",
+    "sources/install.swm",
+];
+
+/// rsync-style wildcard match: `*` and `?` stop at `/`, `**` crosses it. Case-sensitive like
+/// rsync unless `fold_case`, which the setup file check uses because ISOs differ in case.
+fn wildcard_match(pattern: &[u8], text: &[u8], fold_case: bool) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| wildcard_match(rest, &text[i..], fold_case)),
+        [b'*', rest @ ..] => {
+            let segment = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=segment).any(|i| wildcard_match(rest, &text[i..], fold_case))
+        }
+        [b'?', rest @ ..] => text.first().is_some_and(|&c| c != b'/') && wildcard_match(rest, &text[1..], fold_case),
+        [c, rest @ ..] => {
+            text.first().is_some_and(|t| t == c || (fold_case && t.eq_ignore_ascii_case(c)))
+                && wildcard_match(rest, &text[1..], fold_case)
+        }
+    }
+}
+
+/// Whether an exclude pattern matches `rel_path` (relative to the copy root). Like rsync, a
+/// pattern without `/` matches the last path component anywhere, one with `/` matches the
+/// whole path from the root, and a trailing `/` only matches directories.
+fn exclude_matches(pattern: &str, rel_path: &str, is_dir: bool, fold_case: bool) -> bool {
+    let dir_only = pattern.ends_with('/');
+    if dir_only && !is_dir {
+        return false;
+    }
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.trim_start_matches('/').contains('/') || pattern.starts_with('/') {
+        wildcard_match(pattern.trim_start_matches('/').as_bytes(), rel_path.as_bytes(), fold_case)
+    } else {
+        let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        wildcard_match(pattern.as_bytes(), name.as_bytes(), fold_case)
+    }
+}
+
+/// Check a user exclude pattern for the INSTALL copy: it must be a single non-empty rsync
+/// pattern and must not drop anything Windows Setup needs, or any of its parent folders.
+pub fn validate_exclude_pattern(pattern: &str) -> Result<(), String> {
+    if pattern.trim().is_empty() {
+        return Err("Exclude pattern is empty".to_string());
+    }
+    if pattern.contains(['\n', '\r']) || pattern.starts_with(['+', '-']) {
+        return Err(format!("Exclude pattern '{}' must be a plain file pattern, not an rsync rule", pattern));
+    }
+    for required in REQUIRED_SETUP_FILES {
+        let mut parent = String::new();
+        for (index, component) in required.split('/').enumerate() {
+            if index > 0 {
+                parent.push('/');
+            }
+            parent.push_str(component);
+            let is_dir = parent.len() < required.len();
+            if exclude_matches(pattern, &parent, is_dir, true) {
+                return Err(format!("Exclude pattern '{}' would skip {}, which Windows Setup needs", pattern, required));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split a whitespace-separated pattern list as typed in the GUI
+pub fn parse_exclude_patterns(text: &str) -> Vec<String> {
+    text.split_whitespace().map(str::to_string).collect()
+}
+
+/// Files and folders under `root` that the exclude patterns drop, with their total size in bytes.
+/// Excluded folders are reported once, not descended into. Without `fold_case` this is exactly
+/// what rsync skips.
+fn excluded_paths(root: &Path, patterns: &[String], fold_case: bool) -> Vec<(String, u64)> {
+    fn tree_size(path: &Path) -> u64 {
+        scan_content(path, &AtomicBool::new(false), &mut |_| {}).map_or(0, |totals| totals.bytes)
+    }
+    fn walk(root: &Path, dir: &Path, patterns: &[String], fold_case: bool, found: &mut Vec<(String, u64)>) {
+        let Ok(entries) = fs::read_dir(dir) else { return };
+        let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let rel = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().to_string();
+            let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            if patterns.iter().any(|p| exclude_matches(p, &rel, is_dir, fold_case)) {
+                found.push((rel, tree_size(&path)));
+            } else if is_dir {
+                walk(root, &path, patterns, fold_case, found);
+            }
+        }
+    }
+    let mut found = Vec::new();
+    if !patterns.is_empty() {
+        walk(root, root, patterns, fold_case, &mut found);
+    }
+    found
+}
+
+/// Log the user exclude patterns and what they match on the mounted ISO, then add them to the
+/// INSTALL rsync arguments
+fn apply_install_excludes(iso_root: &Path, patterns: &[String], args: &mut Vec<String>, log: &mut dyn Write) -> io::Result<()> {
+    if patterns.is_empty() {
+        return Ok(());
+    }
+    writeln!(log, "Excluding from INSTALL: {}", patterns.join(", "))?;
+    let excluded = excluded_paths(iso_root, patterns, false);
+    if excluded.is_empty() {
+        writeln!(log, "  (no files on the ISO match these patterns)")?;
+    }
+    for (path, bytes) in &excluded {
+        writeln!(log, "  skipped {} ({:.1} MB)", path, *bytes as f64 / 1_000_000.0)?;
+    }
+    // rsync compares names case-sensitively; point out files a different case would have caught
+    for (path, _) in excluded_paths(iso_root, patterns, true) {
+        if !excluded.iter().any(|(skipped, _)| *skipped == path) {
+            writeln!(log, "  not skipped {}: the patterns match it only in a different case", path)?;
+        }
+    }
+    args.extend(patterns.iter().map(|p| format!("--exclude={}", p)));
+    Ok(())
+}
+
+/// GPT name, FAT volume label and end of the FAT32 partition: the classic 1 GiB BOOT, or an ESP
+/// of `esp_mb` after the 1 MiB alignment gap
+fn fat_partition_layout(esp_mb: Option<u64>) -> (&'static str, &'static str, String) {
+    match esp_mb {
+        Some(size_mb) => ("ESP", "ESP", format!("{}MiB", size_mb + 1)),
+        None => ("BOOT", "BOOT", "1GiB".to_string()),
+    }
+}
+
+/// Size in MiB of the EFI tool partition, after checking the tool image; None without a tool
+fn tool_partition_size(options: &WindowsFlowOptions) -> io::Result<Option<u64>> {
+    let Some(tool) = &options.tool_efi else { return Ok(None) };
+    validate_efi_tool(tool).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok(Some(tool_partition_mb(fs::metadata(tool)?.len())))
+}
+
+/// End of INSTALL: the whole device, or short of a `tool_mb` tool partition and the backup GPT
+fn install_partition_end(device_bytes: u64, tool_mb: Option<u64>) -> io::Result<String> {
+    let Some(tool_mb) = tool_mb else { return Ok("100%".to_string()) };
+    let device_mib = device_bytes / (1024 * 1024);
+    device_mib
+        .checked_sub(tool_mb + 1)
+        .map(|end| format!("{}MiB", end))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the device is too small for the EFI tool partition"))
+}
+
+/// Files the ISO's BCD loads from the partition it booted from: the WinPE image and its ramdisk
+const BCD_BOOT_FILES: [&str; 2] = ["sources/boot.wim", "boot/boot.sdi"];
+
+/// What a dedicated ESP receives besides the EFI loader tree: the boot manager, the BIOS-path
+/// BCD and everything the BCD entries load
+const ESP_FILES: [&str; 4] = ["bootmgr.efi", "boot/bcd", "boot/boot.sdi", "sources/boot.wim"];
+
+/// rsync filters for the FAT32 partition: everything but `sources/` on BOOT, only the EFI
+/// loader tree and `ESP_FILES` on a dedicated ESP
+fn fat_copy_filters(esp: bool) -> Vec<String> {
+    if !esp {
+        return vec!["--exclude=sources/".to_string()];
+    }
+    let mut filters = vec!["--include=/efi/***".to_string(), "--include=/EFI/***".to_string()];
+    for file in ESP_FILES {
+        // rsync only descends into directories that are included themselves
+        if let Some((dir, _)) = file.split_once('/') {
+            filters.push(format!("--include=/{}/", dir));
+        }
+        filters.push(format!("--include=/{}", file));
+    }
+    filters.push("--exclude=*".to_string());
+    filters
+}
+
+/// Fail when the ESP at `esp_root` lacks a file the BCD loads; WinPE would not start from it
+fn ensure_bcd_files_copied(esp_root: &Path) -> io::Result<()> {
+    let missing: Vec<&str> = BCD_BOOT_FILES.into_iter().filter(|file| !esp_root.join(file).is_file()).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("The ESP is missing {}, which the boot configuration loads; the ISO may not be a Windows installer", missing.join(", ")),
+    ))
+}
+
+/// Bytes of the files a dedicated ESP receives
+fn esp_payload_bytes(iso_root: &Path) -> io::Result<u64> {
+    ["efi", "EFI"]
+        .into_iter()
+        .chain(ESP_FILES)
+        .map(|name| iso_root.join(name))
+        .filter(|path| path.exists())
+        .try_fold(0, |total, path| Ok(total + scan_content(&path, &AtomicBool::new(false), &mut |_| {})?.bytes))
+}
+
+/// Total size of the files under `iso_root`, walked on a worker thread with a progress line in
+/// the log every few seconds. Large ISOs hold tens of thousands of files.
+fn iso_content_bytes(iso_root: &Path, log: &mut dyn Write) -> io::Result<u64> {
+    let scan = ContentScan::start(iso_root.to_path_buf());
+    let mut last_report = Instant::now();
+    while !scan.is_finished() {
+        std::thread::sleep(Duration::from_millis(100));
+        if let Some(totals) = scan.progress().pop()
+            && last_report.elapsed() >= Duration::from_secs(2)
+        {
+            writeln!(log, "  {}", totals.describe())?;
+            last_report = Instant::now();
+        }
+    }
+    Ok(scan.wait()?.bytes)
+}
+
+/// Refuse an ESP too small for the ISO's EFI files (allowing for the FAT structures)
+fn ensure_esp_fits(iso_root: &Path, esp_mb: u64) -> io::Result<()> {
+    let needed = esp_payload_bytes(iso_root)?;
+    let usable = esp_mb * 1024 * 1024 / 10 * 9;
+    if needed > usable {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The EFI loaders, boot configuration and boot.wim need {} MB but a {} MB ESP only holds about {} MB; choose a larger ESP",
+                needed.div_ceil(1024 * 1024),
+                esp_mb,
+                usable / 1024 / 1024
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Answer file name Windows Setup looks for at the root of removable media and in boot.wim
+const UNATTEND_FILE: &str = "Autounattend.xml";
+
+/// File left at the root of the boot partition of a boot test stick
+pub const BOOT_TEST_MARKER: &str = "BOOT-TEST-ONLY.txt";
+
+/// Mark a boot test stick so nobody mistakes it for install media
+fn write_boot_test_marker(boot_root: &Path, log: &mut dyn Write) -> io::Result<()> {
+    writeln!(log, "Boot test only: INSTALL is left empty, this stick cannot install Windows")?;
+    fs::write(
+        boot_root.join(BOOT_TEST_MARKER),
+        "This stick was created as a boot test: it holds the boot files only and cannot install Windows.\r\n\
+         Write the ISO again without the boot test option to get working install media.\r\n",
+    )
+}
+
+/// Add an answer file with `flags` to boot.wim (image 2, Windows Setup, when it exists)
+fn inject_unattend(boot_wim: &Path, flags: UnattendFlags, log: &mut dyn Write) -> io::Result<()> {
+    let unattend_path = UnattendGenerator::new(Architecture::X64, flags).generate()?;
+    let wim_editor = WimEditor::new(boot_wim);
+    let target_index = if wim_editor.verify_index(2).unwrap_or(false) { 2 } else { 1 };
+    writeln!(log, "Injecting {} into boot.wim (index {})...", UNATTEND_FILE, target_index)?;
+    wim_editor.add_file(target_index, &unattend_path, &format!("/{}", UNATTEND_FILE))?;
+    writeln!(log, "Bypass unattend injected successfully.")?;
+    Ok(())
+}
+
+/// Write the answer file for `flags` at `root` of a mounted Windows stick; without flags an
+/// existing one is removed. With `inject_boot_wim` the copy inside `sources/boot.wim` is replaced
+/// too (an empty answer file when there are no flags).
+fn write_unattend_in_place(root: &Path, flags: UnattendFlags, inject_boot_wim: bool, log: &mut dyn Write) -> io::Result<()> {
+    let answer = root.join(UNATTEND_FILE);
+    if flags.is_empty() {
+        if answer.exists() {
+            fs::remove_file(&answer)?;
+            writeln!(log, "Removed {}", answer.display())?;
+        }
+    } else {
+        UnattendGenerator::new(Architecture::X64, flags).with_output_path(&answer).generate()?;
+        writeln!(log, "Wrote {} ({:?})", answer.display(), flags)?;
+    }
+    if inject_boot_wim {
+        inject_unattend(&root.join("sources/boot.wim"), flags, log)?;
+    }
+    Ok(())
+}
+
+/// Change the bypass answer file of a stick written earlier, without rewriting it: the partition
+/// holding `sources/boot.wim` gets a new `Autounattend.xml` (and, with `inject_boot_wim`, so does
+/// boot.wim). Returns the partition that was updated.
+pub fn update_unattend_on_usb(
+    usb_device: &str,
+    flags: UnattendFlags,
+    inject_boot_wim: bool,
+    log: &mut dyn Write,
+) -> io::Result<String> {
+    let usb_device = &resolve_device_path(usb_device);
+    if inject_boot_wim && !WimEditor::has_wimlib() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "wimlib-imagex is required to update boot.wim"));
+    }
+    ensure_not_system_device(usb_device, log)?;
+    let candidates = list_partitions(usb_device)?
+        .into_iter()
+        .filter(|part| matches!(part.fstype.as_deref(), Some("vfat" | "exfat" | "ntfs")));
+    for partition in candidates {
+        let updated = with_mounted_partition(&partition, false, |root| {
+            if !root.join("sources/boot.wim").is_file() {
+                return Ok(false);
+            }
+            writeln!(log, "Updating the answer file on {}...", partition.path)?;
+            write_unattend_in_place(root, flags, inject_boot_wim, log)?;
+            Ok(true)
+        });
+        match updated {
+            Ok(true) => {
+                flush_with_progress(log)?;
+                return Ok(partition.path);
+            }
+            Ok(false) => {}
+            Err(e) => writeln!(log, "Skipping {}: {}", partition.path, e)?,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no partition on {} holds sources/boot.wim; is it a Windows installer stick?", usb_device),
+    ))
+}
+
+/// rsync flag choosing between whole-file and delta copies. rsync copies whole files between local
+/// paths by default, so a rewrite has to ask for the delta transfer explicitly.
+fn whole_file_arg(strategy: CopyStrategy, usb: bool) -> Option<&'static str> {
+    match strategy {
+        CopyStrategy::FirstWrite if usb => Some("--whole-file"),
+        CopyStrategy::FirstWrite => None,
+        CopyStrategy::Rewrite => Some("--no-whole-file"),
+    }
+}
+
+/// Whether `--whole-file` suits the target: USB devices, unless a quirk rules it out
+fn wants_whole_file(usb_device: &str) -> bool {
+    is_usb_device(usb_device) && !device_quirk(usb_device).is_some_and(|quirk| quirk.avoid_whole_file)
+}
+
+/// Directories tried in turn for the temporary mount points: the configured base (see
+/// `configured_mount_base`), `MOUNT_BASE`, then the system temp directory for hosts where `/mnt` is
+/// missing or read-only
+fn mount_base_candidates(configured: Option<String>) -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    for dir in configured.map(PathBuf::from).into_iter().chain([PathBuf::from(MOUNT_BASE), std::env::temp_dir()]) {
+        if !candidates.contains(&dir) {
+            candidates.push(dir);
+        }
+    }
+    candidates
+}
+
+/// Create the directory holding the ISO, BOOT and INSTALL mount points in the first usable base
+fn create_mount_base(log: &mut dyn Write) -> io::Result<TempDir> {
+    let candidates = mount_base_candidates(configured_mount_base());
+    let mut failures = Vec::new();
+    for base in &candidates {
+        match tempdir_in(base) {
+            Ok(dir) => {
+                if !failures.is_empty() {
+                    writeln!(log, "Using {} for temporary mounts ({})", base.display(), failures.join("; "))?;
+                }
+                return Ok(dir);
+            }
+            Err(e) => failures.push(format!("{}: {}", base.display(), e)),
+        }
+    }
+    Err(io::Error::other(format!("Failed to create a directory for temporary mounts: {}", failures.join("; "))))
+}
+
+/// Unmount whatever a flow mounted under `base`, then remove it and sync. The flows hold their base
+/// in a scope guard running this, so error returns release the mounts as well.
+fn release_mount_base(base: TempDir) {
+    for dir in [INSTALL_MOUNT_DIR, BOOT_MOUNT_DIR, TOOL_MOUNT_DIR, ISO_MOUNT_DIR] {
+        let _ = Command::new("umount").arg(base.path().join(dir)).status();
+    }
+    let _ = fs::remove_dir_all(base.path());
+    let _ = Command::new("sync").status();
+}
+
+/// Userspace readers that unpack an ISO without a loop device. 7-Zip reads the UDF file system
+/// Windows ISOs keep their files in; libarchive mostly sees the ISO9660 side, which on Windows
+/// media only holds a README, so bsdtar is the second choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoExtractor {
+    SevenZip,
+    Bsdtar,
+}
+
+impl IsoExtractor {
+    pub fn program(self) -> &'static str {
+        match self {
+            IsoExtractor::SevenZip => "7z",
+            IsoExtractor::Bsdtar => "bsdtar",
+        }
+    }
+
+    /// Arguments unpacking `iso_path` into `dest`
+    fn args(self, iso_path: &str, dest: &Path) -> Vec<String> {
+        match self {
+            IsoExtractor::SevenZip => vec!["x".to_string(), "-y".to_string(), format!("-o{}", dest.display()), iso_path.to_string()],
+            IsoExtractor::Bsdtar => vec!["-x".to_string(), "-f".to_string(), iso_path.to_string(), "-C".to_string(), dest.display().to_string()],
+        }
+    }
+
+    /// First installed extractor, in order of preference
+    pub fn find() -> Option<Self> {
+        [IsoExtractor::SevenZip, IsoExtractor::Bsdtar]
+            .into_iter()
+            .find(|extractor| which::which(extractor.program()).is_ok())
+    }
+}
+
+/// What an unpacked tree lacks to be Windows setup media, if anything
+fn extracted_tree_problem(root: &Path, extractor: IsoExtractor) -> Option<String> {
+    if root.join("bootmgr").is_file() && root.join("sources").join("boot.wim").is_file() {
+        return None;
+    }
+    let hint = match extractor {
+        IsoExtractor::Bsdtar => "; bsdtar cannot read the UDF file system of Windows ISOs, install 7-Zip (p7zip)",
+        IsoExtractor::SevenZip => "",
+    };
+    Some(format!("{} unpacked no bootmgr and sources/boot.wim{}", extractor.program(), hint))
+}
+
+/// How the copy stages read the ISO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoAccess {
+    LoopMount,
+    Extracted(IsoExtractor),
+    /// A tree prepared once by `stage_iso` for several writes
+    Staged,
+}
+
+/// Make the ISO's files available at `iso_root`: a read-only loop mount, or, where loop devices
+/// are refused (containers, locked-down hosts), an unpacked copy made with 7z or bsdtar. The copy
+/// needs as much free space as the ISO takes and goes away with `iso_root`.
+fn open_iso(iso_path: &str, iso_root: &Path, log: &mut dyn Write) -> io::Result<IsoAccess> {
+    let output = Command::new("mount").args(["-o", "loop,ro", iso_path]).arg(iso_root).output()?;
+    if output.status.success() {
+        return Ok(IsoAccess::LoopMount);
+    }
+    let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    writeln!(log, "Loop mount failed ({}); unpacking the ISO instead", reason)?;
+    let Some(extractor) = IsoExtractor::find() else {
+        return Err(io::Error::other(format!(
+            "mount ISO failed ({}) and neither 7z nor bsdtar is installed to unpack it",
+            reason
+        )));
+    };
+    let iso_bytes = fs::metadata(iso_path)?.len();
+    let free = free_bytes(iso_root)?;
+    if free < iso_bytes {
+        return Err(io::Error::other(format!(
+            "unpacking the ISO needs {} MB but only {} MB are free under {}",
+            iso_bytes / 1024 / 1024,
+            free / 1024 / 1024,
+            iso_root.display()
+        )));
+    }
+    writeln!(log, "Unpacking {} with {}...", iso_path, extractor.program())?;
+    let output = Command::new(extractor.program()).args(extractor.args(iso_path, iso_root)).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} could not unpack the ISO: {}",
+            extractor.program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    if let Some(problem) = extracted_tree_problem(iso_root, extractor) {
+        return Err(io::Error::other(problem));
+    }
+    Ok(IsoAccess::Extracted(extractor))
+}
+
+/// ISO contents copied to local disk once for a batch of writes, so each stick is filled from the
+/// page cache or local disk instead of mounting and reading the ISO again. The copy is removed
+/// when this is dropped.
+pub struct StagedIso {
+    root: PathBuf,
+    base: TempDir,
+}
+
+impl StagedIso {
+    /// Directory holding the ISO's files
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Drop for StagedIso {
+    fn drop(&mut self) {
+        // Only mounted if copying out of the loop mount was interrupted
+        let _ = Command::new("umount").arg(self.base.path().join(ISO_MOUNT_DIR)).status();
+    }
+}
+
+/// Directory under the mount base holding the staged copy of a loop-mounted ISO
+const STAGED_DIR: &str = "staged";
+
+/// Copy the ISO's files to a staging directory under the mount base. An ISO that has to be
+/// unpacked (see `open_iso`) is staged by unpacking it; a loop-mounted one is copied out with rsync
+/// and unmounted again.
+pub fn stage_iso(iso_path: &str, log: &mut dyn Write) -> io::Result<StagedIso> {
+    check_iso_readable(iso_path)?;
+    let base = create_mount_base(log)?;
+    let iso_m = base.path().join(ISO_MOUNT_DIR);
+    fs::create_dir_all(&iso_m)?;
+    writeln!(log, "Staging {} in {}...", iso_path, base.path().display())?;
+    if let IsoAccess::Extracted(_) = open_iso(iso_path, &iso_m, log)? {
+        return Ok(StagedIso { root: iso_m, base });
+    }
+    let staged = StagedIso { root: base.path().join(STAGED_DIR), base };
+    let iso_bytes = iso_content_bytes(&iso_m, log)?;
+    let free = free_bytes(staged.base.path())?;
+    if free < iso_bytes {
+        return Err(io::Error::other(format!(
+            "staging the ISO needs {} MB but only {} MB are free under {}",
+            iso_bytes / 1024 / 1024,
+            free / 1024 / 1024,
+            staged.base.path().display()
+        )));
+    }
+    let output = Command::new("rsync")
+        .args(["-a", "--no-owner", "--no-group"])
+        .arg(format!("{}/", iso_m.display()))
+        .arg(format!("{}/", staged.root.display()))
+        .output()
+        .map_err(|e| missing_program_error("rsync", e))?;
+    let _ = Command::new("umount").arg(&iso_m).status();
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "copying the ISO to {} failed: {}",
+            staged.root.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    writeln!(log, "Staged {} MB for the batch", iso_bytes / 1024 / 1024)?;
+    Ok(staged)
+}
+
+/// Find the install image on the mounted ISO and log it with its editions. Install images live on
+/// INSTALL (NTFS), so a WIM/ESD of any size and a pre-split `.swm` set are copied as they are;
+/// an incomplete split set is an error.
+fn inspect_install_image(iso_root: &Path, log: &mut dyn Write) -> io::Result<Option<InstallImage>> {
+    let sources = iso_root.join("sources");
+    if !sources.is_dir() {
+        return Ok(None);
+    }
+    let Some(image) = InstallImage::find(&sources)? else {
+        writeln!(log, "No install.wim/esd/swm found under sources/; copying the ISO as is")?;
+        return Ok(None);
+    };
+    writeln!(log, "Install image: {}", image.describe())?;
+    if let Ok(flavor) = image.flavor() {
+        writeln!(log, "Media: {}", flavor.product_name())?;
+    }
+    if WimEditor::has_wimlib() {
+        match image.editor().image_names() {
+            Ok(names) if !names.is_empty() => writeln!(log, "Editions: {}", names.join(", "))?,
+            Ok(_) => {}
+            Err(e) => writeln!(log, "Could not list editions: {}", e)?,
+        }
+    }
+    Ok(Some(image))
+}
+
+/// Check that every part of the install image reached INSTALL with its full size
+fn ensure_install_image_copied(image: &InstallImage, iso_root: &Path, install_root: &Path) -> io::Result<()> {
+    for part in image.parts() {
+        let rel = part.strip_prefix(iso_root).unwrap_or(part);
+        let expected = fs::metadata(part)?.len();
+        let copied = fs::metadata(install_root.join(rel)).map(|meta| meta.len()).ok();
+        if copied != Some(expected) {
+            return Err(io::Error::other(format!("{} was not copied completely to INSTALL", rel.display())));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn ensure_not_system_device(device: &str, log: &mut dyn Write) -> io::Result<()> {
     let dev_base = device.trim_start_matches("/dev/");
     let output = Command::new("lsblk")
         .args(["-nr", "-o", "NAME,MOUNTPOINT"])
@@ -95,7 +1239,13 @@ fn ensure_not_system_device(device: &str, log: &mut dyn Write) -> io::Result<()>
     Ok(())
 }
 
-fn unmount_device_mounts(device: &str, log: &mut dyn Write) -> io::Result<()> {
+/// Error for a failed partitioning or formatting `step`, naming the partition when a desktop
+/// automounter mounted one again after `unmount_device_mounts` released it
+fn device_step_failed(usb_device: &str, step: &str) -> io::Error {
+    io::Error::other(with_remount_hint(usb_device, format!("{} failed", step)))
+}
+
+pub(crate) fn unmount_device_mounts(device: &str, log: &mut dyn Write) -> io::Result<()> {
     let dev_name = device.trim_start_matches("/dev/");
     let output = Command::new("lsblk")
         .args(["-nr", "-o", "NAME,MOUNTPOINT"])
@@ -120,29 +1270,14 @@ fn unmount_device_mounts(device: &str, log: &mut dyn Write) -> io::Result<()> {
     Ok(())
 }
 
-fn get_device_size_bytes(device: &str) -> io::Result<u64> {
-    let output = Command::new("blockdev")
-        .args(["--getsize64", device])
-        .output()?;
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to get device size with blockdev",
-        ));
-    }
-    let size_str = String::from_utf8_lossy(&output.stdout);
-    size_str
-        .trim()
-        .parse::<u64>()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Invalid device size: {}", e)))
-}
-
 /// Optional direct-dd write for Windows ISOs (not recommended).
 pub fn write_windows_iso_direct_dd(
     iso_path: &str,
     usb_device: &str,
     log: &mut dyn Write,
 ) -> io::Result<()> {
+    let usb_device = &resolve_device_path(usb_device);
+    check_iso_readable(iso_path)?;
     writeln!(log, "WARNING: Using direct dd mode. This may not boot on Windows 10/11 UEFI systems.")?;
     writeln!(log, "Reference: https://learn.microsoft.com/windows-hardware/manufacture/desktop/create-uefi-based-hard-drive-partitions")?;
 
@@ -193,6 +1328,28 @@ pub fn write_windows_iso_to_usb_with_bypass(
     bypass_flags: Option<UnattendFlags>,
     log: &mut dyn Write,
 ) -> io::Result<WindowsFlowMetrics> {
+    let _ = use_wim; // Placeholder to maintain signature parity until WIM handling is implemented.
+    let options = WindowsFlowOptions {
+        bypass: bypass_flags,
+        ..Default::default()
+    };
+    write_windows_iso_to_usb_with_options(iso_path, usb_device, &options, log, &mut Vec::new())
+}
+
+/// Dual-partition Windows write with the given options; non-fatal issues go to `warnings`
+pub fn write_windows_iso_to_usb_with_options(
+    iso_path: &str,
+    usb_device: &str,
+    options: &WindowsFlowOptions,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
+) -> io::Result<WindowsFlowMetrics> {
+    let usb_device = &resolve_device_path(usb_device);
+    log_device_quirk(usb_device, log)?;
+    if options.legacy {
+        return write_windows_legacy(iso_path, usb_device, options, log, warnings);
+    }
+    let bypass_flags = options.bypass;
     if let Some(ref flags) = bypass_flags {
         if !flags.is_empty() && !WimEditor::has_wimlib() {
             return Err(io::Error::new(
@@ -201,16 +1358,27 @@ pub fn write_windows_iso_to_usb_with_bypass(
             ));
         }
     }
-    let _ = use_wim; // Placeholder to maintain signature parity until WIM handling is implemented.
+    check_iso_readable(iso_path)?;
+    if let Some(oem_folder) = &options.oem_folder {
+        validate_oem_folder(oem_folder).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    for pattern in &options.exclude {
+        validate_exclude_pattern(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    if let Some(size_mb) = options.esp_mb {
+        validate_esp_size_mb(size_mb).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    let tool_mb = tool_partition_size(options)?;
+    let (fat_name, fat_label, fat_end) = fat_partition_layout(options.esp_mb);
     let overall_start = Instant::now();
     let mut metrics = WindowsFlowMetrics::default();
     let mut peak_speed_mbps = 0.0;
 
-    // Create temp mount dirs under /mnt
-    let base = tempdir_in("/mnt").map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to create tempdir: {}", e)))?;
-    let iso_m = base.path().join("iso");
-    let boot_m = base.path().join("boot");
-    let inst_m = base.path().join("install");
+    // Create temp mount dirs under the configured base
+    let base = scopeguard::guard(create_mount_base(log)?, release_mount_base);
+    let iso_m = base.path().join(ISO_MOUNT_DIR);
+    let boot_m = base.path().join(BOOT_MOUNT_DIR);
+    let inst_m = base.path().join(INSTALL_MOUNT_DIR);
     for m in [&iso_m, &boot_m, &inst_m] {
         fs::create_dir_all(m)?;
     }
@@ -218,86 +1386,144 @@ pub fn write_windows_iso_to_usb_with_bypass(
     ensure_not_system_device(usb_device, log)?;
     // Ensure device and its partitions are unmounted before wipefs/partitioning.
     unmount_device_mounts(usb_device, log)?;
-    let mut cleanup = || {
-        let _ = Command::new("umount").arg(&inst_m).status();
-        let _ = Command::new("umount").arg(&boot_m).status();
-        let _ = Command::new("umount").arg(&iso_m).status();
-        let _ = fs::remove_dir_all(base.path());
-        let _ = Command::new("sync").status();
+    // Mount the ISO first so the boot file set can be checked before the device is wiped
+    let iso_root = match &options.staged_iso {
+        Some(staged) => {
+            writeln!(log, "Using the ISO contents staged in {}", staged.display())?;
+            metrics.iso_access = Some(IsoAccess::Staged);
+            staged.clone()
+        }
+        None => {
+            writeln!(log, "Mounting ISO...")?;
+            let access = open_iso(iso_path, &iso_m, log)?;
+            metrics.iso_access = Some(access);
+            iso_m.clone()
+        }
+    };
+    writeln!(log, "Checking boot files against FAT32 limits...")?;
+    let fits = match options.esp_mb {
+        Some(size_mb) => ensure_esp_fits(&iso_root, size_mb),
+        None => ensure_boot_files_fit_fat32(&iso_root),
+    };
+    let install_image = match fits.and_then(|_| inspect_install_image(&iso_root, log)) {
+        Ok(image) => image,
+        Err(e) => {
+            writeln!(log, "ERROR: {}", e)?;
+            return Err(e);
+        }
     };
     // Stage 1: wipe and partition
     let partition_start = Instant::now();
     writeln!(log, "Wiping and partitioning...")?;
+    fail_point::check("wipe")?;
     let status = Command::new("wipefs").arg("-a").arg(usb_device).status()?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "wipefs failed")); }
+    if !status.success() { return Err(io::Error::new(io::ErrorKind::Other, "wipefs failed")); }
+    fail_point::check("partition")?;
     let status = Command::new("parted").args(["-s", usb_device, "mklabel", "gpt"]).status()?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "parted mklabel failed")); }
+    if !status.success() { return Err(io::Error::new(io::ErrorKind::Other, "parted mklabel failed")); }
     // Create partitions
-    let parts = [
-        ("BOOT", "fat32", "1GiB", "BOOT"),
-        ("ESD-USB", "ntfs", "100%", "ESD-USB")
+    let install_end = install_partition_end(get_device_size_bytes(usb_device).unwrap_or(0), tool_mb)?;
+    let mut parts = vec![
+        (fat_name, "fat32", fat_end.as_str()),
+        ("ESD-USB", "ntfs", install_end.as_str()),
     ];
+    if tool_mb.is_some() {
+        parts.push((TOOL_PARTITION_NAME, "fat32", "100%"));
+    }
     let mut start = "0%";
-    for (label, fstype, end, _vol) in parts.iter() {
+    for (label, fstype, end) in parts.iter() {
         writeln!(log, "Creating partition {}...", label)?;
         let status = Command::new("parted").args(["-s", usb_device, "mkpart", label, fstype, start, end]).status()?;
-        if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "parted mkpart failed")); }
+        if !status.success() { return Err(device_step_failed(usb_device, "parted mkpart")); }
         start = end;
     }
+    if options.esp_mb.is_some() {
+        let status = Command::new("parted").args(["-s", usb_device, "set", "1", "esp", "on"]).status()?;
+        if !status.success() { return Err(io::Error::other("parted set esp failed")); }
+    }
+    if tool_mb.is_some() {
+        let status = Command::new("parted").args(["-s", usb_device, "set", "3", "esp", "on"]).status()?;
+        if !status.success() { return Err(io::Error::other("parted set esp failed")); }
+    }
     metrics.partition_time_ms = partition_start.elapsed().as_millis() as u64;
     // Format partitions
     let format_start = Instant::now();
     let p1 = format!("{}1", usb_device);
     let p2 = format!("{}2", usb_device);
-    writeln!(log, "Formatting BOOT as FAT32...")?;
+    let (fat_label, fat_label_warnings) = sanitize_label(fat_label, LabelFs::Fat);
+    let (ntfs_label, ntfs_label_warnings) = sanitize_label("ESD-USB", LabelFs::Ntfs);
+    for warning in fat_label_warnings.into_iter().chain(ntfs_label_warnings) {
+        record_warning(log, warnings, warning)?;
+    }
+    writeln!(log, "Formatting {} as FAT32...", fat_name)?;
     let block_size = match get_device_optimal_block_size(usb_device) {
         Ok(size) => {
             writeln!(log, "Detected optimal block size: {} bytes", size)?;
             size
         }
         Err(e) => {
-            writeln!(log, "Warning: could not detect block size ({}), falling back to 4096", e)?;
+            let warning = Warning::new(
+                WarningKind::Formatting,
+                format!("could not detect block size ({}), falling back to 4096", e),
+            );
+            record_warning(log, warnings, warning)?;
             4096
         }
     };
-    let sectors_per_cluster = ((block_size / 512).max(1)).min(64); // FAT32 sectors per cluster
-    let fat_cluster_bytes = sectors_per_cluster * 512;
-    writeln!(log, "Using FAT32 cluster size: {} bytes ({} sectors)", fat_cluster_bytes, sectors_per_cluster)?;
+    let sector_bytes = get_device_logical_block_size(usb_device).unwrap_or(512);
+    if let Some(sectors) = options.fat32_sectors_per_cluster {
+        writeln!(log, "FAT32 cluster size set to {} sectors per cluster", sectors)?;
+    }
+    let requested = requested_cluster_sizes(block_size, sector_bytes, options.cluster_bytes, options.fat32_sectors_per_cluster);
+    let clusters = match plan_cluster_sizes(usb_device, requested, log) {
+        Ok(clusters) => clusters,
+        Err(e) => {
+            writeln!(log, "ERROR: {}", e)?;
+            return Err(e);
+        }
+    };
+    let sectors_per_cluster = (clusters.fat32_bytes / sector_bytes).max(1); // mkfs.vfat -s counts logical sectors
+    writeln!(log, "Using FAT32 cluster size: {} bytes ({} sectors)", clusters.fat32_bytes, sectors_per_cluster)?;
 
+    fail_point::check("mkfs_fat32")?;
     let status = Command::new("mkfs.vfat")
         .args([
             "-F32",
             "-s",
             &sectors_per_cluster.to_string(),
             "-n",
-            "BOOT",
+            &fat_label,
             &p1,
         ])
         .status()?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mkfs.vfat failed")); }
+    if !status.success() { return Err(device_step_failed(usb_device, "mkfs.vfat")); }
     writeln!(log, "Formatting INSTALL as NTFS...")?;
-    let ntfs_cluster = block_size.clamp(512, 65536);
+    let ntfs_cluster = clusters.ntfs_bytes;
+    fail_point::check("mkfs_ntfs")?;
     let status = Command::new("mkfs.ntfs")
         .args([
             "--quick",
             "-c",
             &ntfs_cluster.to_string(),
             "-L",
-            "ESD-USB",
+            &ntfs_label,
             &p2,
         ])
         .status()?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mkfs.ntfs failed")); }
+    if !status.success() { return Err(device_step_failed(usb_device, "mkfs.ntfs")); }
+    if let Some(tool) = &options.tool_efi {
+        install_tool(&format!("{}3", usb_device), tool, &base.path().join(TOOL_MOUNT_DIR), log)?;
+    }
     metrics.format_time_ms = format_start.elapsed().as_millis() as u64;
-    // Mount ISO
-    writeln!(log, "Mounting ISO...")?;
-    let status = Command::new("mount").args(["-o", "loop,ro", iso_path, iso_m.to_str().unwrap()]).status()?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mount ISO failed")); }
+    // Record what actually landed on the device, not just what was requested
+    metrics.sector_bytes = get_device_logical_block_size(usb_device).unwrap_or(512);
+    metrics.partitions = log_partition_geometry(usb_device, log)?;
     // Copy BOOT files
-    writeln!(log, "Mounting BOOT partition...")?;
+    writeln!(log, "Mounting {} partition...", fat_name)?;
+    fail_point::check("mount_boot")?;
     let status = Command::new("mount").args([&p1, boot_m.to_str().unwrap()]).status()?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mount BOOT failed")); }
-    writeln!(log, "Copying files to BOOT...")?;
+    if !status.success() { return Err(io::Error::other(format!("mount {} failed", fat_name))); }
+    writeln!(log, "Copying files to {}...", fat_name)?;
     let boot_copy_start = Instant::now();
     let mut boot_args = vec![
         "-a".to_string(),
@@ -306,42 +1532,46 @@ pub fn write_windows_iso_to_usb_with_bypass(
         "--no-inc-recursive".to_string(),
         "--inplace".to_string(),
         "--info=progress2".to_string(),
-        "--exclude".to_string(),
-        "sources/".to_string(),
-        format!("{}/", iso_m.to_str().unwrap()),
-        format!("{}/", boot_m.to_str().unwrap()),
     ];
-    if is_usb_device(usb_device) {
-        boot_args.push("--whole-file".to_string());
+    boot_args.extend(fat_copy_filters(options.esp_mb.is_some()));
+    boot_args.push(format!("{}/", iso_root.to_str().unwrap()));
+    boot_args.push(format!("{}/", boot_m.to_str().unwrap()));
+    boot_args.extend(whole_file_arg(options.copy_strategy, wants_whole_file(usb_device)).map(str::to_string));
+    fail_point::check("copy_boot")?;
+    let stall_timeout = Tuning::for_device(usb_device).copy_stall_timeout();
+    let boot_transferred = run_rsync_with_metrics(&boot_args, stall_timeout, &mut peak_speed_mbps).map_err(|e| e.into_error(fat_name))?;
+    if options.esp_mb.is_some() {
+        ensure_bcd_files_copied(&boot_m)?;
     }
-    let boot_transferred = run_rsync_with_metrics(&boot_args, &mut peak_speed_mbps).map_err(|e| {
-        cleanup();
-        io::Error::new(io::ErrorKind::Other, format!("rsync BOOT failed: {}", e))
-    })?;
     metrics.boot_copy_time_ms = boot_copy_start.elapsed().as_millis() as u64;
     metrics.total_bytes = metrics.total_bytes.saturating_add(boot_transferred);
 
-    writeln!(log, "Copying boot.wim...")?;
-    let _ = fs::create_dir_all(boot_m.join("sources"));
-    let status = Command::new("cp").args([iso_m.join("sources/boot.wim").to_str().unwrap(), boot_m.join("sources").to_str().unwrap()]).status()?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "cp boot.wim failed")); }
+    // A dedicated ESP got boot.wim through its filters
+    if options.esp_mb.is_none() {
+        writeln!(log, "Copying boot.wim...")?;
+        let _ = fs::create_dir_all(boot_m.join("sources"));
+        let status = Command::new("cp").args([iso_root.join("sources/boot.wim").to_str().unwrap(), boot_m.join("sources").to_str().unwrap()]).status()?;
+        if !status.success() { return Err(io::Error::new(io::ErrorKind::Other, "cp boot.wim failed")); }
+    }
+    if options.boot_test {
+        write_boot_test_marker(&boot_m, log)?;
+        if let Err(e) = flush_with_progress(log) {
+            writeln!(log, "Warning: flush progress unavailable ({}); continuing with cleanup", e)?;
+        }
+        drop(base);
+        let total_secs = overall_start.elapsed().as_secs_f64().max(f64::EPSILON);
+        metrics.avg_speed_mbps = (metrics.total_bytes as f64 / total_secs) / 1_000_000.0;
+        metrics.peak_speed_mbps = peak_speed_mbps;
+        log_metrics(&metrics, log)?;
+        writeln!(log, "Windows boot test stick completed.")?;
+        return Ok(metrics);
+    }
     // Copy INSTALL files
     writeln!(log, "Mounting INSTALL partition...")?;
-    let ntfs_opts = if has_ntfs3g() {
-        "big_writes,async,noatime,nodiratime"
-    } else {
-        "noatime,nodiratime"
-    };
-    let status = if has_ntfs3g() {
-        Command::new("mount")
-            .args(["-t", "ntfs-3g", "-o", ntfs_opts, &p2, inst_m.to_str().unwrap()])
-            .status()
-    } else {
-        Command::new("mount")
-            .args(["-o", ntfs_opts, &p2, inst_m.to_str().unwrap()])
-            .status()
-    }?;
-    if !status.success() { cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mount INSTALL failed")); }
+    fail_point::check("mount_install")?;
+    let driver = mount_ntfs_partition(&p2, inst_m.to_str().unwrap())?;
+    writeln!(log, "INSTALL partition mounted with {} driver", driver.as_str())?;
+    metrics.ntfs_driver = Some(driver);
     writeln!(log, "Copying files to INSTALL...")?;
     let install_copy_start = Instant::now();
     let mut install_args = vec![
@@ -351,38 +1581,45 @@ pub fn write_windows_iso_to_usb_with_bypass(
         "--no-inc-recursive".to_string(),
         "--inplace".to_string(),
         "--info=progress2".to_string(),
-        format!("{}/", iso_m.to_str().unwrap()),
+        format!("{}/", iso_root.to_str().unwrap()),
         format!("{}/", inst_m.to_str().unwrap()),
     ];
-    if is_usb_device(usb_device) {
-        install_args.push("--whole-file".to_string());
-    }
-    let install_transferred = run_rsync_with_metrics(&install_args, &mut peak_speed_mbps).map_err(|e| {
-        cleanup();
-        io::Error::new(io::ErrorKind::Other, format!("rsync INSTALL failed: {}", e))
+    install_args.extend(whole_file_arg(options.copy_strategy, wants_whole_file(usb_device)).map(str::to_string));
+    apply_install_excludes(&iso_root, &options.exclude, &mut install_args, log)?;
+    fail_point::check("copy_install")?;
+    let install_transferred = rsync_with_retry("INSTALL", usb_device, log, || {
+        run_rsync_with_metrics(&install_args, stall_timeout, &mut peak_speed_mbps)
     })?;
+    if let Some(image) = &install_image {
+        ensure_install_image_copied(image, &iso_root, &inst_m)?;
+    }
+    if let Some(oem_folder) = &options.oem_folder {
+        copy_oem_folder(oem_folder, &inst_m, log)?;
+    }
     metrics.install_copy_time_ms = install_copy_start.elapsed().as_millis() as u64;
     metrics.total_bytes = metrics.total_bytes.saturating_add(install_transferred);
 
     // Cleanup
     // Optional: apply unattend bypass if requested.
     if let Some(flags) = bypass_flags {
-        let unattend_gen = UnattendGenerator::new(Architecture::X64, flags);
-        let unattend_path = unattend_gen.generate()?;
         let boot_wim = boot_m.join("sources/boot.wim");
         if boot_wim.exists() {
-            let wim_editor = WimEditor::new(&boot_wim);
-            let target_index = if wim_editor.verify_index(2).unwrap_or(false) { 2 } else { 1 };
-            writeln!(log, "Injecting Autounattend.xml into boot.wim (index {})...", target_index)?;
-            wim_editor.add_file(target_index, &unattend_path, "/Autounattend.xml")?;
-            writeln!(log, "Bypass unattend injected successfully.")?;
+            inject_unattend(&boot_wim, flags, log)?;
         } else {
-            writeln!(log, "Warning: boot.wim not found at {}, skipping unattend injection.", boot_wim.display())?;
+            let warning = Warning::new(
+                WarningKind::Bypass,
+                format!("boot.wim not found at {}, skipping unattend injection.", boot_wim.display()),
+            );
+            record_warning(log, warnings, warning)?;
         }
     }
 
+    // Flush before unmounting so the UI sees progress instead of a silent umount/sync
+    if let Err(e) = flush_with_progress(log) {
+        writeln!(log, "Warning: flush progress unavailable ({}); continuing with cleanup", e)?;
+    }
     writeln!(log, "Cleaning up mounts...")?;
-    cleanup();
+    drop(base);
     let total_secs = overall_start.elapsed().as_secs_f64().max(f64::EPSILON);
     metrics.avg_speed_mbps = (metrics.total_bytes as f64 / total_secs) / 1_000_000.0;
     metrics.peak_speed_mbps = peak_speed_mbps;
@@ -392,6 +1629,169 @@ pub fn write_windows_iso_to_usb_with_bypass(
     Ok(metrics)
 }
 
+/// Options the legacy layout cannot honour: it has no separate BOOT or EFI partition to size or
+/// test. Bypass flags only matter to Windows 11 setup and are dropped with a warning instead.
+fn validate_legacy_options(options: &WindowsFlowOptions) -> Result<(), String> {
+    if options.esp_mb.is_some() {
+        return Err("the legacy layout has a single partition; a dedicated ESP cannot be added".to_string());
+    }
+    if options.boot_test {
+        return Err("the boot test needs the dual-partition layout".to_string());
+    }
+    if options.tool_efi.is_some() {
+        return Err("the legacy layout has a single partition; an EFI tool partition cannot be added".to_string());
+    }
+    Ok(())
+}
+
+/// Windows 7/8 write: MBR table, one active NTFS partition holding the whole ISO, the Windows 7
+/// MBR and NTFS boot record from `ms-sys` so BIOS firmware loads `bootmgr`. x64 media still boot
+/// on UEFI machines with NTFS support in their firmware; others need CSM/legacy boot.
+fn write_windows_legacy(
+    iso_path: &str,
+    usb_device: &str,
+    options: &WindowsFlowOptions,
+    log: &mut dyn Write,
+    warnings: &mut Vec<Warning>,
+) -> io::Result<WindowsFlowMetrics> {
+    validate_legacy_options(options).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    check_iso_readable(iso_path)?;
+    if let Some(oem_folder) = &options.oem_folder {
+        validate_oem_folder(oem_folder).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    for pattern in &options.exclude {
+        validate_exclude_pattern(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    if which::which("ms-sys").is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "ms-sys is required to write the BIOS boot code of the legacy layout; install ms-sys.",
+        ));
+    }
+    if options.bypass.is_some_and(|flags| !flags.is_empty()) {
+        let warning = Warning::new(
+            WarningKind::Bypass,
+            "TPM/Secure Boot/RAM bypass only applies to Windows 11 setup; ignored for the legacy layout",
+        );
+        record_warning(log, warnings, warning)?;
+    }
+    let overall_start = Instant::now();
+    let mut metrics = WindowsFlowMetrics::default();
+    let mut peak_speed_mbps = 0.0;
+
+    let base = scopeguard::guard(create_mount_base(log)?, release_mount_base);
+    let iso_m = base.path().join(ISO_MOUNT_DIR);
+    let inst_m = base.path().join(INSTALL_MOUNT_DIR);
+    for m in [&iso_m, &inst_m] {
+        fs::create_dir_all(m)?;
+    }
+    ensure_not_system_device(usb_device, log)?;
+    unmount_device_mounts(usb_device, log)?;
+    let iso_root = match &options.staged_iso {
+        Some(staged) => {
+            writeln!(log, "Using the ISO contents staged in {}", staged.display())?;
+            metrics.iso_access = Some(IsoAccess::Staged);
+            staged.clone()
+        }
+        None => {
+            writeln!(log, "Mounting ISO...")?;
+            let access = open_iso(iso_path, &iso_m, log)?;
+            metrics.iso_access = Some(access);
+            iso_m.clone()
+        }
+    };
+    let install_image = inspect_install_image(&iso_root, log)?;
+
+    let partition_start = Instant::now();
+    writeln!(log, "Wiping and partitioning (MBR, single NTFS partition)...")?;
+    let steps: [&[&str]; 4] = [
+        &["wipefs", "-a", usb_device],
+        &["parted", "-s", usb_device, "mklabel", "msdos"],
+        &["parted", "-s", usb_device, "mkpart", "primary", "ntfs", "1MiB", "100%"],
+        &["parted", "-s", usb_device, "set", "1", "boot", "on"],
+    ];
+    for step in steps {
+        fail_point::check(if step[0] == "wipefs" { "wipe" } else { "partition" })?;
+        let status = Command::new(step[0]).args(&step[1..]).status()?;
+        if !status.success() {
+            return Err(device_step_failed(usb_device, &step[..2].join(" ")));
+        }
+    }
+    let _ = Command::new("partprobe").arg(usb_device).status();
+    let _ = Command::new("udevadm").arg("settle").status();
+    metrics.partition_time_ms = partition_start.elapsed().as_millis() as u64;
+
+    let format_start = Instant::now();
+    let part = crate::utils::partition_path(usb_device, 1);
+    let (label, label_warnings) = sanitize_label("ESD-USB", LabelFs::Ntfs);
+    for warning in label_warnings {
+        record_warning(log, warnings, warning)?;
+    }
+    let cluster = options.cluster_bytes.unwrap_or(4096);
+    writeln!(log, "Formatting {} as NTFS ({} byte clusters)...", part, cluster)?;
+    fail_point::check("mkfs_ntfs")?;
+    let status = Command::new("mkfs.ntfs")
+        .args(["--quick", "-c", &cluster.to_string(), "-L", &label, &part])
+        .status()?;
+    if !status.success() { return Err(device_step_failed(usb_device, "mkfs.ntfs")); }
+    writeln!(log, "Writing Windows 7 MBR and NTFS boot record...")?;
+    for args in [["-7", usb_device], ["-n", part.as_str()]] {
+        let output = Command::new("ms-sys").args(args).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "ms-sys {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+    }
+    metrics.format_time_ms = format_start.elapsed().as_millis() as u64;
+    metrics.sector_bytes = get_device_logical_block_size(usb_device).unwrap_or(512);
+    metrics.partitions = log_partition_geometry(usb_device, log)?;
+
+    writeln!(log, "Mounting {}...", part)?;
+    fail_point::check("mount_install")?;
+    let driver = mount_ntfs_partition(&part, inst_m.to_str().unwrap())?;
+    metrics.ntfs_driver = Some(driver);
+    writeln!(log, "Copying files...")?;
+    let copy_start = Instant::now();
+    let mut args = vec![
+        "-a".to_string(),
+        "--no-owner".to_string(),
+        "--no-group".to_string(),
+        "--no-inc-recursive".to_string(),
+        "--inplace".to_string(),
+        "--info=progress2".to_string(),
+        format!("{}/", iso_root.to_str().unwrap()),
+        format!("{}/", inst_m.to_str().unwrap()),
+    ];
+    args.extend(whole_file_arg(options.copy_strategy, wants_whole_file(usb_device)).map(str::to_string));
+    apply_install_excludes(&iso_root, &options.exclude, &mut args, log)?;
+    fail_point::check("copy_install")?;
+    let stall_timeout = Tuning::for_device(usb_device).copy_stall_timeout();
+    let transferred = rsync_with_retry("INSTALL", usb_device, log, || run_rsync_with_metrics(&args, stall_timeout, &mut peak_speed_mbps))?;
+    if let Some(image) = &install_image {
+        ensure_install_image_copied(image, &iso_root, &inst_m)?;
+    }
+    if let Some(oem_folder) = &options.oem_folder {
+        copy_oem_folder(oem_folder, &inst_m, log)?;
+    }
+    metrics.install_copy_time_ms = copy_start.elapsed().as_millis() as u64;
+    metrics.total_bytes = transferred;
+
+    if let Err(e) = flush_with_progress(log) {
+        writeln!(log, "Warning: flush progress unavailable ({}); continuing with cleanup", e)?;
+    }
+    writeln!(log, "Cleaning up mounts...")?;
+    drop(base);
+    let total_secs = overall_start.elapsed().as_secs_f64().max(f64::EPSILON);
+    metrics.avg_speed_mbps = (metrics.total_bytes as f64 / total_secs) / 1_000_000.0;
+    metrics.peak_speed_mbps = peak_speed_mbps;
+    log_metrics(&metrics, log)?;
+    writeln!(log, "Windows legacy USB creation completed.")?;
+    Ok(metrics)
+}
+
 // Helper for verbose step output
 fn print_step(step: usize, total: usize, msg: &str) {
     println!("[STEP] {}/{}: {}", step, total, msg);
@@ -404,7 +1804,7 @@ fn print_error(step: usize, total: usize, msg: &str) {
 
 // Streaming version: print log lines directly to stdout and flush after each
 pub fn write_windows_iso_to_usb_stream(iso_path: &str, usb_device: &str, cluster_bytes: u64) -> io::Result<()> {
-    write_windows_iso_to_usb_stream_with_bypass(iso_path, usb_device, cluster_bytes, None)
+    write_windows_iso_to_usb_stream_with_bypass(iso_path, usb_device, cluster_bytes, None, None, None, None)
 }
 
 pub fn write_windows_iso_to_usb_stream_with_bypass(
@@ -412,7 +1812,55 @@ pub fn write_windows_iso_to_usb_stream_with_bypass(
     usb_device: &str,
     cluster_bytes: u64,
     bypass_flags: Option<UnattendFlags>,
+    fat32_sectors_per_cluster: Option<u64>,
+    oem_folder: Option<&Path>,
+    esp_mb: Option<u64>,
+) -> io::Result<()> {
+    let options = WindowsFlowOptions {
+        bypass: bypass_flags,
+        cluster_bytes: Some(cluster_bytes),
+        fat32_sectors_per_cluster,
+        oem_folder: oem_folder.map(Path::to_path_buf),
+        esp_mb,
+        ..Default::default()
+    };
+    write_windows_iso_to_usb_stream_with_options(iso_path, usb_device, &options)
+}
+
+/// Streaming dual-partition write driven by `WindowsFlowOptions`; a missing cluster size
+/// falls back to 4096 bytes
+pub fn write_windows_iso_to_usb_stream_with_options(
+    iso_path: &str,
+    usb_device: &str,
+    options: &WindowsFlowOptions,
 ) -> io::Result<()> {
+    let usb_device = &resolve_device_path(usb_device);
+    log_device_quirk(usb_device, &mut io::stdout())?;
+    if options.legacy {
+        let mut warnings = Vec::new();
+        write_windows_legacy(iso_path, usb_device, options, &mut io::stdout(), &mut warnings)?;
+        for warning in warnings {
+            println!("Warning: {}", warning);
+        }
+        return Ok(());
+    }
+    let cluster_bytes = options.cluster_bytes.unwrap_or(4096);
+    let bypass_flags = options.bypass;
+    let fat32_sectors_per_cluster = options.fat32_sectors_per_cluster;
+    let oem_folder = options.oem_folder.as_deref();
+    let esp_mb = options.esp_mb;
+    check_iso_readable(iso_path)?;
+    for pattern in &options.exclude {
+        validate_exclude_pattern(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    if let Some(oem_folder) = oem_folder {
+        validate_oem_folder(oem_folder).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    if let Some(size_mb) = esp_mb {
+        validate_esp_size_mb(size_mb).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    let tool_mb = tool_partition_size(options)?;
+    let (fat_name, fat_label, fat_end) = fat_partition_layout(esp_mb);
     if let Some(ref flags) = bypass_flags {
         if !flags.is_empty() && !WimEditor::has_wimlib() {
             return Err(io::Error::new(
@@ -423,11 +1871,10 @@ pub fn write_windows_iso_to_usb_stream_with_bypass(
     }
     let total_steps = 15;
     let mut step = 1;
-    let _ = cluster_bytes; // preserved for signature compatibility
-    let base = tempfile::tempdir_in("/mnt").map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to create tempdir: {}", e)))?;
-    let iso_m = base.path().join("iso");
-    let boot_m = base.path().join("boot");
-    let inst_m = base.path().join("install");
+    let base = scopeguard::guard(create_mount_base(&mut io::stdout())?, release_mount_base);
+    let iso_m = base.path().join(ISO_MOUNT_DIR);
+    let boot_m = base.path().join(BOOT_MOUNT_DIR);
+    let inst_m = base.path().join(INSTALL_MOUNT_DIR);
     for m in [&iso_m, &boot_m, &inst_m] {
         std::fs::create_dir_all(m)?;
     }
@@ -449,30 +1896,57 @@ pub fn write_windows_iso_to_usb_stream_with_bypass(
             }
         }
     }
-    let cleanup = || {
-        let _ = std::process::Command::new("umount").arg(&inst_m).status();
-        let _ = std::process::Command::new("umount").arg(&boot_m).status();
-        let _ = std::process::Command::new("umount").arg(&iso_m).status();
-        let _ = std::fs::remove_dir_all(base.path());
-        let _ = std::process::Command::new("sync").status();
+    print_step(step, total_steps, "Mounting ISO..."); step += 1;
+    if let Err(e) = open_iso(iso_path, &iso_m, &mut io::stdout()) {
+        print_error(step, total_steps, &e.to_string());
+        return Err(e);
+    }
+    let fits = match esp_mb {
+        Some(size_mb) => ensure_esp_fits(&iso_m, size_mb),
+        None => ensure_boot_files_fit_fat32(&iso_m),
+    };
+    let install_image = match fits.and_then(|_| inspect_install_image(&iso_m, &mut io::stdout())) {
+        Ok(image) => image,
+        Err(e) => {
+            print_error(step, total_steps, &e.to_string());
+            return Err(e);
+        }
     };
     print_step(step, total_steps, "Wiping and partitioning..."); step += 1;
+    fail_point::check("wipe").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
     let status = std::process::Command::new("wipefs").arg("-a").arg(usb_device).status()?;
-    if !status.success() { print_error(step, total_steps, "wipefs failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "wipefs failed")); }
+    if !status.success() { print_error(step, total_steps, "wipefs failed"); return Err(io::Error::new(io::ErrorKind::Other, "wipefs failed")); }
     print_step(step, total_steps, "Creating GPT partition table..."); step += 1;
+    fail_point::check("partition").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
     let status = std::process::Command::new("parted").args(["-s", usb_device, "mklabel", "gpt"]).status()?;
-    if !status.success() { print_error(step, total_steps, "parted mklabel failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "parted mklabel failed")); }
-    let parts = [
-        ("BOOT", "fat32", "1GiB", "BOOT"),
-        ("ESD-USB", "ntfs", "100%", "ESD-USB")
+    if !status.success() { print_error(step, total_steps, "parted mklabel failed"); return Err(io::Error::new(io::ErrorKind::Other, "parted mklabel failed")); }
+    let install_end = install_partition_end(get_device_size_bytes(usb_device).unwrap_or(0), tool_mb)?;
+    let mut parts = vec![
+        (fat_name, "fat32", fat_end.as_str()),
+        ("ESD-USB", "ntfs", install_end.as_str()),
     ];
+    if tool_mb.is_some() {
+        parts.push((TOOL_PARTITION_NAME, "fat32", "100%"));
+    }
     let mut start = "0%";
-    for (label, fstype, end, _vol) in parts.iter() {
+    for (label, fstype, end) in parts.iter() {
         print_step(step, total_steps, &format!("Creating partition {}...", label)); step += 1;
         let status = std::process::Command::new("parted").args(["-s", usb_device, "mkpart", label, fstype, start, end]).status()?;
-        if !status.success() { print_error(step, total_steps, &format!("parted mkpart {} failed", label)); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "parted mkpart failed")); }
+        if !status.success() {
+            let error = device_step_failed(usb_device, &format!("parted mkpart {}", label));
+            print_error(step, total_steps, &error.to_string());
+            return Err(error);
+        }
         start = end;
     }
+    if esp_mb.is_some() {
+        let status = std::process::Command::new("parted").args(["-s", usb_device, "set", "1", "esp", "on"]).status()?;
+        if !status.success() { print_error(step, total_steps, "parted set esp failed"); return Err(io::Error::other("parted set esp failed")); }
+    }
+    if tool_mb.is_some() {
+        let status = std::process::Command::new("parted").args(["-s", usb_device, "set", "3", "esp", "on"]).status()?;
+        if !status.success() { print_error(step, total_steps, "parted set esp failed"); return Err(io::Error::other("parted set esp failed")); }
+    }
     let p1 = format!("{}1", usb_device);
     let p2 = format!("{}2", usb_device);
     let block_size = match get_device_optimal_block_size(usb_device) {
@@ -485,28 +1959,48 @@ pub fn write_windows_iso_to_usb_stream_with_bypass(
             4096
         }
     };
-    let sectors_per_cluster = ((block_size / 512).max(1)).min(64); // FAT32 sectors per cluster
-    let fat_cluster_bytes = sectors_per_cluster * 512;
-    println!("Using FAT32 cluster size: {} bytes ({} sectors)", fat_cluster_bytes, sectors_per_cluster);
+    let sector_bytes = get_device_logical_block_size(usb_device).unwrap_or(512);
+    let requested = requested_cluster_sizes(block_size, sector_bytes, Some(cluster_bytes), fat32_sectors_per_cluster);
+    let clusters = match plan_cluster_sizes(usb_device, requested, &mut std::io::stdout()) {
+        Ok(clusters) => clusters,
+        Err(e) => {
+            print_error(step, total_steps, &e.to_string());
+            return Err(e);
+        }
+    };
+    let sectors_per_cluster = (clusters.fat32_bytes / sector_bytes).max(1); // mkfs.vfat -s counts logical sectors
+    println!("Using FAT32 cluster size: {} bytes ({} sectors)", clusters.fat32_bytes, sectors_per_cluster);
 
-    print_step(step, total_steps, "Formatting BOOT as FAT32..."); step += 1;
+    print_step(step, total_steps, &format!("Formatting {} as FAT32...", fat_name)); step += 1;
+    fail_point::check("mkfs_fat32").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
     let status = std::process::Command::new("mkfs.vfat")
-        .args(["-F32", "-s", &sectors_per_cluster.to_string(), "-n", "BOOT", &p1])
+        .args(["-F32", "-s", &sectors_per_cluster.to_string(), "-n", fat_label, &p1])
         .status()?;
-    if !status.success() { print_error(step, total_steps, "mkfs.vfat failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mkfs.vfat failed")); }
+    if !status.success() {
+        let error = device_step_failed(usb_device, "mkfs.vfat");
+        print_error(step, total_steps, &error.to_string());
+        return Err(error);
+    }
     print_step(step, total_steps, "Formatting INSTALL as NTFS..."); step += 1;
-    let ntfs_cluster = block_size.clamp(512, 65536);
+    fail_point::check("mkfs_ntfs").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    let ntfs_cluster = clusters.ntfs_bytes;
     let status = std::process::Command::new("mkfs.ntfs")
         .args(["--quick", "-c", &ntfs_cluster.to_string(), "-L", "ESD-USB", &p2])
         .status()?;
-    if !status.success() { print_error(step, total_steps, "mkfs.ntfs failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mkfs.ntfs failed")); }
-    print_step(step, total_steps, "Mounting ISO..."); step += 1;
-    let status = std::process::Command::new("mount").args(["-o", "loop,ro", iso_path, iso_m.to_str().unwrap()]).status()?;
-    if !status.success() { print_error(step, total_steps, "mount ISO failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mount ISO failed")); }
-    print_step(step, total_steps, "Mounting BOOT partition..."); step += 1;
+    if !status.success() {
+        let error = device_step_failed(usb_device, "mkfs.ntfs");
+        print_error(step, total_steps, &error.to_string());
+        return Err(error);
+    }
+    if let Some(tool) = &options.tool_efi {
+        install_tool(&format!("{}3", usb_device), tool, &base.path().join(TOOL_MOUNT_DIR), &mut io::stdout()).inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    }
+    let _ = log_partition_geometry(usb_device, &mut std::io::stdout());
+    print_step(step, total_steps, &format!("Mounting {} partition...", fat_name)); step += 1;
+    fail_point::check("mount_boot").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
     let status = std::process::Command::new("mount").args([&p1, boot_m.to_str().unwrap()]).status()?;
-    if !status.success() { print_error(step, total_steps, "mount BOOT failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mount BOOT failed")); }
-    print_step(step, total_steps, "Copying files to BOOT..."); step += 1;
+    if !status.success() { print_error(step, total_steps, "mount BOOT failed"); return Err(io::Error::new(io::ErrorKind::Other, "mount BOOT failed")); }
+    print_step(step, total_steps, &format!("Copying files to {}...", fat_name)); step += 1;
     let mut boot_args = vec![
         "-a".to_string(),
         "--no-owner".to_string(),
@@ -514,36 +2008,40 @@ pub fn write_windows_iso_to_usb_stream_with_bypass(
         "--no-inc-recursive".to_string(),
         "--inplace".to_string(),
         "--info=progress2".to_string(),
-        "--exclude".to_string(),
-        "sources/".to_string(),
-        format!("{}/", iso_m.to_str().unwrap()),
-        format!("{}/", boot_m.to_str().unwrap()),
     ];
-    if is_usb_device(usb_device) {
-        boot_args.push("--whole-file".to_string());
-    }
-    let status = std::process::Command::new("rsync").args(boot_args).status()?;
-    if !status.success() { print_error(step, total_steps, "rsync BOOT failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "rsync BOOT failed")); }
-    print_step(step, total_steps, "Copying boot.wim..."); step += 1;
-    let _ = std::fs::create_dir_all(boot_m.join("sources"));
-    let status = std::process::Command::new("cp").args([iso_m.join("sources/boot.wim").to_str().unwrap(), boot_m.join("sources").to_str().unwrap()]).status()?;
-    if !status.success() { print_error(step, total_steps, "cp boot.wim failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "cp boot.wim failed")); }
+    boot_args.extend(fat_copy_filters(esp_mb.is_some()));
+    boot_args.push(format!("{}/", iso_m.to_str().unwrap()));
+    boot_args.push(format!("{}/", boot_m.to_str().unwrap()));
+    boot_args.extend(whole_file_arg(options.copy_strategy, wants_whole_file(usb_device)).map(str::to_string));
+    fail_point::check("copy_boot").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    let stall_timeout = Tuning::for_device(usb_device).copy_stall_timeout();
+    run_rsync_streaming(&boot_args, stall_timeout)
+        .map_err(|e| e.into_error(fat_name))
+        .inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    if esp_mb.is_some() {
+        ensure_bcd_files_copied(&boot_m).inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    }
+    // A dedicated ESP got boot.wim through its filters
+    if esp_mb.is_none() {
+        print_step(step, total_steps, "Copying boot.wim...");
+        let _ = std::fs::create_dir_all(boot_m.join("sources"));
+        let status = std::process::Command::new("cp").args([iso_m.join("sources/boot.wim").to_str().unwrap(), boot_m.join("sources").to_str().unwrap()]).status()?;
+        if !status.success() { print_error(step, total_steps, "cp boot.wim failed"); return Err(io::Error::new(io::ErrorKind::Other, "cp boot.wim failed")); }
+    }
+    step += 1;
+    if options.boot_test {
+        write_boot_test_marker(&boot_m, &mut io::stdout())?;
+        if let Err(e) = flush_with_progress(&mut std::io::stdout()) {
+            println!("Warning: flush progress unavailable ({}); continuing with cleanup", e);
+        }
+        drop(base);
+        print_step(total_steps, total_steps, "Windows boot test stick completed.");
+        return Ok(());
+    }
     print_step(step, total_steps, "Mounting INSTALL partition..."); step += 1;
-    let ntfs_opts = if has_ntfs3g() {
-        "big_writes,async,noatime,nodiratime"
-    } else {
-        "noatime,nodiratime"
-    };
-    let status = if has_ntfs3g() {
-        std::process::Command::new("mount")
-            .args(["-t", "ntfs-3g", "-o", ntfs_opts, &p2, inst_m.to_str().unwrap()])
-            .status()
-    } else {
-        std::process::Command::new("mount")
-            .args(["-o", ntfs_opts, &p2, inst_m.to_str().unwrap()])
-            .status()
-    }?;
-    if !status.success() { print_error(step, total_steps, "mount INSTALL failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "mount INSTALL failed")); }
+    fail_point::check("mount_install").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    let driver = mount_ntfs_partition(&p2, inst_m.to_str().unwrap()).inspect_err(|_| print_error(step, total_steps, "mount INSTALL failed"))?;
+    println!("INSTALL partition mounted with {} driver", driver.as_str());
     print_step(step, total_steps, "Copying files to INSTALL; Please wait this could take a bit..."); step += 1;
     let mut install_args = vec![
         "-a".to_string(),
@@ -555,29 +2053,325 @@ pub fn write_windows_iso_to_usb_stream_with_bypass(
         format!("{}/", iso_m.to_str().unwrap()),
         format!("{}/", inst_m.to_str().unwrap()),
     ];
-    if is_usb_device(usb_device) {
-        install_args.push("--whole-file".to_string());
+    install_args.extend(whole_file_arg(options.copy_strategy, wants_whole_file(usb_device)).map(str::to_string));
+    apply_install_excludes(&iso_m, &options.exclude, &mut install_args, &mut io::stdout())?;
+    fail_point::check("copy_install").inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    rsync_with_retry("INSTALL", usb_device, &mut io::stdout(), || run_rsync_streaming(&install_args, stall_timeout))
+        .inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    if let Some(image) = &install_image {
+        ensure_install_image_copied(image, &iso_m, &inst_m).inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
+    }
+    if let Some(oem_folder) = oem_folder {
+        copy_oem_folder(oem_folder, &inst_m, &mut io::stdout()).inspect_err(|e| print_error(step, total_steps, &e.to_string()))?;
     }
-    let status = std::process::Command::new("rsync").args(install_args).status()?;
-    if !status.success() { print_error(step, total_steps, "rsync INSTALL failed"); cleanup(); return Err(io::Error::new(io::ErrorKind::Other, "rsync INSTALL failed")); }
     // Optional: apply unattend bypass if requested.
     if let Some(flags) = bypass_flags {
-        let unattend_gen = UnattendGenerator::new(Architecture::X64, flags);
-        let unattend_path = unattend_gen.generate()?;
         let boot_wim = boot_m.join("sources/boot.wim");
         if boot_wim.exists() {
-            let wim_editor = WimEditor::new(&boot_wim);
-            let target_index = if wim_editor.verify_index(2).unwrap_or(false) { 2 } else { 1 };
-            println!("Injecting Autounattend.xml into boot.wim (index {})...", target_index);
-            wim_editor.add_file(target_index, &unattend_path, "/Autounattend.xml")?;
-            println!("Bypass unattend injected successfully.");
+            inject_unattend(&boot_wim, flags, &mut io::stdout())?;
         } else {
             println!("Warning: boot.wim not found at {}, skipping unattend injection.", boot_wim.display());
         }
     }
 
     print_step(step, total_steps, "Cleaning up mounts; We're almost done, please wait..."); step += 1;
-    cleanup();
+    if let Err(e) = flush_with_progress(&mut std::io::stdout()) {
+        println!("Warning: flush progress unavailable ({}); continuing with cleanup", e);
+    }
+    drop(base);
     print_step(step, total_steps, "Windows USB creation completed.");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answer_file_is_replaced_or_removed_in_place() {
+        let root = tempfile::tempdir().unwrap();
+        let answer = root.path().join(UNATTEND_FILE);
+        write_unattend_in_place(root.path(), UnattendFlags::BYPASS_TPM, false, &mut io::sink()).unwrap();
+        let xml = fs::read_to_string(&answer).unwrap();
+        assert!(xml.contains("BypassTPMCheck"));
+        assert!(!xml.contains("BypassRAMCheck"));
+
+        write_unattend_in_place(root.path(), UnattendFlags::BYPASS_ALL, false, &mut io::sink()).unwrap();
+        assert!(fs::read_to_string(&answer).unwrap().contains("BypassRAMCheck"));
+
+        write_unattend_in_place(root.path(), UnattendFlags::empty(), false, &mut io::sink()).unwrap();
+        assert!(!answer.exists());
+    }
+
+    #[test]
+    fn tool_partition_is_carved_from_the_end_of_install() {
+        assert_eq!(install_partition_end(32_000_000_000, None).unwrap(), "100%");
+        assert_eq!(install_partition_end(32_000_000_000, Some(32)).unwrap(), "30484MiB");
+        assert!(install_partition_end(16 * 1024 * 1024, Some(32)).is_err());
+    }
+
+    #[test]
+    fn legacy_layout_rejects_options_it_has_no_partition_for() {
+        let legacy = WindowsFlowOptions { legacy: true, ..Default::default() };
+        assert!(validate_legacy_options(&legacy).is_ok());
+        let with_esp = WindowsFlowOptions { esp_mb: Some(260), ..legacy.clone() };
+        assert!(validate_legacy_options(&with_esp).unwrap_err().contains("ESP"));
+        let boot_test = WindowsFlowOptions { boot_test: true, ..legacy };
+        assert!(validate_legacy_options(&boot_test).is_err());
+    }
+
+    #[test]
+    fn copy_strategy_picks_the_rsync_transfer_mode() {
+        assert_eq!(whole_file_arg(CopyStrategy::FirstWrite, true), Some("--whole-file"));
+        assert_eq!(whole_file_arg(CopyStrategy::FirstWrite, false), None);
+        assert_eq!(whole_file_arg(CopyStrategy::Rewrite, true), Some("--no-whole-file"));
+        assert_eq!(whole_file_arg(CopyStrategy::Rewrite, false), Some("--no-whole-file"));
+    }
+
+    #[test]
+    fn mount_base_prefers_the_configured_directory() {
+        let temp = std::env::temp_dir();
+        assert_eq!(
+            mount_base_candidates(Some("/var/mnt".to_string())),
+            [PathBuf::from("/var/mnt"), PathBuf::from("/mnt"), temp.clone()]
+        );
+        assert_eq!(mount_base_candidates(Some("/mnt".to_string())), [PathBuf::from("/mnt"), temp.clone()]);
+        assert_eq!(mount_base_candidates(None), [PathBuf::from("/mnt"), temp]);
+    }
+
+    #[test]
+    fn unpacked_isos_must_hold_the_boot_files() {
+        let dest = Path::new("/mnt/tmp/iso");
+        assert_eq!(
+            IsoExtractor::SevenZip.args("/isos/win.iso", dest),
+            ["x", "-y", "-o/mnt/tmp/iso", "/isos/win.iso"]
+        );
+        assert_eq!(IsoExtractor::Bsdtar.args("/isos/win.iso", dest), ["-x", "-f", "/isos/win.iso", "-C", "/mnt/tmp/iso"]);
+
+        // The ISO9660 side of a Windows ISO, as bsdtar sees it
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("README.TXT"), "This disc contains a \"UDF\" file system").unwrap();
+        let problem = extracted_tree_problem(root.path(), IsoExtractor::Bsdtar).unwrap();
+        assert!(problem.contains("install 7-Zip"), "{}", problem);
+
+        fs::write(root.path().join("bootmgr"), b"").unwrap();
+        fs::create_dir_all(root.path().join("sources")).unwrap();
+        fs::write(root.path().join("sources/boot.wim"), b"").unwrap();
+        assert_eq!(extracted_tree_problem(root.path(), IsoExtractor::SevenZip), None);
+    }
+
+    #[test]
+    fn fits_cluster_sizes_to_partition_size() {
+        let gib = 1u64 << 30;
+        // 1 GiB BOOT: 32K clusters leave too few for FAT32, so the size shrinks
+        let fat = fit_fat32_cluster(gib, 32 * 1024, 512).unwrap();
+        assert!(fat < 32 * 1024 && fat32_cluster_count(gib, fat, 512) >= FAT32_MIN_CLUSTERS);
+        assert_eq!(fit_fat32_cluster(gib, 4096, 512), Ok(4096));
+        // Clusters are never smaller than the sector
+        assert_eq!(fit_fat32_cluster(gib, 512, 4096), Ok(4096));
+        assert!(fit_fat32_cluster(16 * 1024 * 1024, 512, 512).is_err());
+        assert!(fit_fat32_cluster(gib, 3000, 512).is_err());
+
+        assert_eq!(fit_ntfs_cluster(32 * gib, 65536, 512), Ok(65536));
+        assert_eq!(fit_ntfs_cluster(32 * gib, 512, 4096), Ok(4096));
+        assert!(fit_ntfs_cluster(32 * gib, 128 * 1024, 512).is_err());
+        // 4 TiB needs at least 2K clusters to stay under 2^32 clusters
+        assert_eq!(fit_ntfs_cluster(4096 * gib, 512, 512), Ok(2048));
+    }
+
+    #[test]
+    fn fat32_override_is_independent_of_ntfs_cluster() {
+        let auto = requested_cluster_sizes(4096, 512, Some(65536), None);
+        assert_eq!(auto, ClusterSizes { fat32_bytes: 4096, ntfs_bytes: 65536 });
+        let explicit = requested_cluster_sizes(4096, 512, Some(65536), Some(16));
+        assert_eq!(explicit, ClusterSizes { fat32_bytes: 8192, ntfs_bytes: 65536 });
+        assert!(validate_fat32_sectors_per_cluster(64).is_ok());
+        assert!(validate_fat32_sectors_per_cluster(3).is_err());
+        assert!(validate_fat32_sectors_per_cluster(256).is_err());
+    }
+
+    #[test]
+    fn exclude_patterns_keep_setup_files() {
+        assert!(validate_exclude_pattern("sources/??-??/").is_ok());
+        assert!(validate_exclude_pattern("*.txt").is_ok());
+        assert!(validate_exclude_pattern("support").is_ok());
+        for bad in ["", "  ", "*.wim", "sources", "/sources/", "Sources/*", "BOOTMGR", "efi/", "*", "**", "- foo"] {
+            assert!(validate_exclude_pattern(bad).is_err(), "{:?} should be rejected", bad);
+        }
+
+        let iso = tempfile::tempdir().unwrap();
+        fs::create_dir_all(iso.path().join("sources/de-de")).unwrap();
+        fs::create_dir_all(iso.path().join("support/logging")).unwrap();
+        fs::write(iso.path().join("sources/de-de/setup.rll"), vec![0u8; 10]).unwrap();
+        fs::write(iso.path().join("sources/install.wim"), b"wim").unwrap();
+        fs::write(iso.path().join("support/logging/readme.txt"), b"hi").unwrap();
+        fs::write(iso.path().join("support/logging/NOTES.TXT"), b"caps").unwrap();
+        let patterns = vec!["sources/??-??/".to_string(), "*.txt".to_string()];
+        assert_eq!(
+            excluded_paths(iso.path(), &patterns, false),
+            vec![("sources/de-de".to_string(), 10), ("support/logging/readme.txt".to_string(), 2)]
+        );
+
+        let mut args = Vec::new();
+        let mut log = Vec::new();
+        apply_install_excludes(iso.path(), &patterns, &mut args, &mut log).unwrap();
+        assert_eq!(args, ["--exclude=sources/??-??/", "--exclude=*.txt"]);
+        let log = String::from_utf8(log).unwrap();
+        assert!(log.contains("skipped sources/de-de"));
+        assert!(!log.contains("  skipped support/logging/NOTES.TXT"));
+        assert!(log.contains("not skipped support/logging/NOTES.TXT: the patterns match it only in a different case"));
+    }
+
+    #[test]
+    fn oem_folder_needs_a_setup_subfolder() {
+        let dir = tempfile::tempdir().unwrap();
+        let oem = dir.path().join("$OEM$");
+        fs::create_dir_all(oem.join("scripts")).unwrap();
+        assert!(validate_oem_folder(&oem).is_err());
+        assert!(validate_oem_folder(&dir.path().join("missing")).is_err());
+        fs::create_dir_all(oem.join("$$").join("Setup").join("Scripts")).unwrap();
+        assert!(validate_oem_folder(&oem).is_ok());
+
+        let args = oem_copy_args(&oem, Path::new("/mnt/install"));
+        assert_eq!(args[args.len() - 1], "/mnt/install/sources/$OEM$/");
+    }
+
+    #[test]
+    fn classifies_rsync_stalls_as_transient() {
+        let stall = RsyncFailure { code: Some(23), stderr: "read errors mapping \"install.wim\": Input/output error (5)".to_string(), missing: false, stalled_secs: None };
+        assert!(stall.is_transient(true));
+        assert!(!stall.is_transient(false));
+        let full = RsyncFailure { code: Some(11), stderr: "write failed: No space left on device (28)".to_string(), missing: false, stalled_secs: None };
+        assert!(!full.is_transient(true));
+        let usage = RsyncFailure { code: Some(1), stderr: String::new(), missing: false, stalled_secs: None };
+        assert!(!usage.is_transient(true));
+    }
+
+    #[test]
+    fn silent_copy_is_stopped_as_stalled() {
+        // rsync redraws its progress line with \r on stdout
+        let child = Command::new("sh")
+            .args(["-c", "printf '  524,288  5%%  4.00MB/s  0:00:01\\r  1,048,576  10%%  5.00MB/s  0:00:01\\r'; exec sleep 30"])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        let mut peak = 0.0;
+        let failure = watch_rsync(child, Some(Duration::from_secs(1)), false, &mut peak).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(failure.stalled_secs, Some(1));
+        assert!(failure.is_transient(true));
+        assert!(failure.to_string().starts_with("transfer stalled: no progress for 1s"));
+        assert_eq!(peak, 5.0);
+
+        let done = Command::new("sh")
+            .args(["-c", "printf '  2,048  100%%\\n'; echo 'rsync: some warning' >&2; exit 23"])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let failure = watch_rsync(done, Some(Duration::from_secs(5)), false, &mut peak).unwrap_err();
+        assert_eq!((failure.code, failure.stalled_secs), (Some(23), None));
+        assert_eq!(failure.stderr, "rsync: some warning");
+
+        let copied = Command::new("sh").args(["-c", "printf '  4,096  100%%\\n'"]).stdout(std::process::Stdio::piped()).spawn().unwrap();
+        assert_eq!(watch_rsync(copied, Some(Duration::from_secs(5)), false, &mut peak).unwrap(), 4096);
+    }
+
+    #[test]
+    fn fatal_rsync_failure_is_not_retried() {
+        let mut runs = 0;
+        let mut log = Vec::new();
+        let result: io::Result<()> = rsync_with_retry("INSTALL", "/", &mut log, || {
+            runs += 1;
+            Err(RsyncFailure { code: Some(11), stderr: "No space left on device".to_string(), missing: false, stalled_secs: None })
+        });
+        assert_eq!(runs, 1);
+        assert!(result.unwrap_err().to_string().contains("No space left"));
+        assert!(log.is_empty());
+
+        let missing: io::Result<()> =
+            rsync_with_retry("INSTALL", "/", &mut log, || Err(RsyncFailure::spawn(io::Error::from(io::ErrorKind::NotFound))));
+        let error = missing.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().starts_with("rsync is not installed"));
+    }
+
+    #[test]
+    fn dedicated_esp_gets_efi_and_winpe_files() {
+        assert_eq!(fat_partition_layout(None), ("BOOT", "BOOT", "1GiB".to_string()));
+        assert_eq!(fat_partition_layout(Some(1024)), ("ESP", "ESP", "1025MiB".to_string()));
+        assert!(fat_copy_filters(true).ends_with(&["--exclude=*".to_string()]));
+        assert_eq!(fat_copy_filters(false), vec!["--exclude=sources/".to_string()]);
+        assert!(validate_esp_size_mb(300).is_ok());
+        assert!(validate_esp_size_mb(100).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        sparse_file(&root.join("efi/boot/bootx64.efi"), 150 * 1024 * 1024);
+        sparse_file(&root.join("bootmgr.efi"), 2 * 1024 * 1024);
+        sparse_file(&root.join("boot/boot.sdi"), 3 * 1024 * 1024);
+        sparse_file(&root.join("sources/boot.wim"), 600 * 1024 * 1024);
+        sparse_file(&root.join("sources/install.wim"), 900 * 1024 * 1024);
+        assert_eq!(esp_payload_bytes(root).unwrap(), 755 * 1024 * 1024);
+        assert!(ensure_esp_fits(root, 1024).is_ok());
+        assert!(ensure_esp_fits(root, 512).is_err());
+    }
+
+    #[test]
+    fn esp_holds_every_file_the_bcd_loads() {
+        let filters = fat_copy_filters(true);
+        for file in BCD_BOOT_FILES {
+            assert!(ESP_FILES.contains(&file), "{} is missing from the ESP", file);
+            assert!(filters.contains(&format!("--include=/{}", file)));
+            let dir = file.split_once('/').unwrap().0;
+            let dir_filter = filters.iter().position(|f| *f == format!("--include=/{}/", dir)).unwrap();
+            assert!(dir_filter < filters.len() - 1, "{}/ must be included before the final exclude", dir);
+        }
+        assert!(filters.contains(&"--include=/boot/bcd".to_string()));
+
+        let esp = tempfile::tempdir().unwrap();
+        sparse_file(&esp.path().join("sources/boot.wim"), 4096);
+        assert!(ensure_bcd_files_copied(esp.path()).unwrap_err().to_string().contains("boot/boot.sdi"));
+        sparse_file(&esp.path().join("boot/boot.sdi"), 4096);
+        assert!(ensure_bcd_files_copied(esp.path()).is_ok());
+    }
+
+    #[test]
+    fn split_install_image_must_arrive_whole() {
+        let iso = tempfile::tempdir().unwrap();
+        let install = tempfile::tempdir().unwrap();
+        sparse_file(&iso.path().join("sources/install.swm"), 4096);
+        sparse_file(&iso.path().join("sources/install2.swm"), 8192);
+        let mut log = Vec::new();
+        let image = inspect_install_image(iso.path(), &mut log).unwrap().unwrap();
+        assert!(String::from_utf8(log).unwrap().contains("install.swm (split, 2 parts)"));
+
+        sparse_file(&install.path().join("sources/install.swm"), 4096);
+        assert!(ensure_install_image_copied(&image, iso.path(), install.path()).is_err());
+        sparse_file(&install.path().join("sources/install2.swm"), 8192);
+        assert!(ensure_install_image_copied(&image, iso.path(), install.path()).is_ok());
+    }
+
+    fn sparse_file(path: &Path, len: u64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(path).unwrap().set_len(len).unwrap();
+    }
+
+    #[test]
+    fn flags_only_oversized_files_in_boot_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        sparse_file(&root.join("sources/install.wim"), FAT32_MAX_FILE_SIZE + 1);
+        sparse_file(&root.join("sources/boot.wim"), 512 * 1024 * 1024);
+        sparse_file(&root.join("efi/boot/bootx64.efi"), 2 * 1024 * 1024);
+        assert!(find_oversized_boot_files(root).unwrap().is_empty());
+
+        sparse_file(&root.join("efi/microsoft/boot/huge.img"), FAT32_MAX_FILE_SIZE + 1);
+        let found = find_oversized_boot_files(root).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, PathBuf::from("efi/microsoft/boot/huge.img"));
+        assert!(ensure_boot_files_fit_fat32(root).is_err());
+    }
+}
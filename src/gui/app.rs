@@ -2,17 +2,42 @@ use gtk4::prelude::*;
 use gtk4::{Application, ApplicationWindow, Button, FileChooserAction, FileChooserDialog, FileFilter, Orientation, Box as GtkBox, Label, TextView, ProgressBar, MessageDialog, ButtonsType, MessageType};
 use glib::{self, Priority};
 use std::io;
-
-use crate::flows::linux_persistence::{self, PersistenceConfig, PartitionTableType};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::flows::image_copy::PauseControl;
+use crate::flows::job::{is_disk_image, ImageKind, LinuxWriteMode, OsOverride, WriteJob, WriteMode};
+use crate::flows::linux_autoinstall::{self, AnswerFile, InstallerFamily};
+use crate::flows::linux_data_partition::{self, DataFilesystem, DataPartitionConfig};
+use crate::flows::linux_flow::LinuxWriteOptions;
+use crate::flows::linux_persistence::{self, PersistenceConfig, PartitionTableType, RemountPolicy};
+use crate::flows::media_scan::{MediaLabel, MediaReport};
+use crate::flows::outcome::{Warning, WarningKind, WriteOutcome};
+use crate::flows::preflight::{self, CheckStatus};
 use crate::gui::widgets as gui_widgets;
-use crate::gui::dialogs as gui_dialogs;
+use crate::gui::dialogs::{self as gui_dialogs, ChecksumRecovery};
+use crate::error::UsbCreatorError;
+use crate::settings::{LogVerbosity, Settings};
+use crate::write_state::{WritePhase, WriteState};
+use crate::windows::wim::WindowsFlavor;
 
 enum WorkerMessage {
     Log(String),
     Status(String),
-    Done(Result<(), String>),
+    /// Determinate progress (fraction, label) parsed from `[PROGRESS]` lines
+    Progress(f64, String),
+    /// Source checksum did not match; the worker waits for the user's choice on the reply channel
+    ChecksumMismatch(UsbCreatorError, std::sync::mpsc::Sender<ChecksumRecovery>),
+    Done(WriteOutcome),
 }
 
+/// Set when the window is closed during device work and the user chose to cancel it. Workers log
+/// through `ChannelWriter`, which then fails every write, so a flow unwinds through its usual
+/// error path and cleanup guards at its next log line.
+static QUIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// How long quitting waits for a cancelled worker before exiting anyway (hashing does not log)
+const QUIT_WAIT: std::time::Duration = std::time::Duration::from_secs(30);
+
 /// Writer that forwards log output to the GUI channel.
 struct ChannelWriter {
     sender: glib::Sender<WorkerMessage>,
@@ -20,8 +45,17 @@ struct ChannelWriter {
 
 impl std::io::Write for ChannelWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Not `Interrupted`: `write_all` retries that kind forever
+        if QUIT_REQUESTED.load(Ordering::Relaxed) {
+            return Err(std::io::Error::other("cancelled because the window was closed"));
+        }
         let msg = String::from_utf8_lossy(buf).to_string();
-        let _ = self.sender.send(WorkerMessage::Log(msg));
+        // Progress lines drive the bar instead of flooding the log
+        let message = match crate::utils::parse_progress_line(&msg) {
+            Some((fraction, label)) => WorkerMessage::Progress(fraction, label),
+            None => WorkerMessage::Log(msg),
+        };
+        let _ = self.sender.send(message);
         Ok(buf.len())
     }
 
@@ -30,7 +64,732 @@ impl std::io::Write for ChannelWriter {
     }
 }
 
+/// Suffix for device combo entries holding the selected ISO
+const SOURCE_DEVICE_MARK: &str = "(contains your source ISO)";
+/// Suffix for device combo entries holding the running live system
+const LIVE_SYSTEM_MARK: &str = "(running live system)";
+/// Suffix for USB 3 devices that negotiated USB 2.0 speed
+const SLOW_LINK_MARK: &str = "(USB 2.0 speed)";
+
+/// Form widgets and shared state that together describe a write job
+#[derive(Clone)]
+struct JobForm {
+    iso_entry: gtk4::Entry,
+    device_combo: gtk4::ComboBox,
+    checksum_entry: gtk4::Entry,
+    size_entry: gtk4::Entry,
+    media_name_entry: gtk4::Entry,
+    media_description_entry: gtk4::Entry,
+    os_override_combo: gtk4::ComboBoxText,
+    windows_group: GtkBox,
+    linux_group: GtkBox,
+    cluster_combo: gtk4::ComboBoxText,
+    fat32_cluster_combo: gtk4::ComboBoxText,
+    oem_folder_entry: gtk4::Entry,
+    efi_tool_entry: gtk4::Entry,
+    esp_layout_combo: gtk4::ComboBoxText,
+    exclude_entry: gtk4::Entry,
+    boot_test_checkbox: gtk4::CheckButton,
+    legacy_checkbox: gtk4::CheckButton,
+    dd_checkbox: gtk4::CheckButton,
+    bypass_tpm_cb: gtk4::CheckButton,
+    bypass_secure_boot_cb: gtk4::CheckButton,
+    bypass_ram_cb: gtk4::CheckButton,
+    raw_dd_checkbox: gtk4::CheckButton,
+    persistence_checkbox: gtk4::CheckButton,
+    table_type_combo: gtk4::ComboBoxText,
+    placement_combo: gtk4::ComboBoxText,
+    /// Free regions offered in the placement combo (index 0 is "after last partition")
+    placement_regions: std::rc::Rc<std::cell::RefCell<Vec<crate::utils::FreeRegion>>>,
+    kernel_params_entry: gtk4::Entry,
+    fix_pmbr_checkbox: gtk4::CheckButton,
+    repartition_checkbox: gtk4::CheckButton,
+    data_partition_checkbox: gtk4::CheckButton,
+    data_size_entry: gtk4::Entry,
+    data_fs_combo: gtk4::ComboBoxText,
+    max_speed_entry: gtk4::Entry,
+    answer_file_picker: AnswerFilePicker,
+    raw_target_group: GtkBox,
+    raw_target_combo: gtk4::ComboBoxText,
+    raw_targets: RawTargets,
+    /// Last OS detection result for the selected ISO (Some(true) = Windows)
+    last_detection: std::rc::Rc<std::cell::Cell<Option<bool>>>,
+    settings: std::rc::Rc<std::cell::RefCell<Settings>>,
+}
+
+/// OS label text for a Linux ISO, with its Secure Boot support when the ISO can be inspected
+fn linux_os_label(prefix: &str, iso_path: &str) -> String {
+    match linux_persistence::check_secure_boot_support(iso_path) {
+        Ok(support) => format!("{}; {}", prefix, support.description()),
+        Err(_) => prefix.to_string(),
+    }
+}
+
+/// Device the raw target combo was filled from and the partition numbers it offers
+/// (combo index 0 is the whole device)
+type RawTargets = std::rc::Rc<std::cell::RefCell<(String, Vec<u32>)>>;
+
+/// Shows the options group for a detection result: (ISO path, detected type, how it was detected)
+type ShowDetection = std::rc::Rc<dyn Fn(&str, crate::utils::IsoType, &str)>;
+
+/// Controls that start device work or change what a write targets. They are locked for the whole
+/// of a write, clone or multi-device write and unlocked once, when it finishes or is cancelled.
+#[derive(Clone)]
+struct DeviceActions {
+    controls: Vec<gtk4::Widget>,
+    busy: std::rc::Rc<std::cell::Cell<bool>>,
+}
+
+impl DeviceActions {
+    fn set_busy(&self, busy: bool) {
+        self.busy.set(busy);
+        for control in &self.controls {
+            control.set_sensitive(!busy);
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.busy.get()
+    }
+}
+
+/// Combo and confirmation text for a partition, e.g. "/dev/sdb3 - 512 MB ext4 (recovery)"
+fn partition_choice_label(device: &str, partition: &crate::utils::PartitionInfo, sector_size: u64) -> String {
+    let mut text = format!(
+        "{} - {} MB {}",
+        crate::utils::partition_path(device, partition.number),
+        partition.size_sectors * sector_size / 1024 / 1024,
+        if partition.fs_type.is_empty() { "unformatted" } else { &partition.fs_type }
+    );
+    if !partition.name.is_empty() {
+        text.push_str(&format!(" ({})", partition.name));
+    }
+    text
+}
+
+/// Offer the whole device and each of its partitions as raw write targets
+fn fill_raw_targets(combo: &gtk4::ComboBoxText, targets: &RawTargets, device: Option<&str>) {
+    combo.remove_all();
+    combo.append_text("Whole device (default)");
+    combo.set_active(Some(0));
+    *targets.borrow_mut() = (String::new(), Vec::new());
+    let Some(device) = device else {
+        return;
+    };
+    match crate::utils::read_partitions(device) {
+        Ok(partitions) => {
+            let sector_size = crate::utils::get_device_logical_block_size(device).unwrap_or(512);
+            for partition in &partitions {
+                combo.append_text(&format!("Partition only: {}", partition_choice_label(device, partition, sector_size)));
+            }
+            *targets.borrow_mut() = (device.to_string(), partitions.iter().map(|part| part.number).collect());
+        }
+        Err(e) => println!("[DEBUG] Could not list partitions of {}: {}", device, e),
+    }
+}
+
+/// Kickstart/preseed picker, shown only for installer ISOs that read one
+#[derive(Clone)]
+struct AnswerFilePicker {
+    container: GtkBox,
+    label: Label,
+    entry: gtk4::Entry,
+    /// Installer detected on the selected ISO
+    family: std::rc::Rc<std::cell::Cell<Option<InstallerFamily>>>,
+}
+
+impl AnswerFilePicker {
+    /// Detect the installer on `iso_path` and offer the answer file it reads, if any
+    fn update(&self, iso_path: &str) {
+        let family = linux_autoinstall::detect_installer_family(iso_path);
+        self.family.set(family);
+        match family.and_then(|family| AnswerFile::for_family(family, "")) {
+            Some(answer) => {
+                self.label.set_text(&format!("{} file (automated install):", answer.kind()));
+                self.container.set_visible(true);
+            }
+            None => self.reset(),
+        }
+    }
+
+    fn reset(&self) {
+        self.entry.set_text("");
+        self.container.set_visible(false);
+    }
+
+    /// Kind of answer file offered for the current ISO ("Kickstart", "Preseed")
+    fn kind(&self) -> Option<&'static str> {
+        self.family.get().and_then(|family| AnswerFile::for_family(family, "")).map(|answer| answer.kind())
+    }
+
+    /// Answer file entered by the user, checked for size and readability
+    fn answer_file(&self) -> Result<Option<AnswerFile>, String> {
+        let path = self.entry.text().trim().to_string();
+        if path.is_empty() || !self.container.is_visible() {
+            return Ok(None);
+        }
+        let answer = self
+            .family
+            .get()
+            .and_then(|family| AnswerFile::for_family(family, path))
+            .ok_or("This ISO does not support an answer file")?;
+        linux_autoinstall::validate_answer(&answer).map_err(|e| format!("Invalid answer file: {}", e))?;
+        Ok(Some(answer))
+    }
+}
+
+/// Job read from the form, the detection result it was checked against and its log description
+struct FormJob {
+    job: WriteJob,
+    detection: Option<bool>,
+    description: String,
+}
+
+/// Device path of the entry selected in the device combo. Each device entry carries its path as
+/// the combo id, so the label can say anything; placeholder entries have no id.
+fn selected_device_path(device_combo: &gtk4::ComboBox) -> Result<String, String> {
+    device_combo
+        .active_id()
+        .map(|path| path.to_string())
+        .ok_or_else(|| "No USB device selected or no devices found".to_string())
+}
+
+/// Append a line to the log view and keep it scrolled to the end
+fn append_log(log_view: &TextView, line: &str) {
+    let buffer = log_view.buffer();
+    let mut end = buffer.end_iter();
+    buffer.insert(&mut end, line);
+    if !line.ends_with('\n') {
+        let mut end = buffer.end_iter();
+        buffer.insert(&mut end, "\n");
+    }
+    let mut end = buffer.end_iter();
+    log_view.scroll_to_iter(&mut end, 0.0, true, 0.0, 1.0);
+}
+
+impl JobForm {
+    /// Build the job the form currently describes. Errors are messages for the log view.
+    fn build_job(&self) -> Result<FormJob, String> {
+        // Symlinked images are written from their target, so every check looks at the real file
+        let iso_path = crate::utils::resolve_image_path(&self.iso_entry.text());
+        if iso_path.is_empty() {
+            return Err("No ISO file selected".to_string());
+        }
+
+        let device_path = selected_device_path(&self.device_combo)?;
+        // Resolve again here: the ISO may have changed since the device list was refreshed
+        if crate::utils::backing_disks(&iso_path).contains(&device_path) {
+            return Err(format!(
+                "{} contains your source ISO; writing to it would destroy the image. Choose another device.",
+                device_path
+            ));
+        }
+        if crate::utils::live_system_disks().contains(&device_path) {
+            return Err(format!(
+                "{} is the live USB this system is running from; writing to it would crash the session and destroy the medium. Choose another device.",
+                device_path
+            ));
+        }
+
+        let checksum_text = self.checksum_entry.text().to_string();
+        let expected_sha256 = if checksum_text.trim().is_empty() {
+            None
+        } else {
+            Some(crate::checksum::normalize_sha256(&checksum_text).map_err(|e| e.to_string())?)
+        };
+        let size_text = self.size_entry.text().trim().to_string();
+        let expected_size = if size_text.is_empty() {
+            None
+        } else {
+            crate::checksum::parse_expected_size(&size_text).map_err(|e| e.to_string())?;
+            Some(size_text)
+        };
+
+        let size_warning = crate::utils::validate_image_file(&iso_path).map_err(|e| e.to_string())?;
+
+        let mut log_text = "Starting write operation:\n".to_string();
+        log_text.push_str(&format!("  ISO: {}\n", iso_path));
+        if let Some(warning) = size_warning {
+            log_text.push_str(&format!("  WARNING: {}\n", warning));
+        }
+        log_text.push_str(&format!("  Device: {}\n", device_path));
+        if let Some(warning) = crate::utils::usb_link_info(&device_path).and_then(|link| link.warning()) {
+            log_text.push_str(&format!("  NOTE: {}\n", warning));
+        }
+
+        let mut linux_mode = LinuxWriteMode::RawDd;
+        let mut fix_protective_mbr = false;
+        let mut max_speed_mbps = None;
+        let mut answer_file = None;
+        let mut data_partition = None;
+
+        // Determine write mode and options
+        // Prefer explicit detection over UI visibility to avoid falling back to Linux when the Windows group is hidden.
+        // A forced type from the override dropdown takes precedence over detection.
+        let os_override = OsOverride::from_index(self.os_override_combo.active().unwrap_or(0));
+        // Reuse the detection from the advanced panel when available; otherwise detect now.
+        // Saved disk images are not mountable ISOs, so they skip detection.
+        let disk_image = is_disk_image(&iso_path);
+        let detection = if disk_image {
+            None
+        } else {
+            self.last_detection.get().or_else(|| crate::utils::is_windows_iso(&iso_path))
+        };
+        self.last_detection.set(detection);
+        let image_kind = match os_override {
+            OsOverride::Auto if disk_image => ImageKind::DiskImage,
+            OsOverride::Auto if self.windows_group.is_visible() => ImageKind::Windows,
+            OsOverride::Auto => os_override.resolve(detection).unwrap_or(ImageKind::Linux),
+            forced => forced.resolve(None).unwrap_or(ImageKind::Linux),
+        };
+        if os_override != OsOverride::Auto {
+            log_text.push_str(&format!("  Detected type overridden: {:?}\n", image_kind));
+        }
+        let detected_windows = image_kind == ImageKind::Windows;
+        let is_windows_mode = detected_windows && self.windows_group.is_visible();
+
+        let use_dd_mode = is_windows_mode && self.dd_checkbox.is_active();
+        let bypass_tpm = is_windows_mode && self.bypass_tpm_cb.is_active();
+        let bypass_secure_boot = is_windows_mode && self.bypass_secure_boot_cb.is_active();
+        let bypass_ram = is_windows_mode && self.bypass_ram_cb.is_active();
+
+        // Without the advanced panel open, Windows writes use the default cluster size
+        let cluster_bytes = if is_windows_mode {
+            let cluster_idx = self.cluster_combo.active().unwrap_or(3) as usize;
+            *gui_widgets::CLUSTER_SIZES_BYTES.get(cluster_idx).unwrap_or(&4096)
+        } else {
+            self.settings.borrow().default_cluster_bytes
+        };
+        let fat32_sectors_per_cluster = if is_windows_mode {
+            gui_widgets::fat32_sectors_from_index(self.fat32_cluster_combo.active())
+        } else {
+            None
+        };
+        let esp_mb = if is_windows_mode && !use_dd_mode {
+            gui_widgets::esp_mb_from_index(self.esp_layout_combo.active())
+        } else {
+            None
+        };
+        let oem_folder = Some(self.oem_folder_entry.text().trim().to_string())
+            .filter(|folder| is_windows_mode && !use_dd_mode && !folder.is_empty());
+        if let Some(folder) = &oem_folder {
+            crate::flows::windows_flow::validate_oem_folder(std::path::Path::new(folder))?;
+        }
+        let tool_efi = Some(self.efi_tool_entry.text().trim().to_string())
+            .filter(|tool| is_windows_mode && !use_dd_mode && !tool.is_empty());
+        if let Some(tool) = &tool_efi {
+            crate::flows::efi_tool::validate_efi_tool(std::path::Path::new(tool))?;
+        }
+        let exclude = if is_windows_mode && !use_dd_mode {
+            crate::flows::windows_flow::parse_exclude_patterns(&self.exclude_entry.text())
+        } else {
+            Vec::new()
+        };
+        for pattern in &exclude {
+            crate::flows::windows_flow::validate_exclude_pattern(pattern)?;
+        }
+        let boot_test = is_windows_mode && !use_dd_mode && self.boot_test_checkbox.is_active();
+        let legacy = is_windows_mode && !use_dd_mode && self.legacy_checkbox.is_active();
+        if legacy && (esp_mb.is_some() || boot_test || tool_efi.is_some()) {
+            return Err("The Windows 7/8 legacy layout has a single partition: use the default layout, no boot test and no EFI tool.".into());
+        }
+
+        if is_windows_mode {
+            let mode_label = if use_dd_mode { "Windows (direct dd mode)" } else { "Windows" };
+            log_text.push_str(&format!("  Mode: {} (cluster size: {} bytes)\n", mode_label, cluster_bytes));
+            if let Some(sectors) = fat32_sectors_per_cluster {
+                log_text.push_str(&format!("  FAT32 BOOT cluster: {} sectors\n", sectors));
+            }
+            if let Some(folder) = &oem_folder {
+                log_text.push_str(&format!("  $OEM$ folder: {}\n", folder));
+            }
+            if let Some(tool) = &tool_efi {
+                log_text.push_str(&format!("  EFI tool partition: {}\n", tool));
+            }
+            if let Some(size_mb) = esp_mb {
+                log_text.push_str(&format!("  Layout: {} MB ESP + INSTALL (NTFS)\n", size_mb));
+            }
+            if !exclude.is_empty() {
+                log_text.push_str(&format!("  Skipped on INSTALL: {}\n", exclude.join(" ")));
+            }
+            if legacy {
+                log_text.push_str("  Layout: Windows 7/8 legacy (MBR, single NTFS partition, BIOS boot)\n");
+            }
+            if boot_test {
+                log_text.push_str("  Quick boot test: boot files only, the stick will NOT install Windows\n");
+            }
+            if bypass_tpm || bypass_secure_boot || bypass_ram {
+                log_text.push_str(&format!(
+                    "  Bypass options: TPM={} SecureBoot={} RAM={}\n",
+                    bypass_tpm, bypass_secure_boot, bypass_ram
+                ));
+            }
+        } else if detected_windows {
+            log_text.push_str(&format!(
+                "  Mode: Windows (auto-detected, cluster size: {} bytes)\n",
+                cluster_bytes
+            ));
+        } else if self.linux_group.is_visible() && image_kind == ImageKind::Linux {
+            let speed_text = self.max_speed_entry.text();
+            let speed_text = speed_text.trim();
+            if !speed_text.is_empty() {
+                max_speed_mbps = Some(
+                    speed_text
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid maximum write speed '{}': expected MB/s as a whole number", speed_text))?,
+                );
+            }
+            answer_file = self.answer_file_picker.answer_file()?;
+            if let Some(answer) = &answer_file {
+                log_text.push_str(&format!("  {} file: {} (automated install)\n", answer.kind(), answer.path()));
+            }
+            let repartition = self.repartition_checkbox.is_active();
+            // A new table comes with its own protective MBR
+            fix_protective_mbr = self.fix_pmbr_checkbox.is_active() && !repartition;
+            if fix_protective_mbr {
+                log_text.push_str("  Fix GPT protective MBR: enabled\n");
+            }
+            // Raw dd and persistence are exclusive; raw wins if both somehow report active
+            let persistence = self.persistence_checkbox.is_active() && !self.raw_dd_checkbox.is_active();
+            let table_type = match self.table_type_combo.active().unwrap_or(0) {
+                1 => PartitionTableType::Mbr,
+                _ => PartitionTableType::Gpt,
+            };
+            if persistence {
+                let persistence_type = linux_persistence::detect_persistence_type(&iso_path)
+                    .map_err(|e| format!("Could not detect persistence type: {}", e))?;
+
+                let recommended_size = linux_persistence::get_recommended_persistence_size(&iso_path, &device_path)
+                    .map_err(|e| format!("Could not calculate persistence size: {}", e))?;
+
+                // Index 0 keeps the default append-after-last-partition behavior; the listed
+                // regions belong to the ISO layout, which a new table replaces
+                let placement = self
+                    .placement_combo
+                    .active()
+                    .filter(|_| !repartition)
+                    .and_then(|idx| (idx as usize).checked_sub(1))
+                    .and_then(|idx| self.placement_regions.borrow().get(idx).copied());
+                let recommended_size = match placement {
+                    Some(region) => recommended_size.min(region.size_mb()),
+                    None => recommended_size,
+                };
+                if let Some(region) = placement {
+                    log_text.push_str(&format!(
+                        "  Persistence placement: {}s-{}s ({} MB free)\n",
+                        region.start_sector, region.end_sector, region.size_mb()
+                    ));
+                }
+
+                let config = PersistenceConfig {
+                    enabled: true,
+                    size_mb: recommended_size,
+                    persistence_type,
+                    label: "persistence".to_string(),
+                    partition_table: table_type,
+                    start_sector: placement.map(|region| region.start_sector),
+                    kernel_params: linux_persistence::parse_kernel_params(&self.kernel_params_entry.text()),
+                    remount: RemountPolicy::default(),
+                    reserved_percent: 0,
+                };
+
+                if !config.kernel_params.is_empty() {
+                    log_text.push_str(&format!("  Extra kernel parameters: {}\n", config.kernel_params.join(" ")));
+                }
+
+                linux_persistence::validate_persistence_config(&config)
+                    .map_err(|e| format!("Invalid persistence configuration: {}", e))?;
+
+                linux_mode = LinuxWriteMode::Persistent(config);
+            }
+            if repartition {
+                log_text.push_str(&format!("  New {:?} partition table, ISO files copied to FAT32 (UEFI boot only)\n", table_type));
+                let persistence = match std::mem::replace(&mut linux_mode, LinuxWriteMode::RawDd) {
+                    LinuxWriteMode::Persistent(config) => Some(config),
+                    _ => None,
+                };
+                linux_mode = LinuxWriteMode::Extracted { table: table_type, persistence };
+            }
+            if self.data_partition_checkbox.is_active() {
+                let size_text = self.data_size_entry.text();
+                let size_mb = size_text
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid data partition size '{}': expected MB as a whole number", size_text.trim()))?;
+                let filesystem = match self.data_fs_combo.active().unwrap_or(0) {
+                    1 => DataFilesystem::Fat32,
+                    _ => DataFilesystem::Exfat,
+                };
+                let config = DataPartitionConfig { size_mb, filesystem };
+                linux_data_partition::validate_data_partition(&config)
+                    .map_err(|e| format!("Invalid data partition: {}", e))?;
+                log_text.push_str(&format!("  Data partition: {} MB {}\n", size_mb, filesystem.name()));
+                data_partition = Some(config);
+            }
+        }
+
+        let write_mode = if image_kind == ImageKind::DiskImage {
+            WriteMode::RestoreImage
+        } else if image_kind == ImageKind::Raw {
+            let partition = self
+                .raw_target_combo
+                .active()
+                .and_then(|idx| (idx as usize).checked_sub(1))
+                .filter(|_| self.raw_target_group.is_visible());
+            match partition {
+                Some(idx) => {
+                    let targets = self.raw_targets.borrow();
+                    if targets.0 != device_path {
+                        return Err(format!(
+                            "The partition list was read from {}; reopen the advanced options to pick a partition on {}",
+                            targets.0, device_path
+                        ));
+                    }
+                    let number = *targets.1.get(idx).ok_or("Unknown target partition")?;
+                    WriteMode::RawPartition { number }
+                }
+                None => WriteMode::RawImage,
+            }
+        } else if is_windows_mode && use_dd_mode {
+            WriteMode::WindowsDirectDd
+        } else if is_windows_mode || detected_windows {
+            let mut flags = crate::windows::unattend::UnattendFlags::empty();
+            if bypass_tpm {
+                flags |= crate::windows::unattend::UnattendFlags::BYPASS_TPM;
+            }
+            if bypass_secure_boot {
+                flags |= crate::windows::unattend::UnattendFlags::BYPASS_SECURE_BOOT;
+            }
+            if bypass_ram {
+                flags |= crate::windows::unattend::UnattendFlags::BYPASS_RAM;
+            }
+            WriteMode::Windows {
+                bypass: if flags.is_empty() { None } else { Some(flags) },
+                cluster_bytes,
+                fat32_sectors_per_cluster,
+                oem_folder,
+                esp_mb,
+                exclude,
+                boot_test,
+                legacy,
+                tool_efi,
+            }
+        } else {
+            WriteMode::Linux { mode: linux_mode, fix_protective_mbr }
+        };
+        let job = WriteJob::new(iso_path, device_path, write_mode)
+            .with_expected_sha256(expected_sha256)
+            .with_expected_size(expected_size)
+            .with_max_speed(max_speed_mbps)
+            .with_answer_file(answer_file)
+            .with_data_partition(data_partition)
+            .with_thorough_flush(self.settings.borrow().thorough_flush)
+            .with_checksum_manifest(self.settings.borrow().checksum_manifest)
+            .with_verify_boot(self.settings.borrow().verify_after_write)
+            .with_copy_strategy(self.settings.borrow().copy_strategy)
+            .with_media_label(Some(MediaLabel {
+                name: self.media_name_entry.text().trim().to_string(),
+                description: self.media_description_entry.text().trim().to_string(),
+                image: None,
+            }));
+        if let Some(mbps) = job.max_speed_mbps {
+            log_text.push_str(&format!("  Maximum write speed: {} MB/s\n", mbps));
+        }
+        if let Some(label) = &job.media_label {
+            log_text.push_str(&format!("  Name on media: {}\n", label.name));
+        }
+        if job.thorough_flush {
+            log_text.push_str("  Thorough flush after writing\n");
+        }
+        if job.checksum_manifest {
+            log_text.push_str("  Store file checksums on the media\n");
+        }
+        if job.verify_boot {
+            log_text.push_str("  Check the EFI boot files after writing\n");
+        }
+        if job.windows_options().is_some() && job.copy_strategy == crate::settings::CopyStrategy::Rewrite {
+            log_text.push_str("  Copies optimized for re-writing (delta transfer)\n");
+        }
+        if let Some(hash) = &job.expected_sha256 {
+            log_text.push_str(&format!("  Verify SHA-256: {}\n", hash));
+        }
+        if let Some(size) = &job.expected_size {
+            log_text.push_str(&format!("  Published size: {}\n", size));
+        }
+        if let Some(reason) = job.detection_conflict(detection) {
+            log_text.push_str(&format!("  WARNING: {}\n", reason));
+        }
+        if matches!(job.mode, WriteMode::Linux { .. } | WriteMode::RawImage | WriteMode::RawPartition { .. } | WriteMode::RestoreImage) {
+            log_text.push_str(&format!("  Mode: {}\n", job.describe_mode()));
+        }
+
+        Ok(FormJob { job, detection, description: log_text })
+    }
+}
+
+/// Verify the source image when the job carries a published size or checksum. The size check only
+/// logs; on a checksum mismatch the UI is asked how to recover. Returns the outcome to report when
+/// the write must not go ahead.
+fn verify_job_source(job: &WriteJob, sender: &glib::Sender<WorkerMessage>) -> Option<WriteOutcome> {
+    if let Some(expected) = job.expected_size.as_deref().and_then(|size| crate::checksum::parse_expected_size(size).ok()) {
+        let message = match crate::checksum::check_file_size(&job.iso_path, expected) {
+            Ok(check) => check.message(),
+            Err(e) => format!("Warning: {}", e),
+        };
+        let _ = sender.send(WorkerMessage::Log(message));
+    }
+    let expected = job.expected_sha256.as_ref()?;
+    let _ = sender.send(WorkerMessage::Status("Verifying checksum...".into()));
+    let _ = sender.send(WorkerMessage::Log(format!("Verifying SHA-256 of {}...", job.iso_path)));
+    let mut last_percent = None;
+    let mut report = |done, total| {
+        let percent = crate::checksum::progress_percent(done, total);
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            let _ = sender.send(WorkerMessage::Progress(percent as f64 / 100.0, format!("Verifying checksum: {}%", percent)));
+        }
+    };
+    match crate::checksum::verify_sha256(&job.iso_path, expected, &mut report) {
+        Ok(()) => {
+            let _ = sender.send(WorkerMessage::Log("Checksum OK".into()));
+            None
+        }
+        Err(UsbCreatorError::ChecksumMismatch { expected, actual }) => {
+            let (reply, choice) = std::sync::mpsc::channel();
+            let error = UsbCreatorError::ChecksumMismatch { expected: expected.clone(), actual: actual.clone() };
+            let _ = sender.send(WorkerMessage::ChecksumMismatch(error, reply));
+            match choice.recv() {
+                Ok(ChecksumRecovery::ProceedAnyway) => {
+                    let _ = sender.send(WorkerMessage::Log(
+                        "WARNING: Checksum mismatch ignored at the user's request".into(),
+                    ));
+                    None
+                }
+                _ => Some(WriteOutcome::failed(UsbCreatorError::ChecksumMismatch { expected, actual })),
+            }
+        }
+        Err(e) => Some(WriteOutcome::failed(e)),
+    }
+}
+
+/// Run the write described by `job`, streaming log and progress to `sender`. A marker file is
+/// kept for the device until the write succeeds, so a crash mid-write is reported on restart.
+/// `pause` holds copies done by the Rust loop (see [`WriteJob::can_pause`]).
+fn run_job(job: &WriteJob, pause: Option<&PauseControl>, sender: &glib::Sender<WorkerMessage>) -> WriteOutcome {
+    let mut state = WriteState::new(&job.device, &job.iso_path);
+    if let Err(e) = state.save() {
+        let _ = sender.send(WorkerMessage::Log(format!("Warning: could not record write state: {}", e)));
+    }
+    let mut outcome = run_write(job, pause, sender);
+    if outcome.success {
+        let _ = sender.send(WorkerMessage::Status("Flushing device...".into()));
+        let mut logger = ChannelWriter { sender: sender.clone() };
+        let _ = job.finalize(&mut logger, &mut outcome.warnings);
+    }
+    // A degraded link does not break the media but explains a slow write; keep it with the other warnings
+    if outcome.success
+        && let Some(note) = crate::utils::usb_link_info(&job.device).and_then(|link| link.warning())
+    {
+        outcome.warnings.push(Warning::new(WarningKind::UsbSpeed, note));
+    }
+    if outcome.success {
+        let _ = WriteState::clear(&job.device);
+    } else {
+        outcome.device_state = Some(job.partial_write_notice());
+        state.phase = WritePhase::Failed;
+        let _ = state.save();
+    }
+    outcome
+}
 
+fn run_write(job: &WriteJob, pause: Option<&PauseControl>, sender: &glib::Sender<WorkerMessage>) -> WriteOutcome {
+    let send = |m| { let _ = sender.send(m); };
+    let mut logger = ChannelWriter { sender: sender.clone() };
+    match &job.mode {
+        WriteMode::WindowsDirectDd => {
+            send(WorkerMessage::Log("Starting Windows direct dd write (not recommended)...".into()));
+            send(WorkerMessage::Status("Writing image (dd)...".into()));
+            let result = crate::flows::windows_flow::write_windows_iso_direct_dd(
+                &job.iso_path,
+                &job.device,
+                &mut logger
+            ).map(|_| (None, Vec::new()));
+            WriteOutcome::from_io_result(result, "Windows direct dd write")
+        }
+        WriteMode::Windows { bypass, .. } => {
+            send(WorkerMessage::Log("Starting Windows dual-partition write...".into()));
+            if let Some(flags) = bypass {
+                send(WorkerMessage::Log(format!(
+                    "Bypass options selected: TPM={} SecureBoot={} RAM={}",
+                    flags.contains(crate::windows::unattend::UnattendFlags::BYPASS_TPM),
+                    flags.contains(crate::windows::unattend::UnattendFlags::BYPASS_SECURE_BOOT),
+                    flags.contains(crate::windows::unattend::UnattendFlags::BYPASS_RAM)
+                )));
+            }
+            send(WorkerMessage::Status("Creating partitions...".into()));
+            let options = job.windows_options().unwrap_or_default();
+            let mut warnings = Vec::new();
+            let result = crate::flows::windows_flow::write_windows_iso_to_usb_with_options(
+                &job.iso_path,
+                &job.device,
+                &options,
+                &mut logger,
+                &mut warnings
+            ).map(|metrics| (Some(metrics), warnings));
+            WriteOutcome::from_io_result(result, "Windows dual-partition write")
+        }
+        WriteMode::Linux { .. } | WriteMode::RawImage => {
+            send(WorkerMessage::Log("Starting Linux ISO write...".into()));
+            if job.is_raw_dd() {
+                send(WorkerMessage::Log("Raw write: copying image verbatim using dd...".into()));
+            } else {
+                send(WorkerMessage::Log("Writing image using dd (persistence will follow)...".into()));
+            }
+            send(WorkerMessage::Status("Writing image...".into()));
+            let options = LinuxWriteOptions { pause: pause.cloned(), ..job.linux_options().unwrap_or_default() };
+            let mut persistence_max_mb = None;
+            let result = crate::flows::linux_flow::write_iso_to_usb_with_options(
+                &job.iso_path,
+                &job.device,
+                &options,
+                &mut logger
+            ).map(|report| {
+                persistence_max_mb = report.persistence_max_mb;
+                (None, report.warnings)
+            });
+            if options.persistence.is_some() {
+                send(WorkerMessage::Status("Finalizing persistence...".into()));
+            }
+            let mut outcome = WriteOutcome::from_io_result(result, "Linux ISO write");
+            outcome.persistence_max_mb = persistence_max_mb;
+            outcome
+        }
+        WriteMode::RawPartition { number } => {
+            send(WorkerMessage::Log(format!("Raw write into {} only...", job.target())));
+            send(WorkerMessage::Status("Writing image into partition...".into()));
+            let options = crate::flows::image_copy::CopyOptions { max_speed_mbps: job.max_speed_mbps, pause: pause.cloned() };
+            let result = crate::flows::image_copy::write_image_to_partition(
+                &job.iso_path,
+                &job.device,
+                *number,
+                options,
+                &mut logger
+            ).map(|_| (None, Vec::new()));
+            WriteOutcome::from_io_result(result, "Raw partition write")
+        }
+        WriteMode::RestoreImage => {
+            send(WorkerMessage::Log("Restoring disk image...".into()));
+            send(WorkerMessage::Status("Checking image...".into()));
+            let result = crate::flows::image_copy::restore_image_to_device(
+                &job.iso_path,
+                &job.device,
+                pause,
+                &mut logger
+            ).map(|_| (None, Vec::new()));
+            WriteOutcome::from_io_result(result, "Disk image restore")
+        }
+    }
+}
 
 pub fn run_gui(needs_root: bool, is_flatpak: bool) {
     // Apply user's visual theme settings before creating GUI
@@ -160,6 +919,7 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
                 .build();
             window.set_size_request(770, 400);
             let window_weak = window.downgrade();
+            let settings = std::rc::Rc::new(std::cell::RefCell::new(Settings::load()));
 
             // Main vertical box
             let vbox = gui_widgets::create_main_container();
@@ -168,18 +928,30 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
             let (iso_hbox, iso_entry, iso_button) = gui_widgets::create_iso_selection_widget();
             vbox.append(&iso_hbox);
 
+            // Optional published checksum, verified before writing
+            let (checksum_hbox, checksum_entry, size_entry) = gui_widgets::create_checksum_widget();
+            vbox.append(&checksum_hbox);
+
             // --- OS label (for detection) ---
             let os_label = gui_widgets::create_os_label();
             vbox.append(&os_label);
 
+            // --- Override for the detected OS type ---
+            let (os_override_hbox, os_override_combo) = gui_widgets::create_os_override_widget();
+            vbox.append(&os_override_hbox);
+
             // Separator
             let sep1 = gui_widgets::create_separator();
             vbox.append(&sep1);
 
             // USB device selection (inline label, increased height)
-            let (device_hbox, device_combo, refresh_button) = gui_widgets::create_device_selection_widget();
+            let (device_hbox, device_combo, refresh_button, scan_button) = gui_widgets::create_device_selection_widget();
             vbox.append(&device_hbox);
 
+            // Optional name stored on the media
+            let (media_label_hbox, media_name_entry, media_description_entry) = gui_widgets::create_media_label_widget();
+            vbox.append(&media_label_hbox);
+
             // Separator
             let sep2 = gtk4::Separator::new(Orientation::Horizontal);
             sep2.set_halign(gtk4::Align::Center);
@@ -188,20 +960,55 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
             vbox.append(&sep2);
 
             // --- Windows form group (hidden by default) ---
-            let (windows_group, cluster_combo, dd_checkbox, bypass_tpm_cb, bypass_secure_boot_cb, bypass_ram_cb) = gui_widgets::create_windows_advanced_options();
+            let (windows_group, cluster_combo, fat32_cluster_combo, dd_checkbox, bypass_tpm_cb, bypass_secure_boot_cb, bypass_ram_cb) = gui_widgets::create_windows_advanced_options();
+            cluster_combo.set_active(Some(gui_widgets::cluster_size_index(settings.borrow().default_cluster_bytes)));
+            let (oem_folder_box, oem_folder_entry, oem_folder_button) = gui_widgets::create_oem_folder_picker();
+            windows_group.append(&oem_folder_box);
+            let (efi_tool_box, efi_tool_entry, efi_tool_button) = gui_widgets::create_efi_tool_picker();
+            windows_group.append(&efi_tool_box);
+            let (esp_layout_box, esp_layout_combo) = gui_widgets::create_esp_layout_combo();
+            windows_group.append(&esp_layout_box);
+            let (exclude_box, exclude_entry) = gui_widgets::create_exclude_entry();
+            windows_group.append(&exclude_box);
+            let boot_test_checkbox = gui_widgets::create_boot_test_checkbox();
+            windows_group.append(&boot_test_checkbox);
+            let legacy_checkbox = gui_widgets::create_legacy_checkbox();
+            windows_group.append(&legacy_checkbox);
+            let (unattend_update_row, unattend_update_button, unattend_boot_wim_checkbox) = gui_widgets::create_unattend_update_row();
+            windows_group.append(&unattend_update_row);
             vbox.append(&windows_group);
 
             // --- Linux form group (hidden by default) ---
-            let (linux_group, persistence_checkbox, table_type_combo) = gui_widgets::create_linux_advanced_options();
-            persistence_checkbox.set_active(false);
+            let (linux_group, raw_dd_checkbox, persistence_checkbox, table_type_combo, placement_combo, kernel_params_entry, fix_pmbr_checkbox, max_speed_entry) = gui_widgets::create_linux_advanced_options();
+            let (answer_file_box, answer_file_label, answer_file_entry, answer_file_button) = gui_widgets::create_answer_file_picker();
+            linux_group.append(&answer_file_box);
+            let repartition_checkbox = gui_widgets::create_repartition_checkbox();
+            linux_group.insert_child_after(&repartition_checkbox, Some(&persistence_checkbox));
+            let (data_partition_row, data_partition_checkbox, data_size_entry, data_fs_combo) = gui_widgets::create_data_partition_options();
+            linux_group.insert_child_after(&data_partition_row, Some(&repartition_checkbox));
+            let answer_file_picker = AnswerFilePicker {
+                container: answer_file_box,
+                label: answer_file_label,
+                entry: answer_file_entry,
+                family: std::rc::Rc::new(std::cell::Cell::new(None)),
+            };
+            // Free regions offered in the placement combo (index 0 is "after last partition")
+            let placement_regions: std::rc::Rc<std::cell::RefCell<Vec<crate::utils::FreeRegion>>> = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
             vbox.append(&linux_group);
 
+            // --- Raw write target (hidden by default) ---
+            let (raw_target_group, raw_target_combo) = gui_widgets::create_raw_target_options();
+            let raw_targets: RawTargets = std::rc::Rc::new(std::cell::RefCell::new((String::new(), Vec::new())));
+            vbox.append(&raw_target_group);
+
             // Write and Advanced options buttons (side by side, centered)
-            let (button_hbox, write_button, advanced_button) = gui_widgets::create_button_container();
+            let (button_hbox, write_button, multi_write_button, advanced_button, copy_command_button, clone_button, preferences_button) = gui_widgets::create_button_container();
             vbox.append(&button_hbox);
 
             // Move OS label below the buttons
             vbox.append(&os_label);
+            let (detect_root_row, detect_root_button, detect_root_spinner) = gui_widgets::create_detect_root_row();
+            vbox.append(&detect_root_row);
 
             // Log area
             let (log_label, log_view, log_scroll) = gui_widgets::create_log_area();
@@ -211,135 +1018,459 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
             // Add a progress bar below the log area
             let progress_bar = gui_widgets::create_progress_bar();
             vbox.append(&progress_bar);
+            // Pause/Resume for writes done by the Rust copy loop; one write runs at a time
+            let pause_button = gui_widgets::create_pause_button();
+            vbox.append(&pause_button);
+            let pause_control = PauseControl::new();
+            pause_button.connect_clicked({
+                let pause_control = pause_control.clone();
+                move |button| {
+                    if pause_control.is_paused() {
+                        pause_control.resume();
+                        button.set_label("_Pause");
+                    } else {
+                        pause_control.pause();
+                        button.set_label("_Resume");
+                    }
+                }
+            });
+            // One progress row per device when writing several devices at once
+            let multi_progress_area = gui_widgets::create_multi_progress_area();
+            vbox.append(&multi_progress_area);
+
+            let device_actions = DeviceActions {
+                controls: vec![
+                    write_button.clone().upcast(),
+                    multi_write_button.clone().upcast(),
+                    clone_button.clone().upcast(),
+                    advanced_button.clone().upcast(),
+                    preferences_button.clone().upcast(),
+                    refresh_button.clone().upcast(),
+                    scan_button.clone().upcast(),
+                    device_combo.clone().upcast(),
+                    iso_entry.clone().upcast(),
+                    iso_button.clone().upcast(),
+                    unattend_update_button.clone().upcast(),
+                ],
+                busy: std::rc::Rc::default(),
+            };
+
+            // Closing the window mid-write would end the app with its tools still writing to the
+            // device; ask first, and when cancelling, wait for the worker to unwind before quitting
+            {
+                let device_actions = device_actions.clone();
+                let pause_control = pause_control.clone();
+                let progress_bar = progress_bar.clone();
+                let app = app.clone();
+                window.connect_close_request(move |window| {
+                    if !device_actions.is_busy() {
+                        return gtk4::glib::Propagation::Proceed;
+                    }
+                    if QUIT_REQUESTED.load(Ordering::Relaxed) || !gui_dialogs::show_quit_during_write_dialog(window) {
+                        return gtk4::glib::Propagation::Stop;
+                    }
+                    QUIT_REQUESTED.store(true, Ordering::Relaxed);
+                    // A paused copy would never reach its next log line
+                    pause_control.resume();
+                    progress_bar.set_text(Some("Cancelling..."));
+                    // Once only: the cleanup the flow runs on its way out spawns tools too
+                    let stopped = crate::utils::terminate_child_processes();
+                    println!("[DEBUG] Quit during device work: stopped {} helper processes", stopped);
+                    let started = std::time::Instant::now();
+                    let device_actions = device_actions.clone();
+                    let app = app.clone();
+                    glib::timeout_add_local(std::time::Duration::from_millis(200), move || {
+                        if device_actions.is_busy() && started.elapsed() < QUIT_WAIT {
+                            return glib::ControlFlow::Continue;
+                        }
+                        app.quit();
+                        glib::ControlFlow::Break
+                    });
+                    gtk4::glib::Propagation::Stop
+                });
+            }
 
             // --- Advanced options logic with toggle (refactored, reusable reset) ---
             let adv_open = std::rc::Rc::new(std::cell::Cell::new(false));
+            // Last OS detection result for the selected ISO (Some(true) = Windows)
+            let last_detection: std::rc::Rc<std::cell::Cell<Option<bool>>> = std::rc::Rc::new(std::cell::Cell::new(None));
             let advanced_button_ref = std::rc::Rc::new(advanced_button.clone());
             // Extract reusable reset/close logic for advanced options
             let reset_advanced_options = {
                 let windows_group = windows_group.clone();
                 let linux_group = linux_group.clone();
                 let cluster_combo = cluster_combo.clone();
+                let fat32_cluster_combo = fat32_cluster_combo.clone();
+                let oem_folder_entry = oem_folder_entry.clone();
+                let efi_tool_entry = efi_tool_entry.clone();
+                let esp_layout_combo = esp_layout_combo.clone();
+                let exclude_entry = exclude_entry.clone();
+                let boot_test_checkbox = boot_test_checkbox.clone();
+                let legacy_checkbox = legacy_checkbox.clone();
                 let dd_checkbox = dd_checkbox.clone();
                 let bypass_tpm_cb = bypass_tpm_cb.clone();
                 let bypass_secure_boot_cb = bypass_secure_boot_cb.clone();
                 let bypass_ram_cb = bypass_ram_cb.clone();
-                let persistence_checkbox = persistence_checkbox.clone();
+                let raw_dd_checkbox = raw_dd_checkbox.clone();
+                let fix_pmbr_checkbox = fix_pmbr_checkbox.clone();
+                let repartition_checkbox = repartition_checkbox.clone();
+                let data_partition_checkbox = data_partition_checkbox.clone();
+                let data_size_entry = data_size_entry.clone();
+                let data_fs_combo = data_fs_combo.clone();
+                let kernel_params_entry = kernel_params_entry.clone();
+                let max_speed_entry = max_speed_entry.clone();
+                let answer_file_picker = answer_file_picker.clone();
+                let raw_target_group = raw_target_group.clone();
+                let raw_target_combo = raw_target_combo.clone();
+                let raw_targets = raw_targets.clone();
                 let os_label = os_label.clone();
                 let advanced_button_ref = advanced_button_ref.clone();
                 let adv_open = adv_open.clone();
+                let settings = settings.clone();
+                let last_detection = last_detection.clone();
+                let detect_root_row = detect_root_row.clone();
                 move || {
+                    last_detection.set(None);
+                    detect_root_row.set_visible(false);
                     windows_group.set_visible(false);
                     linux_group.set_visible(false);
-                    cluster_combo.set_active(Some(3));
+                    raw_target_group.set_visible(false);
+                    fill_raw_targets(&raw_target_combo, &raw_targets, None);
+                    cluster_combo.set_active(Some(gui_widgets::cluster_size_index(settings.borrow().default_cluster_bytes)));
+                    fat32_cluster_combo.set_active(Some(0));
+                    oem_folder_entry.set_text("");
+                    efi_tool_entry.set_text("");
+                    esp_layout_combo.set_active(Some(0));
+                    exclude_entry.set_text("");
+                    boot_test_checkbox.set_active(false);
+                    legacy_checkbox.set_active(false);
                     dd_checkbox.set_active(false);
-                    bypass_tpm_cb.set_active(false);
-                    bypass_secure_boot_cb.set_active(false);
-                    bypass_ram_cb.set_active(false);
-                    persistence_checkbox.set_active(false);
+                    for bypass in [&bypass_tpm_cb, &bypass_secure_boot_cb, &bypass_ram_cb] {
+                        bypass.set_active(false);
+                        bypass.set_sensitive(true);
+                    }
+                    raw_dd_checkbox.set_active(true);
+                    fix_pmbr_checkbox.set_active(false);
+                    repartition_checkbox.set_active(false);
+                    data_partition_checkbox.set_active(false);
+                    data_size_entry.set_text("");
+                    data_fs_combo.set_active(Some(0));
+                    kernel_params_entry.set_text("");
+                    max_speed_entry.set_text("");
+                    answer_file_picker.reset();
                     os_label.set_text("");
-                    advanced_button_ref.set_label("Advanced options");
+                    advanced_button_ref.set_label("_Advanced options");
                     adv_open.set(false);
                 }
             };
 
-            // --- Advanced options button handler ---
+            // $OEM$ folder picker
+            {
+                let oem_folder_entry = oem_folder_entry.clone();
+                let window = window.clone();
+                oem_folder_button.connect_clicked(move |_| {
+                    if let Some(path) = gui_dialogs::show_oem_folder_dialog(&window) {
+                        oem_folder_entry.set_text(&path);
+                    }
+                });
+            }
+
+            // EFI tool picker
+            {
+                let efi_tool_entry = efi_tool_entry.clone();
+                let window = window.clone();
+                efi_tool_button.connect_clicked(move |_| {
+                    if let Some(path) = gui_dialogs::show_efi_tool_dialog(&window) {
+                        efi_tool_entry.set_text(&path);
+                    }
+                });
+            }
+
+            // Kickstart/preseed file picker
+            {
+                let answer_file_picker = answer_file_picker.clone();
+                let window = window.clone();
+                answer_file_button.connect_clicked(move |_| {
+                    let kind = answer_file_picker.kind().unwrap_or("Answer");
+                    if let Some(path) = gui_dialogs::show_answer_file_dialog(&window, kind) {
+                        answer_file_picker.entry.set_text(&path);
+                    }
+                });
+            }
+
+            // The table combo also picks the new table; the ISO's protective MBR is gone with it
+            {
+                let table_type_combo = table_type_combo.clone();
+                let persistence_checkbox = persistence_checkbox.clone();
+                let fix_pmbr_checkbox = fix_pmbr_checkbox.clone();
+                repartition_checkbox.connect_toggled(move |cb| {
+                    table_type_combo.set_sensitive(cb.is_active() || persistence_checkbox.is_active());
+                    fix_pmbr_checkbox.set_sensitive(!cb.is_active());
+                });
+            }
+            {
+                let table_type_combo = table_type_combo.clone();
+                let repartition_checkbox = repartition_checkbox.clone();
+                persistence_checkbox.connect_toggled(move |cb| {
+                    table_type_combo.set_sensitive(cb.is_active() || repartition_checkbox.is_active());
+                });
+            }
+
+            // Offer the free regions left by the ISO layout when persistence is switched on
+            {
+                let iso_entry = iso_entry.clone();
+                let device_combo = device_combo.clone();
+                let placement_combo = placement_combo.clone();
+                let placement_regions = placement_regions.clone();
+                persistence_checkbox.connect_toggled(move |cb| {
+                    placement_combo.remove_all();
+                    placement_combo.append_text("After last partition (default)");
+                    placement_combo.set_active(Some(0));
+                    placement_regions.borrow_mut().clear();
+                    if !cb.is_active() {
+                        return;
+                    }
+                    let iso_path = iso_entry.text().to_string();
+                    let Ok(device_path) = selected_device_path(&device_combo) else {
+                        return;
+                    };
+                    if iso_path.is_empty() {
+                        return;
+                    }
+                    match linux_persistence::list_persistence_regions(&iso_path, &device_path) {
+                        Ok(regions) => {
+                            for region in &regions {
+                                placement_combo.append_text(&format!(
+                                    "Free region {}s-{}s ({} MB)",
+                                    region.start_sector, region.end_sector, region.size_mb()
+                                ));
+                            }
+                            *placement_regions.borrow_mut() = regions;
+                        }
+                        Err(e) => println!("[DEBUG] Could not list persistence regions: {}", e),
+                    }
+                });
+            }
+
+            // A different ISO path invalidates the cached detection result
             {
-                let is_elevating = std::rc::Rc::new(std::cell::Cell::new(false));
+                let last_detection = last_detection.clone();
+                iso_entry.connect_changed(move |_| last_detection.set(None));
+            }
+
+            // Pick up a checksum file published next to the ISO, without replacing one the user typed
+            {
+                let checksum_entry = checksum_entry.clone();
+                let log_view = log_view.clone();
+                let filled: std::rc::Rc<std::cell::RefCell<String>> = std::rc::Rc::default();
+                iso_entry.connect_changed(move |entry| {
+                    let current = checksum_entry.text();
+                    if !current.trim().is_empty() && current.as_str() != filled.borrow().as_str() {
+                        return;
+                    }
+                    let path = entry.text();
+                    let hash = match crate::checksum::find_sidecar_checksum(std::path::Path::new(path.as_str())) {
+                        Some(crate::checksum::SidecarChecksum::Sha256 { file, hash }) => {
+                            append_log(&log_view, &format!("Found {}; the ISO will be verified against it before writing", file.display()));
+                            hash
+                        }
+                        Some(crate::checksum::SidecarChecksum::Md5 { file }) => {
+                            append_log(&log_view, &format!("Found {}, but only SHA-256 checksums can be verified", file.display()));
+                            String::new()
+                        }
+                        None => String::new(),
+                    };
+                    if hash != current.as_str() {
+                        checksum_entry.set_text(&hash);
+                    }
+                    *filled.borrow_mut() = hash;
+                });
+            }
+
+            // Changing the override invalidates whichever options group is open
+            {
+                let reset_advanced_options = reset_advanced_options.clone();
+                os_override_combo.connect_changed(move |_| reset_advanced_options());
+            }
+
+            // Show the options group for a detected type
+            let show_detection: ShowDetection = {
                 let adv_open = adv_open.clone();
                 let advanced_button_ref = advanced_button_ref.clone();
-                let iso_entry = iso_entry.clone();
                 let os_label = os_label.clone();
                 let windows_group = windows_group.clone();
                 let linux_group = linux_group.clone();
                 let bypass_tpm_cb = bypass_tpm_cb.clone();
                 let bypass_secure_boot_cb = bypass_secure_boot_cb.clone();
                 let bypass_ram_cb = bypass_ram_cb.clone();
+                let legacy_checkbox = legacy_checkbox.clone();
+                let last_detection = last_detection.clone();
+                let answer_file_picker = answer_file_picker.clone();
+                std::rc::Rc::new(move |iso_path: &str, iso_type: crate::utils::IsoType, how: &str| {
+                    println!("[DEBUG] [{}:{}] Detected {} ({})", file!(), line!(), iso_type.describe(), how);
+                    let is_windows = iso_type.is_windows();
+                    last_detection.set(Some(is_windows));
+                    if is_windows {
+                        os_label.set_text(&format!("Detected: {} ({})", iso_type.describe(), how));
+                        windows_group.set_visible(true);
+                        linux_group.set_visible(false);
+                    } else {
+                        os_label.set_text(&linux_os_label(&format!("Detected: Linux ISO ({})", how), iso_path));
+                        windows_group.set_visible(false);
+                        linux_group.set_visible(true);
+                        answer_file_picker.update(iso_path);
+                    }
+                    // Advisory only: the detection still decides which options are shown
+                    if let Some(note) = crate::utils::name_mismatch_warning(iso_path, iso_type) {
+                        os_label.set_text(&format!("{}\n⚠ {}", os_label.text(), note));
+                    }
+                    advanced_button_ref.set_label("Close _advanced options");
+                    adv_open.set(true);
+                    // LabConfig is only read by the Windows 11 client setup; Server and Windows 7/8
+                    // setup do not check TPM, Secure Boot or 4 GB of RAM in the first place
+                    let legacy = iso_type == crate::utils::IsoType::Windows(WindowsFlavor::Legacy);
+                    let server = iso_type == crate::utils::IsoType::Windows(WindowsFlavor::Server);
+                    for bypass in [&bypass_tpm_cb, &bypass_secure_boot_cb, &bypass_ram_cb] {
+                        bypass.set_active(false);
+                        bypass.set_sensitive(!server && !legacy);
+                    }
+                    legacy_checkbox.set_active(legacy);
+                })
+            };
+
+            // --- Advanced options button handler ---
+            {
+                let adv_open = adv_open.clone();
+                let iso_entry = iso_entry.clone();
+                let os_label = os_label.clone();
+                let windows_group = windows_group.clone();
+                let linux_group = linux_group.clone();
                 let reset_advanced_options = reset_advanced_options.clone();
-                // Global elevation counter
-                static ELEVATION_COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+                let os_override_combo = os_override_combo.clone();
+                let answer_file_picker = answer_file_picker.clone();
+                let device_combo = device_combo.clone();
+                let raw_target_group = raw_target_group.clone();
+                let raw_target_combo = raw_target_combo.clone();
+                let raw_targets = raw_targets.clone();
+                let advanced_button_ref = advanced_button_ref.clone();
+                let detect_root_row = detect_root_row.clone();
+                let show_detection = show_detection.clone();
                 advanced_button.connect_clicked(move |_| {
-                    println!("[DEBUG] [{}:{}] Advanced options button clicked. adv_open={}, is_elevating={}", file!(), line!(), adv_open.get(), is_elevating.get());
+                    println!("[DEBUG] [{}:{}] Advanced options button clicked. adv_open={}", file!(), line!(), adv_open.get());
                     if adv_open.get() {
                         println!("[DEBUG] [{}:{}] Closing advanced options.", file!(), line!());
                         reset_advanced_options();
                         return;
                     }
-                    if is_elevating.get() {
-                        println!("[DEBUG] [{}:{}] Elevation already in progress, ignoring click.", file!(), line!());
-                        return;
-                    }
                     let iso_path = iso_entry.text();
                     if iso_path.is_empty() {
                         println!("[DEBUG] [{}:{}] No ISO selected, cannot detect OS.", file!(), line!());
                         os_label.set_text("Please select an ISO first.");
                         return;
                     }
-                    println!("[DEBUG] [{}:{}] Attempting user-mount OS detection...", file!(), line!());
-                    let detected = crate::utils::is_windows_iso(&iso_path);
-                    match detected {
-                        Some(true) => {
-                            println!("[DEBUG] [{}:{}] Detected Windows ISO (user-mount)", file!(), line!());
-                            os_label.set_text("Detected: Windows ISO (mounted)");
-                            windows_group.set_visible(true);
-                            linux_group.set_visible(false);
-                            advanced_button_ref.set_label("Close advanced options");
-                            adv_open.set(true);
-                            bypass_tpm_cb.set_active(false);
-                            bypass_secure_boot_cb.set_active(false);
-                            bypass_ram_cb.set_active(false);
-                        },
-                        Some(false) => {
-                            println!("[DEBUG] [{}:{}] Detected Linux ISO (user-mount)", file!(), line!());
-                            os_label.set_text("Detected: Linux ISO (mounted)");
-                            windows_group.set_visible(false);
-                            linux_group.set_visible(true);
-                            advanced_button_ref.set_label("Close advanced options");
-                            adv_open.set(true);
-                            bypass_tpm_cb.set_active(false);
-                            bypass_secure_boot_cb.set_active(false);
-                            bypass_ram_cb.set_active(false);
-                        },
-                        None => {
-                            println!("[DEBUG] [{}:{}] User-mount detection failed, requesting elevation...", file!(), line!());
-                            is_elevating.set(true);
-                            let prev = ELEVATION_COUNT.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
-                            println!("[DEBUG] [{}:{}] is_elevating set to true, calling is_windows_iso... (ELEVATION_COUNT={})", file!(), line!(), prev);
-                            let result = crate::utils::is_windows_iso(&iso_path);
-                            println!("[DEBUG] [{}:{}] is_windows_iso returned: {:?}", file!(), line!(), result);
-                            match result {
-                                Some(true) => {
-                                    println!("[DEBUG] [{}:{}] Detected Windows ISO (root mount)", file!(), line!());
-                                    os_label.set_text("Detected: Windows ISO (root mount)");
-                                    windows_group.set_visible(true);
-                                    linux_group.set_visible(false);
-                                    advanced_button_ref.set_label("Close advanced options");
-                                    adv_open.set(true);
-                                    bypass_tpm_cb.set_active(false);
-                                    bypass_secure_boot_cb.set_active(false);
-                                    bypass_ram_cb.set_active(false);
-                                },
-                                Some(false) => {
-                                    println!("[DEBUG] [{}:{}] Detected Linux ISO (root mount)", file!(), line!());
-                                    os_label.set_text("Detected: Linux ISO (root mount)");
-                                    windows_group.set_visible(false);
-                                    linux_group.set_visible(true);
-                                    advanced_button_ref.set_label("Close advanced options");
-                                    adv_open.set(true);
-                                    bypass_tpm_cb.set_active(false);
-                                    bypass_secure_boot_cb.set_active(false);
-                                    bypass_ram_cb.set_active(false);
-                                },
-                                None => {
-                                    println!("[DEBUG] [{}:{}] Could not detect OS type even with root", file!(), line!());
-                                    os_label.set_text("Could not detect OS type (even with root)");
-                                    reset_advanced_options();
-                                },
+                    // A forced type skips detection entirely
+                    let os_override = OsOverride::from_index(os_override_combo.active().unwrap_or(0));
+                    if let Some(kind) = os_override.resolve(None) {
+                        println!("[DEBUG] [{}:{}] OS type forced by user: {:?}", file!(), line!(), kind);
+                        match kind {
+                            ImageKind::Windows => {
+                                os_label.set_text("Forced: Windows ISO");
+                                windows_group.set_visible(true);
+                                linux_group.set_visible(false);
+                            }
+                            ImageKind::Linux => {
+                                os_label.set_text(&linux_os_label("Forced: Linux ISO", &iso_path));
+                                windows_group.set_visible(false);
+                                linux_group.set_visible(true);
+                                answer_file_picker.update(&iso_path);
+                            }
+                            ImageKind::Raw => {
+                                os_label.set_text("Forced: raw dd write");
+                                windows_group.set_visible(false);
+                                linux_group.set_visible(false);
+                                let device = selected_device_path(&device_combo).ok();
+                                fill_raw_targets(&raw_target_combo, &raw_targets, device.as_deref());
+                                raw_target_group.set_visible(true);
+                            }
+                            ImageKind::DiskImage => {
+                                os_label.set_text("Forced: restore disk image (no advanced options)");
+                                return;
                             }
-                            is_elevating.set(false);
-                            println!("[DEBUG] [{}:{}] is_elevating set to false after elevation attempt", file!(), line!());
                         }
+                        advanced_button_ref.set_label("Close _advanced options");
+                        adv_open.set(true);
+                        return;
+                    }
+                    if is_disk_image(&iso_path) {
+                        os_label.set_text("Disk image: restored verbatim (no advanced options)");
+                        return;
+                    }
+                    // A permission problem would otherwise surface as "could not detect" after a useless elevation
+                    if let Err(e) = crate::utils::check_iso_readable(&iso_path) {
+                        println!("[DEBUG] [{}:{}] {}", file!(), line!(), e);
+                        os_label.set_text(&format!("Cannot use this file: {}", e));
+                        reset_advanced_options();
+                        return;
+                    }
+                    match crate::utils::detect_iso_type(&iso_path) {
+                        Some(iso_type) => show_detection(&iso_path, iso_type, "mounted"),
+                        None => {
+                            // Mounting needs rights this process may lack; root detection is the user's call
+                            println!("[DEBUG] [{}:{}] Detection failed without elevation", file!(), line!());
+                            reset_advanced_options();
+                            os_label.set_text("Could not detect OS type; try \"Detect (with root)\" or choose it under Image type");
+                            detect_root_row.set_visible(true);
+                        }
+                    }
+                });
+            }
+
+            // --- Detect the ISO type as root, on request ---
+            {
+                let iso_entry = iso_entry.clone();
+                let os_label = os_label.clone();
+                let detect_root_row = detect_root_row.clone();
+                let detect_root_spinner = detect_root_spinner.clone();
+                let show_detection = show_detection.clone();
+                detect_root_button.connect_clicked(move |button| {
+                    let iso_path = iso_entry.text().to_string();
+                    if iso_path.is_empty() {
+                        return;
+                    }
+                    button.set_sensitive(false);
+                    detect_root_spinner.start();
+                    os_label.set_text("Detecting with root rights...");
+                    let (sender, receiver) = glib::MainContext::channel(Priority::default());
+                    {
+                        let iso_path = iso_path.clone();
+                        std::thread::spawn(move || {
+                            let _ = sender.send(crate::utils::detect_iso_type_elevated(&iso_path));
+                        });
                     }
+                    let button = button.clone();
+                    let os_label = os_label.clone();
+                    let detect_root_row = detect_root_row.clone();
+                    let detect_root_spinner = detect_root_spinner.clone();
+                    let show_detection = show_detection.clone();
+                    receiver.attach(None, move |result| {
+                        button.set_sensitive(true);
+                        detect_root_spinner.stop();
+                        match result {
+                            Ok(Some(iso_type)) => {
+                                detect_root_row.set_visible(false);
+                                show_detection(&iso_path, iso_type, "root mount");
+                            }
+                            Ok(None) => {
+                                detect_root_row.set_visible(false);
+                                os_label.set_text("Could not detect OS type (even with root); choose it under Image type");
+                            }
+                            Err(e) => {
+                                println!("[DEBUG] [{}:{}] Elevated detection failed: {}", file!(), line!(), e);
+                                os_label.set_text(&format!("Detection with root failed ({}); choose the type under Image type", e));
+                            }
+                        }
+                        glib::ControlFlow::Break
+                    });
                 });
             }
 
@@ -364,214 +1495,455 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
             // --- USB device refresh functionality ---
             {
                 let device_combo = device_combo.clone();
+                let iso_entry = iso_entry.clone();
                 refresh_button.connect_clicked(move |_| {
                     println!("[DEBUG] Refreshing USB device list...");
-                    device_combo.remove_all();
+                    gui_widgets::clear_devices(&device_combo);
 
                     let devices = crate::utils::list_usb_devices();
                     let device_count = devices.len();
+                    // Flag the drive holding the selected ISO so it is not picked as the target
+                    let source_disks = crate::utils::backing_disks(&iso_entry.text());
+                    let live_disks = crate::utils::live_system_disks();
                     if devices.is_empty() {
-                        device_combo.append_text("(No USB devices found)");
+                        gui_widgets::append_device(&device_combo, None, "(No USB devices found)", None);
                         device_combo.set_active(Some(0));
                     } else {
-                        for (path, description) in devices {
-                            device_combo.append_text(&format!("{} - {}", path, description));
+                        let mut first_safe = None;
+                        for (index, (path, description)) in devices.into_iter().enumerate() {
+                            let mark = if source_disks.contains(&path) {
+                                Some(SOURCE_DEVICE_MARK)
+                            } else if live_disks.contains(&path) {
+                                Some(LIVE_SYSTEM_MARK)
+                            } else {
+                                first_safe.get_or_insert(index as u32);
+                                // Point out USB 3 sticks stuck on a USB 2 link before the user blames the write speed
+                                crate::utils::usb_link_info(&path)
+                                    .is_some_and(|link| link.is_degraded())
+                                    .then_some(SLOW_LINK_MARK)
+                            };
+                            let label = match mark {
+                                Some(mark) => format!("{} - {} {}", path, description, mark),
+                                None => format!("{} - {}", path, description),
+                            };
+                            // The path is the entry's id: selection never depends on parsing the label
+                            let risk = crate::utils::device_risk(&path);
+                            gui_widgets::append_device(&device_combo, Some(&path), &label, Some(risk));
                         }
-                        device_combo.set_active(Some(0));
+                        device_combo.set_active(Some(first_safe.unwrap_or(0)));
                     }
                     println!("[DEBUG] Found {} USB devices", device_count);
                 });
             }
 
-            // --- Write button functionality ---
+            // --- Scan the selected device for media written earlier ---
             {
-                let write_button = write_button.clone();
-                let iso_entry = iso_entry.clone();
                 let device_combo = device_combo.clone();
-                let windows_group = windows_group.clone();
-                let linux_group = linux_group.clone();
-                let cluster_combo = cluster_combo.clone();
-                let persistence_checkbox = persistence_checkbox.clone();
                 let log_view = log_view.clone();
-                let progress_bar = progress_bar.clone();
-                let window_for_dialog = window.clone();
-
-                write_button.clone().connect_clicked(move |_| {
-                    let iso_path = iso_entry.text().to_string();
-                    if iso_path.is_empty() {
-                        let buffer = log_view.buffer();
-                        buffer.set_text("ERROR: No ISO file selected\n");
-                        return;
+                scan_button.connect_clicked(move |scan_button| {
+                    let device_path = match selected_device_path(&device_combo) {
+                        Ok(path) => path,
+                        Err(msg) => {
+                            log_view.buffer().set_text(&format!("ERROR: {}\n", msg));
+                            return;
+                        }
+                    };
+                    log_view.buffer().set_text(&format!("=== Scanning {} ===\n", device_path));
+                    scan_button.set_sensitive(false);
+                    let (sender, receiver) = glib::MainContext::channel(Priority::default());
+                    {
+                        let log_view = log_view.clone();
+                        let scan_button = scan_button.clone();
+                        receiver.attach(None, move |result: io::Result<MediaReport>| {
+                            scan_button.set_sensitive(true);
+                            match result {
+                                Ok(report) => {
+                                    append_log(&log_view, &report.description);
+                                    if !report.editions.is_empty() {
+                                        append_log(&log_view, &format!("Editions: {}", report.editions.join(", ")));
+                                    }
+                                    for part in &report.partitions {
+                                        append_log(&log_view, &format!(
+                                            "  {} {} MB {} {}",
+                                            part.path,
+                                            part.size_bytes / 1_000_000,
+                                            part.fstype.as_deref().unwrap_or("-"),
+                                            part.label.as_deref().unwrap_or(""),
+                                        ));
+                                    }
+                                }
+                                Err(e) => append_log(&log_view, &format!("✗ Scan failed: {}", e)),
+                            }
+                            glib::ControlFlow::Break
+                        });
                     }
+                    std::thread::spawn(move || {
+                        let _ = sender.send(crate::flows::media_scan::scan_device(&device_path));
+                    });
+                });
+            }
 
-                    let active_device = device_combo.active_text().unwrap_or_default();
-                    if active_device.is_empty() || active_device.contains("(refresh to list devices)") || active_device.contains("(No USB devices found)") {
-                        let buffer = log_view.buffer();
-                        buffer.set_text("ERROR: No USB device selected or no devices found\n");
-                        return;
+            // --- Change the bypass answer file on a stick written earlier ---
+            {
+                let device_combo = device_combo.clone();
+                let log_view = log_view.clone();
+                let bypass_tpm_cb = bypass_tpm_cb.clone();
+                let bypass_secure_boot_cb = bypass_secure_boot_cb.clone();
+                let bypass_ram_cb = bypass_ram_cb.clone();
+                unattend_update_button.connect_clicked(move |update_button| {
+                    let device_path = match selected_device_path(&device_combo) {
+                        Ok(path) => path,
+                        Err(msg) => {
+                            log_view.buffer().set_text(&format!("ERROR: {}\n", msg));
+                            return;
+                        }
+                    };
+                    let mut flags = crate::windows::unattend::UnattendFlags::empty();
+                    for (checkbox, flag) in [
+                        (&bypass_tpm_cb, crate::windows::unattend::UnattendFlags::BYPASS_TPM),
+                        (&bypass_secure_boot_cb, crate::windows::unattend::UnattendFlags::BYPASS_SECURE_BOOT),
+                        (&bypass_ram_cb, crate::windows::unattend::UnattendFlags::BYPASS_RAM),
+                    ] {
+                        if checkbox.is_active() {
+                            flags |= flag;
+                        }
                     }
-
-                    // Extract device path (before " - " separator)
-                    let device_path = active_device.split(" - ").next().unwrap_or("").trim();
-                    let device_path = device_path.to_string(); // Create owned copy
-                    if device_path.is_empty() {
-                        let buffer = log_view.buffer();
-                        buffer.set_text("ERROR: Could not parse device path\n");
-                        return;
+                    let inject_boot_wim = unattend_boot_wim_checkbox.is_active();
+                    log_view.buffer().set_text(&format!("=== Updating the answer file on {} ===\n", device_path));
+                    update_button.set_sensitive(false);
+                    let (sender, receiver) = glib::MainContext::channel(Priority::default());
+                    {
+                        let log_view = log_view.clone();
+                        let update_button = update_button.clone();
+                        receiver.attach(None, move |(log, result): (String, io::Result<String>)| {
+                            update_button.set_sensitive(true);
+                            append_log(&log_view, &log);
+                            match result {
+                                Ok(partition) => append_log(&log_view, &format!("✓ Answer file updated on {}", partition)),
+                                Err(e) => append_log(&log_view, &format!("✗ Update failed: {}", e)),
+                            }
+                            glib::ControlFlow::Break
+                        });
                     }
+                    std::thread::spawn(move || {
+                        let mut log = Vec::new();
+                        let result = crate::flows::windows_flow::update_unattend_on_usb(&device_path, flags, inject_boot_wim, &mut log);
+                        let _ = sender.send((String::from_utf8_lossy(&log).into_owned(), result));
+                    });
+                });
+            }
 
-                    println!("[DEBUG] Starting USB write: ISO={}, Device={}", iso_path, device_path);
-
-                    // Update UI for write operation
-                    write_button.set_sensitive(false);
+            // --- Preferences dialog (changes are applied live where possible) ---
+            {
+                let window_weak = window_weak.clone();
+                let settings = settings.clone();
+                let cluster_combo = cluster_combo.clone();
+                let windows_group = windows_group.clone();
+                preferences_button.connect_clicked(move |_| {
+                    if let Some(window) = window_weak.upgrade() {
+                        let settings_apply = settings.clone();
+                        let cluster_combo = cluster_combo.clone();
+                        let windows_group = windows_group.clone();
+                        let current = settings.borrow().clone();
+                        gui_dialogs::show_preferences_dialog(&window, &current, move |updated| {
+                            *settings_apply.borrow_mut() = updated.clone();
+                            // Only adopt the new default when the user is not editing Windows options
+                            if !windows_group.is_visible() {
+                                cluster_combo.set_active(Some(gui_widgets::cluster_size_index(updated.default_cluster_bytes)));
+                            }
+                            println!("[SETTINGS] Preferences saved to {}", Settings::path().display());
+                        });
+                    }
+                });
+            }
 
-                    // Configure infinite progress bar
-                    progress_bar.set_fraction(0.0);
-                    progress_bar.set_show_text(true);
-                    progress_bar.set_text(Some("Preparing to write..."));
-                    progress_bar.set_pulse_step(0.1);
-                    progress_bar.set_visible(true);
+            // Everything that defines a write job, shared by the write and copy-command buttons
+            let form = JobForm {
+                iso_entry: iso_entry.clone(),
+                device_combo: device_combo.clone(),
+                checksum_entry: checksum_entry.clone(),
+                size_entry: size_entry.clone(),
+                media_name_entry: media_name_entry.clone(),
+                media_description_entry: media_description_entry.clone(),
+                os_override_combo: os_override_combo.clone(),
+                windows_group: windows_group.clone(),
+                linux_group: linux_group.clone(),
+                cluster_combo: cluster_combo.clone(),
+                fat32_cluster_combo: fat32_cluster_combo.clone(),
+                oem_folder_entry: oem_folder_entry.clone(),
+                efi_tool_entry: efi_tool_entry.clone(),
+                esp_layout_combo: esp_layout_combo.clone(),
+                exclude_entry: exclude_entry.clone(),
+                boot_test_checkbox: boot_test_checkbox.clone(),
+                legacy_checkbox: legacy_checkbox.clone(),
+                dd_checkbox: dd_checkbox.clone(),
+                bypass_tpm_cb: bypass_tpm_cb.clone(),
+                bypass_secure_boot_cb: bypass_secure_boot_cb.clone(),
+                bypass_ram_cb: bypass_ram_cb.clone(),
+                raw_dd_checkbox: raw_dd_checkbox.clone(),
+                persistence_checkbox: persistence_checkbox.clone(),
+                table_type_combo: table_type_combo.clone(),
+                placement_combo: placement_combo.clone(),
+                placement_regions: placement_regions.clone(),
+                kernel_params_entry: kernel_params_entry.clone(),
+                fix_pmbr_checkbox: fix_pmbr_checkbox.clone(),
+                repartition_checkbox: repartition_checkbox.clone(),
+                data_partition_checkbox: data_partition_checkbox.clone(),
+                data_size_entry: data_size_entry.clone(),
+                data_fs_combo: data_fs_combo.clone(),
+                max_speed_entry: max_speed_entry.clone(),
+                answer_file_picker: answer_file_picker.clone(),
+                raw_target_group: raw_target_group.clone(),
+                raw_target_combo: raw_target_combo.clone(),
+                raw_targets: raw_targets.clone(),
+                last_detection: last_detection.clone(),
+                settings: settings.clone(),
+            };
 
+            // --- Copy the current configuration as a cli_helper command ---
+            {
+                let form = form.clone();
+                let log_view = log_view.clone();
+                let settings = settings.clone();
+                copy_command_button.connect_clicked(move |_| {
                     let buffer = log_view.buffer();
-                    let mut log_text = format!("Starting write operation:\n");
-                    log_text.push_str(&format!("  ISO: {}\n", iso_path));
-                    log_text.push_str(&format!("  Device: {}\n", device_path));
-
-                    let mut persistence_config: Option<PersistenceConfig> = None;
+                    match form.build_job() {
+                        Ok(FormJob { job, .. }) => {
+                            let command = format!(
+                                "{} {}",
+                                settings.borrow().elevation_method.program(),
+                                job.to_cli_command()
+                            );
+                            if let Some(display) = gtk4::gdk::Display::default() {
+                                display.clipboard().set_text(&command);
+                            }
+                            buffer.set_text(&format!("Copied to clipboard:\n  {}\n", command));
+                        }
+                        Err(msg) => buffer.set_text(&format!("ERROR: {}\n", msg)),
+                    }
+                });
+            }
 
-                    // Determine write mode and options
-                    // Prefer explicit detection over UI visibility to avoid falling back to Linux when the Windows group is hidden.
-                    let detected_windows = crate::utils::is_windows_iso(&iso_path).unwrap_or(false);
-                    let is_windows_mode = if windows_group.is_visible() {
-                        true
-                    } else {
-                        detected_windows
+            // --- Save the selected stick as an image ---
+            {
+                let device_combo = device_combo.clone();
+                let log_view = log_view.clone();
+                let progress_bar = progress_bar.clone();
+                let device_actions = device_actions.clone();
+                let window = window.clone();
+                clone_button.connect_clicked(move |_| {
+                    let device_path = match selected_device_path(&device_combo) {
+                        Ok(path) => path,
+                        Err(msg) => {
+                            log_view.buffer().set_text(&format!("ERROR: {}\n", msg));
+                            return;
+                        }
                     };
-
-                    let use_dd_mode = if is_windows_mode {
-                        dd_checkbox.is_active()
-                    } else {
-                        false
+                    let Some((dest, trim)) = gui_dialogs::show_image_save_dialog(&window, &device_path) else {
+                        return;
                     };
+                    // Saving onto the stick being read would corrupt the image
+                    let dest_dir = std::path::Path::new(&dest).parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+                    if crate::utils::backing_disks(&dest_dir).contains(&device_path) {
+                        log_view.buffer().set_text(&format!(
+                            "ERROR: {} is stored on {}; choose a location on another drive.\n",
+                            dest, device_path
+                        ));
+                        return;
+                    }
+                    let compress = dest.ends_with(".gz");
+                    log_view.buffer().set_text("=== Saving USB stick as image ===\n");
+                    device_actions.set_busy(true);
+                    progress_bar.set_fraction(0.0);
+                    progress_bar.set_show_text(true);
+                    progress_bar.set_text(Some("Reading device..."));
+                    progress_bar.set_visible(true);
 
-                    let bypass_tpm = if is_windows_mode { bypass_tpm_cb.is_active() } else { false };
-                    let bypass_secure_boot = if is_windows_mode { bypass_secure_boot_cb.is_active() } else { false };
-                    let bypass_ram = if is_windows_mode { bypass_ram_cb.is_active() } else { false };
-
-                    if is_windows_mode {
-                        let cluster_idx = cluster_combo.active().unwrap_or(3) as usize;
-                        let cluster_sizes = [512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
-                        let cluster_size = *cluster_sizes.get(cluster_idx).unwrap_or(&4096);
-                        let mode_label = if use_dd_mode { "Windows (direct dd mode)" } else { "Windows" };
-                        log_text.push_str(&format!("  Mode: {} (cluster size: {} bytes)\n", mode_label, cluster_size));
-                        if bypass_tpm || bypass_secure_boot || bypass_ram {
-                            log_text.push_str(&format!(
-                                "  Bypass options: TPM={} SecureBoot={} RAM={}\n",
-                                bypass_tpm, bypass_secure_boot, bypass_ram
-                            ));
-                        }
-                    } else if detected_windows {
-                        // Windows detected but advanced panel not open; use default cluster size.
-                        log_text.push_str("  Mode: Windows (auto-detected, cluster size: 4096 bytes)\n");
-                    } else if linux_group.is_visible() {
-                        let persistence = persistence_checkbox.is_active();
-                        if persistence {
-                            let table_type = match table_type_combo.active().unwrap_or(0) {
-                                1 => PartitionTableType::Mbr,
-                                _ => PartitionTableType::Gpt,
-                            };
-                            let persistence_type = match linux_persistence::detect_persistence_type(&iso_path) {
-                                Ok(kind) => kind,
-                                Err(e) => {
-                                    let msg = format!("ERROR: Could not detect persistence type: {}\n", e);
-                                    buffer.set_text(&msg);
-                                    write_button.set_sensitive(true);
-                                    progress_bar.set_text(Some("Error"));
-                                    return;
+                    let (sender, receiver) = glib::MainContext::channel(Priority::default());
+                    {
+                        let log_view = log_view.clone();
+                        let progress_bar = progress_bar.clone();
+                        let device_actions = device_actions.clone();
+                        receiver.attach(None, move |msg| {
+                            match msg {
+                                WorkerMessage::Log(line) => append_log(&log_view, &line),
+                                WorkerMessage::Status(status) => progress_bar.set_text(Some(&status)),
+                                WorkerMessage::Progress(fraction, label) => {
+                                    progress_bar.set_fraction(fraction);
+                                    progress_bar.set_text(Some(&label));
                                 }
-                            };
-
-                            let recommended_size = match linux_persistence::get_recommended_persistence_size(&iso_path, &device_path) {
-                                Ok(size) => size,
-                                Err(e) => {
-                                    let msg = format!("ERROR: Could not calculate persistence size: {}\n", e);
-                                    buffer.set_text(&msg);
-                                    write_button.set_sensitive(true);
-                                    progress_bar.set_text(Some("Error"));
-                                    return;
+                                WorkerMessage::ChecksumMismatch(..) => {}
+                                WorkerMessage::Done(outcome) => {
+                                    device_actions.set_busy(false);
+                                    match &outcome.error {
+                                        None => {
+                                            progress_bar.set_fraction(1.0);
+                                            append_log(&log_view, "\n✓ Image saved successfully!");
+                                        }
+                                        Some(e) => append_log(&log_view, &format!("\n✗ Saving image failed: {}", e)),
+                                    }
+                                    progress_bar.set_text(Some(&outcome.summary()));
                                 }
-                            };
+                            }
+                            glib::ControlFlow::Continue
+                        });
+                    }
 
-                            let config = PersistenceConfig {
-                                enabled: true,
-                                size_mb: recommended_size,
-                                persistence_type,
-                                label: "persistence".to_string(),
-                                partition_table: table_type,
-                            };
+                    std::thread::spawn(move || {
+                        let mut logger = ChannelWriter { sender: sender.clone() };
+                        let result = crate::flows::image_copy::clone_device_to_image(&device_path, &dest, compress, trim, &mut logger)
+                            .map(|_| (None, Vec::new()));
+                        let _ = sender.send(WorkerMessage::Done(WriteOutcome::from_io_result(result, "Clone to image")));
+                    });
+                });
+            }
 
-                            if let Err(e) = linux_persistence::validate_persistence_config(&config) {
-                                let msg = format!("ERROR: Invalid persistence configuration: {}\n", e);
-                                buffer.set_text(&msg);
-                                write_button.set_sensitive(true);
-                                progress_bar.set_text(Some("Error"));
-                                return;
-                            }
+            // --- Write button functionality ---
+            {
+                let form = form.clone();
+                let settings = settings.clone();
+                let device_actions = device_actions.clone();
+                let iso_entry = iso_entry.clone();
+                let iso_button = iso_button.clone();
+                let log_view = log_view.clone();
+                let progress_bar = progress_bar.clone();
+                let multi_progress_area = multi_progress_area.clone();
+                let pause_button = pause_button.clone();
+                let pause_control = pause_control.clone();
+                let window_for_dialog = window.clone();
 
-                            log_text.push_str(&format!(
-                                "  Mode: Linux (persistence: enabled, type: {:?}, size: {} MB)\n",
-                                config.persistence_type, config.size_mb
-                            ));
-                            log_text.push_str(&format!(
-                                "  Partition table: {:?}\n",
-                                config.partition_table
-                            ));
-                            persistence_config = Some(config);
-                        } else {
-                            log_text.push_str("  Mode: Linux (persistence: disabled)\n");
+                write_button.clone().connect_clicked(move |_| {
+                    let FormJob { job, detection, description } = match form.build_job() {
+                        Ok(built) => built,
+                        Err(msg) => {
+                            log_view.buffer().set_text(&format!("ERROR: {}\n", msg));
+                            return;
                         }
-                    } else {
-                        // Default to Linux mode if no advanced group is visible
-                        log_text.push_str("  Mode: Linux (persistence: disabled)\n");
-                    }
-
-                    buffer.set_text(&log_text);
+                    };
+                    let device_path = job.device.clone();
+                    println!("[DEBUG] Starting USB write: ISO={}, Device={}", job.iso_path, device_path);
+
+                    // Nothing that changes the target or starts other device work until this write ends
+                    device_actions.set_busy(true);
+                    let cancel_write = {
+                        let device_actions = device_actions.clone();
+                        let progress_bar = progress_bar.clone();
+                        move || {
+                            device_actions.set_busy(false);
+                            progress_bar.set_fraction(0.0);
+                            progress_bar.set_show_text(false);
+                        }
+                    };
 
-                    // Show confirmation dialog before starting
-                    let dialog = gui_dialogs::show_usb_write_confirmation_dialog(
-                        Some(&window_for_dialog),
-                        &device_path
-                    );
+                    // Configure infinite progress bar
+                    progress_bar.set_fraction(0.0);
+                    progress_bar.set_show_text(true);
+                    progress_bar.set_text(Some("Preparing to write..."));
+                    progress_bar.set_pulse_step(0.1);
+                    progress_bar.set_visible(true);
+                    multi_progress_area.set_visible(false);
+
+                    let detection_conflict = job.detection_conflict(detection);
+                    log_view.buffer().set_text(&description);
+
+                    // Every safety check in one place; a failure stops here, warnings need a yes
+                    let report = preflight::run_preflight(&job);
+                    append_log(&log_view, &format!("\nPreflight checks:\n{}\n", report.lines().join("\n")));
+                    if report.status() != CheckStatus::Pass
+                        && !gui_dialogs::show_preflight_dialog(&window_for_dialog, &job.device, &report)
+                    {
+                        cancel_write();
+                        return;
+                    }
+                    // Images on a network share can be copied to local disk before the write starts
+                    let copy_locally = crate::utils::network_filesystem(&job.iso_path)
+                        .is_some_and(|share| gui_dialogs::show_network_source_dialog(&window_for_dialog, &job.iso_path, &share));
+
+                    // Show confirmation dialog with the full plan before starting
+                    let image_bytes = std::fs::metadata(&job.iso_path).map(|meta| meta.len()).unwrap_or(0);
+                    let file_name = std::path::Path::new(&job.iso_path)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_else(|| job.iso_path.clone());
+                    let image_label = match job.mode {
+                        WriteMode::Linux { .. } => linux_persistence::detect_linux_distro(&job.iso_path)
+                            .ok()
+                            .flatten()
+                            .map(|distro| match distro.version {
+                                Some(version) => format!("{} {}", distro.name, version),
+                                None => distro.name,
+                            })
+                            .unwrap_or(file_name),
+                        _ => file_name,
+                    };
+                    let mut plan = job.plan(image_label, image_bytes, crate::utils::get_device_size_bytes(&job.target()).ok());
+                    plan.note = crate::utils::usb_link_info(&device_path).and_then(|link| link.warning());
+                    let dialog = gui_dialogs::show_usb_write_confirmation_dialog(Some(&window_for_dialog), &plan);
 
                     let progress_bar_clone = progress_bar.clone();
-                    let write_button_clone = write_button.clone();
+                    let device_actions_clone = device_actions.clone();
                     let log_view_clone = log_view.clone();
-                    let iso_path_clone = iso_path.clone();
-                    let device_path_clone = device_path.clone();
-                    let persistence_config_clone = persistence_config.clone();
-                    let is_windows_mode_clone = is_windows_mode;
-                    let use_dd_mode_clone = use_dd_mode;
-                    let bypass_tpm_clone = bypass_tpm;
-                    let bypass_secure_boot_clone = bypass_secure_boot;
-                    let bypass_ram_clone = bypass_ram;
+                    let pause_button_clone = pause_button.clone();
+                    let pause_control_clone = pause_control.clone();
                     let window_for_dialog_clone = window_for_dialog.clone();
+                    let settings_for_log = settings.clone();
+                    let iso_entry_for_recovery = iso_entry.clone();
+                    let iso_button_for_recovery = iso_button.clone();
 
                     dialog.connect_response(move |dialog, response| {
                         dialog.close();
 
                         if response != gtk4::ResponseType::Ok {
-                            write_button_clone.set_sensitive(true);
-                            progress_bar_clone.set_fraction(0.0);
-                            progress_bar_clone.set_show_text(false);
+                            cancel_write();
                             return;
                         }
 
-                        if is_windows_mode_clone && use_dd_mode_clone {
+                        if let Some(reason) = &detection_conflict
+                            && !gui_dialogs::show_mode_conflict_dialog(&window_for_dialog_clone, reason)
+                        {
+                            cancel_write();
+                            return;
+                        }
+
+                        if matches!(job.mode, WriteMode::WindowsDirectDd) {
                             // Show dd warning; cancel if user declines.
                             if !gui_dialogs::show_dd_mode_warning_dialog(&window_for_dialog_clone) {
-                                write_button_clone.set_sensitive(true);
-                                progress_bar_clone.set_fraction(0.0);
-                                progress_bar_clone.set_show_text(false);
+                                cancel_write();
+                                return;
+                            }
+                        }
+
+                        if let WriteMode::RawPartition { number } = job.mode {
+                            let target = job.target();
+                            let sector_size = crate::utils::get_device_logical_block_size(&job.device).unwrap_or(512);
+                            let details = crate::utils::read_partitions(&job.device)
+                                .ok()
+                                .and_then(|partitions| partitions.into_iter().find(|part| part.number == number))
+                                .map(|part| partition_choice_label(&job.device, &part, sector_size))
+                                .unwrap_or_else(|| target.clone());
+                            if !gui_dialogs::show_partition_write_dialog(&window_for_dialog_clone, &target, &details) {
+                                cancel_write();
+                                return;
+                            }
+                        } else {
+                            // Fixed or very large disks get a second, deliberate confirmation
+                            let reasons = crate::utils::assess_device_risk(&job.device);
+                            let typed = settings_for_log.borrow().typed_confirmation.required(&reasons);
+                            if (typed || !reasons.is_empty())
+                                && !gui_dialogs::show_erase_acknowledgement_dialog(&window_for_dialog_clone, &job.device, &reasons, typed)
+                            {
+                                cancel_write();
+                                return;
+                            }
+                            if let Ok(Some(layout)) = crate::flows::media_scan::find_multiboot_layout(&job.device)
+                                && !gui_dialogs::show_multiboot_overwrite_dialog(
+                                    &window_for_dialog_clone,
+                                    &job.device,
+                                    layout.name(),
+                                    &layout.data_partition().path,
+                                )
+                            {
+                                cancel_write();
                                 return;
                             }
                         }
@@ -589,6 +1961,12 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
                         progress_bar_clone.set_text(Some("Starting..."));
                         progress_bar_clone.set_visible(true);
 
+                        // Only the Rust copy loop can stop between chunks; dd and rsync cannot
+                        let pause = job.can_pause().then(|| pause_control_clone.clone());
+                        pause_control_clone.resume();
+                        pause_button_clone.set_label("_Pause");
+                        pause_button_clone.set_visible(pause.is_some());
+
                         // Keep UI responsive: run heavy work on a background thread
                         let (sender, receiver) = glib::MainContext::channel(Priority::default());
                         let pulse_running = std::rc::Rc::new(std::cell::Cell::new(true));
@@ -607,13 +1985,27 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
                             let buffer_ui = log_view_clone.buffer();
                             let log_view_ui = log_view_clone.clone();
                             let progress_ui = progress_bar_clone.clone();
-                            let write_button_ui = write_button_clone.clone();
+                            let device_actions_ui = device_actions_clone.clone();
+                            let pause_button_ui = pause_button_clone.clone();
+                            let pause_control_ui = pause_control_clone.clone();
+                            let window_ui = window_for_dialog_clone.clone();
+                            let iso_entry_ui = iso_entry_for_recovery.clone();
+                            let iso_button_ui = iso_button_for_recovery.clone();
+                            let verbose = settings_for_log.borrow().log_verbosity == LogVerbosity::Verbose;
+                            let started_at = std::time::Instant::now();
+                            let job_ui = job.clone();
+                            let sender_ui = sender.clone();
+                            let inspect_after_write = settings_for_log.borrow().inspect_after_write;
                             receiver.attach(None, move |msg| {
                                 match msg {
                                     WorkerMessage::Log(line) => {
                                         let start = buffer_ui.start_iter();
                                         let end = buffer_ui.end_iter();
                                         let mut text = buffer_ui.text(&start, &end, false).to_string();
+                                        if verbose {
+                                            let elapsed = started_at.elapsed().as_secs();
+                                            text.push_str(&format!("[{:02}:{:02}] ", elapsed / 60, elapsed % 60));
+                                        }
                                         text.push_str(&line);
                                         if !text.ends_with('\n') {
                                             text.push('\n');
@@ -625,27 +2017,111 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
                                     WorkerMessage::Status(status) => {
                                         progress_ui.set_text(Some(&status));
                                     }
-                                    WorkerMessage::Done(result) => {
+                                    WorkerMessage::Progress(fraction, label) => {
+                                        // A determinate phase started; stop pulsing so the fraction sticks
+                                        pulse_running.set(false);
+                                        progress_ui.set_fraction(fraction);
+                                        progress_ui.set_text(Some(&label));
+                                    }
+                                    WorkerMessage::ChecksumMismatch(error, reply) => {
+                                        let choice = gui_dialogs::show_checksum_mismatch_dialog(&window_ui, &error, false);
+                                        let _ = reply.send(choice);
+                                        if choice == ChecksumRecovery::PickDifferentFile {
+                                            iso_entry_ui.set_text("");
+                                            iso_button_ui.emit_clicked();
+                                        }
+                                    }
+                                    WorkerMessage::Done(outcome) => {
                                         pulse_running.set(false);
                                         progress_ui.set_fraction(1.0);
-                                        write_button_ui.set_sensitive(true);
+                                        pause_control_ui.resume();
+                                        // The persistence retry below keeps the controls locked until its own Done
+                                        let mut retrying = false;
+                                        pause_button_ui.set_visible(false);
 
                                         let start = buffer_ui.start_iter();
                                         let end = buffer_ui.end_iter();
                                         let mut text = buffer_ui.text(&start, &end, false).to_string();
 
-                                        match result {
-                                            Ok(()) => {
+                                        if outcome.success {
+                                            if outcome.has_warnings() {
+                                                text.push_str(&format!("\n⚠ {}:\n", outcome.summary()));
+                                                for warning in &outcome.warnings {
+                                                    text.push_str(&format!("  - {}\n", warning));
+                                                }
+                                            } else {
                                                 text.push_str("\n✓ Write operation completed successfully!\n");
-                                                progress_ui.set_text(Some("Complete!"));
-                                                let completion_dialog = gui_dialogs::show_usb_completion_dialog();
-                                                completion_dialog.connect_response(|dialog, _| dialog.close());
+                                            }
+                                            if let Some(metrics) = &outcome.metrics {
+                                                text.push_str(&format!("  Average speed: {:.2} MB/s\n", metrics.avg_speed_mbps));
+                                            }
+                                            progress_ui.set_text(Some(&outcome.summary()));
+                                            // Persistence did not fit: offer the largest size that does
+                                            let retry = match (&job_ui.mode, outcome.persistence_max_mb) {
+                                                (WriteMode::Linux { mode: LinuxWriteMode::Persistent(config), .. }, Some(max_mb))
+                                                | (WriteMode::Linux { mode: LinuxWriteMode::Extracted { persistence: Some(config), .. }, .. }, Some(max_mb))
+                                                    if max_mb > 0 && gui_dialogs::show_persistence_retry_dialog(&window_ui, config.size_mb, max_mb) =>
+                                                {
+                                                    Some(PersistenceConfig { size_mb: max_mb, ..config.clone() })
+                                                }
+                                                _ => None,
+                                            };
+                                            if let Some(config) = retry {
+                                                text.push_str(&format!("\n=== Creating {} MB persistence partition ===\n", config.size_mb));
+                                                retrying = true;
+                                                progress_ui.set_text(Some("Creating persistence..."));
+                                                let device = job_ui.device.clone();
+                                                let sender = sender_ui.clone();
+                                                std::thread::spawn(move || {
+                                                    let mut warnings = Vec::new();
+                                                    let mut logger = ChannelWriter { sender: sender.clone() };
+                                                    let outcome = match linux_persistence::create_persistence_partition_with_recovery(&device, &config, &mut logger, &mut warnings) {
+                                                        Ok(()) => WriteOutcome::succeeded(None, warnings),
+                                                        Err(e) => WriteOutcome::failed(e),
+                                                    };
+                                                    let _ = sender.send(WorkerMessage::Done(outcome));
+                                                });
+                                            } else {
+                                                let inspect_mount = if inspect_after_write {
+                                                    match crate::utils::mount_for_inspection(&job_ui.device) {
+                                                        Ok(mount_point) => {
+                                                            text.push_str(&format!("Mounted read-only for inspection at {}\n", mount_point.display()));
+                                                            Some(mount_point)
+                                                        }
+                                                        Err(e) => {
+                                                            text.push_str(&format!("Warning: could not mount the device for inspection: {}\n", e));
+                                                            None
+                                                        }
+                                                    }
+                                                } else {
+                                                    None
+                                                };
+                                                let completion_dialog = gui_dialogs::show_usb_completion_dialog(&outcome.warnings, inspect_mount.as_deref());
+                                                completion_dialog.connect_response(move |dialog, response| {
+                                                    if response == gtk4::ResponseType::Other(gui_dialogs::OPEN_IN_FILE_MANAGER)
+                                                        && let Some(mount_point) = &inspect_mount
+                                                        && let Err(e) = crate::utils::open_in_file_manager(mount_point)
+                                                    {
+                                                        println!("[DEBUG] Could not open file manager: {}", e);
+                                                    }
+                                                    dialog.close();
+                                                });
                                                 completion_dialog.show();
                                             }
-                                            Err(e) => {
-                                                text.push_str(&format!("\n✗ Write operation failed: {}\n", e));
-                                                progress_ui.set_text(Some("Failed"));
+                                        } else {
+                                            let reason = outcome
+                                                .error
+                                                .as_ref()
+                                                .map(|e| e.to_string())
+                                                .unwrap_or_else(|| "unknown error".to_string());
+                                            text.push_str(&format!("\n✗ Write operation failed: {}\n", reason));
+                                            if let Some(state) = &outcome.device_state {
+                                                text.push_str(&format!("⚠ {}\n", state));
                                             }
+                                            progress_ui.set_text(Some("Failed"));
+                                        }
+                                        if !retrying {
+                                            device_actions_ui.set_busy(false);
                                         }
 
                                         buffer_ui.set_text(&text);
@@ -658,66 +2134,34 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
                         }
 
                         // Spawn worker thread
-                        let iso_for_thread = iso_path_clone.clone();
-                        let device_for_thread = device_path_clone.clone();
-                        let persistence_for_thread = persistence_config_clone.clone();
+                        let mut job = job.clone();
                         let sender_clone = sender.clone();
                         std::thread::spawn(move || {
-                            let send = |m| { let _ = sender_clone.send(m); };
-                            if is_windows_mode_clone {
-                                if use_dd_mode_clone {
-                                    send(WorkerMessage::Log("Starting Windows direct dd write (not recommended)...".into()));
-                                    send(WorkerMessage::Status("Writing image (dd)...".into()));
-                                    let mut logger = ChannelWriter { sender: sender_clone.clone() };
-                                    let result = crate::flows::windows_flow::write_windows_iso_direct_dd(
-                                        &iso_for_thread,
-                                        &device_for_thread,
-                                        &mut logger
-                                    ).map_err(|e| e.to_string());
-                                    let _ = sender_clone.send(WorkerMessage::Done(result));
-                                    return;
-                                }
-
-                                send(WorkerMessage::Log("Starting Windows dual-partition write...".into()));
-                                if bypass_tpm_clone || bypass_secure_boot_clone || bypass_ram_clone {
-                                    send(WorkerMessage::Log(format!(
-                                        "Bypass options selected: TPM={} SecureBoot={} RAM={}",
-                                        bypass_tpm_clone, bypass_secure_boot_clone, bypass_ram_clone
-                                    )));
-                                }
-                                send(WorkerMessage::Status("Creating partitions...".into()));
+                            let local_copy = if copy_locally {
                                 let mut logger = ChannelWriter { sender: sender_clone.clone() };
-                                let mut flags = crate::windows::unattend::UnattendFlags::empty();
-                                if bypass_tpm_clone {
-                                    flags |= crate::windows::unattend::UnattendFlags::BYPASS_TPM;
-                                }
-                                if bypass_secure_boot_clone {
-                                    flags |= crate::windows::unattend::UnattendFlags::BYPASS_SECURE_BOOT;
-                                }
-                                if bypass_ram_clone {
-                                    flags |= crate::windows::unattend::UnattendFlags::BYPASS_RAM;
+                                let dest = std::path::Path::new(crate::flows::image_copy::LOCAL_COPY_DIR);
+                                match crate::flows::image_copy::copy_image_locally(&job.iso_path, dest, &mut logger) {
+                                    Ok(path) => {
+                                        job.iso_path = path.clone();
+                                        Some(path)
+                                    }
+                                    Err(e) => {
+                                        let outcome = WriteOutcome::from_io_result(Err(e), "Local copy of the image");
+                                        let _ = sender_clone.send(WorkerMessage::Done(outcome));
+                                        return;
+                                    }
                                 }
-                                let result = crate::flows::windows_flow::write_windows_iso_to_usb_with_bypass(
-                                    &iso_for_thread,
-                                    &device_for_thread,
-                                    false,
-                                    if flags.is_empty() { None } else { Some(flags) },
-                                    &mut logger
-                                ).map(|_| ()).map_err(|e| e.to_string());
-                                let _ = sender_clone.send(WorkerMessage::Done(result));
                             } else {
-                                send(WorkerMessage::Log("Starting Linux ISO write...".into()));
-                                send(WorkerMessage::Log("Writing image using dd...".into()));
-                                send(WorkerMessage::Status("Writing image...".into()));
-                                let result = crate::flows::linux_flow::write_iso_to_usb_with_persistence(
-                                    &iso_for_thread,
-                                    &device_for_thread,
-                                    &mut std::io::Cursor::new(Vec::new()),
-                                    persistence_for_thread
-                                ).map_err(|e| e.to_string());
-                                send(WorkerMessage::Status("Finalizing persistence (if enabled)...".into()));
-                                let _ = sender_clone.send(WorkerMessage::Done(result));
+                                None
+                            };
+                            let outcome = match verify_job_source(&job, &sender_clone) {
+                                Some(outcome) => outcome,
+                                None => run_job(&job, pause.as_ref(), &sender_clone),
+                            };
+                            if let Some(path) = local_copy {
+                                let _ = std::fs::remove_file(path);
                             }
+                            let _ = sender_clone.send(WorkerMessage::Done(outcome));
                         });
                     });
 
@@ -725,10 +2169,194 @@ pub fn run_gui(needs_root: bool, is_flatpak: bool) {
                 });
             }
 
+            // --- Write the same job to several devices in parallel ---
+            {
+                let form = form.clone();
+                let device_actions = device_actions.clone();
+                let log_view = log_view.clone();
+                let progress_bar = progress_bar.clone();
+                let multi_progress_area = multi_progress_area.clone();
+                let window = window.clone();
+                multi_write_button.connect_clicked(move |_| {
+                    let FormJob { job, detection, description } = match form.build_job() {
+                        Ok(built) => built,
+                        Err(msg) => {
+                            log_view.buffer().set_text(&format!("ERROR: {}\n", msg));
+                            return;
+                        }
+                    };
+                    if matches!(job.mode, WriteMode::RawPartition { .. }) {
+                        log_view.buffer().set_text("ERROR: Writing into a partition targets one device; use \"Write to USB\" instead\n");
+                        return;
+                    }
+                    let mut excluded = crate::utils::backing_disks(&job.iso_path);
+                    excluded.extend(crate::utils::live_system_disks());
+                    let devices = gui_dialogs::show_device_multi_select_dialog(
+                        &window,
+                        &crate::utils::list_usb_devices(),
+                        &excluded,
+                        &job.device,
+                    );
+                    if devices.is_empty() {
+                        return;
+                    }
+                    if !gui_dialogs::show_multi_write_confirmation_dialog(&window, &devices) {
+                        return;
+                    }
+                    for device in &devices {
+                        let report = preflight::run_preflight(&job.with_device(device.as_str()));
+                        if report.status() != CheckStatus::Pass && !gui_dialogs::show_preflight_dialog(&window, device, &report) {
+                            return;
+                        }
+                        let reasons = crate::utils::assess_device_risk(device);
+                        let typed = form.settings.borrow().typed_confirmation.required(&reasons);
+                        if (typed || !reasons.is_empty())
+                            && !gui_dialogs::show_erase_acknowledgement_dialog(&window, device, &reasons, typed)
+                        {
+                            return;
+                        }
+                        if let Ok(Some(layout)) = crate::flows::media_scan::find_multiboot_layout(device)
+                            && !gui_dialogs::show_multiboot_overwrite_dialog(&window, device, layout.name(), &layout.data_partition().path)
+                        {
+                            return;
+                        }
+                    }
+                    if let Some(reason) = job.detection_conflict(detection)
+                        && !gui_dialogs::show_mode_conflict_dialog(&window, &reason)
+                    {
+                        return;
+                    }
+                    if matches!(job.mode, WriteMode::WindowsDirectDd) && !gui_dialogs::show_dd_mode_warning_dialog(&window) {
+                        return;
+                    }
+
+                    log_view.buffer().set_text(&description);
+                    append_log(&log_view, &format!("\n=== Writing to {} devices: {} ===", devices.len(), devices.join(", ")));
+                    device_actions.set_busy(true);
+                    progress_bar.set_visible(false);
+                    while let Some(row) = multi_progress_area.first_child() {
+                        multi_progress_area.remove(&row);
+                    }
+                    multi_progress_area.set_visible(true);
+
+                    // Each device gets its own channel and progress row; the log is shared
+                    let finished = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+                    let mut device_senders = Vec::new();
+                    for device in &devices {
+                        let (row, row_progress) = gui_widgets::create_device_progress_row(device);
+                        multi_progress_area.append(&row);
+                        let (sender, receiver) = glib::MainContext::channel(Priority::default());
+                        device_senders.push((device.clone(), sender));
+
+                        let device = device.clone();
+                        let total = devices.len();
+                        let finished = finished.clone();
+                        let log_view = log_view.clone();
+                        let device_actions = device_actions.clone();
+                        receiver.attach(None, move |msg| {
+                            match msg {
+                                WorkerMessage::Log(line) => append_log(&log_view, &format!("[{}] {}", device, line.trim_end())),
+                                WorkerMessage::Status(status) => row_progress.set_text(Some(&status)),
+                                WorkerMessage::Progress(fraction, label) => {
+                                    row_progress.set_fraction(fraction);
+                                    row_progress.set_text(Some(&label));
+                                }
+                                WorkerMessage::ChecksumMismatch(..) => {}
+                                WorkerMessage::Done(outcome) => {
+                                    if outcome.success {
+                                        row_progress.set_fraction(1.0);
+                                    }
+                                    row_progress.set_text(Some(&outcome.summary()));
+                                    for warning in &outcome.warnings {
+                                        append_log(&log_view, &format!("[{}] ⚠ {}", device, warning));
+                                    }
+                                    if let Some(state) = &outcome.device_state {
+                                        append_log(&log_view, &format!("[{}] ⚠ {}", device, state));
+                                    }
+                                    let mut finished = finished.borrow_mut();
+                                    finished.push((device.clone(), outcome.summary(), outcome.success));
+                                    if finished.len() == total {
+                                        let failed = finished.iter().filter(|(_, _, ok)| !ok).count();
+                                        append_log(&log_view, &format!("\n=== {} of {} devices written successfully ===", total - failed, total));
+                                        for (device, summary, ok) in finished.iter() {
+                                            append_log(&log_view, &format!("  {} {}: {}", if *ok { "✓" } else { "✗" }, device, summary));
+                                        }
+                                        device_actions.set_busy(false);
+                                    }
+                                }
+                            }
+                            glib::ControlFlow::Continue
+                        });
+                    }
+
+                    // Checksum verification runs once for all devices on its own channel
+                    let (source_sender, source_receiver) = glib::MainContext::channel(Priority::default());
+                    {
+                        let log_view = log_view.clone();
+                        let window = window.clone();
+                        source_receiver.attach(None, move |msg| {
+                            match msg {
+                                WorkerMessage::Log(line) => append_log(&log_view, &line),
+                                WorkerMessage::ChecksumMismatch(error, reply) => {
+                                    let _ = reply.send(gui_dialogs::show_checksum_mismatch_dialog(&window, &error, false));
+                                }
+                                _ => {}
+                            }
+                            glib::ControlFlow::Continue
+                        });
+                    }
+
+                    let stage = form.settings.borrow().stage_batch_writes && devices.len() > 1;
+                    std::thread::spawn(move || {
+                        if let Some(outcome) = verify_job_source(&job, &source_sender) {
+                            let reason = outcome.error.map(|e| e.to_string()).unwrap_or_else(|| "source check failed".to_string());
+                            for (_, sender) in &device_senders {
+                                let _ = sender.send(WorkerMessage::Done(WriteOutcome::failed(UsbCreatorError::Generic(reason.clone()))));
+                            }
+                            return;
+                        }
+                        // Read a Windows ISO once for the whole batch; the copy goes away with the last write
+                        let staged = if stage && job.windows_options().is_some() {
+                            let _ = source_sender.send(WorkerMessage::Log("Staging the ISO contents for the batch...".into()));
+                            let mut logger = ChannelWriter { sender: source_sender.clone() };
+                            match crate::flows::windows_flow::stage_iso(&job.iso_path, &mut logger) {
+                                Ok(staged) => Some(std::sync::Arc::new(staged)),
+                                Err(e) => {
+                                    let _ = source_sender.send(WorkerMessage::Log(format!(
+                                        "Warning: could not stage the ISO ({}); each device will mount it", e
+                                    )));
+                                    None
+                                }
+                            }
+                        } else {
+                            None
+                        };
+                        for (device, sender) in device_senders {
+                            let job = job.with_device(device).with_staged_iso(staged.as_ref().map(|s| s.root().to_path_buf()));
+                            let staged = staged.clone();
+                            std::thread::spawn(move || {
+                                let _staged = staged;
+                                let outcome = run_job(&job, None, &sender);
+                                let _ = sender.send(WorkerMessage::Done(outcome));
+                            });
+                        }
+                    });
+                });
+            }
+
             // Set the main vbox as the window content
             window.set_child(Some(&vbox));
             window.show();
 
+            // Warn about writes that never completed in an earlier session
+            let unfinished = WriteState::load_all();
+            if !unfinished.is_empty() {
+                gui_dialogs::show_unfinished_writes_dialog(&window, &unfinished);
+                for state in &unfinished {
+                    let _ = WriteState::clear(&state.device);
+                }
+            }
+
             // Show Flatpak permission dialog if needed
             if needs_root && is_flatpak {
                 gui_dialogs::show_flatpak_instructions_dialog(&window);
@@ -3,9 +3,17 @@
 use gtk4::prelude::*;
 use gtk4::{ApplicationWindow, Dialog, MessageDialog, ButtonsType, MessageType, ResponseType,
             Button, Box as GtkBox, Label, TextView, Orientation, FileChooserAction,
-            FileChooserDialog, FileFilter, Entry};
+            FileChooserDialog, FileFilter, Entry, Grid, ComboBoxText, CheckButton, Expander};
 use glib::MainContext;
 
+use crate::error::UsbCreatorError;
+use crate::flows::job::WritePlan;
+use crate::flows::outcome::Warning;
+use crate::flows::preflight::{CheckStatus, PreflightReport};
+use crate::write_state::WriteState;
+use crate::gui::widgets::{cluster_size_index, CLUSTER_SIZES_BYTES};
+use crate::settings::{CopyStrategy, ElevationMethod, LogVerbosity, Settings, TypedConfirmation};
+
 /// Show missing packages dialog with installation command
 pub fn show_missing_packages_dialog_simple(
     parent: Option<&ApplicationWindow>,
@@ -45,11 +53,19 @@ pub fn show_missing_packages_dialog_simple(
 /// Show confirmation dialog for USB write operation (exact app.rs implementation)
 pub fn show_usb_write_confirmation_dialog(
     parent: Option<&ApplicationWindow>,
-    device_path: &str,
+    plan: &WritePlan,
 ) -> gtk4::MessageDialog {
+    let mut text = format!(
+        "{}\n\nThis will completely erase:\n{}\n\nProceed with write operation?",
+        plan.summary(),
+        plan.device
+    );
+    if let Some(note) = &plan.note {
+        text.push_str(&format!("\n\nNote: {}", note));
+    }
     let dialog = gtk4::MessageDialog::builder()
         .text("Confirm USB Write Operation")
-        .secondary_text(&format!("This will completely erase:\n{}\n\nProceed with write operation?", device_path))
+        .secondary_text(&text)
         .buttons(gtk4::ButtonsType::OkCancel)
         .message_type(gtk4::MessageType::Warning)
         .build();
@@ -62,14 +78,75 @@ pub fn show_usb_write_confirmation_dialog(
     dialog
 }
 
-/// Show completion dialog after successful USB creation
-pub fn show_usb_completion_dialog() -> gtk4::MessageDialog {
-    let dialog = gtk4::MessageDialog::builder()
-        .text("USB creation complete!")
-        .message_type(gtk4::MessageType::Info)
-        .buttons(gtk4::ButtonsType::Ok)
+/// Show completion dialog after successful USB creation, summarizing any non-fatal warnings
+/// (details in an expander) and, when the device was left mounted, offering to open it
+pub fn show_usb_completion_dialog(warnings: &[Warning], inspect_mount: Option<&std::path::Path>) -> gtk4::MessageDialog {
+    let builder = gtk4::MessageDialog::builder().buttons(gtk4::ButtonsType::Ok);
+    let dialog = if warnings.is_empty() {
+        builder
+            .text("USB creation complete!")
+            .message_type(gtk4::MessageType::Info)
+            .build()
+    } else {
+        builder
+            .text(format!(
+                "Completed with {} warning{}",
+                warnings.len(),
+                if warnings.len() == 1 { "" } else { "s" }
+            ))
+            .secondary_text("The USB drive was written, but some steps did not go as planned.")
+            .message_type(gtk4::MessageType::Warning)
+            .build()
+    };
+    dialog.set_default_width(640);
+    if !warnings.is_empty()
+        && let Ok(area) = dialog.message_area().downcast::<GtkBox>()
+    {
+        let details = warnings
+            .iter()
+            .map(|w| format!("• {}", w))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let label = Label::new(Some(&details));
+        label.set_wrap(true);
+        label.set_xalign(0.0);
+        label.set_selectable(true);
+        let expander = Expander::new(Some("Details"));
+        expander.set_child(Some(&label));
+        area.append(&expander);
+    }
+    if let Some(mount_point) = inspect_mount {
+        let text = format!("The device is mounted read-only at {}.", mount_point.display());
+        let secondary = dialog.secondary_text().map(|s| format!("{}\n\n{}", s, text)).unwrap_or(text);
+        dialog.set_secondary_text(Some(&secondary));
+        dialog.add_button("Open in file manager", ResponseType::Other(OPEN_IN_FILE_MANAGER));
+    }
+    dialog
+}
+
+/// Response id of the completion dialog's "Open in file manager" button
+pub const OPEN_IN_FILE_MANAGER: u16 = 1;
+
+/// Warn that writes from an earlier session never completed
+pub fn show_unfinished_writes_dialog(window: &ApplicationWindow, states: &[WriteState]) -> gtk4::MessageDialog {
+    let details = states
+        .iter()
+        .map(|state| format!("• {}", state.warning()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let dialog = MessageDialog::builder()
+        .text("Previous write did not complete")
+        .secondary_text(details)
+        .buttons(ButtonsType::Ok)
+        .message_type(MessageType::Warning)
+        .modal(true)
+        .transient_for(window)
         .build();
     dialog.set_default_width(640);
+    dialog.connect_response(|dialog, _| {
+        dialog.close();
+    });
+    dialog.show();
     dialog
 }
 
@@ -125,6 +202,12 @@ pub fn show_iso_file_chooser_dialog_app(
     filter.add_pattern("*.iso");
     filter.set_name(Some("ISO files"));
     dialog.add_filter(&filter);
+    let image_filter = FileFilter::new();
+    for pattern in ["*.img", "*.img.gz", "*.raw", "*.raw.gz"] {
+        image_filter.add_pattern(pattern);
+    }
+    image_filter.set_name(Some("Disk images to restore"));
+    dialog.add_filter(&image_filter);
 
     // Set initial folder to user's home directory
     let user_home = crate::utils::get_user_home();
@@ -141,6 +224,18 @@ pub fn show_iso_file_chooser_dialog_app(
                 // Call the reusable reset logic
                 reset_advanced_options();
 
+                if crate::flows::job::is_disk_image(&path_str) {
+                    os_label_clone.set_text("Disk image: will be restored to the device and verified");
+                    dialog.close();
+                    return;
+                }
+
+                if let Err(e) = crate::utils::check_iso_readable(&path_str) {
+                    os_label_clone.set_text(&format!("Cannot use this file: {}", e));
+                    dialog.close();
+                    return;
+                }
+
                 // Auto-detect OS type when ISO is selected
                 os_label_clone.set_text("Detecting OS type...");
                 let detected = crate::utils::is_windows_iso(&path_str);
@@ -156,6 +251,420 @@ pub fn show_iso_file_chooser_dialog_app(
     dialog.show();
 }
 
+/// Pick a kickstart/preseed file (`kind`) for an automated install. Returns None on cancel.
+pub fn show_answer_file_dialog(parent: &ApplicationWindow, kind: &str) -> Option<String> {
+    let dialog = FileChooserDialog::new(
+        Some(&format!("Select {} File", kind)),
+        Some(parent),
+        FileChooserAction::Open,
+        &[ ]
+    );
+    dialog.set_default_width(640);
+    dialog.add_button("Open", gtk4::ResponseType::Ok);
+    dialog.add_button("Cancel", gtk4::ResponseType::Cancel);
+    let filter = FileFilter::new();
+    for pattern in ["*.cfg", "*.ks", "*.seed", "user-data", "*.yaml", "*.yml"] {
+        filter.add_pattern(pattern);
+    }
+    filter.set_name(Some(&format!("{} files", kind)));
+    dialog.add_filter(&filter);
+    let all_filter = FileFilter::new();
+    all_filter.add_pattern("*");
+    all_filter.set_name(Some("All files"));
+    dialog.add_filter(&all_filter);
+    let user_home = crate::utils::get_user_home();
+    let _ = dialog.set_current_folder(Some(&gtk4::gio::File::for_path(&user_home)));
+
+    let response = MainContext::default().block_on(dialog.run_future());
+    let result = if response == ResponseType::Ok {
+        dialog.file().and_then(|f| f.path()).map(|path| path.to_string_lossy().to_string())
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
+
+/// Pick an EFI application for the Windows tool partition. Returns None on cancel.
+pub fn show_efi_tool_dialog(parent: &ApplicationWindow) -> Option<String> {
+    let dialog = FileChooserDialog::new(
+        Some("Select EFI Tool"),
+        Some(parent),
+        FileChooserAction::Open,
+        &[ ]
+    );
+    dialog.set_default_width(640);
+    dialog.add_button("Open", gtk4::ResponseType::Ok);
+    dialog.add_button("Cancel", gtk4::ResponseType::Cancel);
+    let filter = FileFilter::new();
+    filter.add_pattern("*.efi");
+    filter.add_pattern("*.EFI");
+    filter.set_name(Some("EFI applications"));
+    dialog.add_filter(&filter);
+    let all_filter = FileFilter::new();
+    all_filter.add_pattern("*");
+    all_filter.set_name(Some("All files"));
+    dialog.add_filter(&all_filter);
+    let user_home = crate::utils::get_user_home();
+    let _ = dialog.set_current_folder(Some(&gtk4::gio::File::for_path(&user_home)));
+
+    let response = MainContext::default().block_on(dialog.run_future());
+    let result = if response == ResponseType::Ok {
+        dialog.file().and_then(|f| f.path()).map(|path| path.to_string_lossy().to_string())
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
+
+/// Pick a `$OEM$` folder for the Windows INSTALL partition
+pub fn show_oem_folder_dialog(parent: &ApplicationWindow) -> Option<String> {
+    let dialog = FileChooserDialog::new(
+        Some("Select $OEM$ Folder"),
+        Some(parent),
+        FileChooserAction::SelectFolder,
+        &[ ]
+    );
+    dialog.set_default_width(640);
+    dialog.add_button("Select", gtk4::ResponseType::Ok);
+    dialog.add_button("Cancel", gtk4::ResponseType::Cancel);
+    let user_home = crate::utils::get_user_home();
+    let _ = dialog.set_current_folder(Some(&gtk4::gio::File::for_path(&user_home)));
+
+    let response = MainContext::default().block_on(dialog.run_future());
+    let result = if response == ResponseType::Ok {
+        dialog.file().and_then(|f| f.path()).map(|path| path.to_string_lossy().to_string())
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
+
+/// Ask where to save a clone of `device_path`. Returns the destination and whether trailing
+/// empty space should be trimmed; a `.gz` name selects compression.
+pub fn show_image_save_dialog(parent: &ApplicationWindow, device_path: &str) -> Option<(String, bool)> {
+    let dialog = FileChooserDialog::new(
+        Some(&format!("Save {} as Image", device_path)),
+        Some(parent),
+        FileChooserAction::Save,
+        &[ ]
+    );
+    dialog.set_default_width(640);
+    dialog.add_button("Save", gtk4::ResponseType::Ok);
+    dialog.add_button("Cancel", gtk4::ResponseType::Cancel);
+    dialog.set_current_name("usb-backup.img.gz");
+    let user_home = crate::utils::get_user_home();
+    let _ = dialog.set_current_folder(Some(&gtk4::gio::File::for_path(&user_home)));
+    dialog.add_choice("trim", "Leave out trailing empty space", &[]);
+    dialog.set_choice("trim", "true");
+
+    let response = MainContext::default().block_on(dialog.run_future());
+    let result = if response == ResponseType::Ok {
+        dialog.file().and_then(|f| f.path()).map(|path| {
+            let trim = dialog.choice("trim").is_none_or(|value| value == "true");
+            (path.to_string_lossy().to_string(), trim)
+        })
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
+
+/// Let the user tick the devices to write. `excluded` devices (e.g. the one holding the source
+/// image) are listed but cannot be selected. Returns the chosen device paths, empty on cancel.
+pub fn show_device_multi_select_dialog(
+    parent: &ApplicationWindow,
+    devices: &[(String, String)],
+    excluded: &[String],
+    preselected: &str,
+) -> Vec<String> {
+    let dialog = Dialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .title("Write to Several Devices")
+        .default_width(480)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Continue", ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Accept);
+
+    let list = GtkBox::new(Orientation::Vertical, 6);
+    list.set_margin_top(16);
+    list.set_margin_bottom(16);
+    list.set_margin_start(16);
+    list.set_margin_end(16);
+    list.append(&Label::new(Some("Select every USB device to write the same image to:")));
+    let mut checks = Vec::new();
+    for (path, description) in devices {
+        let check = CheckButton::with_label(&format!("{} - {}", path, description));
+        if excluded.contains(path) {
+            check.set_label(Some(&format!("{} - {} (holds your source image or the running system)", path, description)));
+            check.set_sensitive(false);
+        } else {
+            check.set_active(path == preselected);
+        }
+        list.append(&check);
+        checks.push((path.clone(), check));
+    }
+    dialog.content_area().append(&list);
+
+    let response = MainContext::default().block_on(dialog.run_future());
+    let selected = if response == ResponseType::Accept {
+        checks
+            .into_iter()
+            .filter(|(_, check)| check.is_sensitive() && check.is_active())
+            .map(|(path, _)| path)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    dialog.close();
+    selected
+}
+
+/// Confirm erasing every listed device before a multi-device write
+pub fn show_multi_write_confirmation_dialog(parent: &ApplicationWindow, devices: &[String]) -> bool {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Warning)
+        .buttons(ButtonsType::OkCancel)
+        .text(format!("Erase {} devices?", devices.len()))
+        .secondary_text(format!(
+            "All data on these devices will be destroyed:\n\n{}\n\nProceed with the write?",
+            devices.iter().map(|d| format!("  • {}", d)).collect::<Vec<_>>().join("\n")
+        ))
+        .build();
+    dialog.set_default_width(640);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Ok
+}
+
+/// Last confirmation before a raw write into a single partition, naming it and what it holds.
+/// Returns true only when the user picks the overwrite button.
+pub fn show_partition_write_dialog(parent: &ApplicationWindow, partition: &str, details: &str) -> bool {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Warning)
+        .buttons(ButtonsType::None)
+        .text(format!("Overwrite partition {}?", partition))
+        .secondary_text(format!(
+            "The image will be written over:\n  {}\n\n\
+             Everything on {} will be destroyed. The partition table and the other partitions are kept, \
+             but the partition keeps its type and size: an image that carries its own partition table, \
+             or that the firmware does not expect there, will not be usable or bootable.\n\n\
+             Only continue if this image is meant to be written into a partition.",
+            details, partition
+        ))
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    let overwrite = dialog.add_button(&format!("Overwrite {}", partition), ResponseType::Accept);
+    overwrite.add_css_class("destructive-action");
+    dialog.set_default_response(ResponseType::Cancel);
+    dialog.set_default_width(640);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Accept
+}
+
+/// Extra gate for risky targets (see `utils::assess_device_risk`): the erase button stays
+/// disabled until "I understand this will erase <device>" is ticked or, with `typed`, until the
+/// exact device path is typed (see `TypedConfirmation`). Returns true only then.
+pub fn show_erase_acknowledgement_dialog(parent: &ApplicationWindow, device: &str, reasons: &[String], typed: bool) -> bool {
+    let (title, details) = if reasons.is_empty() {
+        (format!("Erase {}?", device), String::new())
+    } else {
+        (
+            format!("{} may not be a USB stick", device),
+            format!(
+                "Take a second look before erasing {}:\n\n{}\n\n",
+                device,
+                reasons.iter().map(|r| format!("  • {}", r)).collect::<Vec<_>>().join("\n")
+            ),
+        )
+    };
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Warning)
+        .buttons(ButtonsType::None)
+        .text(title)
+        .secondary_text(format!("{}Everything on {}, including other partitions, will be destroyed.", details, device))
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    let erase = dialog.add_button(&format!("Erase {}", device), ResponseType::Accept);
+    erase.add_css_class("destructive-action");
+    erase.set_sensitive(false);
+    let area = dialog.message_area().downcast::<GtkBox>().ok();
+    let acknowledged: Box<dyn Fn() -> bool> = if typed {
+        let prompt = Label::new(Some(&format!("Type {} to confirm:", device)));
+        prompt.set_halign(gtk4::Align::Start);
+        let entry = Entry::builder().placeholder_text(device).build();
+        let expected = device.to_string();
+        entry.connect_changed(move |entry| erase.set_sensitive(entry.text().trim() == expected));
+        if let Some(area) = &area {
+            area.append(&prompt);
+            area.append(&entry);
+        }
+        let expected = device.to_string();
+        Box::new(move || entry.text().trim() == expected)
+    } else {
+        let understood = CheckButton::with_label(&format!("I understand this will erase {}", device));
+        understood.connect_toggled(move |cb| erase.set_sensitive(cb.is_active()));
+        if let Some(area) = &area {
+            area.append(&understood);
+        }
+        Box::new(move || understood.is_active())
+    };
+    dialog.set_default_response(ResponseType::Cancel);
+    dialog.set_default_width(640);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Accept && acknowledged()
+}
+
+/// Show the preflight report for `device` when a check did not pass. With a failure the only way
+/// out is "Close"; warnings alone can be accepted. Returns true to go ahead with the write.
+pub fn show_preflight_dialog(parent: &ApplicationWindow, device: &str, report: &PreflightReport) -> bool {
+    let failed = report.has_failures();
+    let problems: Vec<String> = report
+        .items
+        .iter()
+        .filter(|item| item.status != CheckStatus::Pass)
+        .map(|item| format!("  • {} ({}): {}", item.check, item.status.label(), item.detail))
+        .collect();
+    let passed = report.items_with(CheckStatus::Pass).count();
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(if failed { MessageType::Error } else { MessageType::Warning })
+        .buttons(ButtonsType::None)
+        .text(if failed {
+            format!("Cannot write to {}", device)
+        } else {
+            format!("Preflight warnings for {}", device)
+        })
+        .secondary_text(format!(
+            "{}\n\n{} of {} checks passed.",
+            problems.join("\n"),
+            passed,
+            report.items.len()
+        ))
+        .build();
+    if failed {
+        dialog.add_button("Close", ResponseType::Cancel);
+    } else {
+        dialog.add_button("Cancel", ResponseType::Cancel);
+        dialog.add_button("Continue", ResponseType::Accept);
+    }
+    dialog.set_default_response(ResponseType::Cancel);
+    dialog.set_default_width(640);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    !failed && response == ResponseType::Accept
+}
+
+/// Confirm erasing a stick that already holds a Ventoy or multiboot layout; its ISOs would be lost
+pub fn show_multiboot_overwrite_dialog(parent: &ApplicationWindow, device: &str, layout: &str, data_partition: &str) -> bool {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Warning)
+        .buttons(ButtonsType::None)
+        .text(format!("{} is a {} multiboot stick", device, layout))
+        .secondary_text(format!(
+            "Writing a single image erases the multiboot layout and every ISO stored on {}.\n\n\
+             To keep them, add ISOs with `cli_helper multiboot-add {} <iso>...` instead.",
+            data_partition, device
+        ))
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    let erase = dialog.add_button("Erase Multiboot Stick", ResponseType::Accept);
+    erase.add_css_class("destructive-action");
+    dialog.set_default_response(ResponseType::Cancel);
+    dialog.set_default_width(640);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Accept
+}
+
+/// Offer to copy an image that lives on a network share to local disk before writing.
+/// Returns true when the user chooses to copy it first.
+pub fn show_network_source_dialog(parent: &ApplicationWindow, iso_path: &str, share: &str) -> bool {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Question)
+        .buttons(ButtonsType::None)
+        .text("The image is on a network share")
+        .secondary_text(format!(
+            "{} is read from a {}. If the network stalls, the write stalls with it and the stick is left half written.\n\n\
+             Copy the image to {} first? The copy is removed once the write finishes.",
+            iso_path,
+            share,
+            crate::flows::image_copy::LOCAL_COPY_DIR
+        ))
+        .build();
+    dialog.add_button("Write from the share", ResponseType::Cancel);
+    dialog.add_button("Copy locally first", ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Accept);
+    dialog.set_default_width(640);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Accept
+}
+
+/// Offer to create persistence at the largest size that fits after the requested size did not.
+/// Returns true when the user chooses "Use maximum".
+pub fn show_persistence_retry_dialog(parent: &ApplicationWindow, requested_mb: u64, max_mb: u64) -> bool {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Question)
+        .buttons(ButtonsType::None)
+        .text("Persistence did not fit")
+        .secondary_text(format!(
+            "The image was written, but there is not enough space left for {} MB of persistence.\n\n\
+             Up to {} MB is available. Create a persistence partition of that size instead?",
+            requested_mb, max_mb
+        ))
+        .build();
+    dialog.add_button("Skip persistence", ResponseType::Cancel);
+    dialog.add_button(&format!("Use maximum ({} MB)", max_mb), ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Accept);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Accept
+}
+
+/// Ask what to do when the window is closed while device work is running. Returns true when
+/// the user chooses to cancel it and quit.
+pub fn show_quit_during_write_dialog(parent: &ApplicationWindow) -> bool {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Warning)
+        .buttons(ButtonsType::None)
+        .text("A write is in progress")
+        .secondary_text(
+            "Cancel it and quit, or keep it running?\n\n\
+             Cancelling stops the tools writing to the device and leaves it partially written; \
+             it has to be written again before use.",
+        )
+        .build();
+    dialog.add_button("Keep running", ResponseType::Cancel);
+    dialog.add_button("Cancel and quit", ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Cancel);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Accept
+}
+
 /// Warning dialog for direct dd mode with Windows ISOs
 pub fn show_dd_mode_warning_dialog(parent: &ApplicationWindow) -> bool {
     let dialog = MessageDialog::builder()
@@ -181,3 +690,251 @@ pub fn show_dd_mode_warning_dialog(parent: &ApplicationWindow) -> bool {
     dialog.close();
     response == ResponseType::Yes
 }
+
+/// Ask the user to confirm a write mode that contradicts OS detection
+pub fn show_mode_conflict_dialog(parent: &ApplicationWindow, reason: &str) -> bool {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Warning)
+        .buttons(ButtonsType::YesNo)
+        .text("Selected mode does not match the detected ISO type")
+        .secondary_text(format!("{}\n\nWrite anyway?", reason))
+        .build();
+
+    dialog.set_default_width(640);
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    response == ResponseType::Yes
+}
+
+/// User's choice after the source image failed checksum verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumRecovery {
+    /// Fetch the image again (only offered for downloaded images)
+    Redownload,
+    /// Abort and choose another ISO file
+    PickDifferentFile,
+    /// Write the image despite the mismatch
+    ProceedAnyway,
+    Cancel,
+}
+
+/// Offer recovery actions for a failed checksum verification. Proceeding requires a second,
+/// explicit confirmation; declining it counts as Cancel.
+pub fn show_checksum_mismatch_dialog(
+    parent: &ApplicationWindow,
+    error: &UsbCreatorError,
+    can_redownload: bool,
+) -> ChecksumRecovery {
+    const REDOWNLOAD: u16 = 1;
+    const PICK_FILE: u16 = 2;
+    const PROCEED: u16 = 3;
+
+    let details = match error {
+        UsbCreatorError::ChecksumMismatch { expected, actual } => {
+            format!("Expected: {}\nActual:   {}", expected, actual)
+        }
+        other => other.to_string(),
+    };
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(MessageType::Error)
+        .buttons(ButtonsType::None)
+        .text("The ISO does not match the expected checksum")
+        .secondary_text(format!(
+            "The file may be corrupted, incomplete or tampered with.\n\n{}",
+            details
+        ))
+        .build();
+    dialog.set_default_width(640);
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    if can_redownload {
+        dialog.add_button("Download again", ResponseType::Other(REDOWNLOAD));
+    }
+    dialog.add_button("Choose another file", ResponseType::Other(PICK_FILE));
+    dialog.add_button("Write anyway", ResponseType::Other(PROCEED));
+    dialog.set_default_response(ResponseType::Other(PICK_FILE));
+
+    let response = MainContext::default().block_on(dialog.run_future());
+    dialog.close();
+    match response {
+        ResponseType::Other(REDOWNLOAD) => ChecksumRecovery::Redownload,
+        ResponseType::Other(PICK_FILE) => ChecksumRecovery::PickDifferentFile,
+        ResponseType::Other(PROCEED) => {
+            let confirm = MessageDialog::builder()
+                .transient_for(parent)
+                .modal(true)
+                .message_type(MessageType::Warning)
+                .buttons(ButtonsType::YesNo)
+                .text("Write an unverified image?")
+                .secondary_text(
+                    "Booting a corrupted or modified installer can fail halfway through an \
+                     installation or compromise the machine it runs on. Continue at your own risk?",
+                )
+                .build();
+            let confirmed = MainContext::default().block_on(confirm.run_future()) == ResponseType::Yes;
+            confirm.close();
+            if confirmed { ChecksumRecovery::ProceedAnyway } else { ChecksumRecovery::Cancel }
+        }
+        _ => ChecksumRecovery::Cancel,
+    }
+}
+
+/// Preferences dialog backed by the TOML settings file. `on_save` runs after a successful save
+/// so the caller can apply changes live.
+pub fn show_preferences_dialog<F: Fn(&Settings) + 'static>(
+    parent: &ApplicationWindow,
+    current: &Settings,
+    on_save: F,
+) {
+    let dialog = Dialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .title("Preferences")
+        .default_width(520)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Save", ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Accept);
+
+    let grid = Grid::new();
+    grid.set_row_spacing(8);
+    grid.set_column_spacing(12);
+    grid.set_margin_top(16);
+    grid.set_margin_bottom(16);
+    grid.set_margin_start(16);
+    grid.set_margin_end(16);
+
+    let add_row = |row: i32, label: &str, widget: &gtk4::Widget| {
+        let label = Label::new(Some(label));
+        label.set_halign(gtk4::Align::Start);
+        grid.attach(&label, 0, row, 1, 1);
+        widget.set_hexpand(true);
+        grid.attach(widget, 1, row, 1, 1);
+    };
+
+    let cluster_combo = ComboBoxText::new();
+    for size in CLUSTER_SIZES_BYTES {
+        cluster_combo.append_text(&format!("{} bytes", size));
+    }
+    cluster_combo.set_active(Some(cluster_size_index(current.default_cluster_bytes)));
+    add_row(0, "Default cluster size (Windows):", cluster_combo.upcast_ref());
+
+    let verify_checkbox = CheckButton::with_label("Verify after write (check the EFI boot files on the media)");
+    verify_checkbox.set_active(current.verify_after_write);
+    grid.attach(&verify_checkbox, 0, 1, 2, 1);
+
+    let inspect_checkbox = CheckButton::with_label("Keep the device mounted for inspection after writing");
+    inspect_checkbox.set_active(current.inspect_after_write);
+    grid.attach(&inspect_checkbox, 0, 4, 2, 1);
+
+    let thorough_flush_checkbox = CheckButton::with_label("Thorough flush after writing (for sticks that lose data when unplugged)");
+    thorough_flush_checkbox.set_active(current.thorough_flush);
+    grid.attach(&thorough_flush_checkbox, 0, 5, 2, 1);
+
+    let manifest_checkbox = CheckButton::with_label("Store file checksums on the media (check later with cli_helper verify-media)");
+    manifest_checkbox.set_active(current.checksum_manifest);
+    grid.attach(&manifest_checkbox, 0, 6, 2, 1);
+
+    let staging_checkbox = CheckButton::with_label("Copy Windows ISOs to local disk once when writing several devices");
+    staging_checkbox.set_active(current.stage_batch_writes);
+    grid.attach(&staging_checkbox, 0, 7, 2, 1);
+
+    let elevation_combo = ComboBoxText::new();
+    elevation_combo.append_text("pkexec (graphical prompt)");
+    elevation_combo.append_text("sudo (terminal)");
+    elevation_combo.set_active(Some(match current.elevation_method {
+        ElevationMethod::Pkexec => 0,
+        ElevationMethod::Sudo => 1,
+    }));
+    add_row(2, "Elevation method (next launch):", elevation_combo.upcast_ref());
+
+    let verbosity_combo = ComboBoxText::new();
+    verbosity_combo.append_text("Normal");
+    verbosity_combo.append_text("Verbose (timestamps)");
+    verbosity_combo.set_active(Some(match current.log_verbosity {
+        LogVerbosity::Normal => 0,
+        LogVerbosity::Verbose => 1,
+    }));
+    add_row(3, "Log verbosity:", verbosity_combo.upcast_ref());
+
+    let copy_strategy_combo = ComboBoxText::new();
+    copy_strategy_combo.append_text("First write (whole files)");
+    copy_strategy_combo.append_text("Re-write (only changed data)");
+    copy_strategy_combo.set_active(Some(match current.copy_strategy {
+        CopyStrategy::FirstWrite => 0,
+        CopyStrategy::Rewrite => 1,
+    }));
+    add_row(8, "Optimize Windows copies for:", copy_strategy_combo.upcast_ref());
+
+    let typed_confirmation_combo = ComboBoxText::new();
+    typed_confirmation_combo.append_text("Never");
+    typed_confirmation_combo.append_text("For fixed or large disks");
+    typed_confirmation_combo.append_text("For every device");
+    typed_confirmation_combo.set_active(Some(match current.typed_confirmation {
+        TypedConfirmation::Off => 0,
+        TypedConfirmation::RiskyDevices => 1,
+        TypedConfirmation::Always => 2,
+    }));
+    add_row(9, "Type the device path to confirm erasing:", typed_confirmation_combo.upcast_ref());
+
+    dialog.content_area().append(&grid);
+
+    // Tuning and the mount base have no widgets; keep whatever the config file holds
+    let tuning = current.tuning;
+    let mount_base = current.mount_base.clone();
+    dialog.connect_response(move |dialog, response| {
+        if response == ResponseType::Accept {
+            let idx = cluster_combo.active().unwrap_or(3) as usize;
+            let updated = Settings {
+                default_cluster_bytes: *CLUSTER_SIZES_BYTES.get(idx).unwrap_or(&4096),
+                verify_after_write: verify_checkbox.is_active(),
+                inspect_after_write: inspect_checkbox.is_active(),
+                thorough_flush: thorough_flush_checkbox.is_active(),
+                checksum_manifest: manifest_checkbox.is_active(),
+                stage_batch_writes: staging_checkbox.is_active(),
+                copy_strategy: if copy_strategy_combo.active() == Some(1) {
+                    CopyStrategy::Rewrite
+                } else {
+                    CopyStrategy::FirstWrite
+                },
+                typed_confirmation: match typed_confirmation_combo.active() {
+                    Some(1) => TypedConfirmation::RiskyDevices,
+                    Some(2) => TypedConfirmation::Always,
+                    _ => TypedConfirmation::Off,
+                },
+                elevation_method: if elevation_combo.active() == Some(1) {
+                    ElevationMethod::Sudo
+                } else {
+                    ElevationMethod::Pkexec
+                },
+                log_verbosity: if verbosity_combo.active() == Some(1) {
+                    LogVerbosity::Verbose
+                } else {
+                    LogVerbosity::Normal
+                },
+                mount_base: mount_base.clone(),
+                tuning,
+            };
+            match updated.save() {
+                Ok(()) => on_save(&updated),
+                Err(e) => {
+                    let error_dialog = MessageDialog::builder()
+                        .transient_for(dialog)
+                        .modal(true)
+                        .message_type(MessageType::Error)
+                        .buttons(ButtonsType::Ok)
+                        .text("Could not save preferences")
+                        .secondary_text(format!("{}: {}", Settings::path().display(), e))
+                        .build();
+                    error_dialog.connect_response(|d, _| d.close());
+                    error_dialog.show();
+                }
+            }
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
@@ -1,7 +1,59 @@
 // Widget creation functions (ISO selection, device selection, etc.)
 
 use gtk4::prelude::*;
-use gtk4::{Button, ComboBoxText, Entry, Orientation, Box as GtkBox, Label, ScrolledWindow, TextView, ProgressBar, CheckButton};
+use gtk4::{Button, CellRendererText, ComboBox, ComboBoxText, Entry, ListStore, Orientation, Box as GtkBox, Label, ScrolledWindow, TextView, ProgressBar, CheckButton};
+
+/// NTFS cluster sizes offered in the Windows options, in combo order
+pub const CLUSTER_SIZES_BYTES: [u64; 8] = [512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+
+/// Combo index for a cluster size, defaulting to 4K when the size is not offered
+pub fn cluster_size_index(bytes: u64) -> u32 {
+    CLUSTER_SIZES_BYTES
+        .iter()
+        .position(|&size| size == bytes)
+        .unwrap_or(3) as u32
+}
+
+/// FAT32 sectors-per-cluster choices for the BOOT partition, after "Auto" in combo order
+pub const FAT32_SECTORS_PER_CLUSTER: [u64; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+/// Sectors per cluster selected in the FAT32 combo, or None for "Auto"
+pub fn fat32_sectors_from_index(index: Option<u32>) -> Option<u64> {
+    index
+        .and_then(|index| (index as usize).checked_sub(1))
+        .and_then(|index| FAT32_SECTORS_PER_CLUSTER.get(index).copied())
+}
+
+/// Dedicated ESP sizes offered after the default BOOT layout, in MB
+pub const ESP_SIZES_MB: [u64; 3] = [512, 1024, 2048];
+
+/// ESP size selected in the layout combo, or None for the 1 GiB BOOT partition
+pub fn esp_mb_from_index(index: Option<u32>) -> Option<u64> {
+    index
+        .and_then(|index| (index as usize).checked_sub(1))
+        .and_then(|index| ESP_SIZES_MB.get(index).copied())
+}
+
+/// Label for `widget` with an Alt+key mnemonic (`_` before the key). GTK also names the widget by
+/// it for screen readers, which otherwise only hear an unlabelled entry or combo.
+fn mnemonic_label(text: &str, widget: &impl IsA<gtk4::Widget>) -> Label {
+    let label = Label::with_mnemonic(text);
+    label.set_mnemonic_widget(Some(widget));
+    label
+}
+
+/// Name a widget that has no visible label (icon buttons, fields explained by a placeholder only)
+fn set_accessible_label(widget: &impl IsA<gtk4::Accessible>, name: &str) {
+    widget.update_property(&[gtk4::accessible::Property::Label(name)]);
+}
+
+/// Mirror the progress text to assistive technology, which otherwise only reads the percentage
+fn announce_progress_text(progress: &ProgressBar) {
+    progress.connect_text_notify(|bar| {
+        let text = bar.text().unwrap_or_default();
+        bar.update_property(&[gtk4::accessible::Property::ValueText(&text)]);
+    });
+}
 
 /// Create main vertical box for the application
 pub fn create_main_container() -> GtkBox {
@@ -16,14 +68,14 @@ pub fn create_main_container() -> GtkBox {
 /// Create ISO selection widget (label + entry + browse button)
 pub fn create_iso_selection_widget() -> (GtkBox, Entry, Button) {
     let iso_hbox = GtkBox::new(Orientation::Horizontal, 8);
-    let iso_label = Label::new(Some("ISO Image:"));
+    let iso_entry = Entry::builder()
+        .placeholder_text("Select ISO file...")
+        .build();
+    let iso_label = mnemonic_label("_ISO Image:", &iso_entry);
     iso_label.set_halign(gtk4::Align::Start);
     iso_label.set_valign(gtk4::Align::Center);
     iso_label.set_margin_top(3);
     iso_label.set_margin_bottom(3);
-    let iso_entry = Entry::builder()
-        .placeholder_text("Select ISO file...")
-        .build();
     iso_entry.set_hexpand(true);
     iso_entry.set_margin_top(3);
     iso_entry.set_margin_bottom(3);
@@ -33,6 +85,7 @@ pub fn create_iso_selection_widget() -> (GtkBox, Entry, Button) {
     iso_button.set_hexpand(false);
     iso_button.set_halign(gtk4::Align::End);
     iso_button.set_tooltip_text(Some("Browse for ISO file"));
+    set_accessible_label(&iso_button, "Browse for ISO file");
     iso_button.set_margin_top(3);
     iso_button.set_margin_bottom(3);
 
@@ -48,6 +101,89 @@ pub fn create_os_label() -> Label {
     Label::new(None)
 }
 
+/// "Detect (with root)" button with its spinner, shown when detection without root gave up
+pub fn create_detect_root_row() -> (GtkBox, Button, gtk4::Spinner) {
+    let row = GtkBox::new(Orientation::Horizontal, 8);
+    row.set_halign(gtk4::Align::Center);
+    let button = Button::with_label("Detect (with root)");
+    button.set_tooltip_text(Some("Mount the image as root through cli_helper to tell Windows from Linux"));
+    let spinner = gtk4::Spinner::new();
+    row.append(&button);
+    row.append(&spinner);
+    row.set_visible(false);
+    (row, button, spinner)
+}
+
+/// Create the "override detected type" selector (Auto / Force Windows / Force Linux / Force Raw / Restore image)
+pub fn create_os_override_widget() -> (GtkBox, ComboBoxText) {
+    let hbox = GtkBox::new(Orientation::Horizontal, 8);
+    let combo = ComboBoxText::new();
+    let label = mnemonic_label("Image _type:", &combo);
+    label.set_halign(gtk4::Align::Start);
+    label.set_valign(gtk4::Align::Center);
+    combo.append_text("Auto-detect");
+    combo.append_text("Force Windows");
+    combo.append_text("Force Linux");
+    combo.append_text("Force Raw (dd)");
+    combo.append_text("Restore disk image");
+    combo.set_active(Some(0));
+    combo.set_hexpand(true);
+    combo.set_tooltip_text(Some("Override OS detection when it picks the wrong type for this ISO."));
+    hbox.append(&label);
+    hbox.append(&combo);
+
+    (hbox, combo)
+}
+
+/// Create the optional expected-checksum and published-size fields shown under the ISO selector
+pub fn create_checksum_widget() -> (GtkBox, Entry, Entry) {
+    let hbox = GtkBox::new(Orientation::Horizontal, 8);
+    let entry = Entry::builder()
+        .placeholder_text("Optional: published checksum to verify the ISO before writing")
+        .build();
+    let label = mnemonic_label("SHA-_256:", &entry);
+    label.set_halign(gtk4::Align::Start);
+    label.set_valign(gtk4::Align::Center);
+    entry.set_hexpand(true);
+    let size_entry = Entry::builder()
+        .placeholder_text("e.g. 4.89GB")
+        .tooltip_text("Optional: published size, checked instantly to catch incomplete downloads")
+        .width_chars(10)
+        .build();
+    let size_label = mnemonic_label("Si_ze:", &size_entry);
+    size_label.set_valign(gtk4::Align::Center);
+    hbox.append(&label);
+    hbox.append(&entry);
+    hbox.append(&size_label);
+    hbox.append(&size_entry);
+
+    (hbox, entry, size_entry)
+}
+
+/// Create the optional name/description written to the media so a scan can identify it later
+pub fn create_media_label_widget() -> (GtkBox, Entry, Entry) {
+    let hbox = GtkBox::new(Orientation::Horizontal, 8);
+    let name_entry = Entry::builder()
+        .placeholder_text("Optional, e.g. Rescue stick")
+        .tooltip_text("Stored in .majusb/label.json on the stick and shown by \"Scan device\"")
+        .width_chars(18)
+        .build();
+    let name_label = mnemonic_label("_Name:", &name_entry);
+    name_label.set_valign(gtk4::Align::Center);
+    let description_entry = Entry::builder()
+        .placeholder_text("Optional")
+        .build();
+    let description_label = mnemonic_label("_Description:", &description_entry);
+    description_label.set_valign(gtk4::Align::Center);
+    description_entry.set_hexpand(true);
+    hbox.append(&name_label);
+    hbox.append(&name_entry);
+    hbox.append(&description_label);
+    hbox.append(&description_entry);
+
+    (hbox, name_entry, description_entry)
+}
+
 /// Create separator widget
 pub fn create_separator() -> gtk4::Separator {
     let sep = gtk4::Separator::new(Orientation::Horizontal);
@@ -57,37 +193,100 @@ pub fn create_separator() -> gtk4::Separator {
     sep
 }
 
-/// Create device selection widget (label + combo + refresh button)
-pub fn create_device_selection_widget() -> (GtkBox, ComboBoxText, Button) {
+/// Device picker model columns: device path (the entry id, unset for placeholders), label,
+/// risk marker and its colour
+const DEVICE_COLUMNS: [gtk4::glib::Type; 4] = [gtk4::glib::Type::STRING; 4];
+const DEVICE_ID_COLUMN: u32 = 0;
+const DEVICE_LABEL_COLUMN: u32 = 1;
+const DEVICE_MARKER_COLUMN: u32 = 2;
+const DEVICE_COLOR_COLUMN: u32 = 3;
+
+/// Marker colour for a device risk level: green stick, yellow fixed disk or reader, red large disk
+fn risk_color(risk: crate::utils::DeviceRisk) -> &'static str {
+    match risk {
+        crate::utils::DeviceRisk::Removable => "#2e9d3a",
+        crate::utils::DeviceRisk::Fixed => "#d4a017",
+        crate::utils::DeviceRisk::Large => "#d03030",
+    }
+}
+
+/// Remove every entry from the device picker
+pub fn clear_devices(device_combo: &ComboBox) {
+    if let Some(store) = device_combo.model().and_downcast::<ListStore>() {
+        store.clear();
+    }
+}
+
+/// Append an entry to the device picker. Devices carry their path as id and a coloured risk
+/// marker in front of the label; placeholders (`id` None) have neither.
+pub fn append_device(device_combo: &ComboBox, id: Option<&str>, label: &str, risk: Option<crate::utils::DeviceRisk>) {
+    let Some(store) = device_combo.model().and_downcast::<ListStore>() else {
+        return;
+    };
+    let marker = if risk.is_some() { "●" } else { "" };
+    let color = risk.map(risk_color);
+    store.set(
+        &store.append(),
+        &[
+            (DEVICE_ID_COLUMN, &id),
+            (DEVICE_LABEL_COLUMN, &label),
+            (DEVICE_MARKER_COLUMN, &marker),
+            (DEVICE_COLOR_COLUMN, &color),
+        ],
+    );
+}
+
+/// Create device selection widget (label + combo + refresh and scan buttons)
+pub fn create_device_selection_widget() -> (GtkBox, ComboBox, Button, Button) {
     let device_hbox = GtkBox::new(Orientation::Horizontal, 8);
-    let device_label = Label::new(Some("USB Device:"));
+    let device_combo = ComboBox::with_model(&ListStore::new(&DEVICE_COLUMNS));
+    let device_label = mnemonic_label("_USB Device:", &device_combo);
     device_label.set_halign(gtk4::Align::Start);
     device_label.set_valign(gtk4::Align::Center);
     device_label.set_margin_top(3);
     device_label.set_margin_bottom(3);
-    let device_combo = ComboBoxText::new();
+    device_combo.set_id_column(DEVICE_ID_COLUMN as i32);
+    let marker_cell = CellRendererText::new();
+    device_combo.pack_start(&marker_cell, false);
+    device_combo.add_attribute(&marker_cell, "text", DEVICE_MARKER_COLUMN as i32);
+    device_combo.add_attribute(&marker_cell, "foreground", DEVICE_COLOR_COLUMN as i32);
+    let label_cell = CellRendererText::new();
+    device_combo.pack_start(&label_cell, true);
+    device_combo.add_attribute(&label_cell, "text", DEVICE_LABEL_COLUMN as i32);
+    device_combo.set_tooltip_text(Some("Green: removable stick. Yellow: not marked removable (USB disk, SSD or card reader). Red: far larger than a USB stick."));
     device_combo.set_hexpand(true);
     device_combo.set_margin_top(3);
     device_combo.set_margin_bottom(3);
-    device_combo.append_text("(refresh to list devices)");
+    append_device(&device_combo, None, "(refresh to list devices)", None);
+    device_combo.set_active(Some(0));
     let refresh_button = Button::builder()
         .icon_name("view-refresh")
         .build();
     refresh_button.set_hexpand(false);
     refresh_button.set_halign(gtk4::Align::End);
     refresh_button.set_tooltip_text(Some("Refresh device list"));
+    set_accessible_label(&refresh_button, "Refresh device list");
     refresh_button.set_margin_top(3);
     refresh_button.set_margin_bottom(3);
+    let scan_button = Button::builder()
+        .icon_name("system-search")
+        .build();
+    scan_button.set_hexpand(false);
+    scan_button.set_tooltip_text(Some("Scan device: show what is already written on it"));
+    set_accessible_label(&scan_button, "Scan device");
+    scan_button.set_margin_top(3);
+    scan_button.set_margin_bottom(3);
 
     device_hbox.append(&device_label);
     device_hbox.append(&device_combo);
     device_hbox.append(&refresh_button);
+    device_hbox.append(&scan_button);
 
-    (device_hbox, device_combo, refresh_button)
+    (device_hbox, device_combo, refresh_button, scan_button)
 }
 
 /// Create Windows advanced options with title bar and cluster size selection
-pub fn create_windows_advanced_options() -> (GtkBox, ComboBoxText, CheckButton, CheckButton, CheckButton, CheckButton) {
+pub fn create_windows_advanced_options() -> (GtkBox, ComboBoxText, ComboBoxText, CheckButton, CheckButton, CheckButton, CheckButton) {
     let windows_group = GtkBox::new(Orientation::Vertical, 8);
     windows_group.set_visible(false);
 
@@ -105,7 +304,6 @@ pub fn create_windows_advanced_options() -> (GtkBox, ComboBoxText, CheckButton,
     windows_title_bar.append(&right_sep);
     windows_group.append(&windows_title_bar);
 
-    let cluster_label = Label::new(Some("Cluster Size:"));
     let cluster_sizes = vec![
         ("512 bytes", 512),
         ("1K", 1024),
@@ -121,9 +319,23 @@ pub fn create_windows_advanced_options() -> (GtkBox, ComboBoxText, CheckButton,
         cluster_combo.append_text(label);
     }
     cluster_combo.set_active(Some(3)); // Default to 4K (4096 bytes)
+    let cluster_label = mnemonic_label("_Cluster Size (INSTALL, NTFS):", &cluster_combo);
     windows_group.append(&cluster_label);
     windows_group.append(&cluster_combo);
 
+    // Some firmwares only read the BOOT partition with particular FAT32 cluster sizes
+    let fat32_cluster_combo = ComboBoxText::new();
+    let fat32_cluster_label = mnemonic_label("Cluster Size (_BOOT, FAT32):", &fat32_cluster_combo);
+    fat32_cluster_combo.append_text("Auto");
+    for sectors in FAT32_SECTORS_PER_CLUSTER {
+        let label = if sectors == 1 { "1 sector".to_string() } else { format!("{} sectors", sectors) };
+        fat32_cluster_combo.append_text(&label);
+    }
+    fat32_cluster_combo.set_active(Some(0));
+    fat32_cluster_combo.set_tooltip_text(Some("Sectors per cluster passed to mkfs.vfat -s; Auto follows the device block size."));
+    windows_group.append(&fat32_cluster_label);
+    windows_group.append(&fat32_cluster_combo);
+
     // Optional dd mode (not recommended)
     let dd_checkbox = CheckButton::builder()
         .label("Use direct dd mode (⚠️ not recommended for Windows 10/11 UEFI)")
@@ -152,11 +364,11 @@ pub fn create_windows_advanced_options() -> (GtkBox, ComboBoxText, CheckButton,
     windows_group.append(&bypass_secure_boot);
     windows_group.append(&bypass_ram);
 
-    (windows_group, cluster_combo, dd_checkbox, bypass_tpm, bypass_secure_boot, bypass_ram)
+    (windows_group, cluster_combo, fat32_cluster_combo, dd_checkbox, bypass_tpm, bypass_secure_boot, bypass_ram)
 }
 
-/// Create Linux advanced options with title bar, persistence checkbox, and partition table type
-pub fn create_linux_advanced_options() -> (GtkBox, CheckButton, ComboBoxText) {
+/// Create Linux advanced options with title bar, write mode (raw dd / persistence), and partition table type
+pub fn create_linux_advanced_options() -> (GtkBox, CheckButton, CheckButton, ComboBoxText, ComboBoxText, Entry, CheckButton, Entry) {
     let linux_group = GtkBox::new(Orientation::Vertical, 8);
     linux_group.set_visible(false);
 
@@ -174,9 +386,18 @@ pub fn create_linux_advanced_options() -> (GtkBox, CheckButton, ComboBoxText) {
     linux_title_bar.append(&right_sep2);
     linux_group.append(&linux_title_bar);
 
+    // Write mode: raw dd and persistence are mutually exclusive (radio group)
+    let raw_dd_checkbox = CheckButton::builder()
+        .label("Raw write (dd)")
+        .tooltip_text("Copy the ISO image verbatim. No partitions are added after the write.")
+        .build();
     let persistence_checkbox = CheckButton::builder()
         .label("Enable persistence (store changes)")
+        .tooltip_text("Write the ISO with dd, then add a persistence partition in the remaining space.")
         .build();
+    persistence_checkbox.set_group(Some(&raw_dd_checkbox));
+    raw_dd_checkbox.set_active(true);
+    linux_group.append(&raw_dd_checkbox);
     linux_group.append(&persistence_checkbox);
 
     // Partition table type selector
@@ -184,29 +405,322 @@ pub fn create_linux_advanced_options() -> (GtkBox, CheckButton, ComboBoxText) {
     table_type_combo.append_text("GPT (default)");
     table_type_combo.append_text("MBR (msdos)");
     table_type_combo.set_active(Some(0));
-    let table_type_label = Label::new(Some("Partition table type (persistence):"));
+    table_type_combo.set_sensitive(false);
+    let table_type_label = mnemonic_label("Partition table t_ype (persistence or new table):", &table_type_combo);
     linux_group.append(&table_type_label);
     linux_group.append(&table_type_combo);
 
-    (linux_group, persistence_checkbox, table_type_combo)
+    // Placement of the persistence partition (filled with free regions when persistence is enabled)
+    let placement_combo = ComboBoxText::new();
+    placement_combo.append_text("After last partition (default)");
+    placement_combo.set_active(Some(0));
+    placement_combo.set_sensitive(false);
+    let placement_label = mnemonic_label("Persistence _placement:", &placement_combo);
+    linux_group.append(&placement_label);
+    linux_group.append(&placement_combo);
+
+    // Kernel parameters added to the boot entries on top of the persistence defaults
+    let kernel_params_entry = Entry::builder()
+        .placeholder_text("e.g. toram nomodeset")
+        .tooltip_text("Extra kernel parameters added to the boot entries when persistence is enabled, separated by spaces.")
+        .sensitive(false)
+        .build();
+    let kernel_params_label = mnemonic_label("Extra _kernel parameters (persistence):", &kernel_params_entry);
+    linux_group.append(&kernel_params_label);
+    linux_group.append(&kernel_params_entry);
+
+    // Protective MBR repair for firmwares confused by hybrid ISO layouts
+    let fix_pmbr_checkbox = CheckButton::builder()
+        .label("Fix GPT protective MBR")
+        .tooltip_text("After writing, regenerate the protective MBR so stubborn UEFI firmwares boot the stick. May disable legacy BIOS boot on hybrid ISOs.")
+        .build();
+    fix_pmbr_checkbox.set_active(false);
+    linux_group.append(&fix_pmbr_checkbox);
+
+    // Optional write speed cap; the image is then copied by a paced loop instead of dd
+    let max_speed_entry = Entry::builder()
+        .placeholder_text("Unlimited")
+        .tooltip_text("Cap the write speed in MB/s to spare cheap flash drives from sustained full-speed writes. Leave empty or 0 for unlimited.")
+        .input_purpose(gtk4::InputPurpose::Digits)
+        .build();
+    let max_speed_label = mnemonic_label("_Maximum write speed (MB/s):", &max_speed_entry);
+    linux_group.append(&max_speed_label);
+    linux_group.append(&max_speed_entry);
+
+    // The table type only applies to persistence; grey it out for raw writes
+    {
+        let table_type_combo = table_type_combo.clone();
+        let placement_combo = placement_combo.clone();
+        let kernel_params_entry = kernel_params_entry.clone();
+        persistence_checkbox.connect_toggled(move |cb| {
+            table_type_combo.set_sensitive(cb.is_active());
+            placement_combo.set_sensitive(cb.is_active());
+            kernel_params_entry.set_sensitive(cb.is_active());
+        });
+    }
+
+    (linux_group, raw_dd_checkbox, persistence_checkbox, table_type_combo, placement_combo, kernel_params_entry, fix_pmbr_checkbox, max_speed_entry)
+}
+
+/// Create the option that replaces the ISO's partition table and copies its files instead of dd
+pub fn create_repartition_checkbox() -> CheckButton {
+    CheckButton::builder()
+        .label("Create a new partition table and copy files (UEFI only)")
+        .tooltip_text("Wipe the ISO's own layout, create the table chosen below with one FAT32 partition and copy the ISO files to it. Legacy BIOS boot is not set up.")
+        .build()
+}
+
+/// Create the optional storage partition added after a Linux ISO: checkbox, size in MB and
+/// filesystem (exFAT, FAT32)
+pub fn create_data_partition_options() -> (GtkBox, CheckButton, Entry, ComboBoxText) {
+    let row = GtkBox::new(Orientation::Horizontal, 8);
+    let checkbox = CheckButton::builder()
+        .label("Add data partition")
+        .tooltip_text("Append a plain partition behind the ISO (and persistence) for files readable on Windows, macOS and Linux")
+        .build();
+    let size_entry = Entry::builder()
+        .placeholder_text("Size in MB")
+        .width_chars(10)
+        .sensitive(false)
+        .build();
+    let fs_combo = ComboBoxText::new();
+    fs_combo.append_text("exFAT");
+    fs_combo.append_text("FAT32");
+    fs_combo.set_active(Some(0));
+    fs_combo.set_sensitive(false);
+    set_accessible_label(&size_entry, "Data partition size in MB");
+    set_accessible_label(&fs_combo, "Data partition filesystem");
+    {
+        let size_entry = size_entry.clone();
+        let fs_combo = fs_combo.clone();
+        checkbox.connect_toggled(move |cb| {
+            size_entry.set_sensitive(cb.is_active());
+            fs_combo.set_sensitive(cb.is_active());
+        });
+    }
+    row.append(&checkbox);
+    row.append(&size_entry);
+    row.append(&fs_combo);
+    (row, checkbox, size_entry, fs_combo)
+}
+
+/// Answer file (kickstart/preseed) picker for installer ISOs (hidden until an installer is detected)
+pub fn create_answer_file_picker() -> (GtkBox, Label, Entry, Button) {
+    let picker_box = GtkBox::new(Orientation::Vertical, 4);
+    picker_box.set_visible(false);
+    let row = GtkBox::new(Orientation::Horizontal, 8);
+    let answer_file_entry = Entry::builder()
+        .placeholder_text("None (interactive install)")
+        .tooltip_text("Kickstart/preseed go to the stick's EFI partition; autoinstall user-data (with a meta-data file next to it, if any) to a new CIDATA partition.")
+        .hexpand(true)
+        .build();
+    let picker_label = mnemonic_label("Answer _file (automated install):", &answer_file_entry);
+    picker_label.set_halign(gtk4::Align::Start);
+    let browse_button = Button::with_label("Browse...");
+    set_accessible_label(&browse_button, "Browse for answer file");
+    row.append(&answer_file_entry);
+    row.append(&browse_button);
+    picker_box.append(&picker_label);
+    picker_box.append(&row);
+    (picker_box, picker_label, answer_file_entry, browse_button)
+}
+
+/// Create the optional `$OEM$` folder picker shown in the Windows options
+pub fn create_oem_folder_picker() -> (GtkBox, Entry, Button) {
+    let picker_box = GtkBox::new(Orientation::Vertical, 4);
+    let row = GtkBox::new(Orientation::Horizontal, 8);
+    let oem_folder_entry = Entry::builder()
+        .placeholder_text("None")
+        .tooltip_text("Copied to sources/$OEM$ on the INSTALL partition; needs $$, $1 or drive-letter subfolders.")
+        .hexpand(true)
+        .build();
+    let picker_label = mnemonic_label("$_OEM$ folder (scripts and drivers for Setup):", &oem_folder_entry);
+    picker_label.set_halign(gtk4::Align::Start);
+    let browse_button = Button::with_label("Browse...");
+    set_accessible_label(&browse_button, "Browse for $OEM$ folder");
+    row.append(&oem_folder_entry);
+    row.append(&browse_button);
+    picker_box.append(&picker_label);
+    picker_box.append(&row);
+    (picker_box, oem_folder_entry, browse_button)
+}
+
+/// Create the EFI tool picker: an EFI application (e.g. memtest86+) given its own boot partition
+pub fn create_efi_tool_picker() -> (GtkBox, Entry, Button) {
+    let picker_box = GtkBox::new(Orientation::Vertical, 4);
+    let row = GtkBox::new(Orientation::Horizontal, 8);
+    let tool_entry = Entry::builder()
+        .placeholder_text("None")
+        .tooltip_text("An EFI application written to a small TOOLS partition after INSTALL, listed as its own entry in the firmware boot menu.")
+        .hexpand(true)
+        .build();
+    let picker_label = mnemonic_label("Extra _EFI tool (e.g. memtest86+):", &tool_entry);
+    picker_label.set_halign(gtk4::Align::Start);
+    let browse_button = Button::with_label("Browse...");
+    set_accessible_label(&browse_button, "Browse for EFI tool");
+    row.append(&tool_entry);
+    row.append(&browse_button);
+    picker_box.append(&picker_label);
+    picker_box.append(&row);
+    (picker_box, tool_entry, browse_button)
+}
+
+/// Create the entry for extra rsync patterns skipped when copying the INSTALL partition
+pub fn create_exclude_entry() -> (GtkBox, Entry) {
+    let exclude_box = GtkBox::new(Orientation::Vertical, 4);
+    let exclude_entry = Entry::builder()
+        .placeholder_text("e.g. sources/??-??/ support/")
+        .tooltip_text("Files matching these patterns, separated by spaces, are not copied to INSTALL. Patterns that would skip files Setup needs are refused.")
+        .hexpand(true)
+        .build();
+    let exclude_label = mnemonic_label("S_kip on INSTALL (rsync patterns):", &exclude_entry);
+    exclude_label.set_halign(gtk4::Align::Start);
+    exclude_box.append(&exclude_label);
+    exclude_box.append(&exclude_entry);
+    (exclude_box, exclude_entry)
+}
+
+/// Create the "quick boot test" checkbox: boot files only, to see whether a machine lists the stick
+pub fn create_boot_test_checkbox() -> CheckButton {
+    CheckButton::builder()
+        .label("Quick boot test only (boot files, cannot install)")
+        .tooltip_text("Partition the stick and copy only the boot files, skipping the multi-gigabyte INSTALL copy. Use it to check that a machine lists the stick in its boot menu; the result cannot install Windows.")
+        .build()
+}
+
+/// Create the "Windows 7/8 legacy" checkbox: MBR, one active NTFS partition, BIOS boot code
+pub fn create_legacy_checkbox() -> CheckButton {
+    CheckButton::builder()
+        .label("Windows 7/8 legacy layout (MBR, single NTFS partition)")
+        .tooltip_text("Write one active NTFS partition on an MBR table with BIOS boot code (needs ms-sys), as Windows 7 and 8.1 setup expect. Checked automatically when the ISO is detected as Windows 7/8.")
+        .build()
+}
+
+/// Create the row that applies the bypass checkboxes to a Windows stick written earlier
+pub fn create_unattend_update_row() -> (GtkBox, Button, CheckButton) {
+    let row = GtkBox::new(Orientation::Horizontal, 8);
+    let update_button = Button::builder()
+        .label("Apply bypass to existing stick")
+        .tooltip_text("Rewrite Autounattend.xml on the selected stick with the bypass options above, without rewriting the stick. No options removes it.")
+        .build();
+    let boot_wim_checkbox = CheckButton::builder()
+        .label("Also update boot.wim")
+        .tooltip_text("Replace the answer file inside sources/boot.wim as well (needs wimlib-imagex).")
+        .build();
+    row.append(&update_button);
+    row.append(&boot_wim_checkbox);
+    (row, update_button, boot_wim_checkbox)
+}
+
+/// Create the Windows partition layout picker: 1 GiB BOOT (default) or a dedicated ESP
+pub fn create_esp_layout_combo() -> (GtkBox, ComboBoxText) {
+    let layout_box = GtkBox::new(Orientation::Vertical, 4);
+    let layout_combo = ComboBoxText::new();
+    let layout_label = mnemonic_label("Boot partition la_yout:", &layout_combo);
+    layout_label.set_halign(gtk4::Align::Start);
+    layout_combo.append_text("1 GiB BOOT (FAT32) with boot.wim");
+    for size_mb in ESP_SIZES_MB {
+        layout_combo.append_text(&format!("{} MB EFI System Partition with boot.wim", size_mb));
+    }
+    layout_combo.set_active(Some(0));
+    layout_combo.set_tooltip_text(Some(
+        "A dedicated ESP holds the EFI loader, the boot configuration and boot.wim; setup stays on the NTFS partition.",
+    ));
+    layout_box.append(&layout_label);
+    layout_box.append(&layout_combo);
+    (layout_box, layout_combo)
 }
 
-/// Create button container with write and advanced buttons
-pub fn create_button_container() -> (GtkBox, Button, Button) {
+/// Create the raw write target picker (hidden by default): the whole device or one of its partitions
+pub fn create_raw_target_options() -> (GtkBox, ComboBoxText) {
+    let raw_group = GtkBox::new(Orientation::Vertical, 4);
+    raw_group.set_visible(false);
+    let target_combo = ComboBoxText::new();
+    let target_label = mnemonic_label("Write the image t_o:", &target_combo);
+    target_label.set_halign(gtk4::Align::Start);
+    target_combo.append_text("Whole device (default)");
+    target_combo.set_active(Some(0));
+    target_combo.set_tooltip_text(Some(
+        "Advanced: copy the image into one existing partition, e.g. a recovery partition. \
+         The partition table and the other partitions are kept.",
+    ));
+    let warning = Label::new(Some(
+        "Writing into a partition replaces everything on it and does not change its type or size.",
+    ));
+    warning.set_halign(gtk4::Align::Start);
+    warning.set_wrap(true);
+    warning.add_css_class("warning");
+    raw_group.append(&target_label);
+    raw_group.append(&target_combo);
+    raw_group.append(&warning);
+    (raw_group, target_combo)
+}
+
+/// Create button container with write, multi-write, advanced, copy-command, clone-to-image and preferences buttons
+pub fn create_button_container() -> (GtkBox, Button, Button, Button, Button, Button, Button) {
     let button_hbox = GtkBox::new(Orientation::Horizontal, 8);
     button_hbox.set_halign(gtk4::Align::Center);
-    let write_button = Button::with_label("Write to USB");
-    let advanced_button = Button::with_label("Advanced options");
+    let write_button = Button::with_mnemonic("_Write to USB");
+    let multi_write_button = Button::builder()
+        .icon_name("media-removable")
+        .tooltip_text("Write the same image to several USB devices at once")
+        .build();
+    set_accessible_label(&multi_write_button, "Write to several USB devices");
+    let advanced_button = Button::with_mnemonic("_Advanced options");
+    let copy_command_button = Button::builder()
+        .icon_name("edit-copy")
+        .tooltip_text("Copy as command: the cli_helper invocation for the current settings")
+        .build();
+    set_accessible_label(&copy_command_button, "Copy as command");
+    let clone_button = Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save the selected USB stick as an image file")
+        .build();
+    set_accessible_label(&clone_button, "Save USB stick as image");
+    let preferences_button = Button::builder()
+        .icon_name("preferences-system")
+        .tooltip_text("Preferences")
+        .build();
+    set_accessible_label(&preferences_button, "Preferences");
     button_hbox.append(&write_button);
+    button_hbox.append(&multi_write_button);
     button_hbox.append(&advanced_button);
+    button_hbox.append(&copy_command_button);
+    button_hbox.append(&clone_button);
+    button_hbox.append(&preferences_button);
 
-    (button_hbox, write_button, advanced_button)
+    (button_hbox, write_button, multi_write_button, advanced_button, copy_command_button, clone_button, preferences_button)
+}
+
+/// Container for per-device progress rows when writing several devices (hidden until used)
+pub fn create_multi_progress_area() -> GtkBox {
+    let area = GtkBox::new(Orientation::Vertical, 4);
+    area.set_visible(false);
+    area
+}
+
+/// One device row in the multi-device progress area
+pub fn create_device_progress_row(device: &str) -> (GtkBox, ProgressBar) {
+    let row = GtkBox::new(Orientation::Horizontal, 8);
+    let label = Label::new(Some(device));
+    label.set_width_chars(12);
+    label.set_halign(gtk4::Align::Start);
+    let progress = ProgressBar::new();
+    progress.set_show_text(true);
+    progress.set_text(Some("Waiting..."));
+    progress.set_hexpand(true);
+    progress.set_valign(gtk4::Align::Center);
+    set_accessible_label(&progress, &format!("Progress of {}", device));
+    announce_progress_text(&progress);
+    row.append(&label);
+    row.append(&progress);
+    (row, progress)
 }
 
 /// Create log area with scrolled window
 pub fn create_log_area() -> (Label, TextView, ScrolledWindow) {
-    let log_label = Label::new(Some("Log:"));
     let log_view = TextView::new();
+    let log_label = mnemonic_label("_Log:", &log_view);
     log_view.set_editable(false);
     log_view.set_wrap_mode(gtk4::WrapMode::Word);
     log_view.set_monospace(true);
@@ -218,15 +732,28 @@ pub fn create_log_area() -> (Label, TextView, ScrolledWindow) {
     log_view.set_right_margin(10);
     log_view.set_justification(gtk4::Justification::Left);
     log_view.set_cursor_visible(false);
+    // Read-only: let Tab leave the log instead of being swallowed by it
+    log_view.set_accepts_tab(false);
     let log_scroll = ScrolledWindow::builder().min_content_height(100).child(&log_view).build();
 
     (log_label, log_view, log_scroll)
 }
 
+/// Pause/Resume toggle shown while a write that can be paused is running
+pub fn create_pause_button() -> Button {
+    let button = Button::with_mnemonic("_Pause");
+    button.set_tooltip_text(Some("Pause the write; the device stays idle until you resume"));
+    button.set_halign(gtk4::Align::Center);
+    button.set_visible(false);
+    button
+}
+
 /// Create progress bar
 pub fn create_progress_bar() -> ProgressBar {
     let progress_bar = ProgressBar::new();
     progress_bar.set_show_text(false);
     progress_bar.set_fraction(0.0);
+    set_accessible_label(&progress_bar, "Write progress");
+    announce_progress_text(&progress_bar);
     progress_bar
 }
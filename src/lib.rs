@@ -2,6 +2,13 @@
 // This file allows the main crate to be used as a library by binaries in src/bin/
 
 pub mod utils;
+pub mod command;
 pub mod flows;
 pub mod error;
 pub mod windows;
+pub mod settings;
+pub mod quirks;
+pub mod config;
+pub mod checksum;
+pub mod write_state;
+pub mod fail_point;
@@ -1,8 +1,15 @@
 mod flows;
 mod gui;
 mod utils;
+mod command;
 mod error;
 mod windows;
+mod settings;
+mod quirks;
+mod config;
+mod checksum;
+mod write_state;
+mod fail_point;
 
 use gui::run_gui;
 
@@ -0,0 +1,116 @@
+//! Known-problematic USB devices, keyed by the USB vendor:product ID read from sysfs, and how
+//! writes adapt to them. Add an entry with the ID from `lsusb` and the smallest change that helps.
+
+use std::io::{self, Write};
+
+use crate::settings::Tuning;
+use crate::utils::usb_id;
+
+/// Behaviour changes for one USB device. Neutral values (100 %, 0 s, false) leave a setting alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceQuirk {
+    /// `idVendor`, lowercase hex
+    pub vendor: &'static str,
+    /// `idProduct`, lowercase hex
+    pub product: &'static str,
+    pub name: &'static str,
+    /// Scale applied on top of the configured settle delays, in percent
+    pub settle_delay_percent: u64,
+    /// Lower bound for the partition node and re-enumeration timeouts, in seconds
+    pub min_timeout_secs: u64,
+    /// Leave `--whole-file` out of the Windows copies
+    pub avoid_whole_file: bool,
+    /// What goes wrong without the quirk
+    pub reason: &'static str,
+}
+
+const QUIRKS: [DeviceQuirk; 2] = [
+    DeviceQuirk {
+        vendor: "0bda",
+        product: "0129",
+        name: "Realtek RTS5129 card reader",
+        settle_delay_percent: 300,
+        min_timeout_secs: 45,
+        avoid_whole_file: false,
+        reason: "the reader is slow to publish partitions after the table is rewritten",
+    },
+    DeviceQuirk {
+        vendor: "152d",
+        product: "0578",
+        name: "JMicron JMS578 bridge",
+        settle_delay_percent: 100,
+        min_timeout_secs: 0,
+        avoid_whole_file: true,
+        reason: "long whole-file writes through its cache stall until the copy times out",
+    },
+];
+
+/// Quirk entry for a USB ID, as read from sysfs
+pub fn find_quirk(vendor: &str, product: &str) -> Option<&'static DeviceQuirk> {
+    QUIRKS
+        .iter()
+        .find(|quirk| quirk.vendor.eq_ignore_ascii_case(vendor) && quirk.product.eq_ignore_ascii_case(product))
+}
+
+/// Quirk entry for the USB device behind `device` (e.g. `/dev/sdb`)
+pub fn device_quirk(device: &str) -> Option<&'static DeviceQuirk> {
+    let (vendor, product) = usb_id(device)?;
+    find_quirk(&vendor, &product)
+}
+
+impl DeviceQuirk {
+    /// `tuning` adjusted for this device
+    pub fn apply(&self, mut tuning: Tuning) -> Tuning {
+        tuning.settle_delay_percent = tuning.settle_delay_percent.saturating_mul(self.settle_delay_percent) / 100;
+        tuning.partition_node_timeout_secs = tuning.partition_node_timeout_secs.max(self.min_timeout_secs);
+        tuning.reenumeration_timeout_secs = tuning.reenumeration_timeout_secs.max(self.min_timeout_secs);
+        tuning
+    }
+
+    /// Changes made for this device, e.g. "settle delays x3, timeouts of at least 45 s"
+    pub fn changes(&self) -> String {
+        let mut changes = Vec::new();
+        if self.settle_delay_percent != 100 {
+            changes.push(format!("settle delays x{}", self.settle_delay_percent as f64 / 100.0));
+        }
+        if self.min_timeout_secs > 0 {
+            changes.push(format!("timeouts of at least {} s", self.min_timeout_secs));
+        }
+        if self.avoid_whole_file {
+            changes.push("no --whole-file copies".to_string());
+        }
+        changes.join(", ")
+    }
+}
+
+/// Log the quirk applied to `device`, if any, once at the start of a write
+pub fn log_device_quirk(device: &str, log: &mut dyn Write) -> io::Result<()> {
+    if let Some(quirk) = device_quirk(device) {
+        writeln!(
+            log,
+            "Device quirk for {} ({}:{}): {} ({})",
+            quirk.name, quirk.vendor, quirk.product, quirk.changes(), quirk.reason
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quirks_adjust_tuning_for_listed_devices_only() {
+        assert!(find_quirk("abcd", "0001").is_none());
+        let reader = find_quirk("0BDA", "0129").unwrap();
+        let tuning = reader.apply(Tuning::default());
+        assert_eq!(tuning.settle_delay_percent, 300);
+        assert_eq!(tuning.partition_node_timeout_secs, 45);
+        assert_eq!(tuning.reenumeration_timeout_secs, 45);
+        assert_eq!(reader.changes(), "settle delays x3, timeouts of at least 45 s");
+
+        let bridge = find_quirk("152d", "0578").unwrap();
+        assert_eq!(bridge.apply(Tuning::default()), Tuning::default());
+        assert_eq!(bridge.changes(), "no --whole-file copies");
+    }
+}
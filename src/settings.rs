@@ -0,0 +1,365 @@
+//! User preferences persisted as TOML in ~/.config/majusb/config.toml
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::utils::get_user_home;
+
+/// How the GUI obtains root privileges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ElevationMethod {
+    Pkexec,
+    Sudo,
+}
+
+impl ElevationMethod {
+    /// Program used to relaunch the GUI as root
+    pub fn program(self) -> &'static str {
+        match self {
+            ElevationMethod::Pkexec => "pkexec",
+            ElevationMethod::Sudo => "sudo",
+        }
+    }
+}
+
+/// Amount of detail written to the log view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogVerbosity {
+    Normal,
+    Verbose,
+}
+
+/// What the Windows flow's rsync copies are tuned for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CopyStrategy {
+    /// Copy whole files to USB devices (`--whole-file`): fastest onto freshly formatted media
+    #[default]
+    FirstWrite,
+    /// Delta transfer (`--no-whole-file`): only changed blocks are written when rewriting a stick
+    Rewrite,
+}
+
+/// When erasing a device also needs its path typed, on top of the usual confirmation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TypedConfirmation {
+    #[default]
+    Off,
+    /// Devices `utils::assess_device_risk` flags: fixed or very large disks
+    RiskyDevices,
+    Always,
+}
+
+impl TypedConfirmation {
+    /// Whether a device with these risk `reasons` must have its path typed before it is erased
+    pub fn required(self, reasons: &[String]) -> bool {
+        match self {
+            TypedConfirmation::Off => false,
+            TypedConfirmation::RiskyDevices => !reasons.is_empty(),
+            TypedConfirmation::Always => true,
+        }
+    }
+}
+
+/// Retry counts and waits for timing-sensitive steps (partition table refresh, udev settling,
+/// re-enumeration, copy retries). Raise them for slow or flaky USB controllers. Each field can
+/// also be set with the `MAJUSB_*` environment variable named in `ENV_OVERRIDES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tuning {
+    /// Attempts to get the kernel to see a rewritten partition table
+    pub table_refresh_attempts: u32,
+    /// Scale for the fixed settle sleeps after partitioning, in percent (200 doubles them)
+    pub settle_delay_percent: u64,
+    /// Seconds to wait for a device to come back after a rescan or power cycle
+    pub reenumeration_timeout_secs: u64,
+    /// Seconds to wait for a new partition's node before formatting it
+    pub partition_node_timeout_secs: u64,
+    /// Attempts at copying the Windows INSTALL files when the device stalls
+    pub copy_attempts: u32,
+    /// Seconds between those copy attempts
+    pub copy_retry_delay_secs: u64,
+    /// Seconds a Windows file copy may go without progress before it is stopped as stalled;
+    /// 0 waits forever
+    pub copy_stall_timeout_secs: u64,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            table_refresh_attempts: 5,
+            settle_delay_percent: 100,
+            reenumeration_timeout_secs: 30,
+            partition_node_timeout_secs: 15,
+            copy_attempts: 3,
+            copy_retry_delay_secs: 5,
+            copy_stall_timeout_secs: 120,
+        }
+    }
+}
+
+impl Tuning {
+    /// Environment variables that override the config file, by field
+    pub const ENV_OVERRIDES: [&'static str; 7] = [
+        "MAJUSB_TABLE_REFRESH_ATTEMPTS",
+        "MAJUSB_SETTLE_DELAY_PERCENT",
+        "MAJUSB_REENUMERATION_TIMEOUT_SECS",
+        "MAJUSB_PARTITION_NODE_TIMEOUT_SECS",
+        "MAJUSB_COPY_ATTEMPTS",
+        "MAJUSB_COPY_RETRY_DELAY_SECS",
+        "MAJUSB_COPY_STALL_TIMEOUT_SECS",
+    ];
+
+    /// Values for this process: the `[tuning]` table of the config file, then the environment.
+    /// Read once; later changes to the config file apply to the next run.
+    pub fn current() -> Tuning {
+        static CURRENT: OnceLock<Tuning> = OnceLock::new();
+        *CURRENT.get_or_init(|| Settings::load().tuning.with_overrides(|name| std::env::var(name).ok()))
+    }
+
+    /// `current()` adjusted for a device listed in the quirks table (see `crate::quirks`)
+    pub fn for_device(device: &str) -> Tuning {
+        let tuning = Tuning::current();
+        crate::quirks::device_quirk(device).map_or(tuning, |quirk| quirk.apply(tuning))
+    }
+
+    /// Apply overrides looked up by variable name; unparsable values are ignored
+    pub fn with_overrides(mut self, lookup: impl Fn(&str) -> Option<String>) -> Tuning {
+        let [refresh, settle, reenumeration, node, copies, delay, stall] = Self::ENV_OVERRIDES;
+        let number = |name: &str| {
+            let value = lookup(name)?;
+            let parsed = value.trim().parse::<u64>().ok();
+            if parsed.is_none() {
+                println!("[SETTINGS] Ignoring {}={}: not a whole number", name, value);
+            }
+            parsed
+        };
+        if let Some(value) = number(refresh) {
+            self.table_refresh_attempts = value.clamp(1, u32::MAX as u64) as u32;
+        }
+        if let Some(value) = number(settle) {
+            self.settle_delay_percent = value;
+        }
+        if let Some(value) = number(reenumeration) {
+            self.reenumeration_timeout_secs = value;
+        }
+        if let Some(value) = number(node) {
+            self.partition_node_timeout_secs = value;
+        }
+        if let Some(value) = number(copies) {
+            self.copy_attempts = value.clamp(1, u32::MAX as u64) as u32;
+        }
+        if let Some(value) = number(delay) {
+            self.copy_retry_delay_secs = value;
+        }
+        if let Some(value) = number(stall) {
+            self.copy_stall_timeout_secs = value;
+        }
+        self
+    }
+
+    /// How long a copy may make no progress, or None when stalls are not detected
+    pub fn copy_stall_timeout(&self) -> Option<Duration> {
+        (self.copy_stall_timeout_secs > 0).then(|| Duration::from_secs(self.copy_stall_timeout_secs))
+    }
+
+    /// A fixed settle sleep of `millis`, scaled by `settle_delay_percent`
+    pub fn settle(&self, millis: u64) -> Duration {
+        Duration::from_millis(millis.saturating_mul(self.settle_delay_percent) / 100)
+    }
+}
+
+/// Persisted user preferences. Missing keys fall back to defaults so older files keep loading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Default NTFS cluster size for the Windows flow, in bytes
+    pub default_cluster_bytes: u64,
+    /// Check after writing that the media holds a valid EFI loader
+    pub verify_after_write: bool,
+    /// Leave the written media mounted read-only so it can be browsed
+    pub inspect_after_write: bool,
+    /// Flush the device's buffers and read back its ends after writing, for USB bridges that cache writes
+    pub thorough_flush: bool,
+    /// Store SHA-256 hashes of the written files on the media after writing
+    pub checksum_manifest: bool,
+    /// Copy a Windows ISO to local disk once when writing several sticks, instead of mounting it for each
+    pub stage_batch_writes: bool,
+    /// Whether the Windows copies favour first writes or rewrites of the same stick
+    pub copy_strategy: CopyStrategy,
+    /// When the device path must be typed to confirm erasing it
+    pub typed_confirmation: TypedConfirmation,
+    /// Privilege escalation method
+    pub elevation_method: ElevationMethod,
+    /// Log verbosity in the GUI
+    pub log_verbosity: LogVerbosity,
+    /// Directory for the Windows flow's temporary mount points (empty for `config::temp::MOUNT_BASE`)
+    pub mount_base: String,
+    /// Retry counts and waits, edited in the config file
+    pub tuning: Tuning,
+}
+
+/// Environment variable that overrides `mount_base`
+pub const MOUNT_BASE_ENV: &str = "MAJUSB_MOUNT_BASE";
+
+/// Base directory for temporary mounts chosen by the user or packager: `MAJUSB_MOUNT_BASE`, then
+/// `mount_base` in the config file. None leaves the built-in default.
+pub fn configured_mount_base() -> Option<String> {
+    std::env::var(MOUNT_BASE_ENV)
+        .ok()
+        .or_else(|| Some(Settings::load().mount_base))
+        .map(|dir| dir.trim().to_string())
+        .filter(|dir| !dir.is_empty())
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            default_cluster_bytes: 4096,
+            verify_after_write: false,
+            inspect_after_write: false,
+            thorough_flush: false,
+            checksum_manifest: false,
+            stage_batch_writes: false,
+            copy_strategy: CopyStrategy::FirstWrite,
+            typed_confirmation: TypedConfirmation::Off,
+            elevation_method: ElevationMethod::Pkexec,
+            log_verbosity: LogVerbosity::Normal,
+            mount_base: String::new(),
+            tuning: Tuning::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Location of the config file for the invoking (non-root) user
+    pub fn path() -> PathBuf {
+        let base = std::env::var("XDG_CONFIG_HOME")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(get_user_home()).join(".config"));
+        base.join("majusb").join("config.toml")
+    }
+
+    /// Load settings, falling back to defaults when the file is missing or invalid
+    pub fn load() -> Self {
+        match fs::read_to_string(Self::path()) {
+            Ok(text) => Self::from_toml(&text).unwrap_or_else(|e| {
+                println!("[SETTINGS] Ignoring invalid config file: {}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Write settings to the config file, creating the directory if needed
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, self.to_toml()?)?;
+        hand_back_to_original_user(&path);
+        Ok(())
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    pub fn to_toml(&self) -> io::Result<String> {
+        toml::to_string_pretty(self).map_err(io::Error::other)
+    }
+}
+
+/// The GUI runs elevated; keep files in the user's home owned by the user who launched it (best effort)
+pub(crate) fn hand_back_to_original_user(path: &std::path::Path) {
+    if let Ok(user) = std::env::var("ORIGINAL_USER")
+        && !user.is_empty()
+        && user != "root"
+    {
+        if let Some(dir) = path.parent() {
+            let _ = std::process::Command::new("chown").arg(&user).arg(dir).status();
+        }
+        let _ = std::process::Command::new("chown").arg(&user).arg(path).status();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip_through_toml() {
+        let settings = Settings {
+            default_cluster_bytes: 65536,
+            verify_after_write: true,
+            inspect_after_write: true,
+            thorough_flush: true,
+            checksum_manifest: true,
+            stage_batch_writes: true,
+            copy_strategy: CopyStrategy::Rewrite,
+            typed_confirmation: TypedConfirmation::RiskyDevices,
+            elevation_method: ElevationMethod::Sudo,
+            log_verbosity: LogVerbosity::Verbose,
+            mount_base: "/var/mnt".to_string(),
+            tuning: Tuning { copy_attempts: 6, ..Tuning::default() },
+        };
+        let text = settings.to_toml().unwrap();
+        assert_eq!(Settings::from_toml(&text).unwrap(), settings);
+    }
+
+    #[test]
+    fn missing_keys_use_defaults() {
+        let settings = Settings::from_toml("verify_after_write = true\n").unwrap();
+        assert!(settings.verify_after_write);
+        assert_eq!(settings.default_cluster_bytes, 4096);
+        assert_eq!(settings.elevation_method, ElevationMethod::Pkexec);
+        assert_eq!(settings.tuning, Tuning::default());
+
+        // Files from versions with the API key still load, and the key is dropped on the next save
+        let old = Settings::from_toml("api_key = \"secret\"\ndownload_dir = \"/tmp/isos\"\n").unwrap();
+        assert!(!old.to_toml().unwrap().contains("secret"));
+    }
+
+    #[test]
+    fn typed_confirmation_follows_the_device_risk() {
+        let risky = ["it is not removable".to_string()];
+        assert!(!TypedConfirmation::Off.required(&risky));
+        assert!(TypedConfirmation::RiskyDevices.required(&risky));
+        assert!(!TypedConfirmation::RiskyDevices.required(&[]));
+        assert!(TypedConfirmation::Always.required(&[]));
+        let settings = Settings::from_toml("typed_confirmation = \"risky-devices\"\n").unwrap();
+        assert_eq!(settings.typed_confirmation, TypedConfirmation::RiskyDevices);
+    }
+
+    #[test]
+    fn tuning_reads_config_then_environment() {
+        let settings = Settings::from_toml("[tuning]\ntable_refresh_attempts = 10\nsettle_delay_percent = 200\n").unwrap();
+        assert_eq!(settings.tuning.table_refresh_attempts, 10);
+        assert_eq!(settings.tuning.copy_attempts, 3);
+        assert_eq!(settings.tuning.settle(300), Duration::from_millis(600));
+
+        let tuning = settings.tuning.with_overrides(|name| match name {
+            "MAJUSB_TABLE_REFRESH_ATTEMPTS" => Some("0".to_string()),
+            "MAJUSB_COPY_RETRY_DELAY_SECS" => Some(" 12 ".to_string()),
+            "MAJUSB_REENUMERATION_TIMEOUT_SECS" => Some("soon".to_string()),
+            "MAJUSB_COPY_STALL_TIMEOUT_SECS" => Some("0".to_string()),
+            _ => None,
+        });
+        assert_eq!(tuning.table_refresh_attempts, 1);
+        assert_eq!(tuning.copy_retry_delay_secs, 12);
+        assert_eq!(tuning.reenumeration_timeout_secs, 30);
+        assert_eq!(tuning.settle_delay_percent, 200);
+        assert_eq!(settings.tuning.copy_stall_timeout(), Some(Duration::from_secs(120)));
+        assert_eq!(tuning.copy_stall_timeout(), None);
+    }
+}
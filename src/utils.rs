@@ -3,10 +3,15 @@
 use std::collections::HashMap;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::os::unix::fs::PermissionsExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
 
+use crate::error::{IoResultExt, UsbCreatorError, UsbCreatorResult};
+use crate::windows::wim::{InstallImage, WindowsFlavor};
 use libc; // For geteuid
 use serde_json; // For JSON parsing
 use which; // To check if a binary exists
@@ -38,6 +43,256 @@ pub fn parse_rsync_progress(line: &str) -> Option<(u64, Option<f64>)> {
     Some((bytes, speed_mb))
 }
 
+/// Parse a `[PROGRESS] <phase>: ... (<n>%)` log line into (fraction 0.0-1.0, label).
+pub fn parse_progress_line(line: &str) -> Option<(f64, String)> {
+    let rest = line.trim().strip_prefix("[PROGRESS]")?.trim();
+    let open = rest.rfind('(')?;
+    let percent = rest[open + 1..].strip_suffix("%)")?.trim().parse::<f64>().ok()?;
+    Some(((percent / 100.0).clamp(0.0, 1.0), rest.to_string()))
+}
+
+/// Sum Dirty + Writeback (in kB) from a /proc/meminfo listing
+fn parse_meminfo_pending_kb(content: &str) -> Option<u64> {
+    let mut total = None;
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        if matches!(parts.next(), Some("Dirty:" | "Writeback:")) {
+            let kb = parts.next()?.parse::<u64>().ok()?;
+            total = Some(total.unwrap_or(0) + kb);
+        }
+    }
+    total
+}
+
+/// Bytes of page cache still waiting to be written back to storage
+pub fn pending_writeback_bytes() -> io::Result<u64> {
+    let content = fs::read_to_string("/proc/meminfo")?;
+    parse_meminfo_pending_kb(&content)
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| io::Error::other("Dirty/Writeback not found in /proc/meminfo"))
+}
+
+/// Run `sync` while polling the kernel's dirty page counters, writing
+/// `[PROGRESS] flush: ...` lines so callers can show the flush advancing.
+pub fn flush_with_progress(log: &mut dyn io::Write) -> io::Result<()> {
+    let initial = pending_writeback_bytes().unwrap_or(0);
+    writeln!(log, "Flushing {} MB of cached writes to disk...", initial / 1024 / 1024)?;
+    sync_watching_writeback(&mut |remaining, percent| {
+        writeln!(log, "[PROGRESS] flush: {} MB remaining ({}%)", remaining / 1024 / 1024, percent)?;
+        log.flush().ok();
+        Ok(())
+    })
+}
+
+/// Run `sync`, calling `report` with the bytes still waiting for writeback and the percentage
+/// drained so far each time that percentage changes (and with 0, 100 once `sync` returns)
+pub fn sync_watching_writeback(report: &mut dyn FnMut(u64, u8) -> io::Result<()>) -> io::Result<()> {
+    let initial = pending_writeback_bytes().unwrap_or(0);
+    let mut child = Command::new("sync").spawn()?;
+    let mut last_percent = None;
+    loop {
+        let done = child.try_wait()?.is_some();
+        let remaining = if done { 0 } else { pending_writeback_bytes().unwrap_or(0).min(initial) };
+        let percent = ((initial - remaining) * 100).checked_div(initial).unwrap_or(100) as u8;
+        if last_percent != Some(percent) {
+            report(remaining, percent)?;
+            last_percent = Some(percent);
+        }
+        if done {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+    Ok(())
+}
+
+/// A partition as it actually ended up on the device, read back with `parted -ms`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub number: u32,
+    pub start_sector: u64,
+    pub end_sector: u64,
+    pub size_sectors: u64,
+    pub fs_type: String,
+    pub name: String,
+    /// Filesystem cluster size read from the boot sector (FAT/NTFS/exFAT), when known
+    pub cluster_bytes: Option<u64>,
+}
+
+impl PartitionInfo {
+    /// One-line description for logs
+    pub fn summary(&self, sector_size: u64) -> String {
+        format!(
+            "#{} start={}s end={}s size={}s ({} MiB) fs={} name={} cluster={}",
+            self.number,
+            self.start_sector,
+            self.end_sector,
+            self.size_sectors,
+            self.size_sectors * sector_size / (1024 * 1024),
+            if self.fs_type.is_empty() { "-" } else { &self.fs_type },
+            if self.name.is_empty() { "-" } else { &self.name },
+            self.cluster_bytes
+                .map(|c| format!("{} bytes", c))
+                .unwrap_or_else(|| "unknown".to_string()),
+        )
+    }
+}
+
+/// Unallocated region on a device, in 512-byte sectors (inclusive bounds)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeRegion {
+    pub start_sector: u64,
+    pub end_sector: u64,
+}
+
+impl FreeRegion {
+    pub fn size_sectors(&self) -> u64 {
+        self.end_sector.saturating_sub(self.start_sector) + 1
+    }
+
+    pub fn size_mb(&self) -> u64 {
+        self.size_sectors() * 512 / (1024 * 1024)
+    }
+
+    pub fn contains(&self, start: u64, end: u64) -> bool {
+        start >= self.start_sector && end <= self.end_sector
+    }
+}
+
+/// Sectors kept free at the end of the device for a backup GPT
+pub(crate) const GPT_BACKUP_SECTORS: u64 = 34;
+/// Partition alignment (1 MiB in 512-byte sectors)
+const ALIGNMENT_SECTORS: u64 = 2048;
+
+/// Free gaps between existing partitions and at the end of the device, aligned to 1 MiB.
+/// Gaps smaller than `min_sectors` after alignment are dropped.
+pub fn find_free_regions(partitions: &[PartitionInfo], total_sectors: u64, min_sectors: u64) -> Vec<FreeRegion> {
+    let last_usable = total_sectors.saturating_sub(GPT_BACKUP_SECTORS + 1);
+    let mut sorted: Vec<&PartitionInfo> = partitions.iter().collect();
+    sorted.sort_by_key(|p| p.start_sector);
+
+    let mut regions = Vec::new();
+    let mut push_gap = |from: u64, to: u64| {
+        let start = from.div_ceil(ALIGNMENT_SECTORS) * ALIGNMENT_SECTORS;
+        if to >= start && to - start + 1 >= min_sectors.max(1) {
+            regions.push(FreeRegion { start_sector: start, end_sector: to });
+        }
+    };
+    let mut cursor = ALIGNMENT_SECTORS;
+    for part in sorted {
+        if part.start_sector > cursor {
+            push_gap(cursor, (part.start_sector - 1).min(last_usable));
+        }
+        cursor = cursor.max(part.end_sector + 1);
+    }
+    if cursor <= last_usable {
+        push_gap(cursor, last_usable);
+    }
+    regions
+}
+
+/// Build the device node for a partition (`/dev/sdb` + 1 -> `/dev/sdb1`, `/dev/nvme0n1` + 1 -> `/dev/nvme0n1p1`)
+pub fn partition_path(device: &str, number: u32) -> String {
+    if device.chars().last().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+        format!("{}p{}", device, number)
+    } else {
+        format!("{}{}", device, number)
+    }
+}
+
+/// Parse the partition lines of `parted -ms <dev> unit s print`
+fn parse_parted_partitions(output: &str) -> Vec<PartitionInfo> {
+    let sectors = |field: &str| field.trim_end_matches('s').parse::<u64>().ok();
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim().trim_end_matches(';').split(':').collect();
+            if fields.len() < 5 {
+                return None;
+            }
+            Some(PartitionInfo {
+                number: fields[0].parse().ok()?,
+                start_sector: sectors(fields[1])?,
+                end_sector: sectors(fields[2])?,
+                size_sectors: sectors(fields[3])?,
+                fs_type: fields[4].to_string(),
+                name: fields.get(5).copied().unwrap_or("").to_string(),
+                cluster_bytes: None,
+            })
+        })
+        .collect()
+}
+
+/// Cluster size from a FAT, NTFS or exFAT boot sector
+fn parse_boot_sector_cluster_bytes(sector: &[u8]) -> Option<u64> {
+    if sector.len() < 512 {
+        return None;
+    }
+    if &sector[3..11] == b"EXFAT   " {
+        let bytes_per_sector = 1u64 << sector[108];
+        return Some(bytes_per_sector << sector[109]);
+    }
+    let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]) as u64;
+    if bytes_per_sector == 0 {
+        return None;
+    }
+    let spc = sector[13];
+    if &sector[3..11] == b"NTFS    " {
+        // Values above 0x80 encode a negative power of two (clusters larger than 64K)
+        let sectors_per_cluster = if spc > 0x80 { 1u64 << (256 - spc as u32) } else { spc as u64 };
+        return Some(bytes_per_sector * sectors_per_cluster);
+    }
+    if &sector[82..90] == b"FAT32   " || &sector[54..59] == b"FAT16" || &sector[54..59] == b"FAT12" {
+        return Some(bytes_per_sector * spc as u64);
+    }
+    None
+}
+
+/// Read the cluster size of the filesystem on a partition (None when unformatted/unknown)
+pub fn read_cluster_size(partition: &str) -> io::Result<Option<u64>> {
+    use std::io::Read;
+    let mut sector = [0u8; 512];
+    fs::File::open(partition)?.read_exact(&mut sector)?;
+    Ok(parse_boot_sector_cluster_bytes(&sector))
+}
+
+/// Read back the partitions present on a device, including filesystem cluster sizes
+pub fn read_partitions(device: &str) -> io::Result<Vec<PartitionInfo>> {
+    let output = Command::new("parted")
+        .args(["-ms", device, "unit", "s", "print"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "parted print failed on {}: {}",
+            device,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let mut partitions = parse_parted_partitions(&String::from_utf8_lossy(&output.stdout));
+    for part in &mut partitions {
+        part.cluster_bytes = read_cluster_size(&partition_path(device, part.number)).ok().flatten();
+    }
+    Ok(partitions)
+}
+
+/// Write the on-device partition geometry to the log (best effort)
+pub fn log_partition_geometry(device: &str, log: &mut dyn io::Write) -> io::Result<Vec<PartitionInfo>> {
+    let sector_size = get_device_logical_block_size(device).unwrap_or(512);
+    match read_partitions(device) {
+        Ok(partitions) => {
+            writeln!(log, "---- Partition geometry on {} ({}-byte sectors) ----", device, sector_size)?;
+            for part in &partitions {
+                writeln!(log, "{}", part.summary(sector_size))?;
+            }
+            Ok(partitions)
+        }
+        Err(e) => {
+            writeln!(log, "Warning: could not read back partition geometry: {}", e)?;
+            Ok(Vec::new())
+        }
+    }
+}
+
 /// Detect if a device path refers to a USB device via lsblk transport.
 pub fn is_usb_device(device: &str) -> bool {
     let dev_name = device.trim_start_matches("/dev/");
@@ -57,6 +312,30 @@ pub fn get_device_optimal_block_size(device: &str) -> io::Result<u64> {
     Ok(size.max(512))
 }
 
+/// Get the size of a block device in bytes via blockdev.
+pub fn get_device_size_bytes(device: &str) -> io::Result<u64> {
+    let output = Command::new("blockdev")
+        .args(["--getsize64", device])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("Failed to get device size with blockdev"));
+    }
+    let size_str = String::from_utf8_lossy(&output.stdout);
+    size_str
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| io::Error::other(format!("Invalid device size: {}", e)))
+}
+
+/// Detect the logical sector size for a device. Falls back to 512 on errors.
+pub fn get_device_logical_block_size(device: &str) -> io::Result<u64> {
+    let dev_name = device.trim_start_matches("/dev/");
+    let path = format!("/sys/block/{}/queue/logical_block_size", dev_name);
+    let contents = fs::read_to_string(&path)?;
+    let size = contents.trim().parse::<u64>().unwrap_or(512);
+    Ok(size.max(512))
+}
+
 /// Check if ntfs-3g is available on the system.
 pub fn has_ntfs3g() -> bool {
     Command::new("which")
@@ -66,6 +345,30 @@ pub fn has_ntfs3g() -> bool {
         .unwrap_or(false)
 }
 
+/// Check whether the kernel ntfs3 driver is available (built in, loaded, or loadable as a module)
+pub fn has_ntfs3_kernel_driver() -> bool {
+    if let Ok(filesystems) = std::fs::read_to_string("/proc/filesystems")
+        && proc_filesystems_lists(&filesystems, "ntfs3")
+    {
+        return true;
+    }
+    Command::new("modinfo")
+        .arg("ntfs3")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// True if a /proc/filesystems listing contains the given filesystem type
+fn proc_filesystems_lists(content: &str, fs_type: &str) -> bool {
+    content
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .any(|name| name == fs_type)
+}
+
 /// Detailed package check result separating required and optional dependencies.
 pub struct PackageCheckResult {
     pub missing_required: Vec<String>,
@@ -79,6 +382,32 @@ pub fn is_root() -> bool {
     unsafe { libc::geteuid() == 0 }
 }
 
+/// Parent pid in the text of `/proc/<pid>/stat`; the command name before it may hold spaces and
+/// parentheses, so fields are counted from the last `)`
+fn stat_parent_pid(stat: &str) -> Option<u32> {
+    stat.rsplit_once(')')?.1.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Send SIGTERM to the direct children of this process (dd, rsync, mkfs, ...), so a worker waiting
+/// on one of them gets a failure back and unwinds. Returns how many were signalled.
+pub fn terminate_child_processes() -> usize {
+    let own = std::process::id();
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return 0;
+    };
+    let mut signalled = 0;
+    for entry in entries.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) else {
+            continue;
+        };
+        let is_child = fs::read_to_string(entry.path().join("stat")).ok().and_then(|stat| stat_parent_pid(&stat)) == Some(own);
+        if is_child && unsafe { libc::kill(pid, libc::SIGTERM) } == 0 {
+            signalled += 1;
+        }
+    }
+    signalled
+}
+
 /// Utility: Check if running inside Flatpak
 pub fn is_flatpak() -> bool {
     // Check for the Flatpak info file
@@ -120,6 +449,101 @@ pub fn get_user_home() -> String {
     "/".to_string()
 }
 
+/// Partition to browse after a write: the largest partition carrying a filesystem, else the whole
+/// device when it has one. Takes `lsblk -lnbp -o NAME,TYPE,SIZE,FSTYPE` output.
+fn pick_inspection_target(lsblk_output: &str) -> Option<String> {
+    let mut disk = None;
+    let mut best: Option<(u64, &str)> = None;
+    for line in lsblk_output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [name, kind, size, fstype, ..] = fields.as_slice() else {
+            continue;
+        };
+        if *fstype == "swap" {
+            continue;
+        }
+        let size: u64 = size.parse().unwrap_or(0);
+        match *kind {
+            "part" if best.is_none_or(|(largest, _)| size > largest) => best = Some((size, name)),
+            "disk" => disk = Some(*name),
+            _ => {}
+        }
+    }
+    best.map(|(_, name)| name).or(disk).map(str::to_string)
+}
+
+/// Mount the written device read-only under the temp directory so its contents can be browsed.
+/// Returns the mount point.
+pub fn mount_for_inspection(device: &str) -> io::Result<std::path::PathBuf> {
+    let _ = Command::new("udevadm").arg("settle").status();
+    let output = Command::new("lsblk").args(["-lnbp", "-o", "NAME,TYPE,SIZE,FSTYPE", device]).output()?;
+    let target = pick_inspection_target(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no filesystem found on {}", device)))?;
+    let name = target.rsplit('/').next().unwrap_or("device");
+    let mount_point = std::env::temp_dir().join(format!("majusb-{}", name));
+    fs::create_dir_all(&mount_point)?;
+    let output = Command::new("mount").args(["-o", "ro", &target]).arg(&mount_point).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "mount {} failed: {}",
+            target,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(mount_point)
+}
+
+/// Mounted partitions as (node, mount point), from `lsblk -rnp -o NAME,TYPE,MOUNTPOINT` output
+fn mounted_partitions(lsblk_output: &str) -> Vec<(String, String)> {
+    lsblk_output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some("part"), Some(mount_point)) if !mount_point.is_empty() => {
+                    // Raw output escapes blanks in mount points
+                    Some((name.to_string(), mount_point.replace("\\x20", " ")))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Why a partitioning or formatting step on `device` may have failed when a desktop automounter
+/// mounted one of its partitions again after it was released. Call it right after the failure:
+/// None when nothing on the device is mounted.
+pub fn remount_hint(device: &str) -> Option<String> {
+    let output = Command::new("lsblk").args(["-rnp", "-o", "NAME,TYPE,MOUNTPOINT", device]).output().ok()?;
+    let mounts = mounted_partitions(&String::from_utf8_lossy(&output.stdout));
+    let (partition, mount_point) = mounts.first()?;
+    Some(format!(
+        "partition {} was re-mounted by the system (at {}); disable automount or try again",
+        partition, mount_point
+    ))
+}
+
+/// `message` with the remount hint for `device` appended, if one applies
+pub fn with_remount_hint(device: &str, message: String) -> String {
+    match remount_hint(device) {
+        Some(hint) => format!("{}: {}", message, hint),
+        None => message,
+    }
+}
+
+/// Open `path` in the desktop file manager, as the user who launched the app when running elevated
+pub fn open_in_file_manager(path: &Path) -> io::Result<()> {
+    let user = get_original_user();
+    let mut command = if is_root() && user != "root" && std::env::var("ORIGINAL_USER").is_ok() {
+        let mut command = Command::new("runuser");
+        command.args(["-u", &user, "--", "xdg-open"]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(path).spawn().map(|_| ())
+}
+
 /// Utility: Get the original username
 pub fn get_original_user() -> String {
     if let Ok(user) = std::env::var("ORIGINAL_USER") {
@@ -220,7 +644,8 @@ pub fn ensure_root_normal() {
         let gtk_theme = std::env::var("GTK_THEME").unwrap_or_default();
         let icon_theme = std::env::var("ICON_THEME").unwrap_or_default();
 
-        let mut cmd = std::process::Command::new("pkexec");
+        let elevation = crate::settings::Settings::load().elevation_method;
+        let mut cmd = std::process::Command::new(elevation.program());
         cmd.arg("env");
 
         // Variables gráficas
@@ -257,6 +682,12 @@ pub fn ensure_root_normal() {
         if !icon_theme.is_empty() {
             cmd.arg(format!("ICON_THEME={}", icon_theme));
         }
+        // Timing overrides for flaky hardware and the failure-injection hook
+        for name in crate::settings::Tuning::ENV_OVERRIDES.into_iter().chain([crate::settings::MOUNT_BASE_ENV, crate::fail_point::FAIL_AT_ENV]) {
+            if let Ok(value) = std::env::var(name) {
+                cmd.arg(format!("{}={}", name, value));
+            }
+        }
         cmd.arg(exe);
         for arg in args {
             cmd.arg(arg);
@@ -297,9 +728,818 @@ pub fn list_usb_devices() -> Vec<(String, String)> {
     devices
 }
 
+/// Negotiated USB link of a block device, read from its USB device node in sysfs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UsbLinkInfo {
+    /// Negotiated link speed in Mbit/s (`speed`)
+    pub speed_mbps: f64,
+    /// USB version the device reports (`version`, from bcdUSB), e.g. 3.2
+    pub usb_version: f64,
+}
+
+impl UsbLinkInfo {
+    /// A USB 3 device that negotiated USB 2.0 speed or lower (USB 2 port, hub or cable)
+    pub fn is_degraded(&self) -> bool {
+        self.usb_version >= 3.0 && self.speed_mbps <= 480.0
+    }
+
+    /// Advice to show when the link is slower than the device supports
+    pub fn warning(&self) -> Option<String> {
+        self.is_degraded().then(|| {
+            format!(
+                "This USB {:.1} device is connected at USB 2.0 speed ({} Mbps) — consider a USB 3 port",
+                self.usb_version, self.speed_mbps
+            )
+        })
+    }
+}
+
+/// Negotiated USB link for `device` (e.g. `/dev/sdb`); None for non-USB or unknown devices
+pub fn usb_link_info(device: &str) -> Option<UsbLinkInfo> {
+    let dev_name = device.trim_start_matches("/dev/");
+    let node = fs::canonicalize(format!("/sys/block/{}/device", dev_name)).ok()?;
+    find_usb_link(&node)
+}
+
+/// Walk up from a SCSI device node to the first ancestor carrying USB `speed` and `version`
+fn find_usb_link(node: &Path) -> Option<UsbLinkInfo> {
+    node.ancestors().find_map(|dir| {
+        let speed = fs::read_to_string(dir.join("speed")).ok()?;
+        let version = fs::read_to_string(dir.join("version")).ok()?;
+        Some(UsbLinkInfo {
+            speed_mbps: speed.trim().parse().ok()?,
+            usb_version: version.trim().parse().ok()?,
+        })
+    })
+}
+
+/// USB-to-storage bridges the kernel flags as mishandling forced writes (US_FL_BROKEN_FUA), so a
+/// finished `sync` does not guarantee the data reached flash: (idVendor, idProduct, name)
+const CACHING_BRIDGES: [(&str, &str, &str); 2] = [
+    ("152d", "0567", "JMicron JMS567"),
+    ("152d", "0578", "JMicron JMS578"),
+];
+
+/// Name of the USB bridge behind `device` when it is known to acknowledge writes before they are stored
+pub fn caching_bridge_name(device: &str) -> Option<&'static str> {
+    let dev_name = device.trim_start_matches("/dev/");
+    let node = fs::canonicalize(format!("/sys/block/{}/device", dev_name)).ok()?;
+    find_caching_bridge(&node)
+}
+
+/// USB (idVendor, idProduct) of the device behind `device`, lowercase hex as in sysfs
+pub fn usb_id(device: &str) -> Option<(String, String)> {
+    let dev_name = device.trim_start_matches("/dev/");
+    let node = fs::canonicalize(format!("/sys/block/{}/device", dev_name)).ok()?;
+    find_usb_id(&node)
+}
+
+/// Walk up from a SCSI device node to the USB device carrying `idVendor`/`idProduct`
+fn find_usb_id(node: &Path) -> Option<(String, String)> {
+    node.ancestors().find_map(|dir| {
+        let vendor = fs::read_to_string(dir.join("idVendor")).ok()?;
+        let product = fs::read_to_string(dir.join("idProduct")).ok()?;
+        Some((vendor.trim().to_ascii_lowercase(), product.trim().to_ascii_lowercase()))
+    })
+}
+
+/// Known caching bridge carrying the SCSI device `node`
+fn find_caching_bridge(node: &Path) -> Option<&'static str> {
+    let (vendor, product) = find_usb_id(node)?;
+    CACHING_BRIDGES
+        .iter()
+        .find(|(v, p, _)| *v == vendor && *p == product)
+        .map(|(_, _, name)| *name)
+}
+
+/// Targets larger than this (decimal bytes) are treated as disks rather than sticks
+const LARGE_DEVICE_BYTES: u64 = 256_000_000_000;
+
+/// Why erasing a target needs an explicit acknowledgement, from its sysfs `removable` flag and
+/// size. Empty for ordinary removable sticks; unknown facts raise nothing.
+pub fn device_risk_reasons(removable: Option<bool>, size_bytes: Option<u64>) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if removable == Some(false) {
+        reasons.push("it is not marked removable, so it is likely an external hard disk or SSD".to_string());
+    }
+    if let Some(bytes) = size_bytes.filter(|&bytes| bytes > LARGE_DEVICE_BYTES) {
+        reasons.push(format!("it holds {:.0} GB, far more than a typical USB stick", bytes as f64 / 1e9));
+    }
+    reasons
+}
+
+/// How likely a target is to be something other than a USB stick, worst first wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceRisk {
+    /// Removable stick or card, or facts unknown
+    Removable,
+    /// Not marked removable: USB hard disk, SSD or fixed SD reader
+    Fixed,
+    /// Larger than any typical stick, whatever the removable flag says
+    Large,
+}
+
+impl DeviceRisk {
+    /// Risk level from the same facts `device_risk_reasons` explains
+    pub fn from_facts(removable: Option<bool>, size_bytes: Option<u64>) -> Self {
+        if size_bytes.is_some_and(|bytes| bytes > LARGE_DEVICE_BYTES) {
+            DeviceRisk::Large
+        } else if removable == Some(false) {
+            DeviceRisk::Fixed
+        } else {
+            DeviceRisk::Removable
+        }
+    }
+}
+
+/// `/sys/block/<name>/removable` and the size of `device`, either unknown when unreadable
+fn device_risk_facts(device: &str) -> (Option<bool>, Option<u64>) {
+    let dev_name = device.trim_start_matches("/dev/");
+    let removable = fs::read_to_string(format!("/sys/block/{}/removable", dev_name))
+        .ok()
+        .map(|flag| flag.trim() == "1");
+    (removable, get_device_size_bytes(device).ok())
+}
+
+/// Risk assessment for `device` (e.g. `/dev/sdb`) from `/sys/block/<name>/removable` and its size
+pub fn assess_device_risk(device: &str) -> Vec<String> {
+    let (removable, size_bytes) = device_risk_facts(device);
+    device_risk_reasons(removable, size_bytes)
+}
+
+/// Risk level of `device` for the device picker
+pub fn device_risk(device: &str) -> DeviceRisk {
+    let (removable, size_bytes) = device_risk_facts(device);
+    DeviceRisk::from_facts(removable, size_bytes)
+}
+
+/// Bytes read back from each end of the device by `thorough_flush`
+const READ_BACK_BYTES: u64 = 1024 * 1024;
+
+/// Push cached writes through bridges that answer `sync` early: flush the kernel's buffers for
+/// `device`, sync, then read back the first and last MiB (where the partition tables live) so
+/// the bridge has to serve them from the medium, and flush once more.
+pub fn thorough_flush(device: &str, log: &mut dyn io::Write) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    writeln!(log, "Thorough flush of {}...", device)?;
+    let flushbufs = || -> io::Result<()> {
+        let output = Command::new("blockdev").args(["--flushbufs", device]).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "blockdev --flushbufs failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    };
+    flushbufs()?;
+    Command::new("sync").status()?;
+    // The buffers were just dropped, so these reads go to the device rather than the page cache
+    let size = get_device_size_bytes(device)?;
+    let mut file = fs::File::open(device)?;
+    let mut buf = vec![0u8; READ_BACK_BYTES.min(size) as usize];
+    file.read_exact(&mut buf)?;
+    file.seek(SeekFrom::Start(size - buf.len() as u64))?;
+    file.read_exact(&mut buf)?;
+    flushbufs()?;
+    writeln!(log, "Read back the start and end of {}; cached writes are on the device.", device)?;
+    Ok(())
+}
+
+/// Whole disks (e.g. `/dev/sdb`) backing the filesystem that holds `path`, following
+/// device-mapper slaves (LUKS, LVM). Empty when the backing device cannot be resolved.
+pub fn backing_disks(path: &str) -> Vec<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(meta) = fs::metadata(path) else {
+        return Vec::new();
+    };
+    let mut dev = meta.dev();
+    // btrfs and similar report an anonymous device number; ask findmnt for the real source
+    if !Path::new(&format!("/sys/dev/block/{}:{}", libc::major(dev), libc::minor(dev))).exists() {
+        let Ok(output) = Command::new("findmnt").args(["-n", "-o", "SOURCE", "--target", path]).output() else {
+            return Vec::new();
+        };
+        let source = String::from_utf8_lossy(&output.stdout);
+        // btrfs subvolumes are reported as /dev/sdb2[/@home]
+        let source = source.trim().split('[').next().unwrap_or("");
+        match fs::metadata(source) {
+            Ok(source_meta) if source.starts_with("/dev/") => dev = source_meta.rdev(),
+            _ => return Vec::new(),
+        }
+    }
+    let node = format!("/sys/dev/block/{}:{}", libc::major(dev), libc::minor(dev));
+    match fs::canonicalize(node) {
+        Ok(node) => sysfs_whole_disks(&node).into_iter().map(|disk| format!("/dev/{}", disk)).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Where live systems mount the medium they booted from (Debian live-boot, Fedora dracut,
+/// Ubuntu casper, archiso), including ISOs looped from a file on the stick
+const LIVE_MEDIUM_MOUNTS: [&str; 8] = [
+    "/run/live/medium",
+    "/run/live/findiso",
+    "/lib/live/mount/medium",
+    "/run/initramfs/live",
+    "/run/initramfs/isoscan",
+    "/cdrom",
+    "/isodevice",
+    "/run/archiso/bootmnt",
+];
+/// Mount targets of a live system's read-only root image
+const LIVE_ROOT_PREFIXES: [&str; 5] = ["/run/live/", "/run/initramfs/", "/run/archiso/", "/rofs", "/media/root-ro"];
+
+/// True when `path` is the root of a mounted filesystem (its device differs from its parent's)
+fn is_mount_point(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(path), path.parent().map(fs::metadata)) {
+        (Ok(meta), Some(Ok(parent))) => meta.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+/// Loop devices (`/dev/loopN`) mounted as a live root image, from `findmnt -rn -o SOURCE,TARGET`
+fn live_root_loop_devices(findmnt: &str) -> Vec<String> {
+    findmnt
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(source, target)| {
+            source.starts_with("/dev/loop") && (*target == "/" || LIVE_ROOT_PREFIXES.iter().any(|prefix| target.starts_with(prefix)))
+        })
+        .map(|(source, _)| source.to_string())
+        .collect()
+}
+
+/// Filesystem types served over the network; FUSE mounts appear as `fuse.<name>`
+const NETWORK_FILESYSTEMS: [&str; 12] = [
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "9p", "afs", "ceph", "glusterfs", "ncpfs", "fuse.sshfs", "fuse.rclone",
+];
+
+/// Mount point and filesystem type of the `/proc/mounts` entry that holds the absolute `path`
+/// (the deepest mount point containing it)
+fn mount_containing(mounts: &str, path: &Path) -> Option<(String, String)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _source = fields.next()?;
+            // Spaces and tabs in mount points are written as octal escapes
+            let mount_point = fields.next()?.replace("\\040", " ").replace("\\011", "\t");
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point).then(|| (mount_point, fs_type.to_string()))
+        })
+        .max_by_key(|(mount_point, _)| Path::new(mount_point).components().count())
+}
+
+/// Network share holding `path`, as "<fs type> share at <mount point>"; None on local storage
+/// or when the mount cannot be told
+pub fn network_filesystem(path: &str) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    let (mount_point, fs_type) = mount_containing(&mounts, &path)?;
+    NETWORK_FILESYSTEMS
+        .contains(&fs_type.as_str())
+        .then(|| format!("{} share at {}", fs_type, mount_point))
+}
+
+/// The file a symlinked image points to, so detection and size checks see the real file;
+/// other paths (and broken links) are returned unchanged
+pub fn resolve_image_path(path: &str) -> String {
+    resolve_symlink(path)
+}
+
+/// Canonical node of a device given by a stable link (`/dev/disk/by-id/usb-...`,
+/// `/dev/disk/by-path/...`), e.g. `/dev/sdb`, so partition paths and `/sys/block` lookups work.
+/// Plain nodes and paths that do not resolve are returned unchanged.
+pub fn resolve_device_path(path: &str) -> String {
+    resolve_symlink(path)
+}
+
+fn resolve_symlink(path: &str) -> String {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(path)
+            .map(|target| target.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string()),
+        _ => path.to_string(),
+    }
+}
+
+/// Whole disks holding the medium the running live system booted from; empty on installed systems
+pub fn live_system_disks() -> Vec<String> {
+    let mut disks = Vec::new();
+    let mut add = |found: Vec<String>| {
+        for disk in found {
+            if !disks.contains(&disk) {
+                disks.push(disk);
+            }
+        }
+    };
+    for mount in LIVE_MEDIUM_MOUNTS {
+        if is_mount_point(Path::new(mount)) {
+            add(backing_disks(mount));
+        }
+    }
+    // The squashfs root is looped from a file on the medium, even when the medium is mounted elsewhere
+    if let Ok(output) = Command::new("findmnt").args(["-rn", "-o", "SOURCE,TARGET"]).output() {
+        for loop_device in live_root_loop_devices(&String::from_utf8_lossy(&output.stdout)) {
+            let backing_file = format!("/sys/block/{}/loop/backing_file", loop_device.trim_start_matches("/dev/"));
+            if let Ok(backing) = fs::read_to_string(backing_file) {
+                add(backing_disks(backing.trim()));
+            }
+        }
+    }
+    disks
+}
+
+/// Resolve a sysfs block node to the names of its whole disks: partitions map to their
+/// parent directory, stacked devices to the disks behind their `slaves`.
+fn sysfs_whole_disks(node: &Path) -> Vec<String> {
+    if node.join("partition").exists() {
+        return node
+            .parent()
+            .and_then(Path::file_name)
+            .map(|disk| vec![disk.to_string_lossy().into_owned()])
+            .unwrap_or_default();
+    }
+    let mut disks = Vec::new();
+    if let Ok(slaves) = fs::read_dir(node.join("slaves")) {
+        for slave in slaves.flatten() {
+            let Ok(target) = fs::canonicalize(slave.path()) else { continue };
+            for disk in sysfs_whole_disks(&target) {
+                if !disks.contains(&disk) {
+                    disks.push(disk);
+                }
+            }
+        }
+    }
+    if disks.is_empty()
+        && let Some(name) = node.file_name()
+    {
+        disks.push(name.to_string_lossy().into_owned());
+    }
+    disks
+}
+
+/// Files and directories at the root of a Linux image; any one of them marks the image as Linux
+pub(crate) const LINUX_ROOT_MARKERS: [&str; 17] = [
+    "boot", "casper", "syslinux", "isolinux", "EFI", "live", "kernel", "initrd", "vmlinuz", "arch", "loader", "install", "preseed", "dists", "pool", ".disk", "filesystem.squashfs",
+];
+
+/// Root markers that Windows media carry as well, so they say nothing about the image on their own
+const SHARED_ROOT_MARKERS: [&str; 2] = ["boot", "EFI"];
+
+/// Install images under `sources/`; any of them makes an image Windows setup media
+const WINDOWS_INSTALL_IMAGES: [&str; 3] = ["install.wim", "install.esd", "install.swm"];
+
+/// Image type found by mounting an ISO; Windows media also say whether they install Server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoType {
+    Windows(WindowsFlavor),
+    Linux,
+}
+
+impl IsoType {
+    pub fn is_windows(self) -> bool {
+        matches!(self, IsoType::Windows(_))
+    }
+
+    /// e.g. "Windows Server ISO"
+    pub fn describe(self) -> String {
+        match self {
+            IsoType::Windows(flavor) => format!("{} ISO", flavor.product_name()),
+            IsoType::Linux => "Linux ISO".to_string(),
+        }
+    }
+}
+
+/// Distribution names that mark an image file name as Linux
+const LINUX_NAME_HINTS: [&str; 20] = [
+    "linux", "ubuntu", "fedora", "debian", "mint", "manjaro", "opensuse", "kali", "centos", "rocky",
+    "almalinux", "pop-os", "elementary", "endeavouros", "zorin", "tails", "nixos", "gentoo", "kubuntu", "xubuntu",
+];
+
+/// Advisory note when the image's file name points to another OS than detection found (e.g.
+/// `Win11.iso` detected as Linux), which hints at a renamed or damaged file or a detection bug.
+/// Names that point nowhere or both ways give no note.
+pub fn name_mismatch_warning(iso_path: &str, detected: IsoType) -> Option<String> {
+    let name = Path::new(iso_path).file_name()?.to_string_lossy().to_lowercase();
+    let windows = name.contains("windows")
+        || name.split(|c: char| !c.is_ascii_alphanumeric()).any(|token| {
+            token.strip_prefix("win").is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()))
+        });
+    let linux = LINUX_NAME_HINTS.iter().find(|hint| name.contains(*hint));
+    let suggested = match (windows, linux, detected.is_windows()) {
+        (true, None, false) => "Windows".to_string(),
+        (false, Some(hint), true) => format!("Linux ({})", hint),
+        _ => return None,
+    };
+    Some(format!(
+        "The file name suggests {}, but the image was detected as {}. Double-check that this is the image you meant to write.",
+        suggested,
+        if detected.is_windows() { "Windows" } else { "Linux" }
+    ))
+}
+
+/// Flavor of the Windows media mounted at `root`, from its install image metadata. Media whose
+/// image cannot be read (boot-only sticks, damaged WIMs) are judged by their files: without
+/// `efi/boot/`, which every Windows 8 and later ISO has, they are legacy media, else client media.
+pub fn windows_flavor_of_root(root: &Path) -> WindowsFlavor {
+    let by_files = || if root.join("efi/boot").is_dir() { WindowsFlavor::Client } else { WindowsFlavor::Legacy };
+    let image = match InstallImage::find(&root.join("sources")) {
+        Ok(Some(image)) => image,
+        _ => return by_files(),
+    };
+    image.flavor().unwrap_or_else(|e| {
+        eprintln!("[DEBUG] Could not read the metadata of {}: {}", image.describe(), e);
+        by_files()
+    })
+}
+
+/// What the files at the root of a mounted image say about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoRootKind {
+    Windows,
+    Linux,
+    /// Windows boot files next to a Linux live system (hybrid recovery images): left to the user
+    Ambiguous,
+    Unknown,
+}
+
+/// Classify an image from its root. An install image under `sources/` settles it as Windows; a
+/// bare `bootmgr` + `sources/` next to Linux-only markers (`casper`, `live`, ...) is ambiguous.
+pub fn classify_iso_root(root: &Path) -> IsoRootKind {
+    let sources = root.join("sources");
+    let install_image = WINDOWS_INSTALL_IMAGES.iter().any(|name| sources.join(name).is_file());
+    let windows_boot = root.join("bootmgr").is_file() && sources.is_dir();
+    let present = |marker: &&str| root.join(marker).exists();
+    let linux_only = LINUX_ROOT_MARKERS
+        .iter()
+        .filter(|marker| !SHARED_ROOT_MARKERS.contains(marker))
+        .any(present);
+    match (install_image, windows_boot, linux_only) {
+        (true, _, _) | (false, true, false) => IsoRootKind::Windows,
+        (false, true, true) => IsoRootKind::Ambiguous,
+        _ if LINUX_ROOT_MARKERS.iter().any(present) => IsoRootKind::Linux,
+        _ => IsoRootKind::Unknown,
+    }
+}
+
+/// Running totals of a content scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContentTotals {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl ContentTotals {
+    /// Progress line for the log, e.g. "scanning: 12,000 files (4,213 MB)"
+    pub fn describe(&self) -> String {
+        fn grouped(n: u64) -> String {
+            let digits = n.to_string();
+            let mut out = String::new();
+            for (i, c) in digits.chars().enumerate() {
+                if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                    out.push(',');
+                }
+                out.push(c);
+            }
+            out
+        }
+        format!("scanning: {} files ({} MB)", grouped(self.files), grouped(self.bytes / 1_000_000))
+    }
+}
+
+/// Files between two progress reports of `scan_content`
+const SCAN_REPORT_EVERY: u64 = 1000;
+
+/// Add up the regular files under `root` (a file or a directory; symlinks are not followed and
+/// unreadable directories are skipped). `report` gets the running totals every 1000 files; the
+/// walk stops with `ErrorKind::Interrupted` once `cancel` is set.
+pub fn scan_content(
+    root: &Path,
+    cancel: &AtomicBool,
+    report: &mut dyn FnMut(ContentTotals),
+) -> io::Result<ContentTotals> {
+    let mut totals = ContentTotals::default();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "content scan cancelled"));
+        }
+        let Ok(meta) = fs::symlink_metadata(&path) else { continue };
+        if meta.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+            }
+        } else if meta.is_file() {
+            totals.files += 1;
+            totals.bytes += meta.len();
+            if totals.files.is_multiple_of(SCAN_REPORT_EVERY) {
+                report(totals);
+            }
+        }
+    }
+    Ok(totals)
+}
+
+/// `scan_content` running on a worker thread. Dropping the handle cancels the walk.
+pub struct ContentScan {
+    cancel: Arc<AtomicBool>,
+    progress: mpsc::Receiver<ContentTotals>,
+    worker: Option<JoinHandle<io::Result<ContentTotals>>>,
+}
+
+impl ContentScan {
+    pub fn start(root: PathBuf) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, progress) = mpsc::channel();
+        let worker = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                scan_content(&root, &cancel, &mut |totals| {
+                    let _ = sender.send(totals);
+                })
+            })
+        };
+        Self { cancel, progress, worker: Some(worker) }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Totals reported since the last call, without blocking
+    pub fn progress(&self) -> Vec<ContentTotals> {
+        self.progress.try_iter().collect()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.worker.as_ref().is_none_or(|worker| worker.is_finished())
+    }
+
+    /// Block until the walk ends; `Interrupted` when it was cancelled
+    pub fn wait(mut self) -> io::Result<ContentTotals> {
+        let worker = self.worker.take().expect("content scan joined twice");
+        worker.join().unwrap_or_else(|_| Err(io::Error::other("content scan thread panicked")))
+    }
+}
+
+impl Drop for ContentScan {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Extra mount options tried in turn when an ISO's file names come out garbled: some images carry
+/// broken Rock Ridge or Joliet records that the kernel prefers over the plain ISO9660 names
+const ISO_MOUNT_ATTEMPTS: [&str; 4] = ["", "norock", "nojoliet", "norock,nojoliet"];
+
+/// True when a root listing looks mis-decoded (empty, replacement or control characters,
+/// or raw ISO9660 version suffixes such as `BOOTMGR.;1`)
+fn listing_looks_garbled(names: &[String]) -> bool {
+    names.is_empty()
+        || names
+            .iter()
+            .any(|name| name.contains('\u{FFFD}') || name.contains(';') || name.chars().any(char::is_control))
+}
+
+/// `-o` argument for one attempt on top of the caller's base options
+fn iso_mount_options(base: &str, attempt: &str) -> String {
+    [base, "ro", attempt].iter().filter(|part| !part.is_empty()).copied().collect::<Vec<_>>().join(",")
+}
+
+/// Describe a failure to open or read the ISO so permission problems are not mistaken for a bad image
+fn iso_read_error(iso_path: &str, err: io::Error) -> io::Error {
+    let message = match err.kind() {
+        io::ErrorKind::PermissionDenied => format!("cannot read ISO file (permission denied): {}", iso_path),
+        io::ErrorKind::NotFound => format!("ISO file not found: {}", iso_path),
+        _ => format!("cannot read ISO file {}: {}", iso_path, err),
+    };
+    io::Error::new(err.kind(), message)
+}
+
+/// Check that the current process (user or elevated helper) can open and read the ISO, and that
+/// it is not an empty placeholder
+pub fn check_iso_readable(iso_path: &str) -> io::Result<()> {
+    use std::io::Read;
+    let mut file = fs::File::open(iso_path).map_err(|e| iso_read_error(iso_path, e))?;
+    file.read(&mut [0u8; 1]).map_err(|e| iso_read_error(iso_path, e))?;
+    validate_image_file(iso_path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    Ok(())
+}
+
+/// Share of an image (in percent) that must be backed by allocated blocks; less means holes
+const MIN_ALLOCATED_PERCENT: u64 = 50;
+
+/// Judge an image from its apparent size and the bytes allocated for it (`du --apparent-size`
+/// vs `du`): empty images are refused, mostly unallocated ones get a warning
+fn image_size_problem(path: &str, apparent_bytes: u64, allocated_bytes: u64) -> Result<Option<String>, String> {
+    if apparent_bytes == 0 {
+        return Err(format!("{} is empty (0 bytes); the download probably did not finish", path));
+    }
+    if allocated_bytes * 100 >= apparent_bytes * MIN_ALLOCATED_PERCENT {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "{} only has {} MB of data for its {} MB size; it looks like an unfinished download. \
+         Check it against the published checksum before writing.",
+        path,
+        allocated_bytes / 1024 / 1024,
+        apparent_bytes / 1024 / 1024
+    )))
+}
+
+/// Refuse an empty image file and describe a sparse one (mostly holes, as left by a download
+/// that preallocated its file and stopped). Devices used as a source are not checked.
+pub fn validate_image_file(path: &str) -> UsbCreatorResult<Option<String>> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path).with_context(format!("cannot read {}", path))?;
+    if !metadata.is_file() {
+        return Ok(None);
+    }
+    // st_blocks counts 512-byte units regardless of the filesystem block size
+    image_size_problem(path, metadata.len(), metadata.blocks() * 512).map_err(UsbCreatorError::ValidationError)
+}
+
+/// Mount an ISO (file or loop device) read-only on `mount_point`, trying the options in
+/// `ISO_MOUNT_ATTEMPTS` until the root listing is readable. Returns the options that were used;
+/// when every readable mount looks garbled, the first one that mounted is kept.
+pub(crate) fn mount_iso_readable(source: &str, mount_point: &Path, base_options: &str) -> Result<String, String> {
+    let mut first_mountable = None;
+    let mut last_error = String::new();
+    for attempt in ISO_MOUNT_ATTEMPTS {
+        let options = iso_mount_options(base_options, attempt);
+        let output = Command::new("mount")
+            .args(["-o", &options, source])
+            .arg(mount_point)
+            .output()
+            .map_err(|e| format!("Failed to spawn mount: {}", e))?;
+        if !output.status.success() {
+            last_error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            continue;
+        }
+        let names: Vec<String> = fs::read_dir(mount_point)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.file_name().to_string_lossy().to_string()).collect())
+            .unwrap_or_default();
+        if !listing_looks_garbled(&names) {
+            return Ok(options);
+        }
+        first_mountable.get_or_insert(options);
+        let _ = Command::new("umount").arg(mount_point).status();
+    }
+    match first_mountable {
+        Some(options) => {
+            let status = Command::new("mount").args(["-o", &options, source]).arg(mount_point).status();
+            match status {
+                Ok(status) if status.success() => Ok(options),
+                _ => Err(format!("mount {} failed on retry", source)),
+            }
+        }
+        None => Err(last_error),
+    }
+}
+
+/// Extract the loop device from `udisksctl loop-setup` output ("Mapped file X as /dev/loopN.").
+/// Only the device after the final " as " is accepted, so paths containing "/dev/loop" are not misread.
+fn parse_udisks_loop_device(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix("Mapped file ")?;
+        let (_, device) = rest.rsplit_once(" as ")?;
+        let device = device.trim().trim_end_matches('.');
+        let number = device.strip_prefix("/dev/loop")?;
+        (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit())).then(|| device.to_string())
+    })
+}
+
+/// `cli_helper` next to the running executable, or the one on `PATH`
+pub fn cli_helper_path() -> std::path::PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("cli_helper")))
+        .filter(|helper| helper.exists())
+        .unwrap_or_else(|| "cli_helper".into())
+}
+
+/// The report `cli_helper detect` prints for `iso_type`; the helper adds the distribution and
+/// persistence fields to Linux reports. It must be the only thing on stdout, so diagnostics in
+/// the detection path go to stderr.
+pub fn detect_report(iso_type: IsoType) -> serde_json::Value {
+    match iso_type {
+        IsoType::Windows(flavor) => serde_json::json!({
+            "type": "windows",
+            "flavor": match flavor {
+                WindowsFlavor::Client => "client",
+                WindowsFlavor::Server => "server",
+                WindowsFlavor::Legacy => "legacy",
+            },
+        }),
+        IsoType::Linux => serde_json::json!({ "type": "linux" }),
+    }
+}
+
+/// Verdict of `cli_helper detect`; Windows reports carry `"flavor": "server"` for Server media
+/// and `"flavor": "legacy"` for Windows 7/8
+fn parse_detect_output(stdout: &str) -> Option<IsoType> {
+    let report: serde_json::Value = serde_json::from_str(stdout.trim()).ok()?;
+    match report.get("type")?.as_str()? {
+        "windows" => Some(IsoType::Windows(match report.get("flavor").and_then(|f| f.as_str()) {
+            Some("server") => WindowsFlavor::Server,
+            Some("legacy") => WindowsFlavor::Legacy,
+            _ => WindowsFlavor::Client,
+        })),
+        "linux" => Some(IsoType::Linux),
+        _ => None,
+    }
+}
+
+/// Command line that runs `helper detect <iso>` as root. pkexec asks for the password in a
+/// graphical prompt, which works without a terminal (sudo does not); inside Flatpak it has to
+/// run on the host.
+fn elevated_detect_command(helper: &Path, iso_path: &str, in_flatpak: bool) -> Vec<String> {
+    let mut command = Vec::new();
+    if in_flatpak {
+        command.extend(["flatpak-spawn".to_string(), "--host".to_string()]);
+    }
+    command.push("pkexec".to_string());
+    command.push(helper.to_string_lossy().into_owned());
+    command.push("detect".to_string());
+    command.push(iso_path.to_string());
+    command
+}
+
+/// Detect the ISO type with root rights: in-process when already root, otherwise by running
+/// `cli_helper detect` through pkexec, which mounts the image as root instead of going through
+/// udisks. Ok(None) means the helper ran but could not tell; an error means it could not run or
+/// authorization was refused.
+pub fn detect_iso_type_elevated(iso_path: &str) -> io::Result<Option<IsoType>> {
+    if is_root() {
+        return Ok(detect_iso_type(iso_path));
+    }
+    let command = elevated_detect_command(&cli_helper_path(), iso_path, is_flatpak());
+    let output = Command::new(&command[0]).args(&command[1..]).output()?;
+    match output.status.code() {
+        Some(0) => Ok(parse_detect_output(&String::from_utf8_lossy(&output.stdout))),
+        // cli_helper exits with 2 when the image type cannot be told
+        Some(2) => Ok(None),
+        // pkexec: 126 when the prompt is dismissed, 127 when not authorized
+        Some(126) | Some(127) => Err(io::Error::new(io::ErrorKind::PermissionDenied, "root authorization was not granted")),
+        _ => Err(io::Error::other(format!(
+            "cli_helper detect failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
 /// Detect if the ISO is a Windows installer by mounting and checking for Windows-specific files.
 /// Returns Some(true) if Windows ISO, Some(false) if Linux ISO, None if detection failed (e.g. permission denied)
 pub fn is_windows_iso(iso_path: &str) -> Option<bool> {
+    detect_iso_type(iso_path).map(IsoType::is_windows)
+}
+
+/// What a detection result is valid for: the image file as it was when mounted. A file replaced
+/// or rewritten under the same name gets a new key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DetectionKey {
+    path: PathBuf,
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+impl DetectionKey {
+    fn of(iso_path: &str) -> Option<Self> {
+        let path = fs::canonicalize(iso_path).ok()?;
+        let meta = fs::metadata(&path).ok()?;
+        Some(Self { path, len: meta.len(), modified: meta.modified().ok() })
+    }
+}
+
+/// Types detected so far in this process. The lock is held for a whole detection, so detections
+/// (the GUI's, the "Detect (with root)" thread's, a job's fallback) run one at a time instead of
+/// setting up and tearing down loop devices concurrently, and switching back to an ISO reuses its
+/// result instead of mounting it again.
+static DETECTIONS: Mutex<Vec<(DetectionKey, IsoType)>> = Mutex::new(Vec::new());
+
+/// Mount the ISO and classify it from its root; Windows media are also told apart as client or
+/// Server. None if detection failed (e.g. permission denied) or the image is ambiguous.
+pub fn detect_iso_type(iso_path: &str) -> Option<IsoType> {
+    let key = DetectionKey::of(iso_path);
+    let mut detections = DETECTIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(key) = &key
+        && let Some((_, iso_type)) = detections.iter().find(|(known, _)| known == key)
+    {
+        return Some(*iso_type);
+    }
+    let detected = detect_mounted_iso_type(iso_path);
+    // Failures are not kept: they may be a permission problem fixed in the meantime
+    if let (Some(key), Some(iso_type)) = (key, detected) {
+        detections.push((key, iso_type));
+    }
+    detected
+}
+
+/// `detect_iso_type` without the cache; the caller holds the detection lock
+fn detect_mounted_iso_type(iso_path: &str) -> Option<IsoType> {
     use std::thread::sleep;
     use std::time::Duration;
     use std::fs;
@@ -315,50 +1555,50 @@ pub fn is_windows_iso(iso_path: &str) -> Option<bool> {
     if !mount_output.status.success() {
         return None;
     }
-    // Parse device path from output: "Mapped file ... as /dev/loopX."
+    // Use exactly the loop device udisksctl created for this file; other loop devices may exist
     let stdout = String::from_utf8_lossy(&mount_output.stdout);
-    let dev_line = stdout.lines().find(|l| l.contains("/dev/loop"))?;
-    let dev_path = dev_line.split_whitespace().last()?.trim_end_matches('.');
+    let Some(dev_path) = parse_udisks_loop_device(&stdout) else {
+        eprintln!("[DEBUG] Could not parse loop device from udisksctl output: {}", stdout.trim());
+        return None;
+    };
+    // Detach our loop device on every exit path
+    let _loop_guard = scopeguard::guard(dev_path.clone(), |dev| {
+        let _ = Command::new("udisksctl").arg("loop-delete").arg("-b").arg(&dev).status();
+    });
 
     // Mount the loop device
-    let mount_dir = tempfile::tempdir().ok()?;
-    let mount_status = Command::new("mount")
-        .arg(dev_path)
-        .arg(mount_dir.path())
-        .output()
-        .ok()?;
-    if !mount_status.status.success() {
-        // Clean up loop device
-        let _ = Command::new("udisksctl").arg("loop-delete").arg("-b").arg(dev_path).status();
-        return None;
+    let mount_dir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("[DEBUG] {}", crate::error::UsbCreatorError::temp_dir_failed(&std::env::temp_dir(), e));
+            return None;
+        }
+    };
+    match mount_iso_readable(&dev_path, mount_dir.path(), "") {
+        Ok(options) if options != iso_mount_options("", "") => {
+            eprintln!("[DEBUG] Mounted {} with -o {} after garbled file names", dev_path, options);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("[DEBUG] Could not mount {}: {}", dev_path, e);
+            return None;
+        }
     }
     sleep(Duration::from_millis(200));
     let mount_point = mount_dir.path();
 
-    // Check for Windows files
-    let bootmgr = mount_point.join("bootmgr");
-    let sources = mount_point.join("sources");
-    if bootmgr.is_file() && sources.is_dir() {
-        // Unmount and clean up
-        let _ = Command::new("umount").arg(mount_point).status();
-        let _ = Command::new("udisksctl").arg("loop-delete").arg("-b").arg(dev_path).status();
-        return Some(true); // Windows ISO
-    }
-
-    // Check for Linux markers (must match at least one directory or file)
-    let linux_markers = [
-        "boot", "casper", "syslinux", "isolinux", "EFI", "live", "kernel", "initrd", "vmlinuz", "arch", "loader", "install", "preseed", "dists", "pool", ".disk", "filesystem.squashfs"
-    ];
-    let found_linux = linux_markers.iter().any(|m| mount_point.join(m).exists());
-
-    // Unmount and clean up
+    let kind = classify_iso_root(mount_point);
+    let flavor = (kind == IsoRootKind::Windows).then(|| windows_flavor_of_root(mount_point));
     let _ = Command::new("umount").arg(mount_point).status();
-    let _ = Command::new("udisksctl").arg("loop-delete").arg("-b").arg(dev_path).status();
 
-    if found_linux {
-        Some(false) // Linux ISO
-    } else {
-        None // Unknown or not a bootable ISO
+    match kind {
+        IsoRootKind::Windows => flavor.map(IsoType::Windows),
+        IsoRootKind::Linux => Some(IsoType::Linux),
+        IsoRootKind::Ambiguous => {
+            eprintln!("[DEBUG] {} carries both Windows and Linux boot files; leaving the type to the override", iso_path);
+            None
+        }
+        IsoRootKind::Unknown => None, // Not a bootable ISO
     }
 }
 
@@ -739,9 +1979,122 @@ pub fn check_required_packages_split() -> Option<PackageCheckResult> {
     })
 }
 
+/// Message for a helper program that could not be started, with the command that installs the
+/// missing packages when the distribution is known
+fn not_installed_message(program: &str, install_cmd: Option<&str>) -> String {
+    match install_cmd {
+        Some(cmd) => format!("{} is not installed (or not in PATH). Install it with: {}", program, cmd),
+        None => format!("{} is not installed (or not in PATH); install it from your distribution's packages", program),
+    }
+}
+
+/// Error for `program` missing at write time, although `check_required_packages` lists it
+pub fn program_not_installed(program: &str) -> io::Error {
+    let install_cmd = check_required_packages_split().and_then(|check| check.install_cmd_required.or(check.install_cmd_optional));
+    io::Error::new(io::ErrorKind::NotFound, not_installed_message(program, install_cmd.as_deref()))
+}
+
+/// Turn the spawn error of `program` into `program_not_installed` when it was not found; other
+/// errors pass through
+pub fn missing_program_error(program: &str, e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::NotFound { program_not_installed(program) } else { e }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::parse_rsync_progress;
+    use super::{check_iso_readable, stat_parent_pid, DetectionKey, image_size_problem, iso_read_error, iso_mount_options, listing_looks_garbled, live_root_loop_devices, mounted_partitions, pick_inspection_target, find_free_regions, FreeRegion, PartitionInfo, parse_boot_sector_cluster_bytes, parse_parted_partitions, partition_path, parse_meminfo_pending_kb, parse_progress_line, parse_rsync_progress, parse_udisks_loop_device, proc_filesystems_lists, not_installed_message, missing_program_error, sysfs_whole_disks, find_usb_link, find_caching_bridge, device_risk_reasons, DeviceRisk, classify_iso_root, IsoRootKind, mount_containing, resolve_image_path, resolve_device_path, parse_detect_output, detect_report, elevated_detect_command, name_mismatch_warning, scan_content, ContentScan, ContentTotals, IsoType, WindowsFlavor};
+    use std::fs;
+    use std::sync::atomic::AtomicBool;
+    use std::io;
+
+    #[test]
+    fn detection_key_changes_when_the_image_is_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let iso = dir.path().join("a.iso");
+        std::fs::write(&iso, b"first").unwrap();
+        let iso = iso.to_str().unwrap();
+        let key = DetectionKey::of(iso).unwrap();
+        assert_eq!(DetectionKey::of(&format!("{}/./a.iso", dir.path().display())), Some(key.clone()));
+        std::fs::write(iso, b"second image").unwrap();
+        assert_ne!(DetectionKey::of(iso), Some(key));
+        assert_eq!(DetectionKey::of(&format!("{}/missing.iso", dir.path().display())), None);
+    }
+
+    #[test]
+    fn parent_pid_is_read_past_odd_command_names() {
+        assert_eq!(stat_parent_pid("4242 (dd) S 1000 4242 1000 0 -1"), Some(1000));
+        assert_eq!(stat_parent_pid("4243 (my (odd) tool) R 77 4243"), Some(77));
+        assert_eq!(stat_parent_pid("garbage"), None);
+    }
+
+    #[test]
+    fn mounted_partitions_skip_the_disk_and_unmounted_ones() {
+        let lsblk = "/dev/sdb disk \n/dev/sdb1 part /run/media/user/BOOT\n/dev/sdb2 part \n/dev/sdb3 part /run/media/user/My\\x20Files\n";
+        assert_eq!(
+            mounted_partitions(lsblk),
+            [
+                ("/dev/sdb1".to_string(), "/run/media/user/BOOT".to_string()),
+                ("/dev/sdb3".to_string(), "/run/media/user/My Files".to_string()),
+            ]
+        );
+        assert!(mounted_partitions("/dev/sdb disk \n").is_empty());
+    }
+
+    #[test]
+    fn live_root_loops_are_told_apart_from_other_loop_mounts() {
+        let findmnt = "/dev/sda2 /\n/dev/loop0 /run/live/rootfs/filesystem.squashfs\n/dev/loop1 /rofs\n/dev/loop2 /mnt/iso\n/dev/loop3 /\n";
+        assert_eq!(live_root_loop_devices(findmnt), vec!["/dev/loop0", "/dev/loop1", "/dev/loop3"]);
+        assert!(live_root_loop_devices("/dev/nvme0n1p2 /\n").is_empty());
+    }
+
+    #[test]
+    fn unreadable_iso_errors_name_the_cause_and_path() {
+        let denied = iso_read_error("/root/win.iso", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(denied.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(denied.to_string(), "cannot read ISO file (permission denied): /root/win.iso");
+
+        let dir = tempfile::tempdir().unwrap();
+        let iso = dir.path().join("image.iso");
+        let missing = check_iso_readable(iso.to_str().unwrap()).unwrap_err();
+        assert_eq!(missing.to_string(), format!("ISO file not found: {}", iso.display()));
+        fs::write(&iso, b"").unwrap();
+        let empty = check_iso_readable(iso.to_str().unwrap()).unwrap_err();
+        assert_eq!(empty.kind(), io::ErrorKind::InvalidData);
+        assert!(empty.to_string().contains("is empty (0 bytes)"));
+        fs::write(&iso, b"CD001").unwrap();
+        assert!(check_iso_readable(iso.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn sparse_images_are_reported_as_unfinished() {
+        let mb = 1024 * 1024;
+        assert_eq!(image_size_problem("a.iso", 4000 * mb, 4004 * mb), Ok(None));
+        assert_eq!(image_size_problem("a.iso", 4000 * mb, 2000 * mb), Ok(None));
+        let warning = image_size_problem("a.iso", 4000 * mb, 300 * mb).unwrap().unwrap();
+        assert!(warning.starts_with("a.iso only has 300 MB of data for its 4000 MB size"));
+        assert!(image_size_problem("a.iso", 0, 0).is_err());
+    }
+
+    #[test]
+    fn inspection_picks_largest_partition_with_filesystem() {
+        let windows = "/dev/sdb disk 32000000000 \n/dev/sdb1 part 1073741824 vfat\n/dev/sdb2 part 30000000000 ntfs\n";
+        assert_eq!(pick_inspection_target(windows).as_deref(), Some("/dev/sdb2"));
+        let hybrid = "/dev/sdc disk 16000000000 iso9660\n/dev/sdc1 part 5000000000 iso9660\n/dev/sdc2 part 5000000 vfat\n";
+        assert_eq!(pick_inspection_target(hybrid).as_deref(), Some("/dev/sdc1"));
+        assert_eq!(pick_inspection_target("/dev/sdd disk 8000000000 iso9660\n").as_deref(), Some("/dev/sdd"));
+        assert_eq!(pick_inspection_target("/dev/sde disk 8000000000\n"), None);
+    }
+
+    #[test]
+    fn garbled_iso_listing_triggers_fallback_options() {
+        let names = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(!listing_looks_garbled(&names(&["boot", "casper", "EFI", ".disk"])));
+        assert!(listing_looks_garbled(&names(&["BOOTMGR.;1", "SOURCES"])));
+        assert!(listing_looks_garbled(&names(&["bo\u{FFFD}t"])));
+        assert!(listing_looks_garbled(&[]));
+        assert_eq!(iso_mount_options("", ""), "ro");
+        assert_eq!(iso_mount_options("loop", "norock,nojoliet"), "loop,ro,norock,nojoliet");
+    }
 
     #[test]
     fn parses_rsync_progress_line_with_speed() {
@@ -758,4 +2111,300 @@ mod tests {
         assert_eq!(parsed.0, 50_000_000);
         assert!(parsed.1.is_none());
     }
+
+    #[test]
+    fn detects_ntfs3_in_proc_filesystems() {
+        let listing = "nodev\tsysfs\n\text4\n\tntfs3\nnodev\tfuseblk\n";
+        assert!(proc_filesystems_lists(listing, "ntfs3"));
+        assert!(!proc_filesystems_lists("\tntfs\n\tvfat\n", "ntfs3"));
+    }
+
+    #[test]
+    fn parses_progress_line_percent() {
+        let (fraction, label) = parse_progress_line("[PROGRESS] flush: 120 MB remaining (40%)").unwrap();
+        assert!((fraction - 0.4).abs() < f64::EPSILON);
+        assert_eq!(label, "flush: 120 MB remaining (40%)");
+        assert!(parse_progress_line("[PROGRESS] dd: 12 MB written").is_none());
+        assert!(parse_progress_line("Copying files (50%)").is_none());
+    }
+
+    #[test]
+    fn sums_dirty_and_writeback_from_meminfo() {
+        let meminfo = "MemTotal:       16318460 kB\nDirty:             20480 kB\nWriteback:          1024 kB\nWritebackTmp:          0 kB\n";
+        assert_eq!(parse_meminfo_pending_kb(meminfo), Some(21504));
+        assert_eq!(parse_meminfo_pending_kb("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn parses_loop_device_created_by_udisksctl() {
+        let out = "Mapped file /home/me/isos/dev/loop9-notes.iso as /dev/loop12.\n";
+        assert_eq!(parse_udisks_loop_device(out).as_deref(), Some("/dev/loop12"));
+        assert_eq!(parse_udisks_loop_device("Error setting up loop device\n"), None);
+        assert_eq!(parse_udisks_loop_device("Mapped file a.iso as /dev/loop.\n"), None);
+    }
+
+    #[test]
+    fn parses_parted_machine_output() {
+        let out = "BYT;\n/dev/sdb:30031872s:scsi:512:512:gpt:Generic Flash Disk:;\n\
+                   1:2048s:2099199s:2097152s:fat32:BOOT:msftdata;\n\
+                   2:2099200s:30029823s:27930624s:ntfs:ESD-USB:msftdata;\n";
+        let parts = parse_parted_partitions(out);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].start_sector, 2048);
+        assert_eq!(parts[1].size_sectors, 27930624);
+        assert_eq!(parts[1].name, "ESD-USB");
+        assert_eq!(partition_path("/dev/nvme0n1", 2), "/dev/nvme0n1p2");
+    }
+
+    #[test]
+    fn reads_cluster_size_from_boot_sectors() {
+        let mut fat32 = [0u8; 512];
+        fat32[11..13].copy_from_slice(&512u16.to_le_bytes());
+        fat32[13] = 8;
+        fat32[82..90].copy_from_slice(b"FAT32   ");
+        assert_eq!(parse_boot_sector_cluster_bytes(&fat32), Some(4096));
+
+        let mut ntfs = [0u8; 512];
+        ntfs[3..11].copy_from_slice(b"NTFS    ");
+        ntfs[11..13].copy_from_slice(&512u16.to_le_bytes());
+        ntfs[13] = 0xF4; // 2^12 sectors
+        assert_eq!(parse_boot_sector_cluster_bytes(&ntfs), Some(512 * 4096));
+
+        assert_eq!(parse_boot_sector_cluster_bytes(&[0u8; 512]), None);
+    }
+
+    #[test]
+    fn finds_aligned_gaps_between_and_after_partitions() {
+        let part = |number, start, end| PartitionInfo {
+            number,
+            start_sector: start,
+            end_sector: end,
+            size_sectors: end - start + 1,
+            ..Default::default()
+        };
+        // ISO partition, a 100 MiB hole, a second partition, then free space to the end
+        let parts = [part(1, 0, 6_000_000), part(2, 6_300_000, 6_400_000)];
+        let regions = find_free_regions(&parts, 10_000_000, 2048);
+        assert_eq!(
+            regions,
+            vec![
+                FreeRegion { start_sector: 6_000_640, end_sector: 6_299_999 },
+                FreeRegion { start_sector: 6_402_048, end_sector: 9_999_965 },
+            ]
+        );
+        assert!(find_free_regions(&parts, 10_000_000, 1_000_000).len() == 1);
+    }
+
+    #[test]
+    fn resolves_partitions_and_stacked_devices_to_whole_disks() {
+        let sys = tempfile::tempdir().unwrap();
+        let sdb1 = sys.path().join("block/sdb/sdb1");
+        fs::create_dir_all(&sdb1).unwrap();
+        fs::write(sdb1.join("partition"), "1\n").unwrap();
+        let dm = sys.path().join("virtual/block/dm-0");
+        fs::create_dir_all(dm.join("slaves")).unwrap();
+        std::os::unix::fs::symlink(&sdb1, dm.join("slaves/sdb1")).unwrap();
+        let sda = sys.path().join("block/sda");
+        fs::create_dir_all(&sda).unwrap();
+
+        assert_eq!(sysfs_whole_disks(&sdb1), vec!["sdb"]);
+        assert_eq!(sysfs_whole_disks(&dm), vec!["sdb"]);
+        assert_eq!(sysfs_whole_disks(&sda), vec!["sda"]);
+    }
+
+    #[test]
+    fn reads_usb_link_from_ancestor_device() {
+        let root = tempfile::tempdir().unwrap();
+        let usb = root.path().join("usb2/2-1");
+        let scsi = usb.join("2-1:1.0/host6/target6:0:0/6:0:0:0");
+        fs::create_dir_all(&scsi).unwrap();
+        fs::write(usb.join("speed"), "480\n").unwrap();
+        fs::write(usb.join("version"), " 3.20\n").unwrap();
+
+        let link = find_usb_link(&scsi).unwrap();
+        assert_eq!(link.speed_mbps, 480.0);
+        assert!(link.is_degraded());
+        assert!(link.warning().unwrap().contains("USB 3 port"));
+
+        fs::write(usb.join("speed"), "5000\n").unwrap();
+        assert!(find_usb_link(&scsi).unwrap().warning().is_none());
+
+        assert_eq!(find_caching_bridge(&scsi), None);
+        fs::write(usb.join("idVendor"), "152d\n").unwrap();
+        fs::write(usb.join("idProduct"), "0578\n").unwrap();
+        assert_eq!(find_caching_bridge(&scsi), Some("JMicron JMS578"));
+        fs::write(usb.join("idProduct"), "0583\n").unwrap();
+        assert_eq!(find_caching_bridge(&scsi), None);
+    }
+
+    #[test]
+    fn fixed_or_large_targets_need_acknowledgement() {
+        assert!(device_risk_reasons(Some(true), Some(32_000_000_000)).is_empty());
+        assert!(device_risk_reasons(None, None).is_empty());
+        let reasons = device_risk_reasons(Some(false), Some(1_000_204_886_016));
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons[0].contains("not marked removable"));
+        assert!(reasons[1].contains("1000 GB"));
+        assert_eq!(device_risk_reasons(Some(true), Some(512_110_190_592)).len(), 1);
+        assert_eq!(DeviceRisk::from_facts(Some(true), Some(32_000_000_000)), DeviceRisk::Removable);
+        assert_eq!(DeviceRisk::from_facts(None, None), DeviceRisk::Removable);
+        assert_eq!(DeviceRisk::from_facts(Some(false), Some(128_000_000_000)), DeviceRisk::Fixed);
+        assert_eq!(DeviceRisk::from_facts(Some(true), Some(512_110_190_592)), DeviceRisk::Large);
+    }
+
+    #[test]
+    fn mixed_windows_and_linux_markers_are_broken_by_the_install_image() {
+        let touch = |root: &std::path::Path, rel: &str| {
+            let path = root.join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        };
+        // Windows setup media carry boot/ and EFI/ too
+        let windows = tempfile::tempdir().unwrap();
+        for rel in ["bootmgr", "sources/boot.wim", "boot/bcd", "efi/microsoft/boot/bcd", "EFI/BOOT/bootx64.efi"] {
+            touch(windows.path(), rel);
+        }
+        assert_eq!(classify_iso_root(windows.path()), IsoRootKind::Windows);
+
+        // A recovery image with WinPE boot files and an Ubuntu live system
+        let hybrid = tempfile::tempdir().unwrap();
+        for rel in ["bootmgr", "sources/boot.wim", "casper/vmlinuz", "EFI/BOOT/bootx64.efi"] {
+            touch(hybrid.path(), rel);
+        }
+        assert_eq!(classify_iso_root(hybrid.path()), IsoRootKind::Ambiguous);
+        touch(hybrid.path(), "sources/install.wim");
+        assert_eq!(classify_iso_root(hybrid.path()), IsoRootKind::Windows);
+
+        let linux = tempfile::tempdir().unwrap();
+        touch(linux.path(), "bootmgr");
+        touch(linux.path(), "casper/vmlinuz");
+        assert_eq!(classify_iso_root(linux.path()), IsoRootKind::Linux);
+        assert_eq!(classify_iso_root(tempfile::tempdir().unwrap().path()), IsoRootKind::Unknown);
+    }
+
+    #[test]
+    fn finds_the_mount_holding_a_path_and_resolves_symlinked_images() {
+        let mounts = "/dev/nvme0n1p2 / ext4 rw,relatime 0 0\n\
+            nas:/export/isos /mnt/nas nfs4 rw,vers=4.2 0 0\n\
+            //nas/Public\\040Share /mnt/public\\040share cifs rw 0 0\n";
+        let found = |path: &str| mount_containing(mounts, std::path::Path::new(path));
+        assert_eq!(found("/mnt/nas/fedora.iso"), Some(("/mnt/nas".to_string(), "nfs4".to_string())));
+        assert_eq!(found("/mnt/public share/x.iso"), Some(("/mnt/public share".to_string(), "cifs".to_string())));
+        // A sibling directory sharing the prefix is not inside the mount
+        assert_eq!(found("/mnt/nasty/x.iso"), Some(("/".to_string(), "ext4".to_string())));
+
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real.iso");
+        fs::write(&real, b"iso").unwrap();
+        let link = dir.path().join("latest.iso");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let real_path = fs::canonicalize(&real).unwrap().to_string_lossy().into_owned();
+        assert_eq!(resolve_image_path(link.to_str().unwrap()), real_path);
+        assert_eq!(resolve_image_path("/no/such.iso"), "/no/such.iso");
+    }
+
+    #[test]
+    fn stable_device_links_resolve_to_the_kernel_node() {
+        let dev = tempfile::tempdir().unwrap();
+        let by_id = dev.path().join("disk/by-id");
+        fs::create_dir_all(&by_id).unwrap();
+        for node in ["sdz", "nvme0n1"] {
+            fs::write(dev.path().join(node), b"").unwrap();
+        }
+        // udev creates relative links
+        let usb = by_id.join("usb-SanDisk_Cruzer_Blade_4C530001-0:0");
+        std::os::unix::fs::symlink("../../sdz", &usb).unwrap();
+        let nvme = by_id.join("nvme-Samsung_SSD_980_1TB");
+        std::os::unix::fs::symlink("../../nvme0n1", &nvme).unwrap();
+
+        let sdz = fs::canonicalize(dev.path().join("sdz")).unwrap().to_string_lossy().into_owned();
+        let resolved = resolve_device_path(usb.to_str().unwrap());
+        assert_eq!(resolved, sdz);
+        assert_eq!(partition_path(&resolved, 2), format!("{}2", sdz));
+        let resolved = resolve_device_path(nvme.to_str().unwrap());
+        assert!(resolved.ends_with("/nvme0n1"));
+        assert!(partition_path(&resolved, 1).ends_with("/nvme0n1p1"));
+
+        assert_eq!(resolve_device_path("/dev/sdz"), "/dev/sdz");
+        let dangling = by_id.join("usb-unplugged");
+        std::os::unix::fs::symlink("../../sdy", &dangling).unwrap();
+        assert_eq!(resolve_device_path(dangling.to_str().unwrap()), dangling.to_str().unwrap());
+    }
+
+    #[test]
+    fn file_names_pointing_to_another_os_are_flagged() {
+        let windows = IsoType::Windows(WindowsFlavor::Client);
+        let note = name_mismatch_warning("/isos/Win11_23H2_English_x64.iso", IsoType::Linux).unwrap();
+        assert!(note.starts_with("The file name suggests Windows, but the image was detected as Linux."));
+        assert!(name_mismatch_warning("/isos/ubuntu-24.04-desktop-amd64.iso", windows).unwrap().contains("Linux (ubuntu)"));
+        // Matching, neutral and mixed names say nothing
+        assert_eq!(name_mismatch_warning("/isos/en-us_windows_server_2022.iso", windows), None);
+        assert_eq!(name_mismatch_warning("/isos/Fedora-Workstation-Live-x86_64-40.iso", IsoType::Linux), None);
+        assert_eq!(name_mismatch_warning("/isos/image.iso", windows), None);
+        assert_eq!(name_mismatch_warning("/isos/windows-linux-rescue.iso", windows), None);
+        assert_eq!(name_mismatch_warning("/isos/ubuntu-on-win10.iso", IsoType::Linux), None);
+    }
+
+    #[test]
+    fn reads_the_verdict_of_the_detect_helper() {
+        assert_eq!(parse_detect_output("{\"type\":\"windows\"}\n"), Some(IsoType::Windows(WindowsFlavor::Client)));
+        assert_eq!(
+            parse_detect_output(r#"{"flavor":"server","type":"windows"}"#),
+            Some(IsoType::Windows(WindowsFlavor::Server))
+        );
+        assert_eq!(
+            parse_detect_output(r#"{"flavor":"legacy","type":"windows"}"#),
+            Some(IsoType::Windows(WindowsFlavor::Legacy))
+        );
+        assert_eq!(
+            parse_detect_output(r#"{"distro":"Ubuntu","persistence":"casper","type":"linux","version":"24.04"}"#),
+            Some(IsoType::Linux)
+        );
+        assert_eq!(parse_detect_output("Could not detect ISO type"), None);
+        assert_eq!(parse_detect_output(r#"{"type":"raw"}"#), None);
+        for iso_type in [IsoType::Windows(WindowsFlavor::Server), IsoType::Windows(WindowsFlavor::Legacy), IsoType::Linux] {
+            assert_eq!(parse_detect_output(&format!("{}\n", detect_report(iso_type))), Some(iso_type));
+        }
+        // A diagnostic on stdout would hide the verdict
+        let noisy = format!("[DEBUG] Mounted /dev/loop0 with -o ro,utf8 after garbled file names\n{}", detect_report(IsoType::Linux));
+        assert_eq!(parse_detect_output(&noisy), None);
+
+        let helper = std::path::Path::new("/usr/bin/cli_helper");
+        assert_eq!(elevated_detect_command(helper, "/isos/x.iso", false), ["pkexec", "/usr/bin/cli_helper", "detect", "/isos/x.iso"]);
+        assert_eq!(elevated_detect_command(helper, "/isos/x.iso", true)[..3], ["flatpak-spawn", "--host", "pkexec"]);
+    }
+
+    #[test]
+    fn content_scan_reports_progress_and_stops_when_cancelled() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("sources/en-us")).unwrap();
+        for i in 0..2500 {
+            fs::write(root.path().join(format!("sources/en-us/{}.mui", i)), [0u8; 4]).unwrap();
+        }
+        fs::write(root.path().join("bootmgr"), [0u8; 1000]).unwrap();
+        std::os::unix::fs::symlink("/usr", root.path().join("loop")).unwrap();
+
+        let mut reports = Vec::new();
+        let totals = scan_content(root.path(), &AtomicBool::new(false), &mut |t| reports.push(t.files)).unwrap();
+        assert_eq!(totals, ContentTotals { files: 2501, bytes: 11_000 });
+        assert_eq!(reports, [1000, 2000]);
+        assert_eq!(totals.describe(), "scanning: 2,501 files (0 MB)");
+
+        let cancelled = scan_content(root.path(), &AtomicBool::new(true), &mut |_| {}).unwrap_err();
+        assert_eq!(cancelled.kind(), io::ErrorKind::Interrupted);
+
+        let scan = ContentScan::start(root.path().to_path_buf());
+        assert_eq!(scan.wait().unwrap().files, 2501);
+    }
+
+    #[test]
+    fn missing_programs_get_an_install_hint() {
+        assert_eq!(
+            not_installed_message("rsync", Some("sudo dnf install -y rsync")),
+            "rsync is not installed (or not in PATH). Install it with: sudo dnf install -y rsync"
+        );
+        assert!(not_installed_message("rsync", None).contains("distribution's packages"));
+        let denied = missing_program_error("rsync", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(denied.kind(), io::ErrorKind::PermissionDenied);
+    }
 }
@@ -24,6 +24,7 @@ impl Architecture {
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct UnattendFlags: u32 {
         const BYPASS_TPM = 0x0001;
         const BYPASS_SECURE_BOOT = 0x0002;
@@ -131,4 +132,56 @@ mod tests {
         assert!(content.contains("BypassRAMCheck"));
         assert!(content.contains("windowsPE"));
     }
+
+    /// Generate into a private directory and compare with `testdata/unattend/<fixture>.xml`
+    fn assert_matches_golden(arch: Architecture, flags: UnattendFlags, golden: &str, fixture: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = UnattendGenerator::new(arch, flags)
+            .with_output_path(dir.path().join("Autounattend.xml"))
+            .generate()
+            .expect("failed to generate unattend");
+        let content = fs::read_to_string(path).expect("failed to read unattend");
+        assert_eq!(content, golden, "generated XML differs from testdata/unattend/{}.xml", fixture);
+    }
+
+    macro_rules! golden {
+        ($arch:expr, $flags:expr, $fixture:literal) => {
+            assert_matches_golden(
+                $arch,
+                $flags,
+                include_str!(concat!("testdata/unattend/", $fixture, ".xml")),
+                $fixture,
+            )
+        };
+    }
+
+    #[test]
+    fn no_flags_produce_a_minimal_document() {
+        golden!(Architecture::X64, UnattendFlags::empty(), "no_flags");
+    }
+
+    #[test]
+    fn each_flag_adds_only_its_labconfig_entry() {
+        golden!(Architecture::X64, UnattendFlags::BYPASS_TPM, "bypass_tpm");
+        golden!(Architecture::X64, UnattendFlags::BYPASS_SECURE_BOOT, "bypass_secure_boot");
+        golden!(Architecture::X64, UnattendFlags::BYPASS_RAM, "bypass_ram");
+    }
+
+    #[test]
+    fn combined_flags_are_numbered_consecutively() {
+        golden!(Architecture::X64, UnattendFlags::BYPASS_TPM | UnattendFlags::BYPASS_RAM, "bypass_tpm_ram");
+        golden!(
+            Architecture::X64,
+            UnattendFlags::BYPASS_SECURE_BOOT | UnattendFlags::BYPASS_RAM,
+            "bypass_secure_boot_ram"
+        );
+        golden!(Architecture::X64, UnattendFlags::BYPASS_ALL, "bypass_all_x64");
+    }
+
+    #[test]
+    fn each_architecture_sets_processor_architecture() {
+        golden!(Architecture::X86, UnattendFlags::BYPASS_ALL, "bypass_all_x86");
+        golden!(Architecture::Arm, UnattendFlags::BYPASS_ALL, "bypass_all_arm");
+        golden!(Architecture::Arm64, UnattendFlags::BYPASS_ALL, "bypass_all_arm64");
+    }
 }
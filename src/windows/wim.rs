@@ -1,7 +1,184 @@
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::io;
 
+/// Windows setup image under `sources/`: a single WIM/ESD, or a set that was split
+/// into `install.swm`, `install2.swm`, ... before the ISO was built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallImage {
+    Wim(PathBuf),
+    Esd(PathBuf),
+    Split(Vec<PathBuf>),
+}
+
+impl InstallImage {
+    /// Look for the install image in `sources_dir`, matching names case-insensitively.
+    /// A split set must be numbered without gaps; a missing part is an error.
+    pub fn find(sources_dir: &Path) -> io::Result<Option<Self>> {
+        let mut files: Vec<(String, PathBuf)> = fs::read_dir(sources_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .map(|entry| (entry.file_name().to_string_lossy().to_ascii_lowercase(), entry.path()))
+            .collect();
+        files.sort();
+        let named = |name: &str| files.iter().find(|(file, _)| file == name).map(|(_, path)| path.clone());
+        if let Some(path) = named("install.wim") {
+            return Ok(Some(Self::Wim(path)));
+        }
+        if let Some(path) = named("install.esd") {
+            return Ok(Some(Self::Esd(path)));
+        }
+        let Some(first) = named("install.swm") else {
+            return Ok(None);
+        };
+        let mut numbers: Vec<u32> = files
+            .iter()
+            .filter_map(|(file, _)| file.strip_prefix("install")?.strip_suffix(".swm")?.parse().ok())
+            .collect();
+        numbers.sort_unstable();
+        let mut parts = vec![first];
+        for (expected, number) in (2..).zip(numbers) {
+            if number != expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("split install image is incomplete: install{}.swm is missing", expected),
+                ));
+            }
+            parts.push(named(&format!("install{}.swm", number)).unwrap_or_default());
+        }
+        Ok(Some(Self::Split(parts)))
+    }
+
+    /// Every file of the image, first part first
+    pub fn parts(&self) -> &[PathBuf] {
+        match self {
+            Self::Wim(path) | Self::Esd(path) => std::slice::from_ref(path),
+            Self::Split(parts) => parts,
+        }
+    }
+
+    /// The file wimlib opens; for a split set this is `install.swm`, which carries the image metadata
+    pub fn primary(&self) -> &Path {
+        &self.parts()[0]
+    }
+
+    /// Short description for the log, e.g. "install.swm (split, 3 parts)"
+    pub fn describe(&self) -> String {
+        let name = self.primary().file_name().unwrap_or_default().to_string_lossy().into_owned();
+        match self {
+            Self::Split(parts) => format!("{} (split, {} parts)", name, parts.len()),
+            _ => name,
+        }
+    }
+
+    /// Query the image with wimlib; `info` reads the edition list from the first part of a split set
+    pub fn editor(&self) -> WimEditor {
+        WimEditor::new(self.primary())
+    }
+
+    /// Client or Server, from the XML metadata of the first part (no wimlib needed)
+    pub fn flavor(&self) -> io::Result<WindowsFlavor> {
+        read_wim_xml(self.primary()).map(|xml| WindowsFlavor::from_wim_xml(&xml))
+    }
+}
+
+/// Product line of an install image. Server media share the client layout (`bootmgr`,
+/// `sources/boot.wim`, `sources/install.wim`) and only differ in the image metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowsFlavor {
+    Client,
+    Server,
+    /// Client media older than Windows 10 (7, 8, 8.1), best written with the legacy layout
+    Legacy,
+}
+
+impl WindowsFlavor {
+    /// Name shown in detection results and logs
+    pub fn product_name(self) -> &'static str {
+        match self {
+            WindowsFlavor::Client => "Windows",
+            WindowsFlavor::Server => "Windows Server",
+            WindowsFlavor::Legacy => "Windows 7/8",
+        }
+    }
+
+    /// Server images carry `<PRODUCTTYPE>ServerNT</PRODUCTTYPE>` and an installation type of
+    /// "Server" or "Server Core"; client images say `WinNT` and "Client". Client images whose
+    /// `<VERSION><MAJOR>` is below 10 (6.1 for 7, 6.2 and 6.3 for 8 and 8.1) are legacy media.
+    pub fn from_wim_xml(xml: &str) -> Self {
+        let server = xml_values(xml, "PRODUCTTYPE").contains(&"ServerNT")
+            || xml_values(xml, "INSTALLATIONTYPE").iter().any(|value| value.starts_with("Server"));
+        let majors: Vec<u32> = xml_values(xml, "MAJOR").iter().filter_map(|value| value.parse().ok()).collect();
+        if server {
+            WindowsFlavor::Server
+        } else if !majors.is_empty() && majors.iter().all(|major| *major < 10) {
+            WindowsFlavor::Legacy
+        } else {
+            WindowsFlavor::Client
+        }
+    }
+}
+
+/// Text of every `<tag>...</tag>` element in `xml`
+fn xml_values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    xml.split(open.as_str())
+        .skip(1)
+        .filter_map(|rest| rest.split_once(close.as_str()).map(|(value, _)| value.trim()))
+        .collect()
+}
+
+/// Magic at the start of a WIM, ESD or SWM file
+const WIM_MAGIC: &[u8; 8] = b"MSWIM\0\0\0";
+/// Offset of the XML resource header (7-byte size, 1-byte flags, 8-byte offset) in the WIM header
+const WIM_XML_RESHDR: usize = 72;
+/// Size of the WIM header
+const WIM_HEADER_LEN: usize = 208;
+/// The XML of a many-edition image is a few hundred KB; anything far larger is not a WIM
+const MAX_WIM_XML_BYTES: u64 = 16 << 20;
+
+/// XML metadata of a WIM. It is stored uncompressed as UTF-16LE even in ESD files, so it can be
+/// read straight from the header pointers without wimlib.
+pub fn read_wim_xml(path: &Path) -> io::Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(path)?;
+    let mut header = [0u8; WIM_HEADER_LEN];
+    file.read_exact(&mut header)?;
+    if &header[..8] != WIM_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a WIM file", path.display()),
+        ));
+    }
+    let reshdr = &header[WIM_XML_RESHDR..WIM_XML_RESHDR + 16];
+    let mut size_bytes = [0u8; 8];
+    size_bytes[..7].copy_from_slice(&reshdr[..7]);
+    let size = u64::from_le_bytes(size_bytes);
+    let offset = u64::from_le_bytes(reshdr[8..16].try_into().unwrap_or_default());
+    if size == 0 || size > MAX_WIM_XML_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no usable XML metadata", path.display()),
+        ));
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut raw = vec![0u8; size as usize];
+    file.read_exact(&mut raw)?;
+    let units: Vec<u16> = raw.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    Ok(String::from_utf16_lossy(&units).trim_start_matches('\u{FEFF}').to_string())
+}
+
+/// Image names from `wimlib-imagex info` output, in index order
+pub fn parse_image_names(info: &str) -> Vec<String> {
+    info.lines()
+        .filter_map(|line| line.trim().strip_prefix("Name:"))
+        .map(|name| name.trim().to_string())
+        .collect()
+}
+
 pub struct WimEditor {
     wim_path: PathBuf,
 }
@@ -72,4 +249,99 @@ impl WimEditor {
             .output()?;
         Ok(output.status.success())
     }
+
+    /// Names of the editions in the image, in index order
+    pub fn image_names(&self) -> io::Result<Vec<String>> {
+        if !Self::has_wimlib() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "wimlib-imagex not found. Install wimtools/wimlib",
+            ));
+        }
+        let output = Command::new("wimlib-imagex")
+            .arg("info")
+            .arg(&self.wim_path)
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "wimlib-imagex info failed for {}",
+                self.wim_path.display()
+            )));
+        }
+        Ok(parse_image_names(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(dir: &Path, name: &str) {
+        fs::write(dir.join(name), b"").unwrap();
+    }
+
+    #[test]
+    fn detects_split_install_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let sources = dir.path();
+        touch(sources, "boot.wim");
+        assert_eq!(InstallImage::find(sources).unwrap(), None);
+
+        touch(sources, "INSTALL.SWM");
+        touch(sources, "install2.swm");
+        touch(sources, "install3.swm");
+        let image = InstallImage::find(sources).unwrap().unwrap();
+        let names: Vec<_> = image.parts().iter().map(|p| p.file_name().unwrap().to_owned()).collect();
+        assert_eq!(names, ["INSTALL.SWM", "install2.swm", "install3.swm"]);
+        assert_eq!(image.describe(), "INSTALL.SWM (split, 3 parts)");
+
+        fs::remove_file(sources.join("install2.swm")).unwrap();
+        let err = InstallImage::find(sources).unwrap_err();
+        assert!(err.to_string().contains("install2.swm is missing"));
+
+        touch(sources, "install.wim");
+        assert_eq!(InstallImage::find(sources).unwrap(), Some(InstallImage::Wim(sources.join("install.wim"))));
+    }
+
+    #[test]
+    fn parses_edition_names() {
+        let info = "WIM Information:\n----------------\nPart Number:    1/2\n\nAvailable Images:\n\
+            ----------------\nIndex:                  1\nName:                   Windows 11 Home\n\
+            Index:                  2\nName:                   Windows 11 Pro\nDisplay Name:           Windows 11 Pro\n";
+        assert_eq!(parse_image_names(info), ["Windows 11 Home", "Windows 11 Pro"]);
+    }
+
+    /// Minimal WIM: a header whose XML resource points at `xml` encoded as UTF-16LE with a BOM
+    fn write_wim(path: &Path, xml: &str) {
+        let mut data: Vec<u8> = std::iter::once(0xFEFFu16)
+            .chain(xml.encode_utf16())
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let mut header = vec![0u8; WIM_HEADER_LEN];
+        header[..8].copy_from_slice(WIM_MAGIC);
+        header[WIM_XML_RESHDR..WIM_XML_RESHDR + 7].copy_from_slice(&(data.len() as u64).to_le_bytes()[..7]);
+        header[WIM_XML_RESHDR + 8..WIM_XML_RESHDR + 16].copy_from_slice(&(WIM_HEADER_LEN as u64).to_le_bytes());
+        header.append(&mut data);
+        fs::write(path, header).unwrap();
+    }
+
+    #[test]
+    fn tells_server_images_from_client_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let sources = dir.path();
+
+        write_wim(&sources.join("install.wim"), include_str!("testdata/wim/server_2022.xml"));
+        let image = InstallImage::find(sources).unwrap().unwrap();
+        assert_eq!(image.flavor().unwrap(), WindowsFlavor::Server);
+        assert_eq!(WindowsFlavor::Server.product_name(), "Windows Server");
+
+        write_wim(&sources.join("install.wim"), include_str!("testdata/wim/windows_11.xml"));
+        assert_eq!(image.flavor().unwrap(), WindowsFlavor::Client);
+
+        write_wim(&sources.join("install.wim"), include_str!("testdata/wim/windows_7.xml"));
+        assert_eq!(image.flavor().unwrap(), WindowsFlavor::Legacy);
+
+        fs::write(sources.join("install.wim"), vec![0u8; WIM_HEADER_LEN]).unwrap();
+        assert_eq!(image.flavor().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
 }
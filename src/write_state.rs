@@ -0,0 +1,133 @@
+//! Marker files for writes in progress, kept in ~/.local/state/majusb/writes so a write that never
+//! finished (crash, power loss, failure) can be reported when the GUI starts again
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::settings::hand_back_to_original_user;
+use crate::utils::get_user_home;
+
+/// Last known stage of a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WritePhase {
+    /// The device was being written when the marker was last updated
+    Writing,
+    /// The write reported an error and left the device as it was
+    Failed,
+}
+
+/// One device being written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteState {
+    pub device: String,
+    pub iso_path: String,
+    pub phase: WritePhase,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+}
+
+impl WriteState {
+    pub fn new(device: impl Into<String>, iso_path: impl Into<String>) -> Self {
+        Self {
+            device: device.into(),
+            iso_path: iso_path.into(),
+            phase: WritePhase::Writing,
+            started_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+        }
+    }
+
+    /// Directory holding one marker per device, for the invoking (non-root) user
+    pub fn dir() -> PathBuf {
+        let base = std::env::var("XDG_STATE_HOME")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(get_user_home()).join(".local").join("state"));
+        base.join("majusb").join("writes")
+    }
+
+    fn path_in(dir: &Path, device: &str) -> PathBuf {
+        dir.join(format!("{}.toml", device.trim_start_matches('/').replace('/', "-")))
+    }
+
+    /// Record (or update) the marker for this device
+    pub fn save(&self) -> io::Result<()> {
+        self.save_in(&Self::dir())
+    }
+
+    pub fn save_in(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let path = Self::path_in(dir, &self.device);
+        fs::write(&path, toml::to_string_pretty(self).map_err(io::Error::other)?)?;
+        hand_back_to_original_user(&path);
+        Ok(())
+    }
+
+    /// Remove the marker for `device` once its write has finished
+    pub fn clear(device: &str) -> io::Result<()> {
+        Self::clear_in(&Self::dir(), device)
+    }
+
+    pub fn clear_in(dir: &Path, device: &str) -> io::Result<()> {
+        match fs::remove_file(Self::path_in(dir, device)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Markers left by writes that never completed, oldest first; unreadable files are skipped
+    pub fn load_all() -> Vec<Self> {
+        Self::load_all_in(&Self::dir())
+    }
+
+    pub fn load_all_in(dir: &Path) -> Vec<Self> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut states: Vec<Self> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|text| toml::from_str(&text).ok())
+            .collect();
+        states.sort_by_key(|state| state.started_at);
+        states
+    }
+
+    /// Sentence for the startup warning
+    pub fn warning(&self) -> String {
+        let what = match self.phase {
+            WritePhase::Writing => "was interrupted",
+            WritePhase::Failed => "failed",
+        };
+        format!(
+            "A previous write of {} to {} {}; the device may be in an inconsistent state. Write it again before use.",
+            self.iso_path, self.device, what
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_survive_until_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sdb = WriteState::new("/dev/sdb", "/tmp/ubuntu.iso");
+        sdb.save_in(dir.path()).unwrap();
+        WriteState::new("/dev/sdc", "/tmp/win.iso").save_in(dir.path()).unwrap();
+        assert_eq!(WriteState::load_all_in(dir.path()).len(), 2);
+
+        sdb.phase = WritePhase::Failed;
+        sdb.save_in(dir.path()).unwrap();
+        WriteState::clear_in(dir.path(), "/dev/sdc").unwrap();
+        WriteState::clear_in(dir.path(), "/dev/sdc").unwrap();
+        assert_eq!(WriteState::load_all_in(dir.path()), vec![sdb.clone()]);
+        assert!(sdb.warning().contains("/tmp/ubuntu.iso to /dev/sdb failed"));
+    }
+}